@@ -0,0 +1,745 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeSet;
+use std::os::unix::fs::FileExt;
+use fxhash::FxHashMap;
+use zip::ZipArchive;
+use anyhow::{Error, format_err};
+use super::{Document, Location, TocEntry, BoundedText};
+use super::pdf::{PdfOpener, PdfDocument};
+use super::html::xml::XmlParser;
+use crate::helpers::decode_entities;
+use crate::framebuffer::Pixmap;
+use crate::metadata::TextAlign;
+use crate::geom::{Boundary, CycleDir};
+
+const COMIC_INFO_NAME: &str = "comicinfo.xml";
+// The dimensions of the common image formats are stored within this many bytes.
+const IMAGE_HEAD_SIZE: usize = 64 * 1024;
+const UNRAR_PATH: &str = "bin/unrar";
+const IMAGE_EXTENSIONS: [&str; 9] = ["jpg", "jpeg", "png", "gif", "bmp",
+                                     "tif", "tiff", "jp2", "jpx"];
+
+trait Container: Send {
+    fn names(&mut self) -> Result<Vec<String>, Error>;
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error>;
+
+    // Fetches at least the first `len` bytes of the given entry.
+    fn fetch_head(&mut self, name: &str, _len: usize) -> Result<Vec<u8>, Error> {
+        self.fetch(name)
+    }
+}
+
+struct DirContainer(PathBuf);
+
+impl Container for DirContainer {
+    fn names(&mut self) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.0)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        fs::read(self.0.join(name)).map_err(Into::into)
+    }
+
+    fn fetch_head(&mut self, name: &str, len: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        File::open(self.0.join(name))?.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl Container for ZipArchive<File> {
+    fn names(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.file_names().map(String::from).collect())
+    }
+
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let mut file = self.by_name(name)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn fetch_head(&mut self, name: &str, len: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.by_name(name)?.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+struct TarContainer {
+    file: File,
+    // Maps each file name to the position and size of its data.
+    entries: FxHashMap<String, (u64, u64)>,
+}
+
+impl TarContainer {
+    fn new<P: AsRef<Path>>(path: P) -> Result<TarContainer, Error> {
+        let mut archive = tar::Archive::new(File::open(path.as_ref())?);
+        let mut entries = FxHashMap::default();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().into_owned();
+            entries.insert(name, (entry.raw_file_position(), entry.size()));
+        }
+        let file = File::open(path.as_ref())?;
+        Ok(TarContainer { file, entries })
+    }
+}
+
+impl Container for TarContainer {
+    fn names(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.entries.keys().cloned().collect())
+    }
+
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let (position, size) = self.entries.get(name).cloned()
+                                   .ok_or_else(|| format_err!("can't find {}", name))?;
+        let mut buf = vec![0; size as usize];
+        self.file.read_exact_at(&mut buf, position)?;
+        Ok(buf)
+    }
+
+    fn fetch_head(&mut self, name: &str, len: usize) -> Result<Vec<u8>, Error> {
+        let (position, size) = self.entries.get(name).cloned()
+                                   .ok_or_else(|| format_err!("can't find {}", name))?;
+        let mut buf = vec![0; size.min(len as u64) as usize];
+        self.file.read_exact_at(&mut buf, position)?;
+        Ok(buf)
+    }
+}
+
+struct SevenZContainer {
+    path: PathBuf,
+    archive: Arc<sevenz_rust::Archive>,
+    // The block being decoded.
+    stream: Option<(usize, EntryStream)>,
+}
+
+impl SevenZContainer {
+    fn new<P: AsRef<Path>>(path: P) -> Result<SevenZContainer, Error> {
+        let archive = sevenz_rust::Archive::open(path.as_ref())?;
+        Ok(SevenZContainer { path: path.as_ref().to_path_buf(), archive: Arc::new(archive), stream: None })
+    }
+
+    fn stream(&self, folder_index: usize) -> EntryStream {
+        let path = self.path.clone();
+        let archive = Arc::clone(&self.archive);
+
+        EntryStream::new(0, move |sender| {
+            let result = File::open(&path).map_err(Into::into).and_then(|mut file| {
+                let decoder = sevenz_rust::BlockDecoder::new(folder_index, &archive, &[], &mut file);
+                decoder.for_each_entries(&mut |_, reader| {
+                    let mut buf = Vec::new();
+                    reader.read_to_end(&mut buf)?;
+                    Ok(sender.send(buf).is_ok())
+                })
+            });
+            if let Err(e) = result {
+                eprintln!("Can't decode block {} of {}: {:#}.", folder_index, path.display(), e);
+            }
+        })
+    }
+}
+
+impl Container for SevenZContainer {
+    fn names(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.archive.files.iter()
+               .filter(|entry| entry.has_stream())
+               .map(|entry| entry.name().to_string())
+               .collect())
+    }
+
+    // The entries of a block are compressed as a single stream: the block is decoded
+    // sequentially, and only restarted when an earlier entry is requested.
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let index = self.archive.files.iter().position(|entry| entry.name() == name)
+                        .ok_or_else(|| format_err!("can't find {}", name))?;
+        let folder_index = self.archive.stream_map.file_folder_index[index]
+                               .ok_or_else(|| format_err!("{} is empty", name))?;
+        let position = index - self.archive.stream_map.folder_first_file_index[folder_index];
+
+        if self.stream.as_ref().is_none_or(|(i, stream)| *i != folder_index || !stream.has(position)) {
+            self.stream = Some((folder_index, self.stream(folder_index)));
+        }
+
+        let result = self.stream.as_mut().unwrap().1.take(position);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+// The entries of some archives can only be decoded in order: they're decoded ahead
+// by a producer running in the background.
+struct EntryStream {
+    receiver: Receiver<Vec<u8>>,
+    // The position of the next entry within the stream.
+    position: usize,
+    // The last entry taken: its dimensions are usually read before it's rendered.
+    last: Option<(usize, Vec<u8>)>,
+}
+
+impl EntryStream {
+    fn new<F>(position: usize, producer: F) -> EntryStream where F: FnOnce(SyncSender<Vec<u8>>) + Send + 'static {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        thread::spawn(move || producer(sender));
+        EntryStream { receiver, position, last: None }
+    }
+
+    fn has(&self, position: usize) -> bool {
+        position >= self.position || self.last.as_ref().is_some_and(|(i, _)| *i == position)
+    }
+
+    // Skips the entries preceding the requested one.
+    fn take(&mut self, position: usize) -> Result<Vec<u8>, Error> {
+        if let Some((_, buf)) = self.last.as_ref().filter(|(i, _)| *i == position) {
+            return Ok(buf.clone());
+        }
+        if position < self.position {
+            return Err(format_err!("entry {} was already read", position));
+        }
+        loop {
+            let buf = self.receiver.recv()
+                          .map_err(|_| format_err!("the stream ended before entry {}", position))?;
+            self.position += 1;
+            if self.position > position {
+                self.last = Some((position, buf.clone()));
+                return Ok(buf);
+            }
+        }
+    }
+}
+
+// There's no RAR decoder written in Rust: the entries are extracted with *unrar*,
+// which is looked for in the *bin* directory and then in the `PATH`.
+// A single process writes the entries, in order, starting at the requested one.
+struct RarContainer {
+    path: PathBuf,
+    // The names and sizes of the files, in the order of the archive.
+    entries: Vec<(String, usize)>,
+    stream: Option<EntryStream>,
+}
+
+fn unrar() -> Command {
+    let program = if Path::new(UNRAR_PATH).exists() {
+        UNRAR_PATH
+    } else {
+        "unrar"
+    };
+    Command::new(program)
+}
+
+// Parses the technical listing of an archive.
+fn rar_entries(listing: &str) -> Vec<(String, usize)> {
+    let mut entries = Vec::new();
+    let mut name = None;
+    let mut is_file = false;
+
+    for line in listing.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("Name: ") {
+            name = Some(value.to_string());
+            is_file = false;
+        } else if let Some(value) = line.strip_prefix("Type: ") {
+            is_file = value == "File";
+        } else if let Some(value) = line.strip_prefix("Size: ") {
+            if let (true, Some(name), Ok(size)) = (is_file, name.take(), value.parse::<usize>()) {
+                entries.push((name, size));
+            }
+        }
+    }
+
+    entries
+}
+
+impl RarContainer {
+    fn new<P: AsRef<Path>>(path: P) -> Result<RarContainer, Error> {
+        let output = unrar().args(["lt", "-p-"])
+                            .arg(path.as_ref())
+                            .output()
+                            .map_err(|e| format_err!("can't execute unrar: {}", e))?;
+        if !output.status.success() {
+            return Err(format_err!("unrar failed to list the archive"));
+        }
+        let entries = rar_entries(&String::from_utf8_lossy(&output.stdout));
+        Ok(RarContainer { path: path.as_ref().to_path_buf(), entries, stream: None })
+    }
+
+    fn stream(&self, position: usize) -> EntryStream {
+        let mut command = unrar();
+        command.args(["p", "-inul", "-p-"])
+               .arg(&self.path)
+               .stdout(Stdio::piped())
+               .stderr(Stdio::null());
+        let entries = &self.entries[position..];
+        // The names given to unrar are masks.
+        let position = if position > 0 && entries.iter().all(|(name, _)| !name.contains(['*', '?'])) {
+            command.arg("--").args(entries.iter().map(|(name, _)| name));
+            position
+        } else {
+            0
+        };
+        let sizes: Vec<usize> = self.entries[position..].iter().map(|(_, size)| *size).collect();
+
+        EntryStream::new(position, move |sender| {
+            let mut child = match command.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("Can't execute unrar: {:#}.", e);
+                    return;
+                },
+            };
+            if let Some(mut stdout) = child.stdout.take() {
+                for size in sizes {
+                    let mut buf = vec![0; size];
+                    if stdout.read_exact(&mut buf).is_err() || sender.send(buf).is_err() {
+                        break;
+                    }
+                }
+            }
+            child.kill().ok();
+            child.wait().ok();
+        })
+    }
+}
+
+impl Container for RarContainer {
+    fn names(&mut self) -> Result<Vec<String>, Error> {
+        Ok(self.entries.iter().map(|(name, _)| name.clone()).collect())
+    }
+
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let position = self.entries.iter().position(|(n, _)| n == name)
+                           .ok_or_else(|| format_err!("can't find {}", name))?;
+        if self.stream.as_ref().is_none_or(|stream| !stream.has(position)) {
+            self.stream = Some(self.stream(position));
+        }
+        let result = self.stream.as_mut().unwrap().take(position);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+struct Pages {
+    container: Box<dyn Container>,
+    // The dimensions of the pages: one pixel per point.
+    dims: FxHashMap<usize, (f32, f32)>,
+    last: Option<(usize, Vec<u8>)>,
+}
+
+pub struct ComicDocument {
+    names: Vec<String>,
+    pages: Mutex<Pages>,
+    info: FxHashMap<String, String>,
+}
+
+fn image_dims(buf: &[u8]) -> Option<(f32, f32)> {
+    imagesize::blob_size(buf).ok()
+             .map(|size| (size.width as f32, size.height as f32))
+}
+
+fn is_image(name: &str) -> bool {
+    Path::new(name).extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// Don't let the resource forks created by macOS pass for pages.
+fn is_ignored(name: &str) -> bool {
+    Path::new(name).components().any(|c| {
+        c.as_os_str().to_str().is_some_and(|s| s.starts_with('.') || s == "__MACOSX")
+    })
+}
+
+pub fn is_image_folder<P: AsRef<Path>>(path: P) -> bool {
+    let mut has_images = false;
+
+    if let Ok(entries) = fs::read_dir(path.as_ref()) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if is_ignored(&name) {
+                continue;
+            }
+            if entry.file_type().map_or(true, |ft| !ft.is_file()) {
+                return false;
+            }
+            if is_image(&name) {
+                has_images = true;
+            } else if name.to_lowercase() != COMIC_INFO_NAME {
+                return false;
+            }
+        }
+    }
+
+    has_images
+}
+
+fn read_comic_info(text: &str) -> FxHashMap<String, String> {
+    let mut info = FxHashMap::default();
+    let xml = XmlParser::new(text).parse();
+
+    if let Some(node) = xml.root().find("ComicInfo") {
+        for child in node.children() {
+            if let Some(name) = child.tag_name() {
+                let text = decode_entities(child.text().trim()).into_owned();
+                if !text.is_empty() {
+                    info.insert(name.to_string(), text);
+                }
+            }
+        }
+    }
+
+    info
+}
+
+impl ComicDocument {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<ComicDocument, Error> {
+        let path = path.as_ref();
+        let mut container: Box<dyn Container> = if path.is_dir() {
+            Box::new(DirContainer(path.to_path_buf()))
+        } else {
+            let file = File::open(path)?;
+            let mut magic = [0; 6];
+            file.read_exact_at(&mut magic, 0)?;
+            if &magic[..4] == b"PK\x03\x04" {
+                Box::new(ZipArchive::new(file)?)
+            } else if &magic == b"Rar!\x1A\x07" {
+                Box::new(RarContainer::new(path)?)
+            } else if &magic == b"7z\xBC\xAF\x27\x1C" {
+                Box::new(SevenZContainer::new(path)?)
+            } else {
+                Box::new(TarContainer::new(path)?)
+            }
+        };
+
+        let all_names = container.names()?;
+        let mut names: Vec<String> = all_names.iter()
+                                              .filter(|name| is_image(name) && !is_ignored(name))
+                                              .cloned().collect();
+
+        if names.is_empty() {
+            return Err(format_err!("no images found"));
+        }
+
+        names.sort_by(|a, b| natural_cmp(a, b).then_with(|| a.cmp(b)));
+
+        let info = all_names.iter()
+                            .find(|name| {
+                                Path::new(name).file_name()
+                                    .and_then(OsStr::to_str)
+                                    .is_some_and(|s| s.to_lowercase() == COMIC_INFO_NAME)
+                            })
+                            .and_then(|name| container.fetch(name).ok())
+                            .map(|buf| read_comic_info(&String::from_utf8_lossy(&buf)))
+                            .unwrap_or_default();
+
+        Ok(ComicDocument {
+            names,
+            pages: Mutex::new(Pages {
+                container,
+                dims: FxHashMap::default(),
+                last: None,
+            }),
+            info,
+        })
+    }
+
+    fn page(&self, index: usize) -> Option<PdfDocument> {
+        let name = self.names.get(index)?;
+        let mut pages = self.pages.lock().ok()?;
+
+        if pages.last.as_ref().map(|(i, _)| *i) != Some(index) {
+            let buf = pages.container.fetch(name)
+                           .map_err(|e| eprintln!("Can't fetch {}: {:#}.", name, e))
+                           .ok()?;
+            pages.last = Some((index, buf));
+        }
+
+        let buf = pages.last.as_ref().map(|(_, buf)| buf)?;
+        let doc = PdfOpener::new().and_then(|opener| opener.open_memory(name, buf))?;
+
+        // The size of the page depends on the resolution of the image and on its orientation.
+        if let Some((width, height)) = doc.dims(0) {
+            let dims = match image_dims(buf) {
+                Some((w, h)) if (w > h) == (width > height) => (w, h),
+                Some((w, h)) => (h, w),
+                None => (width, height),
+            };
+            pages.dims.insert(index, dims);
+        }
+
+        Some(doc)
+    }
+
+    // Only reads the header of the image, when possible.
+    fn image_dims(&self, index: usize) -> Option<(f32, f32)> {
+        let name = self.names.get(index)?;
+        let mut pages = self.pages.lock().ok()?;
+
+        if let Some(dims) = pages.dims.get(&index) {
+            return Some(*dims);
+        }
+
+        let dims = pages.container.fetch_head(name, IMAGE_HEAD_SIZE).ok()
+                        .and_then(|buf| image_dims(&buf))?;
+        pages.dims.insert(index, dims);
+
+        Some(dims)
+    }
+
+    pub fn year(&self) -> Option<String> {
+        self.metadata("Year")
+    }
+
+    pub fn publisher(&self) -> Option<String> {
+        self.metadata("Publisher")
+    }
+
+    pub fn series(&self) -> Option<String> {
+        self.metadata("Series")
+    }
+
+    pub fn number(&self) -> Option<String> {
+        self.metadata("Number")
+    }
+
+    pub fn volume(&self) -> Option<String> {
+        self.metadata("Volume")
+    }
+
+    pub fn language(&self) -> Option<String> {
+        self.metadata("LanguageISO")
+    }
+
+    pub fn categories(&self) -> BTreeSet<String> {
+        self.metadata("Genre")
+            .map(|genres| genres.split(',')
+                                .map(|s| s.trim().to_string())
+                                .filter(|s| !s.is_empty())
+                                .collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Document for ComicDocument {
+    fn dims(&self, index: usize) -> Option<(f32, f32)> {
+        self.image_dims(index).or_else(|| {
+            self.page(index)?;
+            self.pages.lock().ok()
+                .and_then(|pages| pages.dims.get(&index).cloned())
+        })
+    }
+
+    fn pages_count(&self) -> usize {
+        self.names.len()
+    }
+
+    fn toc(&mut self) -> Option<Vec<TocEntry>> {
+        None
+    }
+
+    fn chapter<'a>(&mut self, _offset: usize, _toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        None
+    }
+
+    fn chapter_relative<'a>(&mut self, _offset: usize, _dir: CycleDir, _toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        None
+    }
+
+    fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.resolve_location(loc).map(|index| (Vec::new(), index))
+    }
+
+    fn lines(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.resolve_location(loc).map(|index| (Vec::new(), index))
+    }
+
+    fn links(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.resolve_location(loc).map(|index| (Vec::new(), index))
+    }
+
+    fn images(&mut self, loc: Location) -> Option<(Vec<Boundary>, usize)> {
+        self.resolve_location(loc).map(|index| (Vec::new(), index))
+    }
+
+    fn pixmap(&mut self, loc: Location, scale: f32) -> Option<(Pixmap, usize)> {
+        let index = self.resolve_location(loc)?;
+        let mut doc = self.page(index)?;
+        let (width, _) = self.dims(index)?;
+        let (page_width, _) = doc.dims(0)?;
+        doc.pixmap(Location::Exact(0), scale * width / page_width)
+           .map(|(pixmap, _)| (pixmap, index))
+    }
+
+    fn layout(&mut self, _width: u32, _height: u32, _font_size: f32, _dpi: u16) {
+    }
+
+    fn set_font_family(&mut self, _family_name: &str, _search_path: &str) {
+    }
+
+    fn set_margin_width(&mut self, _width: i32) {
+    }
+
+    fn set_text_align(&mut self, _text_align: TextAlign) {
+    }
+
+    fn set_line_height(&mut self, _line_height: f32) {
+    }
+
+    fn set_hyphen_penalty(&mut self, _hyphen_penalty: i32) {
+    }
+
+    fn set_stretch_tolerance(&mut self, _stretch_tolerance: f32) {
+    }
+
+    fn set_ignore_document_css(&mut self, _ignore: bool) {
+    }
+
+    fn title(&self) -> Option<String> {
+        self.metadata("Title")
+    }
+
+    fn author(&self) -> Option<String> {
+        self.metadata("Writer")
+    }
+
+    fn metadata(&self, key: &str) -> Option<String> {
+        self.info.get(key).cloned()
+    }
+
+    fn is_reflowable(&self) -> bool {
+        false
+    }
+
+    fn is_rtl(&self) -> bool {
+        self.metadata("Manga").is_some_and(|value| value == "YesAndRightToLeft")
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number
+}
+
+// Compares strings the way a human would: the digit runs are compared numerically.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().cloned(), b.peek().cloned()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let m = take_number(&mut a);
+                let n = take_number(&mut b);
+                let m = m.trim_start_matches('0');
+                let n = n.trim_start_matches('0');
+                let ord = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            },
+            (Some(x), Some(y)) => {
+                let ord = x.to_lowercase().cmp(y.to_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a.next();
+                b.next();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["page10.jpg", "page2.jpg", "Page1.jpg", "page002a.jpg",
+                             "ch2/01.png", "ch10/01.png", "ch2/10.png", "ch2/9.png"];
+        names.sort_by(|a, b| natural_cmp(a, b).then_with(|| a.cmp(b)));
+        assert_eq!(names, vec!["ch2/01.png", "ch2/9.png", "ch2/10.png", "ch10/01.png",
+                               "Page1.jpg", "page2.jpg", "page002a.jpg", "page10.jpg"]);
+    }
+
+    #[test]
+    fn test_rar_entries() {
+        let listing = "\nArchive: comic.cbr\nDetails: RAR 5\n\n\
+                       \x20       Name: Chapter 1\n\
+                       \x20       Type: Directory\n\
+                       \x20 Attributes: drwxr-xr-x\n\n\
+                       \x20       Name: Chapter 1/01.jpg\n\
+                       \x20       Type: File\n\
+                       \x20       Size: 48213\n\
+                       \x20Packed size: 48100\n\n\
+                       \x20       Name: ComicInfo.xml\n\
+                       \x20       Type: File\n\
+                       \x20       Size: 512\n";
+        assert_eq!(rar_entries(listing), vec![("Chapter 1/01.jpg".to_string(), 48213),
+                                              ("ComicInfo.xml".to_string(), 512)]);
+    }
+
+    #[test]
+    fn test_entry_stream() {
+        let mut stream = EntryStream::new(2, |sender| {
+            for i in 2..6u8 {
+                if sender.send(vec![i]).is_err() {
+                    break;
+                }
+            }
+        });
+        assert_eq!(stream.take(3).unwrap(), vec![3]);
+        assert_eq!(stream.take(4).unwrap(), vec![4]);
+        assert!(stream.has(4) && !stream.has(3));
+        assert_eq!(stream.take(4).unwrap(), vec![4]);
+        assert!(stream.take(3).is_err());
+        assert_eq!(stream.take(5).unwrap(), vec![5]);
+        assert!(stream.take(6).is_err());
+    }
+
+    #[test]
+    fn test_comic_info() {
+        let text = "<?xml version=\"1.0\"?>\
+                    <ComicInfo><Title>The Kite</Title><Series>Tintin</Series>\
+                    <Number>3</Number><Volume>1</Volume><Writer>Hergé &amp; Co</Writer>\
+                    <Summary> </Summary></ComicInfo>";
+        let info = read_comic_info(text);
+        assert_eq!(info.get("Series").map(String::as_str), Some("Tintin"));
+        assert_eq!(info.get("Number").map(String::as_str), Some("3"));
+        assert_eq!(info.get("Volume").map(String::as_str), Some("1"));
+        assert_eq!(info.get("Writer").map(String::as_str), Some("Hergé & Co"));
+        assert!(!info.contains_key("Summary"));
+    }
+}
@@ -1,6 +1,7 @@
 use super::djvulibre_sys::*;
 
 use std::ptr;
+use std::mem;
 use std::rc::Rc;
 use std::path::Path;
 use std::ffi::{CStr, CString};
@@ -11,6 +12,9 @@ use crate::metadata::TextAlign;
 use crate::framebuffer::Pixmap;
 use crate::geom::{Rectangle, Boundary, CycleDir};
 
+// The zones of the hidden text, from the coarsest to the finest.
+const ZONES: [&[u8]; 7] = [b"page", b"column", b"region", b"para", b"line", b"word", b"char"];
+
 impl Into<DjvuRect> for Rectangle {
     fn into(self) -> DjvuRect {
         DjvuRect {
@@ -29,6 +33,8 @@ pub struct DjvuOpener(Rc<DjvuContext>);
 pub struct DjvuDocument {
     ctx: Rc<DjvuContext>,
     doc: *mut ExoDocument,
+    // Whether any page has a text layer, computed on first use.
+    has_text: Option<bool>,
 }
 
 pub struct DjvuPage<'a> {
@@ -90,6 +96,7 @@ impl DjvuOpener {
                 Some(DjvuDocument {
                     ctx: self.0.clone(),
                     doc,
+                    has_text: None,
                 })
             }
         }
@@ -150,6 +157,29 @@ impl Document for DjvuDocument {
         self.text(loc, b"line")
     }
 
+    fn has_text(&mut self) -> bool {
+        if let Some(has_text) = self.has_text {
+            return has_text;
+        }
+
+        let grain = CString::new("page").unwrap();
+        let has_text = (0..self.pages_count()).any(|index| unsafe {
+            let mut exp = ddjvu_document_get_pagetext(self.doc, index as libc::c_int, grain.as_ptr());
+            while exp == MINIEXP_DUMMY {
+                self.ctx.handle_message();
+                exp = ddjvu_document_get_pagetext(self.doc, index as libc::c_int, grain.as_ptr());
+            }
+            if exp == MINIEXP_NIL {
+                false
+            } else {
+                ddjvu_miniexp_release(self.doc, exp);
+                true
+            }
+        });
+        self.has_text = Some(has_text);
+        has_text
+    }
+
     fn links(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
         unsafe {
             let index = self.resolve_location(loc)?;
@@ -284,11 +314,27 @@ impl DjvuDocument {
         }
     }
 
+    pub fn page_info(&self, index: usize) -> Option<PageInfo> {
+        unsafe {
+            let mut info = PageInfo::default();
+            let size = mem::size_of::<PageInfo>() as libc::c_uint;
+            let mut status = ddjvu_document_get_pageinfo_imp(self.doc, index as libc::c_int, &mut info, size);
+            while status < DDJVU_JOB_OK {
+                self.ctx.handle_message();
+                status = ddjvu_document_get_pageinfo_imp(self.doc, index as libc::c_int, &mut info, size);
+            }
+            if status >= DDJVU_JOB_FAILED {
+                None
+            } else {
+                Some(info)
+            }
+        }
+    }
+
     fn text(&mut self, loc: Location, kind: &[u8]) -> Option<(Vec<BoundedText>, usize)> {
         unsafe {
             let index = self.resolve_location(loc)?;
-            let page = self.page(index)?;
-            let height = page.height() as i32;
+            let height = self.page_info(index)?.height;
             let grain = CString::new(kind).unwrap();
             let mut exp = ddjvu_document_get_pagetext(self.doc, index as libc::c_int, grain.as_ptr());
             while exp == MINIEXP_DUMMY {
@@ -322,17 +368,28 @@ impl DjvuDocument {
                 CStr::from_ptr(raw).to_bytes()
             };
             let has_text = miniexp_stringp(miniexp_nth(5, exp)) == 1;
-            if grain == kind && has_text {
+            if has_text {
                 let raw = miniexp_to_str(miniexp_nth(5, exp));
                 let c_str = CStr::from_ptr(raw);
                 let text = c_str.to_string_lossy().into_owned();
-                *offset += 1;
-                data.push(BoundedText {
-                    rect,
-                    text,
-                    location: TextLocation::Static(index, *offset),
-                });
-            } else if !has_text {
+                // Some text layers stop at a coarser zone than the one requested:
+                // the words of a line are then laid out proportionally.
+                let parts = if grain == b"line" && kind == b"word" {
+                    split_words(&text, rect)
+                } else if grain == kind || zone_rank(grain) < zone_rank(kind) {
+                    vec![(text, rect)]
+                } else {
+                    Vec::new()
+                };
+                for (text, rect) in parts {
+                    *offset += 1;
+                    data.push(BoundedText {
+                        rect,
+                        text,
+                        location: TextLocation::Static(index, *offset),
+                    });
+                }
+            } else {
                 for i in 5..len {
                     Self::walk_text(miniexp_nth(i, exp), height, kind, index, offset, data);
                 }
@@ -387,6 +444,32 @@ impl DjvuDocument {
     }
 }
 
+fn zone_rank(grain: &[u8]) -> usize {
+    ZONES.iter().position(|&zone| zone == grain).unwrap_or(ZONES.len())
+}
+
+fn split_words(text: &str, rect: Boundary) -> Vec<(String, Boundary)> {
+    let count = text.chars().count().max(1) as f32;
+    let advance = rect.width() / count;
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (i, c) in text.chars().chain(std::iter::once(' ')).enumerate() {
+        if c.is_whitespace() {
+            if let Some(j) = start.take() {
+                let word: String = text.chars().skip(j).take(i - j).collect();
+                let x_min = rect.min.x + j as f32 * advance;
+                let x_max = rect.min.x + i as f32 * advance;
+                words.push((word, bndr![x_min, rect.min.y, x_max, rect.max.y]));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+
+    words
+}
+
 impl<'a> DjvuPage<'a> {
     pub fn pixmap(&self, scale: f32) -> Option<Pixmap> {
         unsafe {
@@ -471,3 +554,21 @@ impl Drop for DjvuContext {
         unsafe { ddjvu_context_release(self.0); }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words() {
+        let words = split_words("ab  cd e", bndr![0.0, 10.0, 80.0, 20.0]);
+        assert_eq!(words.len(), 3);
+        assert_eq!(words[0].0, "ab");
+        assert_eq!((words[0].1.min.x, words[0].1.max.x), (0.0, 20.0));
+        assert_eq!(words[1].0, "cd");
+        assert_eq!((words[1].1.min.x, words[1].1.max.x), (40.0, 60.0));
+        assert_eq!(words[2].0, "e");
+        assert_eq!((words[2].1.min.x, words[2].1.max.x), (70.0, 80.0));
+        assert!(zone_rank(b"line") < zone_rank(b"word"));
+    }
+}
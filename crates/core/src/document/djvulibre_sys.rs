@@ -66,6 +66,7 @@ extern {
     pub fn ddjvu_format_release(fmt: *mut ExoFormat);
     pub fn ddjvu_format_set_row_order(fmt: *mut ExoFormat, top_to_bottom: libc::c_int);
     pub fn ddjvu_format_set_y_direction(fmt: *mut ExoFormat, top_to_bottom: libc::c_int);
+    pub fn ddjvu_document_get_pageinfo_imp(doc: *mut ExoDocument, page_idx: libc::c_int, info: *mut PageInfo, infosz: libc::c_uint) -> JobStatus;
     pub fn ddjvu_document_get_pagetext(doc: *mut ExoDocument, page_idx: libc::c_int, max_detail: *const libc::c_char) -> *mut MiniExp;
     pub fn ddjvu_document_get_outline(doc: *mut ExoDocument) -> *mut MiniExp;
     pub fn ddjvu_document_get_anno(doc: *mut ExoDocument, compat: libc::c_int) -> *mut MiniExp;
@@ -97,6 +98,16 @@ impl Default for DjvuRect {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageInfo {
+    pub width: libc::c_int,
+    pub height: libc::c_int,
+    pub dpi: libc::c_int,
+    pub rotation: libc::c_int,
+    pub version: libc::c_int,
+}
+
 #[repr(C)]
 pub struct Message {
     pub tag: MessageTag,
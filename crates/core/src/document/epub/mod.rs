@@ -1,22 +1,25 @@
 use std::io::Read;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::collections::{BTreeMap, BTreeSet};
 use fxhash::FxHashMap;
 use zip::ZipArchive;
 use percent_encoding::percent_decode_str;
 use anyhow::{Error, format_err};
 use crate::framebuffer::Pixmap;
 use crate::helpers::{Normalize, decode_entities};
-use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText, chapter_from_uri};
+use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText, Footnote, PageSpread, chapter_from_uri};
+use crate::document::{chapter, chapter_relative, collect_headings, headings_toc, SYNTHETIC_TOC_DEPTH};
 use crate::unit::pt_to_px;
-use crate::geom::{Boundary, CycleDir};
-use super::pdf::PdfOpener;
-use super::html::dom::{XmlTree, NodeRef};
-use super::html::engine::{Page, Engine, ResourceFetcher};
+use crate::geom::{Boundary, CycleDir, Edge};
+use super::html::dom::{XmlTree, NodeRef, NodeData, TextData};
+use super::html::engine::{Page, Engine, ResourceFetcher, writing_mode, open_image};
 use super::html::layout::{StyleData, LoopContext};
 use super::html::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand};
-use super::html::layout::TextAlign;
+use super::html::layout::{TextAlign, Direction, WritingMode, language_direction};
+use super::html::parse::parse_direction;
+use super::html::footnote::{find_link, note_node, note_html};
 use super::html::style::StyleSheet;
 use super::html::css::CssParser;
 use super::html::xml::XmlParser;
@@ -24,6 +27,12 @@ use super::html::xml::XmlParser;
 const VIEWER_STYLESHEET: &str = "css/epub.css";
 const USER_STYLESHEET: &str = "css/epub-user.css";
 
+// Fixed-layout pages are laid out at the CSS reference resolution,
+// so that one viewport pixel maps to one pixel of the page.
+const FIXED_LAYOUT_DPI: u16 = 96;
+const FIXED_LAYOUT_FONT_SIZE: f32 = 12.0;
+const DEFAULT_VIEWPORT: (u32, u32) = (600, 800);
+
 type UriCache = FxHashMap<String, usize>;
 
 impl ResourceFetcher for ZipArchive<File> {
@@ -36,19 +45,29 @@ impl ResourceFetcher for ZipArchive<File> {
 }
 
 pub struct EpubDocument {
-    archive: ZipArchive<File>,
+    // Shared with `dims`, which reads the viewports of fixed-layout pages.
+    archive: Mutex<ZipArchive<File>>,
     info: XmlTree,
     parent: PathBuf,
     engine: Engine,
     spine: Vec<Chunk>,
     cache: FxHashMap<usize, Vec<Page>>,
     ignore_document_css: bool,
+    extra_css: String,
+    fixed_layout: bool,
+    // The viewport declared in the package document of a fixed-layout document.
+    default_viewport: Option<(u32, u32)>,
+    // Table of contents built from the headings, when the document's own is missing.
+    synthetic_toc: Option<Vec<TocEntry>>,
 }
 
 #[derive(Debug)]
 struct Chunk {
     path: String,
     size: usize,
+    // Page dimensions, in CSS pixels, of a fixed-layout document: read on first access.
+    viewport: Mutex<Option<(u32, u32)>>,
+    spread: Option<PageSpread>,
 }
 
 unsafe impl Send for EpubDocument {}
@@ -81,6 +100,7 @@ impl EpubDocument {
 
         let info = XmlParser::new(&text).parse();
         let mut spine = Vec::new();
+        let mut layouts = Vec::new();
 
         {
             let manifest = info.root().find("manifest")
@@ -107,7 +127,21 @@ impl EpubDocument {
                 });
 
                 if let Some((size, path)) = vertebra_opt {
-                    spine.push(Chunk { path, size });
+                    let properties = child.attribute("properties").unwrap_or_default();
+                    let spread = properties.split_whitespace().find_map(|prop| {
+                        match prop.trim_start_matches("rendition:") {
+                            "page-spread-left" => Some(PageSpread::Left),
+                            "page-spread-right" => Some(PageSpread::Right),
+                            "page-spread-center" => Some(PageSpread::Center),
+                            _ => None,
+                        }
+                    });
+                    spine.push(Chunk { path, size, viewport: Mutex::new(None), spread });
+                    layouts.push(properties.split_whitespace().find_map(|prop| match prop {
+                        "rendition:layout-pre-paginated" => Some(true),
+                        "rendition:layout-reflowable" => Some(false),
+                        _ => None,
+                    }));
                 }
             }
         }
@@ -116,15 +150,64 @@ impl EpubDocument {
             return Err(format_err!("the spine is empty"));
         }
 
-        Ok(EpubDocument {
-            archive,
+        let rendition = |property: &str| {
+            info.root().find("metadata").and_then(|md| {
+                md.children().find(|child| child.tag_name() == Some("meta") &&
+                                           child.attribute("property") == Some(property))
+            }).map(|child| child.text().trim().to_string())
+        };
+
+        let pre_paginated = rendition("rendition:layout").as_deref() == Some("pre-paginated");
+        let fixed_layout = layouts.iter().all(|layout| layout.unwrap_or(pre_paginated));
+
+        let default_viewport = if fixed_layout {
+            rendition("rendition:viewport").and_then(|text| parse_viewport(&text))
+        } else {
+            None
+        };
+
+        let mut doc = EpubDocument {
+            archive: Mutex::new(archive),
             info,
             parent: parent.to_path_buf(),
             engine: Engine::new(),
             spine,
             cache: FxHashMap::default(),
             ignore_document_css: false,
-        })
+            extra_css: String::new(),
+            fixed_layout,
+            default_viewport,
+            synthetic_toc: None,
+        };
+
+        // The reflow settings don't apply to fixed-layout pages.
+        if fixed_layout {
+            let (width, height) = doc.viewport(0);
+            doc.engine.layout(width, height, FIXED_LAYOUT_FONT_SIZE, FIXED_LAYOUT_DPI);
+            doc.engine.set_margin(&Edge::default());
+        }
+
+        Ok(doc)
+    }
+
+    // Pages without a viewport use the document's, or the first page's.
+    fn viewport(&self, index: usize) -> (u32, u32) {
+        let chunk = &self.spine[index];
+
+        if let Some(viewport) = *chunk.viewport.lock().unwrap() {
+            return viewport;
+        }
+
+        let mut text = String::new();
+        if let Ok(mut zf) = self.archive.lock().unwrap().by_name(&chunk.path) {
+            zf.read_to_string(&mut text).ok();
+        }
+        let root = XmlParser::new(&text).parse();
+        let viewport = page_viewport(root.root()).or(self.default_viewport)
+                                     .unwrap_or_else(|| if index > 0 { self.viewport(0) } else { DEFAULT_VIEWPORT });
+        *chunk.viewport.lock().unwrap() = Some(viewport);
+
+        viewport
     }
 
     fn offset(&self, index: usize) -> usize {
@@ -276,6 +359,212 @@ impl EpubDocument {
         })
     }
 
+    // Returns the spine index, the page index and the location of the page at `loc`.
+    fn locate_page(&mut self, loc: Location) -> Option<(usize, usize, usize)> {
+        if self.fixed_layout {
+            let index = self.resolve_location(loc)?;
+            // Each page has its own viewport.
+            self.engine.dims = self.viewport(index);
+            if !self.cache.contains_key(&index) {
+                let display_list = self.build_display_list(index, 0);
+                self.cache.insert(index, display_list);
+            }
+            Some((index, 0, index))
+        } else {
+            let offset = self.resolve_location(loc)?;
+            let (index, start_offset) = self.vertebra_coordinates(offset)?;
+            let page_index = self.page_index(offset, index, start_offset)?;
+            Some((index, page_index, offset))
+        }
+    }
+
+    fn text_location(&self, index: usize, offset: usize) -> TextLocation {
+        if self.fixed_layout {
+            TextLocation::Static(index, offset)
+        } else {
+            TextLocation::Dynamic(offset)
+        }
+    }
+
+    fn resolve_fixed_location(&mut self, loc: Location) -> Option<usize> {
+        let count = self.spine.len();
+        match loc {
+            Location::Exact(index) => Some(index).filter(|&i| i < count),
+            Location::Previous(index) => index.checked_sub(1).filter(|&i| i < count),
+            Location::Next(index) => Some(index + 1).filter(|&i| i < count),
+            Location::LocalUri(index, ref uri) => {
+                let path = &self.spine.get(index)?.path;
+                if uri.starts_with('#') {
+                    Some(index)
+                } else {
+                    let parent = Path::new(path).parent()
+                                      .unwrap_or_else(|| Path::new(""));
+                    let name = parent.join(uri).normalize()
+                                     .to_string_lossy().into_owned();
+                    self.spine_index(&name)
+                }
+            },
+            Location::Uri(ref uri) => self.spine_index(uri),
+        }
+    }
+
+    fn spine_index(&self, uri: &str) -> Option<usize> {
+        let name = uri.split('#').next().unwrap_or(uri);
+        self.spine.iter().position(|chunk| chunk.path == name)
+    }
+
+    fn document_toc(&mut self) -> Option<Vec<TocEntry>> {
+        let name = self.ncx_path().or_else(|| self.nav_path())?;
+
+        let toc_dir = Path::new(&name).parent()
+                           .unwrap_or_else(|| Path::new(""));
+
+        let mut text = String::new();
+        if let Ok(mut zf) = self.archive.get_mut().unwrap().by_name(&name) {
+            zf.read_to_string(&mut text).ok()?;
+        } else {
+            return None;
+        }
+
+        let root = XmlParser::new(&text).parse();
+
+        let mut toc = if name.ends_with(".ncx") {
+            root.root().find("navMap").map(|map| {
+                self.walk_toc_ncx(map, toc_dir, &mut 0, &mut FxHashMap::default())
+            })
+        } else {
+            root.root().descendants()
+                .find(|desc| desc.tag_name() == Some("nav") &&
+                             desc.attribute("epub:type") == Some("toc"))
+                .and_then(|map| map.find("ol")).map(|map| {
+                self.walk_toc_nav(map, toc_dir, &mut 0, &mut FxHashMap::default())
+            })
+        };
+
+        if self.fixed_layout {
+            if let Some(entries) = toc.as_mut() {
+                self.fix_toc_locations(entries);
+            }
+        }
+
+        toc
+    }
+
+    // Headings without identifiers can only be reached when they open their document.
+    fn headings_toc(&mut self) -> Vec<TocEntry> {
+        let mut headings = Vec::new();
+
+        for index in 0..self.spine.len() {
+            let mut text = String::new();
+            if let Ok(mut zf) = self.archive.get_mut().unwrap().by_name(&self.spine[index].path) {
+                zf.read_to_string(&mut text).ok();
+            }
+            let root = XmlParser::new(&text).parse();
+            let mut nodes = Vec::new();
+            collect_headings(root.root(), SYNTHETIC_TOC_DEPTH, &mut nodes);
+            let path = &self.spine[index].path;
+            for (i, (level, title, node)) in nodes.into_iter().enumerate() {
+                let uri = match node.attribute("id") {
+                    Some(id) => format!("{}#{}", path, id),
+                    None if i == 0 => path.clone(),
+                    None => continue,
+                };
+                headings.push((level, title, Location::Uri(uri)));
+            }
+        }
+
+        let mut toc = headings_toc(&headings);
+
+        if self.fixed_layout {
+            self.fix_toc_locations(&mut toc);
+        }
+
+        toc
+    }
+
+    fn fix_toc_locations(&self, entries: &mut [TocEntry]) {
+        for entry in entries {
+            if let Location::Uri(ref uri) = entry.location {
+                if let Some(index) = self.spine_index(uri) {
+                    entry.location = Location::Exact(index);
+                }
+            }
+            self.fix_toc_locations(&mut entry.children);
+        }
+    }
+
+    fn ncx_path(&self) -> Option<String> {
+        self.info.root().find("spine").and_then(|spine| {
+            spine.attribute("toc")
+        }).and_then(|toc_id| {
+            self.info.root().find("manifest")
+                .and_then(|manifest| manifest.find_by_id(toc_id))
+                .and_then(|entry| entry.attribute("href"))
+        }).map(|href| {
+            self.parent.join(href).normalize()
+                .to_string_lossy().into_owned()
+        })
+    }
+
+    fn nav_path(&self) -> Option<String> {
+        self.info.root().find("manifest")
+            .and_then(|manifest| manifest.children().find(|child| {
+                child.attribute("properties").iter()
+                     .any(|props| props.split_whitespace().any(|prop| prop == "nav"))
+            }))
+            .and_then(|entry| entry.attribute("href"))
+            .map(|href| {
+                self.parent.join(href).normalize()
+                    .to_string_lossy().into_owned()
+            })
+    }
+
+    // Returns the URIs and the names of the print pages listed in the navigation document or the NCX.
+    fn page_targets(&mut self) -> Vec<(String, String)> {
+        let mut targets = Vec::new();
+
+        for name in [self.nav_path(), self.ncx_path()].into_iter().flatten() {
+            let mut text = String::new();
+            if let Ok(mut zf) = self.archive.get_mut().unwrap().by_name(&name) {
+                zf.read_to_string(&mut text).ok();
+            }
+            let root = XmlParser::new(&text).parse();
+            let dir = Path::new(&name).parent()
+                          .unwrap_or_else(|| Path::new(""));
+            let resolve = |href: &str| {
+                let href = percent_decode_str(&decode_entities(href)).decode_utf8_lossy().into_owned();
+                dir.join(href).normalize().to_string_lossy().into_owned()
+            };
+
+            if name.ends_with(".ncx") {
+                if let Some(page_list) = root.root().find("pageList") {
+                    for target in page_list.children().filter(|child| child.tag_name() == Some("pageTarget")) {
+                        let label = target.find("navLabel").and_then(|label| label.find("text"))
+                                          .map(|text| decode_entities(&text.text()).trim().to_string());
+                        let src = target.find("content").and_then(|content| content.attribute("src"));
+                        if let Some((label, src)) = label.zip(src) {
+                            targets.push((resolve(src), label));
+                        }
+                    }
+                }
+            } else if let Some(page_list) = root.root().descendants()
+                                                .find(|desc| desc.tag_name() == Some("nav") &&
+                                                             desc.attribute("epub:type").is_some_and(|t| t.split_whitespace().any(|t| t == "page-list"))) {
+                for link in page_list.descendants().filter(|desc| desc.tag_name() == Some("a")) {
+                    if let Some(href) = link.attribute("href") {
+                        targets.push((resolve(href), decode_entities(&link.text()).trim().to_string()));
+                    }
+                }
+            }
+
+            if !targets.is_empty() {
+                break;
+            }
+        }
+
+        targets
+    }
+
     fn resolve_link(&mut self, uri: &str, cache: &mut UriCache) -> Option<usize> {
         let frag_index_opt = uri.find('#');
         let name = &uri[..frag_index_opt.unwrap_or_else(|| uri.len())];
@@ -285,7 +574,7 @@ impl EpubDocument {
         if frag_index_opt.is_some() {
             let mut text = String::new();
             {
-                let mut zf = self.archive.by_name(name).ok()?;
+                let mut zf = self.archive.get_mut().unwrap().by_name(name).ok()?;
                 zf.read_to_string(&mut text).ok()?;
             }
             let root = XmlParser::new(&text).parse();
@@ -321,7 +610,7 @@ impl EpubDocument {
                 spine_dir = parent.to_path_buf();
             }
 
-            if let Ok(mut zf) = self.archive.by_name(path) {
+            if let Ok(mut zf) = self.archive.get_mut().unwrap().by_name(path) {
                 zf.read_to_string(&mut text).ok();
             }
         }
@@ -341,6 +630,11 @@ impl EpubDocument {
             stylesheet.append(&mut css, true);
         }
 
+        if !self.extra_css.is_empty() {
+            let mut css = CssParser::new(&self.extra_css).parse();
+            stylesheet.append(&mut css, true);
+        }
+
         if !self.ignore_document_css {
             let mut inner_css = StyleSheet::new();
             if let Some(head) = root.root().find("head") {
@@ -349,15 +643,17 @@ impl EpubDocument {
                         if let Some(href) = child.attribute("href") {
                             if let Some(name) = spine_dir.join(href).normalize().to_str() {
                                 let mut text = String::new();
-                                if let Ok(mut zf) = self.archive.by_name(name) {
+                                if let Ok(mut zf) = self.archive.get_mut().unwrap().by_name(name) {
                                     zf.read_to_string(&mut text).ok();
                                     let mut css = CssParser::new(&text).parse();
+                                    css.resolve_font_sources(Path::new(name).parent().unwrap_or(&spine_dir));
                                     inner_css.append(&mut css, false);
                                 }
                             }
                         }
                     } else if child.tag_name() == Some("style") && child.attribute("type") == Some("text/css") {
                         let mut css = CssParser::new(&child.text()).parse();
+                        css.resolve_font_sources(&spine_dir);
                         inner_css.append(&mut css, false);
                     }
                 }
@@ -366,11 +662,16 @@ impl EpubDocument {
             stylesheet.append(&mut inner_css, true);
         }
 
+        self.engine.load_font_faces(&stylesheet.font_faces, self.archive.get_mut().unwrap());
+
         let mut display_list = Vec::new();
 
         if let Some(body) = root.root().find("body") {
-            let mut rect = self.engine.rect();
-            rect.shrink(&self.engine.margin);
+            let writing_mode = writing_mode(body, &stylesheet).or_else(|| {
+                root.root().find("html")
+                    .and_then(|html| writing_mode(html, &stylesheet))
+            }).unwrap_or(WritingMode::HorizontalTb);
+            let rect = self.engine.content_rect(writing_mode);
 
             let language = self.language().or_else(|| {
                 root.root().find("html")
@@ -378,11 +679,23 @@ impl EpubDocument {
                     .map(String::from)
             });
 
+            let direction = root.root().find("html")
+                                .and_then(|html| html.attribute("dir"))
+                                .and_then(parse_direction)
+                                .unwrap_or_else(|| self.direction());
+            let text_align = if direction == Direction::Rtl {
+                self.engine.text_align.mirror()
+            } else {
+                self.engine.text_align
+            };
+
             let style = StyleData {
                 language,
+                direction,
+                writing_mode,
                 font_size: self.engine.font_size,
                 line_height: pt_to_px(self.engine.line_height * self.engine.font_size, self.engine.dpi).round() as i32,
-                text_align: self.engine.text_align,
+                text_align,
                 start_x: rect.min.x,
                 end_x: rect.max.x,
                 width: rect.max.x - rect.min.x,
@@ -403,7 +716,8 @@ impl EpubDocument {
 
             display_list.push(Vec::new());
 
-            self.engine.build_display_list(body, &style, &loop_context, &stylesheet, &root_data, &mut self.archive, &mut draw_state, &mut display_list);
+            self.engine.build_display_list(body, &style, &loop_context, &stylesheet, &root_data, self.archive.get_mut().unwrap(), &mut draw_state, &mut display_list);
+            self.engine.make_vertical(&mut display_list, writing_mode);
 
             display_list.retain(|page| !page.is_empty());
 
@@ -415,6 +729,49 @@ impl EpubDocument {
         display_list
     }
 
+    // Resolves the XPointers used by KOReader to designate a position,
+    // e.g. `/body/DocFragment[3]/body/div/p[5]/text().42`.
+    pub fn xpointer_location(&mut self, xpointer: &str) -> Option<TextLocation> {
+        let rest = xpointer.strip_prefix("/body/DocFragment")?;
+        let (index, steps) = match rest.strip_prefix('[') {
+            Some(rest) => {
+                let end = rest.find(']')?;
+                (rest[..end].parse::<usize>().ok()?.checked_sub(1)?, &rest[end+1..])
+            },
+            None => (0, rest),
+        };
+
+        let mut text = String::new();
+        self.archive.get_mut().unwrap().by_name(&self.spine.get(index)?.path).ok()?
+            .read_to_string(&mut text).ok()?;
+        let root = XmlParser::new(&text).parse();
+        let offset = xpointer_offset(root.root(), steps.trim_start_matches('/'))?;
+
+        Some(self.text_location(index, self.offset(index) + offset))
+    }
+
+    // The inverse of `xpointer_location`.
+    pub fn location_xpointer(&mut self, location: usize) -> Option<String> {
+        // The locations of fixed-layout documents are spine indices.
+        if self.fixed_layout {
+            return Some(format!("/body/DocFragment[{}]", location + 1))
+                       .filter(|_| location < self.spine.len());
+        }
+
+        let (index, start_offset) = self.vertebra_coordinates(location)?;
+        let mut text = String::new();
+        self.archive.get_mut().unwrap().by_name(&self.spine[index].path).ok()?
+            .read_to_string(&mut text).ok()?;
+        let root = XmlParser::new(&text).parse();
+        let steps = xpointer_steps(root.root(), location - start_offset);
+
+        if steps.is_empty() {
+            Some(format!("/body/DocFragment[{}]", index + 1))
+        } else {
+            Some(format!("/body/DocFragment[{}]/{}", index + 1, steps))
+        }
+    }
+
     pub fn categories(&self) -> BTreeSet<String> {
         let mut result = BTreeSet::new();
 
@@ -578,21 +935,35 @@ impl EpubDocument {
     pub fn year(&self) -> Option<String> {
         self.metadata("dc:date").map(|s| s.chars().take(4).collect())
     }
+
+    pub fn direction(&self) -> Direction {
+        self.language().map(|lang| language_direction(&lang))
+            .unwrap_or(Direction::Ltr)
+    }
+
+    pub fn page_progression_direction(&self) -> Direction {
+        self.info.root().find("spine")
+            .and_then(|spine| spine.attribute("page-progression-direction"))
+            .and_then(parse_direction)
+            .unwrap_or_else(|| self.direction())
+    }
+}
+
+fn count_entries(toc: &[TocEntry]) -> usize {
+    toc.iter().map(|entry| 1 + count_entries(&entry.children)).sum()
 }
 
 impl Document for EpubDocument {
     fn preview_pixmap(&mut self, width: f32, height: f32) -> Option<Pixmap> {
-        let opener = PdfOpener::new()?;
         self.cover_image()
             .map(|path| self.parent.join(path)
                             .to_string_lossy().into_owned())
             .and_then(|path| {
-                self.archive.fetch(&path).ok()
-                    .and_then(|buf| opener.open_memory(&path, &buf))
+                open_image(&path, None, self.archive.get_mut().unwrap())
                     .and_then(|mut doc| {
-                        doc.dims(0).and_then(|dims| {
+                        doc.dims().and_then(|dims| {
                             let scale = (width / dims.0).min(height / dims.1);
-                            doc.pixmap(Location::Exact(0), scale)
+                            doc.pixmap(scale)
                         })
                     })
             })
@@ -600,66 +971,55 @@ impl Document for EpubDocument {
                 self.dims(0).and_then(|dims| {
                     let scale = (width / dims.0).min(height / dims.1);
                     self.pixmap(Location::Exact(0), scale)
+                        .map(|(pixmap, _)| pixmap)
                 })
             })
-            .map(|(pixmap, _)| pixmap)
     }
 
     #[inline]
-    fn dims(&self, _index: usize) -> Option<(f32, f32)> {
-        Some((self.engine.dims.0 as f32, self.engine.dims.1 as f32))
+    fn dims(&self, index: usize) -> Option<(f32, f32)> {
+        if self.fixed_layout {
+            (index < self.spine.len()).then(|| {
+                let (width, height) = self.viewport(index);
+                (width as f32, height as f32)
+            })
+        } else {
+            Some((self.engine.dims.0 as f32, self.engine.dims.1 as f32))
+        }
     }
 
     fn pages_count(&self) -> usize {
-        self.spine.iter().map(|c| c.size).sum()
+        if self.fixed_layout {
+            self.spine.len()
+        } else {
+            self.spine.iter().map(|c| c.size).sum()
+        }
     }
 
     fn toc(&mut self) -> Option<Vec<TocEntry>> {
-        let name = self.info.root().find("spine").and_then(|spine| {
-            spine.attribute("toc")
-        }).and_then(|toc_id| {
-            self.info.root().find("manifest")
-                .and_then(|manifest| manifest.find_by_id(toc_id))
-                .and_then(|entry| entry.attribute("href"))
-        }).or_else(|| {
-            self.info.root().find("manifest")
-                .and_then(|manifest| manifest.children().find(|child| {
-                    child.attribute("properties").iter()
-                         .any(|props| props.split_whitespace().any(|prop| prop == "nav"))
-                }))
-                .and_then(|entry| entry.attribute("href"))
-        }).map(|href| {
-            self.parent.join(href).normalize()
-                .to_string_lossy().into_owned()
-        })?;
+        let toc = self.document_toc();
 
-        let toc_dir = Path::new(&name).parent()
-                           .unwrap_or_else(|| Path::new(""));
-
-        let mut text = String::new();
-        if let Ok(mut zf) = self.archive.by_name(&name) {
-            zf.read_to_string(&mut text).ok()?;
-        } else {
-            return None;
+        if toc.as_ref().is_some_and(|entries| count_entries(entries) > 1) {
+            return toc;
         }
 
-        let root = XmlParser::new(&text).parse();
+        if self.synthetic_toc.is_none() {
+            self.synthetic_toc = Some(self.headings_toc());
+        }
 
-        if name.ends_with(".ncx") {
-            root.root().find("navMap").map(|map| {
-                self.walk_toc_ncx(map, toc_dir, &mut 0, &mut FxHashMap::default())
-            })
-        } else {
-            root.root().descendants()
-                .find(|desc| desc.tag_name() == Some("nav") &&
-                             desc.attribute("epub:type") == Some("toc"))
-                .and_then(|map| map.find("ol")).map(|map| {
-                self.walk_toc_nav(map, toc_dir, &mut 0, &mut FxHashMap::default())
-            })
+        match self.synthetic_toc {
+            Some(ref entries) if count_entries(entries) > toc.as_ref().map_or(0, |toc| count_entries(toc)) => {
+                Some(entries.clone())
+            },
+            _ => toc,
         }
     }
 
     fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        if self.fixed_layout {
+            return chapter(offset, self.pages_count(), toc);
+        }
+
         let next_offset = self.resolve_location(Location::Next(offset))
                               .unwrap_or(usize::MAX);
         let (index, start_offset) = self.vertebra_coordinates(offset)?;
@@ -699,6 +1059,10 @@ impl Document for EpubDocument {
     }
 
     fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        if self.fixed_layout {
+            return chapter_relative(offset, dir, toc);
+        }
+
         let next_offset = self.resolve_location(Location::Next(offset))
                               .unwrap_or(usize::MAX);
         let chap = self.chapter(offset, toc).map(|(c, _)| c);
@@ -712,6 +1076,10 @@ impl Document for EpubDocument {
     fn resolve_location(&mut self, loc: Location) -> Option<usize> {
         self.engine.load_fonts();
 
+        if self.fixed_layout {
+            return self.resolve_fixed_location(loc);
+        }
+
         match loc {
             Location::Exact(offset) => {
                 let (index, start_offset) = self.vertebra_coordinates(offset)?;
@@ -785,9 +1153,7 @@ impl Document for EpubDocument {
             return None;
         }
 
-        let offset = self.resolve_location(loc)?;
-        let (index, start_offset) = self.vertebra_coordinates(offset)?;
-        let page_index = self.page_index(offset, index, start_offset)?;
+        let (index, page_index, location) = self.locate_page(loc)?;
 
         self.cache.get(&index).map(|display_list| {
             (display_list[page_index].iter().filter_map(|dc| {
@@ -796,12 +1162,12 @@ impl Document for EpubDocument {
                         Some(BoundedText {
                             text: text.clone(),
                             rect: (*rect).into(),
-                            location: TextLocation::Dynamic(*offset),
+                            location: self.text_location(index, *offset),
                         })
                     },
                     _ => None,
                 }
-            }).collect(), offset)
+            }).collect(), location)
         })
     }
 
@@ -814,9 +1180,7 @@ impl Document for EpubDocument {
             return None;
         }
 
-        let offset = self.resolve_location(loc)?;
-        let (index, start_offset) = self.vertebra_coordinates(offset)?;
-        let page_index = self.page_index(offset, index, start_offset)?;
+        let (index, page_index, location) = self.locate_page(loc)?;
 
         self.cache.get(&index).map(|display_list| {
             (display_list[page_index].iter().filter_map(|dc| {
@@ -826,12 +1190,12 @@ impl Document for EpubDocument {
                         Some(BoundedText {
                             text: uri.clone().unwrap(),
                             rect: (*rect).into(),
-                            location: TextLocation::Dynamic(*offset),
+                            location: self.text_location(index, *offset),
                         })
                     },
                     _ => None,
                 }
-            }).collect(), offset)
+            }).collect(), location)
         })
     }
 
@@ -840,9 +1204,7 @@ impl Document for EpubDocument {
             return None;
         }
 
-        let offset = self.resolve_location(loc)?;
-        let (index, start_offset) = self.vertebra_coordinates(offset)?;
-        let page_index = self.page_index(offset, index, start_offset)?;
+        let (index, page_index, location) = self.locate_page(loc)?;
 
         self.cache.get(&index).map(|display_list| {
             (display_list[page_index].iter().filter_map(|dc| {
@@ -850,7 +1212,7 @@ impl Document for EpubDocument {
                     DrawCommand::Image(ImageCommand { rect, .. }) => Some((*rect).into()),
                     _ => None,
                 }
-            }).collect(), offset)
+            }).collect(), location)
         })
     }
 
@@ -859,57 +1221,173 @@ impl Document for EpubDocument {
             return None;
         }
 
-        let offset = self.resolve_location(loc)?;
-        let (index, start_offset) = self.vertebra_coordinates(offset)?;
-
-        let page_index = self.page_index(offset, index, start_offset)?;
+        let (index, page_index, location) = self.locate_page(loc)?;
         let page = self.cache.get(&index)?.get(page_index)?.clone();
 
-        let pixmap = self.engine.render_page(&page, scale, &mut self.archive)?;
+        let pixmap = self.engine.render_page(&page, scale, self.archive.get_mut().unwrap())?;
 
-        Some((pixmap, offset))
+        Some((pixmap, location))
     }
 
     fn layout(&mut self, width: u32, height: u32, font_size: f32, dpi: u16) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.layout(width, height, font_size, dpi);
         self.cache.clear();
     }
 
     fn set_text_align(&mut self, text_align: TextAlign) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.set_text_align(text_align);
         self.cache.clear();
     }
 
     fn set_font_family(&mut self, family_name: &str, search_path: &str) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.set_font_family(family_name, search_path);
         self.cache.clear();
     }
 
     fn set_margin_width(&mut self, width: i32) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.set_margin_width(width);
         self.cache.clear();
     }
 
     fn set_line_height(&mut self, line_height: f32) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.set_line_height(line_height);
         self.cache.clear();
     }
 
     fn set_hyphen_penalty(&mut self, hyphen_penalty: i32) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.set_hyphen_penalty(hyphen_penalty);
         self.cache.clear();
     }
 
     fn set_stretch_tolerance(&mut self, stretch_tolerance: f32) {
+        if self.fixed_layout {
+            return;
+        }
         self.engine.set_stretch_tolerance(stretch_tolerance);
         self.cache.clear();
     }
 
+    fn footnote(&mut self, offset: usize, uri: &str) -> Option<Footnote> {
+        if self.fixed_layout {
+            return None;
+        }
+
+        let (index, start_offset) = self.vertebra_coordinates(offset)?;
+        let path = self.spine[index].path.clone();
+        let frag_index = uri.find('#')?;
+        let name = if frag_index == 0 {
+            path.clone()
+        } else {
+            let parent = Path::new(&path).parent()
+                              .unwrap_or_else(|| Path::new(""));
+            parent.join(&uri[..frag_index]).normalize()
+                  .to_string_lossy().into_owned()
+        };
+        let (_, target_start_offset) = self.vertebra_coordinates_from_name(&name)?;
+
+        let mut text = String::new();
+        self.archive.get_mut().unwrap().by_name(&path).ok()?
+            .read_to_string(&mut text).ok()?;
+        let root = XmlParser::new(&text).parse();
+        let link = find_link(root.root(), offset - start_offset, uri)?;
+
+        let other_root;
+        let target_root = if name == path {
+            &root
+        } else {
+            let mut text = String::new();
+            self.archive.get_mut().unwrap().by_name(&name).ok()?
+                .read_to_string(&mut text).ok()?;
+            other_root = XmlParser::new(&text).parse();
+            &other_root
+        };
+
+        let target = target_root.root().find_by_id(&uri[frag_index+1..])?;
+        let note = note_node(link, target)?;
+
+        Some(Footnote {
+            text: note_html(note),
+            location: target_start_offset + target.offset(),
+        })
+    }
+
+    fn page_list(&mut self) -> Option<BTreeMap<usize, String>> {
+        let mut page_list = BTreeMap::new();
+        let targets = self.page_targets();
+
+        if targets.is_empty() {
+            for index in 0..self.spine.len() {
+                let mut text = String::new();
+                if let Ok(mut zf) = self.archive.get_mut().unwrap().by_name(&self.spine[index].path) {
+                    zf.read_to_string(&mut text).ok();
+                }
+                let root = XmlParser::new(&text).parse();
+                let start_offset = self.offset(index);
+                for (offset, name) in page_breaks(root.root()) {
+                    let location = if self.fixed_layout { index } else { start_offset + offset };
+                    page_list.entry(location).or_insert(name);
+                }
+            }
+        } else {
+            let mut cache = FxHashMap::default();
+            for (uri, name) in targets {
+                let location = if self.fixed_layout {
+                    self.spine_index(&uri)
+                } else {
+                    cache.get(&uri).copied()
+                         .or_else(|| self.resolve_link(&uri, &mut cache))
+                };
+                if let Some(location) = location {
+                    page_list.entry(location).or_insert(name);
+                }
+            }
+        }
+
+        if page_list.is_empty() {
+            None
+        } else {
+            Some(page_list)
+        }
+    }
+
     fn set_ignore_document_css(&mut self, ignore: bool) {
         self.ignore_document_css = ignore;
         self.cache.clear();
     }
 
+    fn set_ignore_embedded_fonts(&mut self, ignore: bool) {
+        self.engine.set_ignore_embedded_fonts(ignore);
+        self.cache.clear();
+    }
+
+    fn set_hide_ruby(&mut self, hide: bool) {
+        self.engine.set_hide_ruby(hide);
+        self.cache.clear();
+    }
+
+    fn set_extra_css(&mut self, css: &str) {
+        self.extra_css = css.to_string();
+        self.cache.clear();
+    }
+
     fn title(&self) -> Option<String> {
         self.metadata("dc:title")
     }
@@ -926,10 +1404,220 @@ impl Document for EpubDocument {
     }
 
     fn is_reflowable(&self) -> bool {
-        true
+        !self.fixed_layout
     }
 
     fn has_synthetic_page_numbers(&self) -> bool {
-        true
+        !self.fixed_layout
+    }
+
+    fn is_rtl(&self) -> bool {
+        self.page_progression_direction() == Direction::Rtl
+    }
+
+    fn page_spread(&self, index: usize) -> Option<PageSpread> {
+        if self.fixed_layout {
+            self.spine.get(index).and_then(|chunk| chunk.spread)
+        } else {
+            None
+        }
+    }
+}
+
+// Returns the byte offset designated by the steps of an XPointer, relative
+// to the *html* element. The character offset is counted in decoded text.
+fn xpointer_offset(root: NodeRef, steps: &str) -> Option<usize> {
+    let mut node = root.find("html").unwrap_or(root);
+    let mut char_offset = 0;
+    let steps: Vec<&str> = steps.split('/').filter(|s| !s.is_empty()).collect();
+
+    for (i, step) in steps.iter().enumerate() {
+        let mut step = *step;
+        if i == steps.len() - 1 {
+            if let Some(dot) = step.rfind('.') {
+                char_offset = step[dot+1..].parse().ok()?;
+                step = &step[..dot];
+            }
+        }
+        let (name, position) = match step.find('[') {
+            Some(start) => (&step[..start], step[start+1..].trim_end_matches(']').parse::<usize>().ok()?),
+            None => (step, 1),
+        };
+        node = if name == "text()" {
+            node.children().filter(|child| matches!(child.data(), NodeData::Text(..)))
+                .nth(position.checked_sub(1)?)?
+        } else {
+            node.children().filter(|child| child.tag_name() == Some(name))
+                .nth(position.checked_sub(1)?)?
+        };
+    }
+
+    if let NodeData::Text(TextData { offset, ref text }) = *node.data() {
+        let text = decode_entities(text);
+        let index = text.char_indices().nth(char_offset)
+                        .map_or(text.len(), |(index, _)| index);
+        Some(offset + index)
+    } else {
+        Some(node.offset())
+    }
+}
+
+// Returns the steps of the XPointer designating the given byte offset, relative
+// to the *html* element. Indices are only given to steps with namesakes.
+fn xpointer_steps(root: NodeRef, offset: usize) -> String {
+    fn step_name<'a>(node: &NodeRef<'a>) -> Option<&'a str> {
+        match node.data() {
+            NodeData::Text(..) => Some("text()"),
+            NodeData::Element(..) => node.tag_name(),
+            _ => None,
+        }
+    }
+
+    let mut node = root.find("html").unwrap_or(root);
+    let mut steps = Vec::new();
+
+    while let Some((child, name)) = node.children()
+                                        .filter_map(|child| step_name(&child).map(|name| (child, name)))
+                                        .take_while(|(child, _)| child.offset() <= offset)
+                                        .last() {
+        let namesakes = node.children().filter(|sibling| step_name(sibling) == Some(name));
+        let (mut position, mut count) = (0, 0);
+        for sibling in namesakes {
+            count += 1;
+            if sibling.offset() <= child.offset() {
+                position = count;
+            }
+        }
+        if count > 1 {
+            steps.push(format!("{}[{}]", name, position));
+        } else {
+            steps.push(name.to_string());
+        }
+
+        if let NodeData::Text(TextData { offset: start, ref text }) = *child.data() {
+            let mut end = (offset - start).min(text.len());
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            let char_offset = decode_entities(&text[..end]).chars().count();
+            return format!("{}.{}", steps.join("/"), char_offset);
+        }
+
+        node = child;
+    }
+
+    steps.join("/")
+}
+
+// Returns the dimensions declared by the viewport meta tag of a fixed-layout page,
+// or the view box of an SVG wrapping the page's content.
+fn page_viewport(root: NodeRef) -> Option<(u32, u32)> {
+    root.find("head").and_then(|head| {
+        head.children().find(|child| child.tag_name() == Some("meta") &&
+                                     child.attribute("name") == Some("viewport"))
+    }).and_then(|meta| meta.attribute("content"))
+      .and_then(|content| parse_viewport(&decode_entities(content)))
+      .or_else(|| {
+          root.find("body")
+              .and_then(|body| body.descendants().find(|desc| desc.tag_name() == Some("svg")))
+              .and_then(|svg| svg.attribute("viewBox"))
+              .and_then(|view_box| {
+                  let values = view_box.split(|c: char| c == ',' || c.is_whitespace())
+                                       .filter(|v| !v.is_empty())
+                                       .filter_map(|v| v.parse::<f32>().ok())
+                                       .collect::<Vec<f32>>();
+                  if values.len() == 4 && values[2] > 0.0 && values[3] > 0.0 {
+                      Some((values[2].round() as u32, values[3].round() as u32))
+                  } else {
+                      None
+                  }
+              })
+      })
+}
+
+// Returns the offsets and the names of the page break markers.
+fn page_breaks(root: NodeRef) -> Vec<(usize, String)> {
+    root.descendants().filter(|node| {
+        node.attribute("epub:type").is_some_and(|t| t.split_whitespace().any(|t| t == "pagebreak")) ||
+        node.attribute("role") == Some("doc-pagebreak")
+    }).filter_map(|node| {
+        let name = node.attribute("title").or_else(|| node.attribute("aria-label"))
+                       .map(|name| decode_entities(name).trim().to_string())
+                       .unwrap_or_else(|| decode_entities(&node.text()).trim().to_string());
+        if name.is_empty() {
+            None
+        } else {
+            Some((node.offset(), name))
+        }
+    }).collect()
+}
+
+// Parses viewport declarations such as `width=1072, height=1448`.
+fn parse_viewport(text: &str) -> Option<(u32, u32)> {
+    let mut width = None;
+    let mut height = None;
+
+    for item in text.split([',', ';']) {
+        let mut parts = item.splitn(2, '=');
+        let name = parts.next().map(str::trim);
+        let value = parts.next().and_then(|v| v.trim().trim_end_matches("px").parse::<f32>().ok())
+                         .filter(|v| *v >= 1.0)
+                         .map(|v| v.round() as u32);
+        match name {
+            Some("width") => width = value,
+            Some("height") => height = value,
+            _ => (),
+        }
+    }
+
+    width.zip(height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_viewport, page_breaks, xpointer_offset, xpointer_steps};
+    use super::XmlParser;
+
+    #[test]
+    fn viewport() {
+        assert_eq!(parse_viewport("width=1072, height=1448"), Some((1072, 1448)));
+        assert_eq!(parse_viewport("height = 800px; width = 600.4"), Some((600, 800)));
+        assert_eq!(parse_viewport("width=device-width, height=1448"), None);
+        assert_eq!(parse_viewport("initial-scale=1.0"), None);
+    }
+
+    #[test]
+    fn page_break_names() {
+        let xml = XmlParser::new("<body><span epub:type='pagebreak' title='iv'/><p>a</p>\
+                                  <div role='doc-pagebreak' aria-label=' 12 '/>\
+                                  <span epub:type='pagebreak'>13</span><span epub:type='pagebreak'/></body>").parse();
+        let names = page_breaks(xml.root()).into_iter().map(|(_, name)| name).collect::<Vec<String>>();
+        assert_eq!(names, vec!["iv", "12", "13"]);
+    }
+
+    #[test]
+    fn xpointer_offsets() {
+        let text = "<html><head><title>T</title></head><body><div><p>One</p>\n<p>Caf&eacute; <em>au</em> lait</p></div></body></html>";
+        let xml = XmlParser::new(text).parse();
+        let root = xml.root();
+        let lait = text.find(" lait").unwrap();
+        assert_eq!(xpointer_offset(root, "body/div/p[2]/text()[2].1"), Some(lait + 1));
+        assert_eq!(xpointer_offset(root, "body/div/p[2]/text().4"), Some(text.find("Caf").unwrap() + 5));
+        assert_eq!(xpointer_offset(root, "body/div/p[2]/em/text().0"), Some(text.find("au<").unwrap()));
+        assert_eq!(xpointer_offset(root, "body/div/p.0"), Some(text.find("<p>One").unwrap()));
+        assert_eq!(xpointer_offset(root, "body/div/p[3]/text().0"), None);
+    }
+
+    #[test]
+    fn xpointer_round_trips() {
+        let text = "<html><head><title>T</title></head><body><div><p>One</p>\n<p>Caf&eacute; <em>au</em> lait</p></div></body></html>";
+        let xml = XmlParser::new(text).parse();
+        let root = xml.root();
+        let lait = text.find(" lait").unwrap();
+        assert_eq!(xpointer_steps(root, lait + 1), "body/div/p[2]/text()[2].1");
+        assert_eq!(xpointer_steps(root, text.find("au<").unwrap()), "body/div/p[2]/em/text().0");
+        for offset in [text.find("One").unwrap() + 2, text.find("Caf").unwrap() + 2, lait + 3] {
+            assert_eq!(xpointer_offset(root, &xpointer_steps(root, offset)), Some(offset));
+        }
     }
 }
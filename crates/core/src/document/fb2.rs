@@ -0,0 +1,694 @@
+use std::fs;
+use std::path::Path;
+use std::collections::BTreeSet;
+use fxhash::FxHashMap;
+use anyhow::{Error, format_err};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use encoding_rs::{Encoding, UTF_8};
+use super::{Document, Location, TocEntry, BoundedText, Footnote};
+use super::{chapter, chapter_relative};
+use super::html::HtmlDocument;
+use super::html::dom::{XmlTree, NodeRef, NodeId, NodeData, Attributes, element};
+use super::html::engine::ResourceFetcher;
+use super::html::xml::XmlParser;
+use crate::helpers::decode_entities;
+use crate::framebuffer::Pixmap;
+use crate::metadata::TextAlign;
+use crate::geom::{Boundary, CycleDir};
+
+const VIEWER_STYLESHEET: &str = "css/fb2.css";
+const USER_STYLESHEET: &str = "css/fb2-user.css";
+
+// Maps the resource names of the images to their base64 encoded data.
+struct Binaries(FxHashMap<String, String>);
+
+impl ResourceFetcher for Binaries {
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let data = self.0.get(name)
+                       .ok_or_else(|| format_err!("can't find {}", name))?;
+        let data: Vec<u8> = data.bytes()
+                                .filter(|b| !b.is_ascii_whitespace())
+                                .collect();
+        BASE64.decode(data).map_err(Into::into)
+    }
+}
+
+pub struct Fb2Document {
+    html: HtmlDocument,
+    toc: Vec<TocEntry>,
+    info: FxHashMap<String, String>,
+    categories: BTreeSet<String>,
+}
+
+// The engine guesses the format of an image from the extension of its name.
+fn resource_name(id: &str, content_type: Option<&str>) -> String {
+    let has_extension = Path::new(id).extension()
+                                     .and_then(|ext| ext.to_str())
+                                     .is_some_and(|ext| matches!(ext.to_lowercase().as_str(),
+                                                                 "jpg" | "jpeg" | "png" | "gif"));
+    if has_extension {
+        return id.to_string();
+    }
+    let extension = match content_type {
+        Some("image/png") => "png",
+        Some("image/gif") => "gif",
+        _ => "jpg",
+    };
+    format!("{}.{}", id, extension)
+}
+
+fn href<'a>(node: NodeRef<'a>) -> Option<&'a str> {
+    node.attributes().and_then(|attrs| {
+        attrs.iter().find(|(key, _)| key.as_str() == "href" || key.ends_with(":href"))
+             .map(|(_, value)| value.as_str())
+    })
+}
+
+fn decode(buf: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(buf) {
+        return encoding.decode_without_bom_handling(&buf[bom_length..]).0.into_owned();
+    }
+
+    let prolog = String::from_utf8_lossy(&buf[..buf.len().min(256)]);
+    let encoding = prolog.find("?>")
+                         .map(|end| &prolog[..end])
+                         .and_then(|decl| decl.find("encoding=").map(|start| &decl[start+9..]))
+                         .and_then(|value| {
+                             let quote = value.chars().next()?;
+                             value[1..].split(quote).next()
+                         })
+                         .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+                         .unwrap_or(UTF_8);
+
+    encoding.decode_without_bom_handling(buf).0.into_owned()
+}
+
+fn clean_text(text: &str) -> String {
+    decode_entities(text).split_whitespace()
+                         .collect::<Vec<&str>>()
+                         .join(" ")
+}
+
+fn section_title(node: NodeRef) -> Option<String> {
+    let title = node.children().find(|child| child.tag_name() == Some("title"))?;
+    let parts: Vec<String> = title.children()
+                                  .filter(|child| child.is_element())
+                                  .map(|child| clean_text(&child.text()))
+                                  .filter(|text| !text.is_empty())
+                                  .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
+
+fn walk_toc(node: NodeRef, index: &mut usize) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+
+    for child in node.children() {
+        if child.tag_name() != Some("section") {
+            continue;
+        }
+
+        if let Some(title) = section_title(child) {
+            let current_index = *index;
+            *index += 1;
+            let children = walk_toc(child, index);
+            entries.push(TocEntry {
+                title,
+                location: Location::Exact(child.offset()),
+                index: current_index,
+                children,
+            });
+        } else {
+            entries.append(&mut walk_toc(child, index));
+        }
+    }
+
+    entries
+}
+
+fn is_notes(body: NodeRef) -> bool {
+    body.attribute("name").is_some_and(|name| name == "notes" || name == "comments")
+}
+
+// The notes get a single entry: they usually come in great numbers.
+fn build_toc(root: NodeRef) -> Vec<TocEntry> {
+    let mut toc = Vec::new();
+    let mut index = 0;
+
+    for body in root.children().filter(|child| child.tag_name() == Some("body")) {
+        if is_notes(body) {
+            let title = section_title(body).unwrap_or_else(|| "Notes".to_string());
+            toc.push(TocEntry {
+                title,
+                location: Location::Exact(body.offset()),
+                index,
+                children: Vec::new(),
+            });
+            index += 1;
+        } else {
+            toc.append(&mut walk_toc(body, &mut index));
+        }
+    }
+
+    toc
+}
+
+fn read_info(root: NodeRef) -> (FxHashMap<String, String>, BTreeSet<String>) {
+    let mut info = FxHashMap::default();
+    let mut categories = BTreeSet::new();
+    let description = root.find("description");
+    let title_info = description.and_then(|d| d.find("title-info"));
+
+    if let Some(title_info) = title_info {
+        let mut authors = Vec::new();
+
+        for child in title_info.children() {
+            match child.tag_name() {
+                Some("book-title") => {
+                    info.insert("title".to_string(), clean_text(&child.text()));
+                },
+                Some("author") => {
+                    let names: Vec<String> = ["first-name", "middle-name", "last-name"].iter()
+                                                 .filter_map(|name| child.find(name))
+                                                 .map(|n| clean_text(&n.text()))
+                                                 .filter(|name| !name.is_empty())
+                                                 .collect();
+                    if !names.is_empty() {
+                        authors.push(names.join(" "));
+                    } else if let Some(nickname) = child.find("nickname") {
+                        authors.push(clean_text(&nickname.text()));
+                    }
+                },
+                Some("sequence") => {
+                    if let Some(name) = child.attribute("name") {
+                        info.insert("series".to_string(), clean_text(name));
+                    }
+                    if let Some(number) = child.attribute("number") {
+                        info.insert("number".to_string(), number.trim().to_string());
+                    }
+                },
+                Some("date") => {
+                    let date = child.attribute("value").map(String::from)
+                                    .unwrap_or_else(|| child.text());
+                    info.insert("date".to_string(), date.trim().to_string());
+                },
+                Some("lang") => {
+                    info.insert("language".to_string(), clean_text(&child.text()));
+                },
+                Some("genre") => {
+                    categories.insert(clean_text(&child.text()));
+                },
+                Some("annotation") => {
+                    info.insert("description".to_string(), clean_text(&child.text()));
+                },
+                _ => (),
+            }
+        }
+
+        if !authors.is_empty() {
+            info.insert("author".to_string(), authors.join(", "));
+        }
+    }
+
+    if let Some(publish_info) = description.and_then(|d| d.find("publish-info")) {
+        for child in publish_info.children() {
+            match child.tag_name() {
+                Some("publisher") => {
+                    info.insert("publisher".to_string(), clean_text(&child.text()));
+                },
+                Some("year") if !info.contains_key("date") => {
+                    info.insert("date".to_string(), child.text().trim().to_string());
+                },
+                _ => (),
+            }
+        }
+    }
+
+    info.retain(|_, value| !value.is_empty());
+    categories.retain(|category| !category.is_empty());
+
+    (info, categories)
+}
+
+struct Converter<'a> {
+    tree: XmlTree,
+    images: &'a FxHashMap<String, String>,
+}
+
+impl<'a> Converter<'a> {
+    fn append(&mut self, parent_id: NodeId, name: &str, offset: usize, class: Option<&str>) -> NodeId {
+        let mut attributes = Attributes::default();
+        if let Some(class) = class {
+            attributes.insert("class".to_string(), class.to_string());
+        }
+        self.append_with(parent_id, name, offset, attributes)
+    }
+
+    fn append_with(&mut self, parent_id: NodeId, name: &str, offset: usize, attributes: Attributes) -> NodeId {
+        self.tree.get_mut(parent_id).append(element(name, offset, attributes))
+    }
+
+    fn append_image(&mut self, parent_id: NodeId, node: NodeRef, inline: bool) {
+        let src = href(node).map(|href| href.trim_start_matches('#'))
+                            .and_then(|id| self.images.get(id));
+        if let Some(src) = src.cloned() {
+            let parent_id = if inline {
+                parent_id
+            } else {
+                self.append(parent_id, "div", node.offset(), Some("image"))
+            };
+            let mut attributes = Attributes::default();
+            attributes.insert("src".to_string(), src);
+            if let Some(alt) = node.attribute("alt") {
+                attributes.insert("alt".to_string(), alt.to_string());
+            }
+            self.append_with(parent_id, "img", node.offset(), attributes);
+        }
+    }
+
+    fn convert_children(&mut self, node: NodeRef, parent_id: NodeId, depth: usize) {
+        for child in node.children() {
+            self.convert(child, parent_id, depth);
+        }
+    }
+
+    fn convert(&mut self, node: NodeRef, parent_id: NodeId, depth: usize) {
+        let tag_name = match node.data() {
+            NodeData::Element(..) => node.tag_name().unwrap_or_default(),
+            NodeData::Text(..) | NodeData::Whitespace(..) => {
+                self.tree.get_mut(parent_id).append(node.data().clone());
+                return;
+            },
+            _ => return,
+        };
+
+        let offset = node.offset();
+        let mut attributes = Attributes::default();
+
+        if let Some(id) = node.id() {
+            attributes.insert("id".to_string(), id.to_string());
+        }
+
+        let (name, class) = match tag_name {
+            "section" => {
+                // Give each section a marker, so that TOC entries land on the right page.
+                if node.id().is_none() {
+                    attributes.insert("id".to_string(), format!("section-{}", offset));
+                }
+                let id = self.append_with(parent_id, "section", offset, attributes);
+                self.convert_children(node, id, depth + 1);
+                return;
+            },
+            "image" => {
+                let inline = node.parent_element()
+                                 .and_then(|parent| parent.tag_name())
+                                 .is_some_and(|name| !matches!(name, "body" | "section" | "epigraph" |
+                                                                     "cite" | "annotation" | "coverpage"));
+                self.append_image(parent_id, node, inline);
+                return;
+            },
+            "a" => {
+                if let Some(href) = href(node) {
+                    attributes.insert("href".to_string(), href.to_string());
+                }
+                if node.attribute("type") == Some("note") {
+                    attributes.insert("class".to_string(), "note".to_string());
+                }
+                ("a", None)
+            },
+            "td" | "th" => {
+                for key in ["colspan", "rowspan", "align"] {
+                    if let Some(value) = node.attribute(key) {
+                        attributes.insert(key.to_string(), value.to_string());
+                    }
+                }
+                (tag_name, None)
+            },
+            "title" => match depth {
+                0 => ("h1", Some("title")),
+                1 => ("h2", Some("title")),
+                2 => ("h3", Some("title")),
+                3 => ("h4", Some("title")),
+                4 => ("h5", Some("title")),
+                _ => ("h6", Some("title")),
+            },
+            "p" | "table" | "tr" | "sub" | "sup" | "code" => (tag_name, None),
+            "subtitle" => ("p", Some("subtitle")),
+            "epigraph" => ("blockquote", Some("epigraph")),
+            "cite" => ("blockquote", Some("cite")),
+            "poem" => ("div", Some("poem")),
+            "stanza" => ("div", Some("stanza")),
+            "v" => ("p", Some("verse")),
+            "date" => ("p", Some("date")),
+            "text-author" => ("p", Some("text-author")),
+            "annotation" => ("div", Some("annotation")),
+            "empty-line" => ("p", Some("empty-line")),
+            "emphasis" => ("em", None),
+            "strong" => ("strong", None),
+            "strikethrough" => ("s", None),
+            "style" => ("span", None),
+            _ => ("span", None),
+        };
+
+        if let Some(class) = class {
+            attributes.insert("class".to_string(), class.to_string());
+        }
+
+        let id = self.append_with(parent_id, name, offset, attributes);
+
+        if tag_name == "empty-line" {
+            self.append(id, "br", offset, None);
+        } else {
+            self.convert_children(node, id, depth);
+        }
+    }
+}
+
+// Converts the FictionBook elements to their HTML counterparts, keeping the original offsets.
+fn build_content(root: NodeRef, language: Option<&String>, images: &FxHashMap<String, String>) -> XmlTree {
+    let mut converter = Converter {
+        tree: XmlTree::new(),
+        images,
+    };
+
+    let mut attributes = Attributes::default();
+    if let Some(language) = language {
+        attributes.insert("xml:lang".to_string(), language.clone());
+    }
+    let html_id = converter.append_with(NodeId::from_index(0), "html", root.offset(), attributes);
+    let head_id = converter.append(html_id, "head", root.offset(), None);
+    let description = root.find("description");
+
+    for child in description.into_iter().chain(Some(root)).flat_map(|n| n.children()) {
+        if child.tag_name() == Some("stylesheet") && child.attribute("type") == Some("text/css") {
+            let mut attributes = Attributes::default();
+            attributes.insert("type".to_string(), "text/css".to_string());
+            let style_id = converter.append_with(head_id, "style", child.offset(), attributes);
+            converter.convert_children(child, style_id, 0);
+        }
+    }
+
+    let body_id = converter.append(html_id, "body", root.offset(), None);
+
+    if let Some(coverpage) = description.and_then(|d| d.find("title-info"))
+                                        .and_then(|t| t.find("coverpage")) {
+        let cover_id = converter.append(body_id, "div", coverpage.offset(), Some("cover"));
+        for image in coverpage.children().filter(|child| child.tag_name() == Some("image")) {
+            converter.append_image(cover_id, image, true);
+        }
+    }
+
+    for body in root.children().filter(|child| child.tag_name() == Some("body")) {
+        let class = if is_notes(body) { "notes" } else { "body" };
+        let mut attributes = Attributes::default();
+        attributes.insert("class".to_string(), class.to_string());
+        attributes.insert("id".to_string(), format!("body-{}", body.offset()));
+        let id = converter.append_with(body_id, "div", body.offset(), attributes);
+        converter.convert_children(body, id, 0);
+    }
+
+    let mut content = converter.tree;
+    content.wrap_lost_inlines();
+    content
+}
+
+impl Fb2Document {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Fb2Document, Error> {
+        let buf = fs::read(path.as_ref())?;
+        let text = decode(&buf);
+        Fb2Document::new_from_memory(text)
+    }
+
+    pub fn new_from_memory(text: String) -> Result<Fb2Document, Error> {
+        let source = XmlParser::new(&text).parse();
+        let root = source.root().find("FictionBook")
+                         .ok_or_else(|| format_err!("the FictionBook element is missing"))?;
+
+        let mut binaries = FxHashMap::default();
+        let mut images = FxHashMap::default();
+
+        for child in root.children() {
+            if child.tag_name() == Some("binary") {
+                if let Some(id) = child.attribute("id") {
+                    let name = resource_name(id, child.attribute("content-type"));
+                    binaries.insert(name.clone(), child.text());
+                    images.insert(id.to_string(), name);
+                }
+            }
+        }
+
+        let (info, categories) = read_info(root);
+        let content = build_content(root, info.get("language"), &images);
+
+        let mut html = HtmlDocument::new_from_tree(text, content, Box::new(Binaries(binaries)));
+        html.set_viewer_stylesheet(VIEWER_STYLESHEET);
+        html.set_user_stylesheet(USER_STYLESHEET);
+
+        Ok(Fb2Document {
+            html,
+            toc: build_toc(root),
+            info,
+            categories,
+        })
+    }
+
+    pub fn series(&self) -> Option<String> {
+        self.metadata("series")
+    }
+
+    pub fn number(&self) -> Option<String> {
+        self.metadata("number")
+    }
+
+    pub fn year(&self) -> Option<String> {
+        self.metadata("date").map(|s| s.chars().take(4).collect())
+    }
+
+    pub fn publisher(&self) -> Option<String> {
+        self.metadata("publisher")
+    }
+
+    pub fn language(&self) -> Option<String> {
+        self.metadata("language")
+    }
+
+    pub fn description(&self) -> Option<String> {
+        self.metadata("description")
+    }
+
+    pub fn categories(&self) -> BTreeSet<String> {
+        self.categories.clone()
+    }
+}
+
+impl Document for Fb2Document {
+    fn dims(&self, index: usize) -> Option<(f32, f32)> {
+        self.html.dims(index)
+    }
+
+    fn pages_count(&self) -> usize {
+        self.html.pages_count()
+    }
+
+    fn toc(&mut self) -> Option<Vec<TocEntry>> {
+        if self.toc.is_empty() {
+            None
+        } else {
+            Some(self.toc.clone())
+        }
+    }
+
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        chapter(offset, self.pages_count(), toc)
+    }
+
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        chapter_relative(offset, dir, toc)
+    }
+
+    fn resolve_location(&mut self, loc: Location) -> Option<usize> {
+        self.html.resolve_location(loc)
+    }
+
+    fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.html.words(loc)
+    }
+
+    fn lines(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.html.lines(loc)
+    }
+
+    fn links(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.html.links(loc)
+    }
+
+    fn images(&mut self, loc: Location) -> Option<(Vec<Boundary>, usize)> {
+        self.html.images(loc)
+    }
+
+    fn pixmap(&mut self, loc: Location, scale: f32) -> Option<(Pixmap, usize)> {
+        self.html.pixmap(loc, scale)
+    }
+
+    fn layout(&mut self, width: u32, height: u32, font_size: f32, dpi: u16) {
+        self.html.layout(width, height, font_size, dpi);
+    }
+
+    fn set_font_family(&mut self, family_name: &str, search_path: &str) {
+        self.html.set_font_family(family_name, search_path);
+    }
+
+    fn set_margin_width(&mut self, width: i32) {
+        self.html.set_margin_width(width);
+    }
+
+    fn set_text_align(&mut self, text_align: TextAlign) {
+        self.html.set_text_align(text_align);
+    }
+
+    fn set_line_height(&mut self, line_height: f32) {
+        self.html.set_line_height(line_height);
+    }
+
+    fn set_hyphen_penalty(&mut self, hyphen_penalty: i32) {
+        self.html.set_hyphen_penalty(hyphen_penalty);
+    }
+
+    fn set_stretch_tolerance(&mut self, stretch_tolerance: f32) {
+        self.html.set_stretch_tolerance(stretch_tolerance);
+    }
+
+    fn set_ignore_document_css(&mut self, ignore: bool) {
+        self.html.set_ignore_document_css(ignore);
+    }
+
+    fn set_ignore_embedded_fonts(&mut self, ignore: bool) {
+        self.html.set_ignore_embedded_fonts(ignore);
+    }
+
+    fn set_hide_ruby(&mut self, hide: bool) {
+        self.html.set_hide_ruby(hide);
+    }
+
+    fn set_extra_css(&mut self, css: &str) {
+        self.html.set_extra_css(css);
+    }
+
+    fn title(&self) -> Option<String> {
+        self.metadata("title")
+    }
+
+    fn author(&self) -> Option<String> {
+        self.metadata("author")
+    }
+
+    fn metadata(&self, key: &str) -> Option<String> {
+        self.info.get(key).cloned()
+    }
+
+    fn save(&self, path: &str) -> Result<(), Error> {
+        self.html.save(path)
+    }
+
+    fn footnote(&mut self, offset: usize, uri: &str) -> Option<Footnote> {
+        self.html.footnote(offset, uri)
+    }
+
+    fn is_reflowable(&self) -> bool {
+        true
+    }
+
+    fn has_synthetic_page_numbers(&self) -> bool {
+        true
+    }
+
+    fn is_rtl(&self) -> bool {
+        self.html.is_rtl()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOK: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+<description><title-info><genre>sf</genre>
+<author><first-name>Arkady</first-name><last-name>Strugatsky</last-name></author>
+<author><first-name>Boris</first-name><last-name>Strugatsky</last-name></author>
+<book-title>Roadside Picnic</book-title><date value="1972-01-01">1972</date><lang>en</lang>
+<sequence name="Noon Universe" number="7"/></title-info></description>
+<body><title><p>Roadside Picnic</p></title>
+<section><title><p>Chapter 1</p><p>Redrick Schuhart</p></title><p>Text<a l:href="#n1" type="note">1</a>.</p>
+<section><title><p>Part</p></title><p>More <image l:href="#img"/></p></section></section>
+<section><p>Untitled</p><section><title><p>Chapter 2</p></title><empty-line/></section></section>
+</body>
+<body name="notes"><section id="n1"><title><p>1</p></title><p>A note.</p></section></body>
+<binary id="img" content-type="image/png">iVBORw0K
+GgoAAAA=</binary>
+</FictionBook>"##;
+
+    #[test]
+    fn test_metadata() {
+        let source = XmlParser::new(BOOK).parse();
+        let (info, categories) = read_info(source.root());
+        assert_eq!(info.get("title").map(String::as_str), Some("Roadside Picnic"));
+        assert_eq!(info.get("author").map(String::as_str), Some("Arkady Strugatsky, Boris Strugatsky"));
+        assert_eq!(info.get("series").map(String::as_str), Some("Noon Universe"));
+        assert_eq!(info.get("number").map(String::as_str), Some("7"));
+        assert_eq!(info.get("date").map(String::as_str), Some("1972-01-01"));
+        assert_eq!(info.get("language").map(String::as_str), Some("en"));
+        assert!(categories.contains("sf"));
+    }
+
+    #[test]
+    fn test_toc() {
+        let source = XmlParser::new(BOOK).parse();
+        let toc = build_toc(source.root().find("FictionBook").unwrap());
+        let titles: Vec<&str> = toc.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["Chapter 1 Redrick Schuhart", "Chapter 2", "Notes"]);
+        assert_eq!(toc[0].children.len(), 1);
+        assert_eq!(toc[0].children[0].title, "Part");
+        assert_eq!(toc[0].children[0].index, 1);
+        assert_eq!(toc[1].index, 2);
+        if let Location::Exact(offset) = toc[1].location {
+            assert!(BOOK[offset..].starts_with("<section><title><p>Chapter 2"));
+        } else {
+            panic!("unexpected location");
+        }
+    }
+
+    #[test]
+    fn test_content() {
+        let source = XmlParser::new(BOOK).parse();
+        let mut images = FxHashMap::default();
+        images.insert("img".to_string(), "img.png".to_string());
+        let content = build_content(source.root().find("FictionBook").unwrap(), None, &images);
+        let root = content.root();
+        let title = root.find("h2").unwrap();
+        assert!(BOOK[title.offset()..].starts_with("<title><p>Chapter 1"));
+        assert_eq!(root.find("img").and_then(|n| n.attribute("src")), Some("img.png"));
+        assert_eq!(root.find("a").and_then(|n| n.attribute("href")), Some("#n1"));
+        assert!(root.find("section").is_some_and(|n| n.id().is_some()));
+        assert!(root.find_by_id("n1").is_some());
+    }
+
+    #[test]
+    fn test_binaries() {
+        let mut binaries = Binaries(FxHashMap::default());
+        binaries.0.insert("img.png".to_string(), "iVBORw0K\nGgoAAAA=".to_string());
+        assert_eq!(binaries.fetch("img.png").unwrap(), b"\x89PNG\r\n\x1a\n\0\0\0".to_vec());
+        assert_eq!(resource_name("img", Some("image/png")), "img.png");
+        assert_eq!(resource_name("cover.jpg", None), "cover.jpg");
+    }
+
+    #[test]
+    fn test_decode() {
+        let buf = b"<?xml version=\"1.0\" encoding=\"windows-1251\"?><p>\xcf\xf0\xe8\xe2\xe5\xf2</p>";
+        assert!(decode(buf).ends_with("<p>Привет</p>"));
+    }
+}
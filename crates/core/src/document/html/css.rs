@@ -1,10 +1,13 @@
 use fxhash::FxHashSet;
+use percent_encoding::percent_decode_str;
 use super::style::StyleSheet;
+use super::parse::normalize_font_family;
 
 #[derive(Debug, Clone)]
 pub struct Selector {
     pub simple_selectors: Vec<SimpleSelector>,
     pub combinators: Vec<Combinator>,
+    pub pseudo_element: Option<PseudoElement>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +29,19 @@ pub struct Attribute {
 pub enum PseudoClass {
     FirstChild,
     LastChild,
+    // `:nth-child(an+b)`
+    NthChild(i32, i32),
+    // `:nth-last-child(an+b)`
+    NthLastChild(i32, i32),
+    // Matches the elements that don't match any of the selectors.
+    Not(Vec<SimpleSelector>),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PseudoElement {
+    Before,
+    After,
+    FirstLetter,
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +54,12 @@ pub enum AttributeOperator {
     Contains(String),
     // `[attr|=value]`
     StartsWith(String),
+    // `[attr^=value]`
+    Prefix(String),
+    // `[attr$=value]`
+    Suffix(String),
+    // `[attr*=value]`
+    Substring(String),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -58,6 +80,7 @@ impl Default for Selector {
         Selector {
             simple_selectors: Vec::new(),
             combinators: Vec::new(),
+            pseudo_element: None,
         }
     }
 }
@@ -86,6 +109,7 @@ impl Selector {
             spec[1] = spec[1].saturating_add(sel.attributes.len());
             spec[2] = spec[2].saturating_add(sel.tag_name.iter().count());
         }
+        spec[2] = spec[2].saturating_add(self.pseudo_element.iter().count());
         spec
     }
 }
@@ -113,6 +137,50 @@ pub struct Rule {
     pub declarations: Vec<Declaration>,
 }
 
+#[derive(Debug, Clone)]
+pub struct FontFace {
+    pub family: String,
+    pub style: String,
+    pub weight: String,
+    // The locations of the font files, in order of preference.
+    pub sources: Vec<String>,
+}
+
+impl FontFace {
+    fn from_declarations(declarations: &[Declaration]) -> Option<FontFace> {
+        let mut family = None;
+        let mut style = "normal".to_string();
+        let mut weight = "normal".to_string();
+        let mut sources = Vec::new();
+
+        for declaration in declarations {
+            match declaration.name.as_str() {
+                "font-family" => family = Some(normalize_font_family(&declaration.value)),
+                "font-style" => style = declaration.value.clone(),
+                "font-weight" => weight = declaration.value.clone(),
+                "src" => {
+                    let mut value = declaration.value.as_str();
+                    while let Some(index) = value.find("url(") {
+                        value = &value[index+4..];
+                        let end = value.find(')').unwrap_or(value.len());
+                        let url = value[..end].trim().trim_matches(|c| c == '"' || c == '\'');
+                        if url.starts_with("data:") {
+                            sources.push(url.to_string());
+                        } else {
+                            sources.push(percent_decode_str(url).decode_utf8_lossy().into_owned());
+                        }
+                        value = &value[end..];
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        family.filter(|family| !family.is_empty() && !sources.is_empty())
+              .map(|family| FontFace { family, style, weight, sources })
+    }
+}
+
 #[derive(Debug)]
 pub struct CssParser<'a> {
     input: &'a str,
@@ -210,6 +278,30 @@ impl<'a> CssParser<'a> {
         }
     }
 
+    // Returns the text between the parentheses that follow.
+    fn arguments(&mut self) -> Option<&'a str> {
+        if self.next() != Some('(') {
+            return None;
+        }
+        self.advance(1);
+        let start_offset = self.offset;
+        let mut balance = 1u8;
+        while let Some(c) = self.next() {
+            match c {
+                '(' => balance = balance.saturating_add(1),
+                ')' => balance -= 1,
+                _ => (),
+            }
+            if balance == 0 {
+                break;
+            }
+            self.advance(1);
+        }
+        let end_offset = self.offset;
+        self.advance(1);
+        Some(&self.input[start_offset..end_offset])
+    }
+
     fn attribute_value(&mut self) -> String {
         match self.next() {
             Some(delim @ '"' | delim @ '\'') => {
@@ -304,9 +396,24 @@ impl<'a> CssParser<'a> {
                             self.skip_spaces_and_comments();
                             self.advance(1);
                         },
-                        _ => {
+                        Some(c @ '^' | c @ '$' | c @ '*') if self.starts_with(&format!("{}=", c)) => {
                             self.advance(2);
                             self.skip_spaces_and_comments();
+                            let value = self.attribute_value();
+                            let operator = match c {
+                                '^' => AttributeOperator::Prefix(value),
+                                '$' => AttributeOperator::Suffix(value),
+                                _ => AttributeOperator::Substring(value),
+                            };
+                            selec.attributes.push(Attribute {
+                                name,
+                                operator,
+                            });
+                            self.skip_spaces_and_comments();
+                            self.advance(1);
+                        },
+                        _ => {
+                            self.advance_while(|&c| c != ']');
                             self.advance(1);
                             supported = false;
                         },
@@ -315,27 +422,50 @@ impl<'a> CssParser<'a> {
                 },
                 Some(':') => {
                     self.advance(1);
-                    if self.next() == Some(':') {
-                        supported = false;
+                    let is_element = self.next() == Some(':');
+                    if is_element {
                         self.advance(1);
                     }
                     let offset = self.offset;
                     self.skip_ident();
-                    match &self.input[offset..self.offset] {
-                        "first-child" => {
+                    let name = &self.input[offset..self.offset];
+                    let arguments = self.arguments();
+                    match (name, arguments) {
+                        // The pseudo-elements of CSS 2 can be written with a single colon.
+                        ("before", None) => s.pseudo_element = Some(PseudoElement::Before),
+                        ("after", None) => s.pseudo_element = Some(PseudoElement::After),
+                        ("first-letter", None) => s.pseudo_element = Some(PseudoElement::FirstLetter),
+                        _ if is_element => {
+                            supported = false;
+                        },
+                        ("first-child", None) => {
                             selec.pseudo_classes.push(PseudoClass::FirstChild);
                         },
-                        "last-child" => {
+                        ("last-child", None) => {
                             selec.pseudo_classes.push(PseudoClass::LastChild);
                         },
+                        ("nth-child", Some(arguments)) => {
+                            match parse_nth(arguments) {
+                                Some((a, b)) => selec.pseudo_classes.push(PseudoClass::NthChild(a, b)),
+                                None => supported = false,
+                            }
+                        },
+                        ("nth-last-child", Some(arguments)) => {
+                            match parse_nth(arguments) {
+                                Some((a, b)) => selec.pseudo_classes.push(PseudoClass::NthLastChild(a, b)),
+                                None => supported = false,
+                            }
+                        },
+                        ("not", Some(arguments)) => {
+                            match parse_simple_selectors(arguments) {
+                                Some(selectors) => selec.pseudo_classes.push(PseudoClass::Not(selectors)),
+                                None => supported = false,
+                            }
+                        },
                         _ => {
                             supported = false;
                         },
                     }
-                    if self.next() == Some('(') {
-                        self.advance_while(|&c| c != ')');
-                        self.advance(1);
-                    }
                 },
                 Some('*') => {
                     self.advance(1);
@@ -418,13 +548,20 @@ impl<'a> CssParser<'a> {
 
                     while !self.eof() {
                         self.advance_while(|&c| c != '"' &&
+                                                c != '\'' &&
+                                                c != '(' &&
                                                 c != ';' &&
                                                 c != '}' &&
                                                 c != '!');
                         match self.next() {
-                            Some('"') => {
+                            Some(delim @ '"' | delim @ '\'') => {
+                                self.advance(1);
+                                self.advance_while(|&c| c != delim);
+                                self.advance(1);
+                            },
+                            Some('(') => {
                                 self.advance(1);
-                                self.advance_while(|&c| c != '"');
+                                self.advance_while(|&c| c != ')');
                                 self.advance(1);
                             },
                             Some('!') => {
@@ -480,23 +617,84 @@ impl<'a> CssParser<'a> {
         }
     }
 
+    fn parse_font_face(&mut self, font_faces: &mut Vec<FontFace>) {
+        self.advance_while(|&c| c != '{');
+        self.advance(1);
+        let declarations = self.parse_declarations();
+        if let Some(font_face) = FontFace::from_declarations(&declarations) {
+            font_faces.push(font_face);
+        }
+    }
+
     pub fn parse(&mut self) -> StyleSheet {
         let mut rules = Vec::new();
+        let mut font_faces = Vec::new();
 
         while !self.eof() {
             self.skip_spaces_and_comments();
 
             match self.next() {
                 None => break,
+                Some('@') if self.starts_with("@font-face") => self.parse_font_face(&mut font_faces),
                 Some('@') => self.skip_at_rule(),
                 _ => self.parse_rules(&mut rules),
             }
         }
 
-        StyleSheet { rules }
+        let mut pseudo_elements = Vec::new();
+        for pseudo_element in rules.iter().filter_map(|rule| rule.selector.pseudo_element) {
+            if !pseudo_elements.contains(&pseudo_element) {
+                pseudo_elements.push(pseudo_element);
+            }
+        }
+
+        StyleSheet { rules, font_faces, pseudo_elements }
     }
 }
 
+// Parses the argument of `:nth-child()`.
+fn parse_nth(value: &str) -> Option<(i32, i32)> {
+    let value = value.chars().filter(|c| !c.is_whitespace())
+                     .collect::<String>().to_ascii_lowercase();
+    match value.as_str() {
+        "odd" => Some((2, 1)),
+        "even" => Some((2, 0)),
+        _ => {
+            if let Some(index) = value.find('n') {
+                let a = match &value[..index] {
+                    "" | "+" => 1,
+                    "-" => -1,
+                    v => v.parse().ok()?,
+                };
+                let b = if index + 1 < value.len() {
+                    value[index+1..].parse().ok()?
+                } else {
+                    0
+                };
+                Some((a, b))
+            } else {
+                value.parse().ok().map(|b| (0, b))
+            }
+        },
+    }
+}
+
+// Parses the argument of `:not()`, only simple selectors are supported.
+fn parse_simple_selectors(value: &str) -> Option<Vec<SimpleSelector>> {
+    let text = format!("{} {{", value);
+    let selectors = CssParser::new(&text).parse_selectors();
+    if selectors.is_empty() || value.split(',').count() != selectors.len() {
+        return None;
+    }
+    selectors.into_iter().map(|mut selector| {
+        if selector.simple_selectors.len() == 1 && selector.pseudo_element.is_none() {
+            selector.simple_selectors.pop()
+        } else {
+            None
+        }
+    }).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -514,4 +712,42 @@ mod tests {
         let css = CssParser::new(text).parse();
         println!("{:?}", css);
     }
+
+    #[test]
+    fn font_face_css() {
+        let text = "@font-face { font-family: \"My Font\"; font-weight: bold; \
+                    src: url(data:font/otf;base64,AAAA) format('opentype'), url('../fonts/My%20Font.otf') } \
+                    @font-face { src: url(a.otf) } p { font-family: 'My Font', serif }";
+        let css = CssParser::new(text).parse();
+        assert_eq!(css.rules.len(), 1);
+        assert_eq!(css.rules[0].declarations[0].value, "'My Font', serif");
+        assert_eq!(css.font_faces.len(), 1);
+        let font_face = &css.font_faces[0];
+        assert_eq!(font_face.family, "my font");
+        assert_eq!(font_face.style, "normal");
+        assert_eq!(font_face.weight, "bold");
+        assert_eq!(font_face.sources, vec!["data:font/otf;base64,AAAA".to_string(),
+                                           "../fonts/My Font.otf".to_string()]);
+    }
+
+    #[test]
+    fn pseudo_css() {
+        let text = "p:nth-child(2n+1):not(.a, [b]) > a[href^='#'] { b: c } \
+                    p::first-letter, h1:before { d: e } a::selection { f: g }";
+        let css = CssParser::new(text).parse();
+        assert_eq!(css.rules.len(), 3);
+        let selector = &css.rules[0].selector;
+        assert!(matches!(selector.simple_selectors[0].pseudo_classes[0], PseudoClass::NthChild(2, 1)));
+        assert!(matches!(&selector.simple_selectors[0].pseudo_classes[1], PseudoClass::Not(v) if v.len() == 2));
+        assert!(matches!(&selector.simple_selectors[1].attributes[0].operator, AttributeOperator::Prefix(v) if v == "#"));
+        assert_eq!(selector.specificity(), [0, 3, 2]);
+        assert_eq!(css.rules[1].selector.pseudo_element, Some(PseudoElement::FirstLetter));
+        assert_eq!(css.rules[2].selector.pseudo_element, Some(PseudoElement::Before));
+        assert_eq!(css.pseudo_elements, vec![PseudoElement::FirstLetter, PseudoElement::Before]);
+        assert_eq!(parse_nth("-n + 3"), Some((-1, 3)));
+        assert_eq!(parse_nth("even"), Some((2, 0)));
+        assert_eq!(parse_nth("4"), Some((0, 4)));
+        assert_eq!(parse_nth("2n-1"), Some((2, -1)));
+        assert_eq!(parse_nth("foo"), None);
+    }
 }
@@ -4,6 +4,8 @@ use fxhash::{FxHashMap, FxHashSet};
 pub type Attributes = FxHashMap<String, String>;
 pub const WRAPPER_TAG_NAME: &str = "anonymous";
 
+const VOID_ELEMENTS: [&str; 6] = ["br", "hr", "img", "image", "col", "wbr"];
+
 #[derive(Debug, Clone)]
 pub enum NodeData {
     Root,
@@ -384,6 +386,37 @@ impl<'a> NodeRef<'a> {
         self.descendants()
             .find(|n| n.id() == Some(id))
     }
+
+    // Serializes this node and its descendants, attribute values and texts are written verbatim.
+    pub fn write_xml(&self, buf: &mut String) {
+        match self.node.data {
+            NodeData::Element(ElementData { ref name, ref qualified_name, ref attributes, .. }) => {
+                let name = qualified_name.as_ref().unwrap_or(name);
+                buf.push('<');
+                buf.push_str(name);
+                for (key, value) in attributes {
+                    let quote = if value.contains('"') { '\'' } else { '"' };
+                    buf.push_str(&format!(" {}={}{}{}", key, quote, value, quote));
+                }
+                if !self.has_children() && VOID_ELEMENTS.contains(&name.as_str()) {
+                    buf.push_str("/>");
+                    return;
+                }
+                buf.push('>');
+                for child in self.children() {
+                    child.write_xml(buf);
+                }
+                buf.push_str(&format!("</{}>", name));
+            },
+            NodeData::Text(TextData { ref text, .. }) |
+            NodeData::Whitespace(TextData { ref text, .. }) => buf.push_str(text),
+            NodeData::Wrapper(..) | NodeData::Root => {
+                for child in self.children() {
+                    child.write_xml(buf);
+                }
+            },
+        }
+    }
 }
 
 impl<'a> NodeMut<'a> {
@@ -1,17 +1,23 @@
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::convert::TryFrom;
 use anyhow::Error;
 use kl_hyphenate::{Standard, Hyphenator, Iter};
 use paragraph_breaker::{Item as ParagraphItem, Breakpoint, INFINITE_PENALTY};
 use paragraph_breaker::{total_fit, standard_fit};
 use xi_unicode::LineBreakIterator;
+use unicode_bidi::{BidiInfo, Level};
+use fxhash::FxHashMap;
 use percent_encoding::percent_decode_str;
 use septem::Roman;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use crate::helpers::{Normalize, decode_entities};
 use crate::framebuffer::{Framebuffer, Pixmap};
 use crate::font::{FontOpener, FontFamily};
 use crate::document::{Document, Location};
-use crate::document::pdf::PdfOpener;
+use crate::document::pdf::{PdfOpener, PdfDocument};
 use crate::unit::{mm_to_px, pt_to_px};
 use crate::geom::{Point, Vec2, Rectangle, Edge};
 use crate::settings::{HYPHEN_PENALTY, STRETCH_TOLERANCE};
@@ -19,22 +25,32 @@ use crate::settings::{DEFAULT_FONT_SIZE, DEFAULT_MARGIN_WIDTH, DEFAULT_TEXT_ALIG
 use super::parse::{parse_display, parse_edge, parse_float, parse_text_align, parse_text_indent};
 use super::parse::{parse_width, parse_height, parse_inline_material, parse_font_kind, parse_font_style};
 use super::parse::{parse_font_weight, parse_font_size, parse_font_features, parse_font_variant};
-use super::parse::{parse_letter_spacing, parse_word_spacing};
+use super::parse::{parse_letter_spacing, parse_word_spacing, parse_direction, parse_writing_mode};
 use super::parse::{parse_line_height, parse_vertical_align, parse_color, parse_list_style_type};
+use super::parse::{parse_length, parse_border_width, normalize_font_family};
+use super::parse::{parse_text_transform, parse_white_space, parse_content};
 use super::dom::{NodeRef, NodeData, ElementData, TextData, WRAPPER_TAG_NAME};
 use super::layout::{StyleData, InlineMaterial, TextMaterial, ImageMaterial};
 use super::layout::{GlueMaterial, PenaltyMaterial, ChildArtifact, SiblingStyle, LoopContext};
-use super::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand, FontKind, Fonts};
-use super::layout::{TextAlign, ParagraphElement, TextElement, ImageElement, Display, Float};
-use super::layout::{WordSpacing, ListStyleType, LineStats};
-use super::layout::{hyph_lang, collapse_margins, DEFAULT_HYPH_LANG, HYPHENATION_PATTERNS};
+use super::layout::{MathMaterial, MathElement, RubyMaterial, RubyElement};
+use super::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand, FillCommand, FontKind, Fonts, EmbeddedFamily};
+use super::layout::{TextAlign, ParagraphElement, TextElement, ImageElement, Display, Float, Direction};
+use super::layout::{WordSpacing, ListStyleType, LineStats, WritingMode, GlyphOrientation, Border};
+use super::layout::{FontStyle, FontWeight, TextTransform, WhiteSpace, FloatElement, FloatingText};
+use super::layout::{hyph_lang, collapse_margins, orientation_runs, DEFAULT_HYPH_LANG, HYPHENATION_PATTERNS};
 use super::layout::{EM_SPACE_RATIOS, WORD_SPACE_RATIOS, FONT_SPACES};
-use super::style::{StyleSheet, specified_values};
+use super::style::{StyleSheet, PropertyMap, specified_values, pseudo_element_values};
+use super::css::{FontFace, PseudoElement};
+use super::table::{TableGrid, column_bounds, column_widths, split_columns};
+use super::svg::{SvgImage, is_svg, inline_svg};
+use super::mathml::{MathLayout, MathBox, MathItem, MathGlyphs, parse_math};
 use super::xml::XmlExt;
 
 const DEFAULT_DPI: u16 = 300;
-const DEFAULT_WIDTH: u32 = 1404;
-const DEFAULT_HEIGHT: u32 = 1872;
+pub const DEFAULT_WIDTH: u32 = 1404;
+pub const DEFAULT_HEIGHT: u32 = 1872;
+// The smallest scale applied to the text of tables that are too wide.
+const MIN_TABLE_SCALE: f32 = 0.7;
 
 pub type Page = Vec<DrawCommand>;
 
@@ -42,10 +58,33 @@ pub trait ResourceFetcher {
     fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error>;
 }
 
+struct TableLayout<'a, 'b> {
+    grid: &'b TableGrid<'a>,
+    borders: &'b [Border],
+    // The horizontal extent of each cell.
+    slots: Vec<Option<(i32, i32)>>,
+    style: &'b StyleData,
+    gap: i32,
+}
+
+// A cell laid out from the `top` position to the `end` position.
+// Positions are pairs of page indices and ordinates.
+struct PlacedCell {
+    index: usize,
+    pages: Vec<Page>,
+    rects: Vec<Option<Rectangle>>,
+    top: (usize, i32),
+    end: (usize, i32),
+}
+
 // TODO: Add min_font_size.
 pub struct Engine {
     // The fonts used for each CSS font family.
     fonts: Option<Fonts>,
+    // Whether the fonts provided by the documents are ignored.
+    ignore_embedded_fonts: bool,
+    // Whether the ruby annotations are hidden.
+    hide_ruby: bool,
     // The penalty for lines ending with a hyphen.
     hyphen_penalty: i32,
     // The stretching/shrinking allowed for word spaces.
@@ -62,6 +101,8 @@ pub struct Engine {
     pub dims: (u32, u32),
     // Device DPI.
     pub dpi: u16,
+    // The parsed SVG images, by path or by source for inline images.
+    svg_images: FxHashMap<String, Arc<SvgImage>>,
 }
 
 impl Engine {
@@ -71,6 +112,8 @@ impl Engine {
 
         Engine {
             fonts: None,
+            ignore_embedded_fonts: false,
+            hide_ruby: false,
             hyphen_penalty: HYPHEN_PENALTY,
             stretch_tolerance: STRETCH_TOLERANCE,
             margin,
@@ -79,6 +122,7 @@ impl Engine {
             line_height,
             dims: (DEFAULT_WIDTH, DEFAULT_HEIGHT),
             dpi: DEFAULT_DPI,
+            svg_images: FxHashMap::default(),
         }
     }
 
@@ -125,6 +169,80 @@ impl Engine {
         }
     }
 
+    pub fn set_ignore_embedded_fonts(&mut self, ignore: bool) {
+        self.ignore_embedded_fonts = ignore;
+    }
+
+    pub fn set_hide_ruby(&mut self, hide: bool) {
+        self.hide_ruby = hide;
+    }
+
+    // Loads the faces that weren't already loaded.
+    pub fn load_font_faces(&mut self, font_faces: &[FontFace], resource_fetcher: &mut dyn ResourceFetcher) {
+        if self.ignore_embedded_fonts || font_faces.is_empty() {
+            return;
+        }
+
+        self.load_fonts();
+
+        let opener = match FontOpener::new() {
+            Ok(opener) => opener,
+            Err(_) => return,
+        };
+
+        if let Some(fonts) = self.fonts.as_mut() {
+            for font_face in font_faces {
+                let font_style = parse_font_style(&font_face.style).unwrap_or(FontStyle::Normal);
+                let font_weight = parse_font_weight(&font_face.weight).unwrap_or(FontWeight::Normal);
+                let index = fonts.embedded.iter()
+                                 .position(|family| family.name == font_face.family);
+
+                if index.is_some_and(|index| fonts.embedded[index].has_face(font_style, font_weight)) {
+                    continue;
+                }
+
+                for source in &font_face.sources {
+                    let data = if let Some(data) = source.strip_prefix("data:") {
+                        data.find(";base64,").and_then(|index| {
+                            let text: String = data[index+8..].chars().filter(|c| !c.is_whitespace()).collect();
+                            BASE64.decode(text).ok()
+                        })
+                    } else {
+                        resource_fetcher.fetch(source).ok()
+                    };
+
+                    if let Some(data) = data {
+                        if let Ok(font) = opener.open_memory(&data) {
+                            let family = match index {
+                                Some(index) => &mut fonts.embedded[index],
+                                None => {
+                                    fonts.embedded.push(EmbeddedFamily::new(font_face.family.clone()));
+                                    fonts.embedded.last_mut().unwrap()
+                                },
+                            };
+                            family.insert(font_style, font_weight, font, data);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Prefers the first family, in the list given by `value`, that is provided by the document.
+    fn font_kind(&self, value: &str) -> Option<FontKind> {
+        if !self.ignore_embedded_fonts {
+            if let Some(fonts) = self.fonts.as_ref() {
+                for name in value.split(',').map(normalize_font_family) {
+                    if let Some(index) = fonts.embedded.iter().position(|family| family.name == name) {
+                        return Some(FontKind::Embedded(index));
+                    }
+                }
+            }
+        }
+        parse_font_kind(value)
+    }
+
     pub fn set_margin_width(&mut self, width: i32) {
         self.margin = Edge::uniform(mm_to_px(width as f32, self.dpi).round() as i32);
     }
@@ -139,8 +257,25 @@ impl Engine {
         rect![0, 0, width as i32, height as i32]
     }
 
+    // The area of the page available to the content, in logical coordinates.
+    pub fn content_rect(&self, writing_mode: WritingMode) -> Rectangle {
+        let mut rect = self.rect();
+        rect.shrink(&self.margin);
+        writing_mode.logical_rect(rect, self.dims.0 as i32)
+    }
+
+    pub fn make_vertical(&self, display_list: &mut [Page], writing_mode: WritingMode) {
+        if !writing_mode.is_vertical() {
+            return;
+        }
+        let width = self.dims.0 as i32;
+        for dc in display_list.iter_mut().flatten() {
+            dc.make_vertical(writing_mode, width);
+        }
+    }
+
     pub fn build_display_list(&mut self, node: NodeRef, parent_style: &StyleData, loop_context: &LoopContext, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState, display_list: &mut Vec<Page>) -> ChildArtifact {
-        // TODO: border, background, text-transform, tab-size, text-decoration.
+        // TODO: tab-size, text-decoration.
         let mut style = StyleData::default();
         let mut rects: Vec<Option<Rectangle>> = vec![None];
 
@@ -162,23 +297,26 @@ impl Engine {
         style.font_style = parent_style.font_style;
         style.line_height = parent_style.line_height;
         style.retain_whitespace = parent_style.retain_whitespace;
+        style.retain_newlines = parent_style.retain_newlines;
+        style.writing_mode = parent_style.writing_mode;
 
         match node.tag_name() {
-            Some("pre") => style.retain_whitespace = true,
-            Some("li") | Some(WRAPPER_TAG_NAME) => style.list_style_type = parent_style.list_style_type,
-            Some("table") => {
-                let position = draw_state.position;
-                draw_state.column_widths.clear();
-                draw_state.min_column_widths.clear();
-                draw_state.max_column_widths.clear();
-                draw_state.center_table = style.display == Display::InlineTable &&
-                                          parent_style.text_align == TextAlign::Center;
-                self.compute_column_widths(node, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state);
-                draw_state.position = position;
+            Some("pre") => {
+                style.retain_whitespace = true;
+                style.retain_newlines = true;
             },
+            Some("li") | Some(WRAPPER_TAG_NAME) => style.list_style_type = parent_style.list_style_type,
             _ => (),
         }
 
+        if let Some(value) = props.get("white-space").and_then(|value| parse_white_space(value)) {
+            style.retain_whitespace = matches!(value, WhiteSpace::Pre | WhiteSpace::PreWrap);
+            style.retain_newlines = matches!(value, WhiteSpace::Pre | WhiteSpace::PreWrap | WhiteSpace::PreLine);
+            style.no_wrap = value == WhiteSpace::NoWrap;
+        } else {
+            style.no_wrap = parent_style.no_wrap;
+        }
+
         style.language = props.get("lang").cloned()
                               .or_else(|| parent_style.language.clone());
 
@@ -203,7 +341,7 @@ impl Engine {
                                     .unwrap_or(parent_style.vertical_align);
 
         style.font_kind = props.get("font-family")
-                               .and_then(|value| parse_font_kind(value))
+                               .and_then(|value| self.font_kind(value))
                                .unwrap_or(parent_style.font_kind);
 
         style.font_style = props.get("font-style")
@@ -223,11 +361,21 @@ impl Engine {
                                                                  parent_style.width, self.dpi))
                                  .unwrap_or(parent_style.text_indent);
 
+        style.direction = props.get("direction")
+                               .map(String::as_str)
+                               .or_else(|| node.attribute("dir"))
+                               .and_then(parse_direction)
+                               .unwrap_or(parent_style.direction);
+
         style.text_align = props.get("text-align")
                                 .map(String::as_str)
                                 .or_else(|| node.attribute("align"))
-                                .and_then(|value| parse_text_align(value))
-                                .unwrap_or(parent_style.text_align);
+                                .and_then(|value| parse_text_align(value, style.direction))
+                                .unwrap_or_else(|| if style.direction != parent_style.direction {
+                                    parent_style.text_align.mirror()
+                                } else {
+                                    parent_style.text_align
+                                });
 
         style.font_features = props.get("font-feature-settings")
                                    .map(|value| parse_font_features(value))
@@ -238,13 +386,18 @@ impl Engine {
             style.list_style_type = value;
         }
 
+        style.small_caps = parent_style.small_caps;
+
         if let Some(value) = props.get("font-variant") {
             let mut features = parse_font_variant(value);
-            if let Some(v) = style.font_features.as_mut() {
-                v.append(&mut features);
-            }
+            style.font_features.get_or_insert_with(Vec::new).append(&mut features);
+            style.small_caps = value.split_whitespace().any(|name| name == "small-caps");
         }
 
+        style.text_transform = props.get("text-transform")
+                                    .and_then(|value| parse_text_transform(value))
+                                    .unwrap_or(parent_style.text_transform);
+
         if node.parent().is_some() {
             style.margin = parse_edge(props.get("margin-top").map(String::as_str),
                                       props.get("margin-right").map(String::as_str),
@@ -277,8 +430,17 @@ impl Engine {
                                                            parent_style.width, self.dpi))
                             .unwrap_or(0);
 
-        style.start_x = parent_style.start_x + style.margin.left + style.padding.left;
-        style.end_x = parent_style.end_x - style.margin.right - style.padding.right;
+        // The tables draw the borders of their cells.
+        let is_table_part = matches!(node.tag_name(), Some("table" | "td" | "th"));
+        let (border, background) = if node.parent().is_some() && !is_table_part {
+            (self.parse_border(&props, style.font_size, style.color),
+             props.get("background-color").and_then(|value| parse_color(value)))
+        } else {
+            (Border::default(), None)
+        };
+
+        style.start_x = parent_style.start_x + style.margin.left + border.width.left + style.padding.left;
+        style.end_x = parent_style.end_x - style.margin.right - border.width.right - style.padding.right;
 
         let mut width = style.end_x - style.start_x;
 
@@ -291,8 +453,8 @@ impl Engine {
                 style.padding.left = (style.padding.left as f32 * ratio).round() as i32;
                 style.margin.right = (style.margin.right as f32 * ratio).round() as i32;
                 style.padding.right = (style.padding.right as f32 * ratio).round() as i32;
-                style.start_x = parent_style.start_x + style.margin.left + style.padding.left;
-                style.end_x = parent_style.end_x - style.margin.right - style.padding.right;
+                style.start_x = parent_style.start_x + style.margin.left + border.width.left + style.padding.left;
+                style.end_x = parent_style.end_x - style.margin.right - border.width.right - style.padding.right;
                 width = style.width;
             } else {
                 style.margin.left = 0;
@@ -312,6 +474,22 @@ impl Engine {
             draw_state.position.y = root_data.rect.min.y;
         }
 
+        // The margins can't collapse through a border or a background.
+        let box_top = if !border.is_empty() || background.is_some() {
+            draw_state.position.y += style.margin.top;
+            style.margin.top = 0;
+            if draw_state.position.y + border.width.top + style.padding.top >= root_data.rect.max.y {
+                display_list.push(Vec::new());
+                draw_state.position.y = root_data.rect.min.y;
+            }
+            let page = display_list.len() - 1;
+            let top = (page, draw_state.position.y, display_list[page].len());
+            draw_state.position.y += border.width.top;
+            Some(top)
+        } else {
+            None
+        };
+
         draw_state.position.y += style.padding.top;
 
         let has_blocks = node.children().any(|n| n.is_block());
@@ -321,188 +499,82 @@ impl Engine {
                 display_list.last_mut().unwrap()
                             .push(DrawCommand::Marker(root_data.start_offset + node.offset()));
             }
-            if node.has_children() {
+            if node.tag_name() == Some("table") {
+                rects = self.build_table(node, &style, &props, stylesheet, root_data, resource_fetcher, draw_state, display_list);
+            } else if node.has_children() {
                 let mut inner_loop_context = LoopContext::default();
-
-                if node.tag_name() == Some("tr") {
-                    inner_loop_context.is_first = loop_context.is_first;
-                    inner_loop_context.is_last = loop_context.is_last;
-
-                    if draw_state.column_widths.is_empty() {
-                        let min_row_width: i32 = draw_state.min_column_widths.iter().sum();
-                        let max_row_width: i32 = draw_state.max_column_widths.iter().sum();
-                        // https://www.w3.org/MarkUp/html3/tables.html
-                        if min_row_width >= width {
-                            draw_state.column_widths =
-                                draw_state.min_column_widths.iter()
-                                          .map(|w| ((*w as f32 / min_row_width as f32) *
-                                                   width as f32).round() as i32)
-                                          .collect();
-                        } else if max_row_width <= width {
-                            draw_state.column_widths = draw_state.max_column_widths.clone();
-                        } else {
-                            let dw = (width - min_row_width) as f32;
-                            let dr = (max_row_width - min_row_width) as f32;
-                            let gf = dw / dr;
-                            draw_state.column_widths =
-                                draw_state.min_column_widths.iter()
-                                          .zip(draw_state.max_column_widths.iter())
-                                          .map(|(a, b)| a + ((b - a) as f32 * gf).round() as i32)
-                                          .collect();
-                        }
-                    }
-
-                    if draw_state.center_table {
-                        let actual_width = draw_state.column_widths.iter().sum();
-                        let delta_width = width - actual_width;
-                        let left_shift = delta_width / 2;
-                        let right_shift = delta_width - left_shift;
-                        style.start_x += left_shift;
-                        style.end_x -= right_shift;
-                        style.width = actual_width;
+                let mut iter = node.children().filter(|child| child.is_element()).peekable();
+                inner_loop_context.is_first = true;
+                let is_list_item = node.tag_name() == Some("li");
+                let mut index = 0;
+
+                while let Some(child) = iter.next() {
+                    if iter.peek().is_none() {
+                        inner_loop_context.is_last = true;
                     }
 
-                    let start_x = style.start_x;
-                    let end_x = style.end_x;
-                    let mut cur_x = start_x;
-                    let position = draw_state.position;
-                    let mut final_page = (0, position);
-                    let page_index = display_list.len() - 1;
-                    let mut index = 0;
-
-                    // TODO: rowspan, vertical-align
-                    for child in node.children().filter(|child| child.is_element()) {
-                        if index >= draw_state.column_widths.len() {
-                            break;
-                        }
-
-                        let colspan = child.attribute("colspan")
-                                           .and_then(|v| v.parse().ok())
-                                           .unwrap_or(1)
-                                           .min(draw_state.column_widths.len()-index);
-                        let column_width = draw_state.column_widths[index..index+colspan]
-                                                     .iter().sum::<i32>();
-                        let mut child_display_list = vec![Vec::new()];
-                        style.start_x = cur_x;
-                        style.end_x = cur_x + column_width;
-                        draw_state.position = position;
-                        let artifact = self.build_display_list(child, &style, &inner_loop_context, stylesheet, root_data, resource_fetcher, draw_state, &mut child_display_list);
-                        let pages_count = child_display_list.len();
-                        if pages_count > final_page.0 ||
-                           (pages_count == final_page.0 && draw_state.position.y > final_page.1.y) {
-                            final_page = (pages_count, draw_state.position);
-                        }
-
-                        for (i, mut pg) in child_display_list.into_iter().enumerate() {
-                            if let Some(page) = display_list.get_mut(page_index+i) {
-                                page.append(&mut pg);
-                            } else {
-                                display_list.push(pg);
-                            }
-                        }
-
-                        for (i, rect) in artifact.rects.into_iter().enumerate() {
-                            if let Some(page_rect) = rects.get_mut(i) {
-                                if let Some(pr) = page_rect.as_mut() {
-                                    if let Some(r) = rect.as_ref() {
-                                        pr.absorb(r);
-                                    }
-                                } else {
-                                    *page_rect = rect;
-                                }
-                            } else {
-                                rects.push(rect);
-                            }
-                        }
-
-                        inner_loop_context.sibling_style = artifact.sibling_style;
+                    inner_loop_context.index = index;
 
-                        if inner_loop_context.is_last {
-                            style.margin.bottom = collapse_margins(inner_loop_context.sibling_style.margin.bottom, style.margin.bottom);
-                        }
-
-                        index += colspan;
-                        cur_x += column_width;
+                    if is_list_item || child.is_wrapper() {
+                        inner_loop_context.index = loop_context.index;
                     }
 
-                    style.start_x = start_x;
-                    style.end_x = end_x;
-                    draw_state.position = final_page.1;
-                } else {
-                    let mut iter = node.children().filter(|child| child.is_element()).peekable();
-                    inner_loop_context.is_first = true;
-                    let is_list_item = node.tag_name() == Some("li");
-                    let mut index = 0;
-
-                    while let Some(child) = iter.next() {
-                        if iter.peek().is_none() {
-                            inner_loop_context.is_last = true;
-                        }
-
-                        inner_loop_context.index = index;
-
-                        if is_list_item || child.is_wrapper() {
-                            inner_loop_context.index = loop_context.index;
-                        }
-
-                        let artifact = self.build_display_list(child, &style, &inner_loop_context, stylesheet, root_data, resource_fetcher, draw_state, display_list);
-                        inner_loop_context.sibling_style = artifact.sibling_style;
-                        inner_loop_context.is_first = false;
+                    let artifact = self.build_display_list(child, &style, &inner_loop_context, stylesheet, root_data, resource_fetcher, draw_state, display_list);
+                    inner_loop_context.sibling_style = artifact.sibling_style;
+                    inner_loop_context.is_first = false;
 
-                        // Collapse the bottom margin of the last child and its parent.
-                        if inner_loop_context.is_last {
-                            style.margin.bottom = collapse_margins(inner_loop_context.sibling_style.margin.bottom, style.margin.bottom);
-                        }
+                    // Collapse the bottom margin of the last child and its parent.
+                    if inner_loop_context.is_last {
+                        style.margin.bottom = collapse_margins(inner_loop_context.sibling_style.margin.bottom, style.margin.bottom);
+                    }
 
-                        let last_index = rects.len() - 1;
-                        for (i, rect) in artifact.rects.into_iter().enumerate() {
-                            if let Some(page_rect) = rects.get_mut(last_index + i) {
-                                if let Some(pr) = page_rect.as_mut() {
-                                    if let Some(r) = rect.as_ref() {
-                                        pr.absorb(r);
-                                    }
-                                } else {
-                                    *page_rect = rect;
+                    let last_index = rects.len() - 1;
+                    for (i, rect) in artifact.rects.into_iter().enumerate() {
+                        if let Some(page_rect) = rects.get_mut(last_index + i) {
+                            if let Some(pr) = page_rect.as_mut() {
+                                if let Some(r) = rect.as_ref() {
+                                    pr.absorb(r);
                                 }
                             } else {
-                                rects.push(rect);
+                                *page_rect = rect;
                             }
+                        } else {
+                            rects.push(rect);
                         }
-
-                        index += 1
                     }
+
+                    index += 1
                 }
             }
         } else {
-            if node.has_children() {
-                let mut inlines = Vec::new();
-                let mut markers = Vec::new();
-                if node.id().is_some() {
-                    markers.push(node.offset());
-                }
-                for child in node.children() {
-                    self.gather_inline_material(child, stylesheet, &style, &root_data.spine_dir, &mut markers, &mut inlines);
-                }
-                if !inlines.is_empty() {
-                    draw_state.prefix = match style.list_style_type {
-                        None => {
-                            let parent = node.ancestor_elements()
-                                             .find(|n| matches!(n.tag_name(), Some("ul"|"ol")));
-                            match parent.and_then(|parent| parent.tag_name()) {
-                                Some("ul") => format_list_prefix(ListStyleType::Disc, loop_context.index),
-                                Some("ol") => format_list_prefix(ListStyleType::Decimal, loop_context.index),
-                                _ => None,
-                            }
-                        },
-                        Some(kind) => format_list_prefix(kind, loop_context.index),
-                    };
-                    self.place_paragraphs(&inlines, &style, root_data, &markers, resource_fetcher, draw_state, &mut rects, display_list);
-                }
-            } else {
-                if node.id().is_some() {
-                    display_list.last_mut().unwrap()
-                                .push(DrawCommand::Marker(root_data.start_offset + node.offset()));
-                }
+            let mut inlines = Vec::new();
+            let mut markers = Vec::new();
+            if node.id().is_some() {
+                markers.push(node.offset());
+            }
+            inlines.extend(self.generated_content(node, stylesheet, &style, PseudoElement::Before));
+            for child in node.children() {
+                self.gather_inline_material(child, stylesheet, &style, &root_data.spine_dir, &mut markers, &mut inlines);
+            }
+            inlines.extend(self.generated_content(node, stylesheet, &style, PseudoElement::After));
+            self.apply_first_letter(node, stylesheet, &mut inlines);
+            if !inlines.is_empty() {
+                draw_state.prefix = match style.list_style_type {
+                    None => {
+                        let parent = node.ancestor_elements()
+                                         .find(|n| matches!(n.tag_name(), Some("ul"|"ol")));
+                        match parent.and_then(|parent| parent.tag_name()) {
+                            Some("ul") => format_list_prefix(ListStyleType::Disc, loop_context.index),
+                            Some("ol") => format_list_prefix(ListStyleType::Decimal, loop_context.index),
+                            _ => None,
+                        }
+                    },
+                    Some(kind) => format_list_prefix(kind, loop_context.index),
+                };
+                self.place_paragraphs(&inlines, &style, root_data, &markers, resource_fetcher, draw_state, &mut rects, display_list);
+            } else if node.id().is_some() {
+                display_list.last_mut().unwrap()
+                            .push(DrawCommand::Marker(root_data.start_offset + node.offset()));
             }
         }
 
@@ -521,6 +593,29 @@ impl Engine {
 
         draw_state.position.y += style.padding.bottom;
 
+        if let Some((first_page, top_y, command_index)) = box_top {
+            draw_state.position.y += border.width.bottom;
+            let bottom = (display_list.len() - 1, draw_state.position.y);
+            let start_x = parent_style.start_x + style.margin.left;
+            let end_x = parent_style.end_x - style.margin.right;
+            if let Some(color) = background {
+                // The background is drawn before the content of the box.
+                for (page_index, page) in display_list.iter_mut().enumerate().skip(first_page) {
+                    let min_y = if page_index == first_page { top_y } else { root_data.rect.min.y };
+                    let max_y = if page_index == bottom.0 { bottom.1 } else { root_data.rect.max.y };
+                    if max_y > min_y {
+                        let index = if page_index == first_page { command_index } else { 0 };
+                        page.insert(index, DrawCommand::Fill(FillCommand {
+                            offset: root_data.start_offset + node.offset(),
+                            rect: rect![start_x, min_y, end_x, max_y],
+                            color,
+                        }));
+                    }
+                }
+            }
+            self.push_borders(node.offset(), (first_page, top_y), bottom, (start_x, end_x), &border, root_data, first_page, display_list, &mut rects);
+        }
+
         if props.get("page-break-after").map(String::as_str) == Some("always") {
             display_list.push(Vec::new());
             draw_state.position.y = root_data.rect.min.y;
@@ -535,121 +630,504 @@ impl Engine {
         }
     }
 
-    fn compute_column_widths(&mut self, node: NodeRef, parent_style: &StyleData, loop_context: &LoopContext, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState) {
-        if node.tag_name() == Some("tr") {
-            let mut index = 0;
-            for child in node.children().filter(|c| c.is_element()) {
-                let colspan = child.attribute("colspan")
-                                   .and_then(|v| v.parse().ok())
-                                   .unwrap_or(1);
-                let mut display_list = vec![Vec::new()];
-                let artifact = self.build_display_list(child, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state, &mut display_list);
-                let horiz_padding = artifact.sibling_style.padding.left +
-                                    artifact.sibling_style.padding.right;
-                let min_width = display_list.into_iter()
-                                            .flatten()
-                                            .filter_map(|dc| {
-                                                match dc {
-                                                    DrawCommand::Text(TextCommand { rect, .. }) => Some(rect.width() as i32 + horiz_padding),
-                                                    DrawCommand::Image(ImageCommand { rect, .. }) => Some((rect.width() as i32).min(pt_to_px(parent_style.font_size, self.dpi).round().max(1.0) as i32) + horiz_padding),
-                                                    _ => None,
-                                                }
-                                            })
-                                            .max().unwrap_or(0);
-                let max_width = artifact.rects.into_iter()
-                                        .filter_map(|v| v.map(|r| r.width() as i32 + horiz_padding))
-                                        .max().unwrap_or(0);
-                if colspan == 1 {
-                    if let Some(cw) = draw_state.min_column_widths.get_mut(index) {
-                        *cw = (*cw).max(min_width);
-                    } else {
-                        draw_state.min_column_widths.push(min_width);
+    fn parse_border(&self, props: &PropertyMap, font_size: f32, color: u8) -> Border {
+        let mut width = Edge::default();
+        let mut border_color = None;
+
+        for (edge, value) in ["top", "right", "bottom", "left"].iter().zip(
+                             [&mut width.top, &mut width.right, &mut width.bottom, &mut width.left]) {
+            let border_style = props.get(&format!("border-{}-style", edge)).map(String::as_str);
+            if matches!(border_style, None | Some("none") | Some("hidden")) {
+                continue;
+            }
+            *value = props.get(&format!("border-{}-width", edge))
+                          .and_then(|value| parse_border_width(value, font_size, self.font_size, self.dpi))
+                          .unwrap_or_else(|| parse_border_width("medium", font_size, self.font_size, self.dpi).unwrap_or(0))
+                          .max(0);
+            if border_color.is_none() && *value > 0 {
+                border_color = props.get(&format!("border-{}-color", edge))
+                                    .and_then(|value| parse_color(value));
+            }
+        }
+
+        Border {
+            width,
+            color: border_color.unwrap_or(color),
+        }
+    }
+
+    fn build_table(&mut self, node: NodeRef, style: &StyleData, props: &PropertyMap, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState, display_list: &mut Vec<Page>) -> Vec<Option<Rectangle>> {
+        let grid = TableGrid::new(node);
+        let first_page = display_list.len() - 1;
+        let mut rects = vec![None];
+
+        let mut table_style = style.clone();
+        table_style.margin = Edge::default();
+        table_style.padding = Edge::default();
+
+        draw_state.position.y += style.margin.top;
+
+        // The legacy `border` attribute applies to the table and its cells.
+        let border_attribute = node.attribute("border")
+                                   .map(|value| value.trim().parse::<i32>().unwrap_or(1))
+                                   .filter(|value| *value > 0);
+        let px = |value: i32, font_size: f32| parse_length(&format!("{}px", value), font_size, self.font_size, self.dpi).unwrap_or(0);
+
+        let mut table_border = self.parse_border(props, style.font_size, style.color);
+        if table_border.is_empty() {
+            if let Some(value) = border_attribute {
+                table_border = Border { width: Edge::uniform(px(value, style.font_size)), color: style.color };
+            }
+        }
+
+        let mut borders = Vec::with_capacity(grid.cells.len());
+        let mut specified_widths = Vec::with_capacity(grid.cells.len());
+
+        for cell in &grid.cells {
+            let cell_props = specified_values(cell.node, stylesheet);
+            let mut border = self.parse_border(&cell_props, style.font_size, style.color);
+            if border.is_empty() && border_attribute.is_some() {
+                border = Border { width: Edge::uniform(px(1, style.font_size)), color: style.color };
+            }
+            borders.push(border);
+            specified_widths.push(cell_props.get("width").map(String::as_str)
+                                            .or_else(|| cell.node.attribute("width"))
+                                            .and_then(|value| self.parse_table_width(value, style)));
+        }
+
+        let collapse = props.get("border-collapse").map(String::as_str) == Some("collapse");
+        // In the collapsing border model, the borders of adjacent cells overlap.
+        let (outer_gap_x, outer_gap_y, gap_x, gap_y) = if collapse {
+            let overlap_x = borders.iter().map(|b| b.width.left.min(b.width.right)).max().unwrap_or(0);
+            let overlap_y = borders.iter().map(|b| b.width.top.min(b.width.bottom)).max().unwrap_or(0);
+            (-overlap_x.min(table_border.width.left), -overlap_y.min(table_border.width.top), -overlap_x, -overlap_y)
+        } else {
+            let spacing = props.get("border-spacing")
+                               .and_then(|value| value.split_whitespace().next())
+                               .and_then(|value| parse_length(value, style.font_size, self.font_size, self.dpi))
+                               .or_else(|| node.attribute("cellspacing")
+                                               .and_then(|value| value.trim().parse::<i32>().ok())
+                                               .map(|value| px(value, style.font_size)))
+                               .unwrap_or(0);
+            (spacing, spacing, spacing, spacing)
+        };
+
+        let frame_width = table_border.width.left + table_border.width.right + 2 * outer_gap_x;
+        let specified_width = props.get("width").map(String::as_str)
+                                   .or_else(|| node.attribute("width"))
+                                   .and_then(|value| self.parse_table_width(value, style))
+                                   .filter(|width| *width > 0);
+        let content_width = |count: usize| style.width - frame_width - (count.max(1) as i32 - 1) * gap_x;
+
+        if let Some(caption) = grid.caption {
+            if props.get("caption-side").map(String::as_str) != Some("bottom") {
+                self.build_table_caption(caption, &table_style, first_page, stylesheet, root_data, resource_fetcher, draw_state, display_list, &mut rects);
+            }
+        }
+
+        if grid.columns_count > 0 {
+            let (mut min_widths, mut max_widths) = self.measure_table_cells(&grid, &borders, &specified_widths, &table_style, stylesheet, root_data, resource_fetcher, draw_state);
+            let width = content_width(grid.columns_count);
+            let min_width: i32 = min_widths.iter().sum();
+
+            // Shrink the text of tables that are too wide, up to a point.
+            if min_width > width && width > 0 {
+                let factor = (width as f32 / min_width as f32).max(MIN_TABLE_SCALE);
+                table_style.font_size *= factor;
+                table_style.line_height = (factor * table_style.line_height as f32).round() as i32;
+                (min_widths, max_widths) = self.measure_table_cells(&grid, &borders, &specified_widths, &table_style, stylesheet, root_data, resource_fetcher, draw_state);
+            }
+
+            // And split the columns of the ones that still don't fit.
+            let gapped_widths: Vec<i32> = min_widths.iter().map(|w| w + gap_x).collect();
+            let groups = split_columns(&gapped_widths, width + gap_x);
+
+            let page_top = root_data.rect.min.y + table_border.width.top + outer_gap_y;
+
+            for (group_index, group) in groups.iter().enumerate() {
+                if group_index > 0 {
+                    draw_state.position.y += table_style.line_height;
+                }
+
+                let count = group.len();
+                let width = content_width(count);
+                let stretch = specified_width.is_some() && groups.len() == 1;
+                let target_width = specified_width.map(|w| (w - frame_width - (count as i32 - 1) * gap_x).min(width))
+                                                  .filter(|_| stretch)
+                                                  .unwrap_or(width);
+                let mut widths = column_widths(&min_widths[group.clone()], &max_widths[group.clone()], target_width, stretch);
+                let total_width: i32 = widths.iter().sum();
+
+                if total_width > width && total_width > 0 {
+                    for w in widths.iter_mut() {
+                        *w = (*w as i64 * width.max(0) as i64 / total_width as i64) as i32;
+                    }
+                }
+
+                let table_width = widths.iter().sum::<i32>() + frame_width + (count as i32 - 1) * gap_x;
+                let free_space = (style.width - table_width).max(0);
+                let align = node.attribute("align").map(|value| value.to_lowercase());
+                let is_centered = align.as_deref() == Some("center") ||
+                                  (props.get("margin-left").map(String::as_str) == Some("auto") &&
+                                   props.get("margin-right").map(String::as_str) == Some("auto")) ||
+                                  (style.display == Display::InlineTable && style.text_align == TextAlign::Center);
+                let is_right = align.as_deref() == Some("right") ||
+                               (style.direction == Direction::Rtl && align.as_deref() != Some("left"));
+                let min_x = if is_centered {
+                    style.start_x + free_space / 2
+                } else if is_right {
+                    style.start_x + free_space
+                } else {
+                    style.start_x
+                };
+                let max_x = min_x + table_width;
+
+                let mut column_starts = Vec::with_capacity(count);
+                let mut x = min_x + table_border.width.left + outer_gap_x;
+                for w in &widths {
+                    column_starts.push(x);
+                    x += w + gap_x;
+                }
+
+                let slots = grid.cells.iter().map(|cell| {
+                    if !group.contains(&cell.column) {
+                        return None;
                     }
-                    if let Some(cw) = draw_state.max_column_widths.get_mut(index) {
-                        *cw = (*cw).max(max_width);
+                    let first = cell.column - group.start;
+                    let last = (cell.column + cell.colspan).min(group.end) - 1 - group.start;
+                    let (start_x, end_x) = (column_starts[first], column_starts[last] + widths[last]);
+                    // The columns of right-to-left tables are laid out from right to left.
+                    if style.direction == Direction::Rtl {
+                        Some((min_x + max_x - end_x, min_x + max_x - start_x))
                     } else {
-                        draw_state.max_column_widths.push(max_width);
+                        Some((start_x, end_x))
+                    }
+                }).collect();
+
+                let table = TableLayout {
+                    grid: &grid,
+                    borders: &borders,
+                    slots,
+                    style: &table_style,
+                    gap: gap_y,
+                };
+
+                let mut table_top = (display_list.len() - 1, draw_state.position.y);
+                let mut current = (table_top.0, table_top.1 + table_border.width.top + outer_gap_y);
+                let mut open_cells = Vec::new();
+                let mut header_height = None;
+
+                for row in 0..grid.rows.len() {
+                    let has_incoming_cells = !open_cells.is_empty();
+                    let mut placed_cells = self.place_table_row(&table, row, current, stylesheet, root_data, resource_fetcher, draw_state);
+                    let spills = placed_cells.iter().any(|cell| cell.end.0 > current.0);
+
+                    // Move the rows that would be split to the next page, and repeat the header there.
+                    if spills && current.1 > page_top && !has_incoming_cells {
+                        display_list.push(vec![DrawCommand::Marker(root_data.start_offset + grid.rows[row].offset())]);
+                        if row == 0 {
+                            table_top = (display_list.len() - 1, root_data.rect.min.y);
+                        }
+                        current = (display_list.len() - 1, page_top);
+                        let page_height = root_data.rect.height() as i32;
+                        if row >= grid.header_rows_count && header_height.is_some_and(|h: i32| 3 * h < page_height) {
+                            for header_row in 0..grid.header_rows_count {
+                                let header_cells = self.place_table_row(&table, header_row, current, stylesheet, root_data, resource_fetcher, draw_state);
+                                current = self.finish_table_row(&table, header_row, header_cells, true, current, &mut open_cells, root_data, first_page, display_list, &mut rects);
+                            }
+                        }
+                        placed_cells = self.place_table_row(&table, row, current, stylesheet, root_data, resource_fetcher, draw_state);
+                    }
+
+                    current = self.finish_table_row(&table, row, placed_cells, false, current, &mut open_cells, root_data, first_page, display_list, &mut rects);
+
+                    if row + 1 == grid.header_rows_count && current.0 == table_top.0 {
+                        header_height = Some(current.1 - table_top.1);
                     }
                 }
 
-                index += colspan;
+                let table_bottom = (current.0, current.1 - gap_y + outer_gap_y + table_border.width.bottom);
+                self.push_borders(node.offset(), table_top, table_bottom, (min_x, max_x), &table_border, root_data, first_page, display_list, &mut rects);
+
+                draw_state.position.y = table_bottom.1;
             }
+        }
+
+        if let Some(caption) = grid.caption {
+            if props.get("caption-side").map(String::as_str) == Some("bottom") {
+                self.build_table_caption(caption, &table_style, first_page, stylesheet, root_data, resource_fetcher, draw_state, display_list, &mut rects);
+            }
+        }
+
+        rects
+    }
+
+    fn parse_table_width(&self, value: &str, style: &StyleData) -> Option<i32> {
+        // The widths given by attributes are in pixels.
+        if value.trim().bytes().all(|b| b.is_ascii_digit()) {
+            parse_length(&format!("{}px", value.trim()), style.font_size, self.font_size, self.dpi)
         } else {
-            for child in node.children().filter(|c| c.is_element()) {
-                self.compute_column_widths(child, parent_style, loop_context, stylesheet, root_data, resource_fetcher, draw_state);
+            parse_width(value, style.font_size, self.font_size, style.width, self.dpi)
+        }
+    }
+
+    fn build_table_caption(&mut self, caption: NodeRef, style: &StyleData, first_page: usize, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState, display_list: &mut Vec<Page>, rects: &mut Vec<Option<Rectangle>>) {
+        let page_index = display_list.len() - 1;
+        let artifact = self.build_display_list(caption, style, &LoopContext::default(), stylesheet, root_data, resource_fetcher, draw_state, display_list);
+        for (i, rect) in artifact.rects.into_iter().enumerate() {
+            if let Some(rect) = rect {
+                absorb_page_rect(rects, page_index + i - first_page, &rect);
             }
         }
     }
 
-    fn gather_inline_material(&self, node: NodeRef, stylesheet: &StyleSheet, parent_style: &StyleData, spine_dir: &PathBuf, markers: &mut Vec<usize>, inlines: &mut Vec<InlineMaterial>) {
-        match node.data() {
-            NodeData::Element(ElementData { offset, name, attributes, .. }) => {
-                let mut style = StyleData::default();
-                let props = specified_values(node, stylesheet);
+    // Returns the minimum and maximum widths of the columns.
+    fn measure_table_cells(&mut self, grid: &TableGrid, borders: &[Border], specified_widths: &[Option<i32>], style: &StyleData, stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState) -> (Vec<i32>, Vec<i32>) {
+        let position = draw_state.position;
+        let mut bounds = Vec::with_capacity(grid.cells.len());
+
+        for ((cell, border), specified_width) in grid.cells.iter().zip(borders).zip(specified_widths) {
+            let mut cell_style = style.clone();
+            cell_style.start_x += border.width.left;
+            cell_style.end_x -= border.width.right;
+            cell_style.width = cell_style.end_x - cell_style.start_x;
+            let mut display_list = vec![Vec::new()];
+            let artifact = self.build_display_list(cell.node, &cell_style, &LoopContext::default(), stylesheet, root_data, resource_fetcher, draw_state, &mut display_list);
+            let extra_width = artifact.sibling_style.padding.left + artifact.sibling_style.padding.right +
+                              border.width.left + border.width.right;
+            let min_width = display_list.into_iter()
+                                        .flatten()
+                                        .filter_map(|dc| {
+                                            match dc {
+                                                DrawCommand::Text(TextCommand { rect, .. }) => Some(rect.width() as i32),
+                                                DrawCommand::Image(ImageCommand { rect, .. }) => Some((rect.width() as i32).min(pt_to_px(style.font_size, self.dpi).round().max(1.0) as i32)),
+                                                _ => None,
+                                            }
+                                        })
+                                        .max().unwrap_or(0) + extra_width;
+            let mut max_width = artifact.rects.into_iter()
+                                        .filter_map(|v| v.map(|r| r.width() as i32))
+                                        .max().unwrap_or(0) + extra_width;
+            if let Some(width) = specified_width {
+                max_width = max_width.max(width + extra_width);
+            }
+            bounds.push((cell.columns(), min_width, max_width));
+            draw_state.position = position;
+        }
 
-                style.font_style = parent_style.font_style;
-                style.line_height = parent_style.line_height;
-                style.text_indent = parent_style.text_indent;
-                style.retain_whitespace = parent_style.retain_whitespace;
-                style.language = parent_style.language.clone();
-                style.uri = parent_style.uri.clone();
+        column_bounds(&bounds, grid.columns_count)
+    }
 
-                style.display = props.get("display").and_then(|value| parse_display(value))
-                                     .unwrap_or(Display::Inline);
+    // Lays out the cells that start on the given row, without adding them to the display list.
+    fn place_table_row(&mut self, table: &TableLayout, row: usize, top: (usize, i32), stylesheet: &StyleSheet, root_data: &RootData, resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState) -> Vec<PlacedCell> {
+        let mut placed_cells = Vec::new();
 
-                if style.display == Display::None {
-                    return;
+        for (index, cell) in table.grid.cells.iter().enumerate() {
+            if cell.row != row {
+                continue;
+            }
+            let Some((start_x, end_x)) = table.slots[index] else {
+                continue;
+            };
+            let border = &table.borders[index];
+            let mut cell_style = table.style.clone();
+            cell_style.start_x = start_x + border.width.left;
+            cell_style.end_x = end_x - border.width.right;
+            cell_style.width = cell_style.end_x - cell_style.start_x;
+            draw_state.position.y = top.1 + border.width.top;
+            let mut pages = vec![Vec::new()];
+            let artifact = self.build_display_list(cell.node, &cell_style, &LoopContext::default(), stylesheet, root_data, resource_fetcher, draw_state, &mut pages);
+            let end = (top.0 + pages.len() - 1, draw_state.position.y + border.width.bottom);
+            placed_cells.push(PlacedCell {
+                index,
+                pages,
+                rects: artifact.rects,
+                top,
+                end,
+            });
+        }
+
+        placed_cells
+    }
+
+    // Adds the cells of a row to the display list, draws the borders of the cells that end
+    // on that row, and returns the position of the next row.
+    fn finish_table_row(&mut self, table: &TableLayout, row: usize, placed_cells: Vec<PlacedCell>, is_repeated: bool, current: (usize, i32), open_cells: &mut Vec<PlacedCell>, root_data: &RootData, first_page: usize, display_list: &mut Vec<Page>, rects: &mut Vec<Option<Rectangle>>) -> (usize, i32) {
+        for mut cell in placed_cells {
+            for (i, mut page) in cell.pages.drain(..).enumerate() {
+                if is_repeated {
+                    page.retain(|dc| !matches!(dc, DrawCommand::Marker(..)));
+                }
+                if let Some(list_page) = display_list.get_mut(cell.top.0 + i) {
+                    list_page.append(&mut page);
+                } else {
+                    display_list.push(page);
                 }
+            }
+            for (i, rect) in cell.rects.drain(..).enumerate() {
+                if let Some(rect) = rect {
+                    absorb_page_rect(rects, cell.top.0 + i - first_page, &rect);
+                }
+            }
+            open_cells.push(cell);
+        }
+
+        let header_rows_count = table.grid.header_rows_count;
+        let last_row = |index: usize| {
+            let last_row = table.grid.cells[index].last_row();
+            if is_repeated {
+                last_row.min(header_rows_count - 1)
+            } else {
+                last_row
+            }
+        };
+
+        let end = open_cells.iter()
+                            .filter(|cell| last_row(cell.index) == row)
+                            .map(|cell| cell.end)
+                            .fold(current, |a, b| a.max(b));
+
+        let mut index = 0;
+        while index < open_cells.len() {
+            if last_row(open_cells[index].index) == row {
+                let cell = open_cells.swap_remove(index);
+                let (start_x, end_x) = table.slots[cell.index].unwrap_or_default();
+                let node = table.grid.cells[cell.index].node;
+                self.push_borders(node.offset(), cell.top, end, (start_x, end_x), &table.borders[cell.index], root_data, first_page, display_list, rects);
+            } else {
+                index += 1;
+            }
+        }
+
+        (end.0, end.1 + table.gap)
+    }
+
+    // Draws the borders of a box that might span several pages.
+    fn push_borders(&self, offset: usize, top: (usize, i32), bottom: (usize, i32), (start_x, end_x): (i32, i32), border: &Border, root_data: &RootData, first_page: usize, display_list: &mut [Page], rects: &mut Vec<Option<Rectangle>>) {
+        for page_index in top.0..=bottom.0.min(display_list.len() - 1) {
+            let min_y = if page_index == top.0 { top.1 } else { root_data.rect.min.y };
+            let max_y = if page_index == bottom.0 { bottom.1 } else { root_data.rect.max.y };
+            if max_y <= min_y {
+                continue;
+            }
+            let rect = rect![start_x, min_y, end_x, max_y];
+            absorb_page_rect(rects, page_index - first_page, &rect);
+            if border.is_empty() {
+                continue;
+            }
+            let Edge { top, right, bottom, left } = border.width;
+            let offset = root_data.start_offset + offset;
+            let page = &mut display_list[page_index];
+            for rect in [rect![start_x, min_y, end_x, min_y + top],
+                         rect![end_x - right, min_y, end_x, max_y],
+                         rect![start_x, max_y - bottom, end_x, max_y],
+                         rect![start_x, min_y, start_x + left, max_y]] {
+                if rect.min.x < rect.max.x && rect.min.y < rect.max.y {
+                    page.push(DrawCommand::Fill(FillCommand { offset, rect, color: border.color }));
+                }
+            }
+        }
+    }
+
+    // Computes the style of an inline element, or of a pseudo-element.
+    fn inline_style(&self, node: NodeRef, props: &PropertyMap, parent_style: &StyleData) -> StyleData {
+        let mut style = StyleData {
+            font_style: parent_style.font_style,
+            line_height: parent_style.line_height,
+            text_indent: parent_style.text_indent,
+            retain_whitespace: parent_style.retain_whitespace,
+            retain_newlines: parent_style.retain_newlines,
+            direction: parent_style.direction,
+            writing_mode: parent_style.writing_mode,
+            language: parent_style.language.clone(),
+            uri: parent_style.uri.clone(),
+            .. Default::default()
+        };
+
+        style.display = props.get("display").and_then(|value| parse_display(value))
+                             .unwrap_or(Display::Inline);
 
-                style.font_size = props.get("font-size")
-                                       .and_then(|value| parse_font_size(value, parent_style.font_size, self.font_size))
-                                       .unwrap_or(parent_style.font_size);
+        if style.display == Display::None {
+            return style;
+        }
+
+        style.font_size = props.get("font-size")
+                               .and_then(|value| parse_font_size(value, parent_style.font_size, self.font_size))
+                               .unwrap_or(parent_style.font_size);
+
+        style.width = props.get("width")
+                           .and_then(|value| parse_width(value, style.font_size, self.font_size, parent_style.width, self.dpi))
+                           .unwrap_or(0);
 
-                style.width = props.get("width")
-                                   .and_then(|value| parse_width(value, style.font_size, self.font_size, parent_style.width, self.dpi))
-                                   .unwrap_or(0);
+        style.height = props.get("height")
+                            .and_then(|value| parse_height(value, style.font_size, self.font_size, parent_style.width, self.dpi))
+                            .unwrap_or(0);
+
+        style.font_kind = props.get("font-family")
+                               .and_then(|value| self.font_kind(value))
+                               .unwrap_or(parent_style.font_kind);
+
+        style.color = props.get("color")
+                           .and_then(|value| parse_color(value))
+                           .unwrap_or(parent_style.color);
+
+        style.letter_spacing = props.get("letter-spacing")
+                                    .and_then(|value| parse_letter_spacing(value, style.font_size, self.font_size, self.dpi))
+                                    .unwrap_or(parent_style.letter_spacing);
+
+        style.word_spacing = props.get("word-spacing")
+                                    .and_then(|value| parse_word_spacing(value, style.font_size, self.font_size, self.dpi))
+                                    .unwrap_or(parent_style.word_spacing);
+
+        style.vertical_align = props.get("vertical-align")
+                                    .and_then(|value| parse_vertical_align(value, style.font_size, self.font_size, style.line_height, self.dpi))
+                                    .unwrap_or(parent_style.vertical_align);
+
+        style.font_style = props.get("font-style")
+                                .and_then(|value| parse_font_style(value))
+                                .unwrap_or(parent_style.font_style);
 
-                style.height = props.get("height")
-                                    .and_then(|value| parse_height(value, style.font_size, self.font_size, parent_style.width, self.dpi))
-                                    .unwrap_or(0);
+        style.font_weight = props.get("font-weight")
+                                .and_then(|value| parse_font_weight(value))
+                                .unwrap_or(parent_style.font_weight);
 
-                style.font_kind = props.get("font-family")
-                                       .and_then(|value| parse_font_kind(value))
-                                       .unwrap_or(parent_style.font_kind);
+        style.font_features = props.get("font-feature-settings")
+                                   .map(|value| parse_font_features(value))
+                                   .or_else(|| parent_style.font_features.clone());
 
-                style.color = props.get("color")
-                                   .and_then(|value| parse_color(value))
-                                   .unwrap_or(parent_style.color);
+        style.direction = props.get("direction")
+                               .map(String::as_str)
+                               .or_else(|| node.attribute("dir"))
+                               .and_then(parse_direction)
+                               .unwrap_or(parent_style.direction);
 
-                style.letter_spacing = props.get("letter-spacing")
-                                            .and_then(|value| parse_letter_spacing(value, style.font_size, self.font_size, self.dpi))
-                                            .unwrap_or(parent_style.letter_spacing);
+        style.small_caps = parent_style.small_caps;
 
-                style.word_spacing = props.get("word-spacing")
-                                            .and_then(|value| parse_word_spacing(value, style.font_size, self.font_size, self.dpi))
-                                            .unwrap_or(parent_style.word_spacing);
+        if let Some(value) = props.get("font-variant") {
+            let mut features = parse_font_variant(value);
+            style.font_features.get_or_insert_with(Vec::new).append(&mut features);
+            style.small_caps = value.split_whitespace().any(|name| name == "small-caps");
+        }
 
-                style.vertical_align = props.get("vertical-align")
-                                            .and_then(|value| parse_vertical_align(value, style.font_size, self.font_size, style.line_height, self.dpi))
-                                            .unwrap_or(parent_style.vertical_align);
+        style.text_transform = props.get("text-transform")
+                                    .and_then(|value| parse_text_transform(value))
+                                    .unwrap_or(parent_style.text_transform);
 
-                style.font_style = props.get("font-style")
-                                        .and_then(|value| parse_font_style(value))
-                                        .unwrap_or(parent_style.font_style);
+        style.no_wrap = props.get("white-space")
+                             .and_then(|value| parse_white_space(value))
+                             .map_or(parent_style.no_wrap, |value| value == WhiteSpace::NoWrap);
 
-                style.font_weight = props.get("font-weight")
-                                        .and_then(|value| parse_font_weight(value))
-                                        .unwrap_or(parent_style.font_weight);
+        style
+    }
 
-                style.font_features = props.get("font-feature-settings")
-                                           .map(|value| parse_font_features(value))
-                                           .or_else(|| parent_style.font_features.clone());
+    fn gather_inline_material(&self, node: NodeRef, stylesheet: &StyleSheet, parent_style: &StyleData, spine_dir: &PathBuf, markers: &mut Vec<usize>, inlines: &mut Vec<InlineMaterial>) {
+        match node.data() {
+            NodeData::Element(ElementData { offset, name, attributes, .. }) => {
+                let props = specified_values(node, stylesheet);
+                let mut style = self.inline_style(node, &props, parent_style);
 
-                if let Some(value) = props.get("font-variant") {
-                    let mut features = parse_font_variant(value);
-                    if let Some(v) = style.font_features.as_mut() {
-                        v.append(&mut features);
-                    }
+                if style.display == Display::None {
+                    return;
                 }
 
                 if node.id().is_some() {
@@ -657,15 +1135,19 @@ impl Engine {
                 }
 
                 match name.as_ref() {
-                    "img" | "image" => {
-                        let attr = if name == "img" { "src" } else { "xlink:href" };
-
-                        let path = attributes.get(attr).and_then(|src| {
-                            spine_dir.join(src).normalize().to_str()
-                                     .map(|uri| percent_decode_str(&decode_entities(uri))
-                                                                  .decode_utf8_lossy()
-                                                                  .into_owned())
-                        }).unwrap_or_default();
+                    "img" | "image" | "svg" => {
+                        let (path, svg) = if name == "svg" {
+                            (spine_dir.to_string_lossy().into_owned(), Some(Arc::from(inline_svg(node))))
+                        } else {
+                            let attr = if name == "img" { "src" } else { "xlink:href" };
+                            let path = attributes.get(attr).and_then(|src| {
+                                spine_dir.join(src).normalize().to_str()
+                                         .map(|uri| percent_decode_str(&decode_entities(uri))
+                                                                      .decode_utf8_lossy()
+                                                                      .into_owned())
+                            }).unwrap_or_default();
+                            (path, None)
+                        };
 
                         style.float = props.get("float").and_then(|value| parse_float(value));
 
@@ -683,6 +1165,24 @@ impl Engine {
                         inlines.push(InlineMaterial::Image(ImageMaterial {
                             offset: *offset,
                             path,
+                            svg,
+                            style,
+                        }));
+                        if is_block {
+                            inlines.push(InlineMaterial::LineBreak);
+                        }
+                        return;
+                    },
+                    "math" => {
+                        let is_block = style.display == Display::Block ||
+                                       attributes.get("display").map(String::as_str) == Some("block");
+                        if is_block {
+                            style.display = Display::Block;
+                            inlines.push(InlineMaterial::LineBreak);
+                        }
+                        inlines.push(InlineMaterial::Math(MathMaterial {
+                            offset: *offset,
+                            math: parse_math(node),
                             style,
                         }));
                         if is_block {
@@ -690,6 +1190,34 @@ impl Engine {
                         }
                         return;
                     },
+                    "ruby" if !self.hide_ruby => {
+                        for (offset, text, annotation) in ruby_pairs(node) {
+                            if let Some(annotation) = annotation {
+                                let props = specified_values(annotation, stylesheet);
+                                let annotation_style = self.inline_style(annotation, &props, &style);
+                                inlines.push(InlineMaterial::Ruby(RubyMaterial {
+                                    offset,
+                                    text: transform_text(&text, style.text_transform),
+                                    annotation: TextMaterial {
+                                        offset: annotation.offset(),
+                                        text: collapse_whitespace(&decode_entities(&annotation.text())),
+                                        style: annotation_style,
+                                    },
+                                    style: style.clone(),
+                                }));
+                            } else {
+                                inlines.push(InlineMaterial::Text(TextMaterial {
+                                    offset,
+                                    text: transform_text(&text, style.text_transform),
+                                    style: style.clone(),
+                                }));
+                            }
+                        }
+                        return;
+                    },
+                    "rt" | "rp" | "rtc" if self.hide_ruby => {
+                        return;
+                    },
                     "a" => {
                         style.uri = attributes.get("href")
                                               .map(|uri| percent_decode_str(&decode_entities(uri))
@@ -707,10 +1235,14 @@ impl Engine {
                     inlines.append(&mut v);
                 }
 
+                inlines.extend(self.generated_content(node, stylesheet, &style, PseudoElement::Before));
+
                 for child in node.children() {
                     self.gather_inline_material(child, stylesheet, &style, spine_dir, markers, inlines);
                 }
 
+                inlines.extend(self.generated_content(node, stylesheet, &style, PseudoElement::After));
+
                 if let Some(mut v) = props.get("-plato-insert-after")
                                           .map(|value| parse_inline_material(value, style.font_size, self.font_size, self.dpi)) {
                     inlines.append(&mut v);
@@ -719,7 +1251,7 @@ impl Engine {
             NodeData::Text(TextData { offset, text }) => {
                 inlines.push(InlineMaterial::Text(TextMaterial {
                     offset: *offset,
-                    text: decode_entities(text).into_owned(),
+                    text: transform_text(&decode_entities(text), parent_style.text_transform),
                     style: parent_style.clone(),
                 }));
             },
@@ -734,7 +1266,136 @@ impl Engine {
         }
     }
 
-    fn make_paragraph_items(&mut self, inlines: &[InlineMaterial], parent_style: &StyleData, line_width: i32, resource_fetcher: &mut dyn ResourceFetcher) -> (Vec<ParagraphItem<ParagraphElement>>, Vec<ImageElement>) {
+    // Returns the text of the `::before` or `::after` pseudo-element of `node`.
+    fn generated_content(&self, node: NodeRef, stylesheet: &StyleSheet, style: &StyleData, pseudo_element: PseudoElement) -> Option<InlineMaterial> {
+        if !stylesheet.has_pseudo_element(pseudo_element) {
+            return None;
+        }
+        let props = pseudo_element_values(node, stylesheet, pseudo_element);
+        let text = props.get("content").and_then(|value| parse_content(value, node))?;
+        let style = self.inline_style(node, &props, style);
+        if style.display == Display::None {
+            return None;
+        }
+        Some(InlineMaterial::Text(TextMaterial {
+            offset: node.offset(),
+            text: transform_text(&text, style.text_transform),
+            style,
+        }))
+    }
+
+    // Gives the style of the `::first-letter` pseudo-element to the first letter of the paragraph.
+    fn apply_first_letter(&self, node: NodeRef, stylesheet: &StyleSheet, inlines: &mut Vec<InlineMaterial>) {
+        if !stylesheet.has_pseudo_element(PseudoElement::FirstLetter) {
+            return;
+        }
+        let props = pseudo_element_values(node, stylesheet, PseudoElement::FirstLetter);
+        if props.is_empty() {
+            return;
+        }
+        let Some(index) = inlines.iter().position(|mater| {
+            mater.text().is_some_and(|text| text.chars().any(|c| !c.is_xml_whitespace()))
+        }) else {
+            return;
+        };
+        let InlineMaterial::Text(TextMaterial { offset, text, style }) = inlines.remove(index) else {
+            return;
+        };
+        let is_punctuation = |c: &char| c.is_ascii_punctuation() ||
+                                        matches!(*c, '“' | '”' | '‘' | '’' | '«' | '»' | '„' | '‚' | '¡' | '¿');
+        let start = text.len() - text.trim_start_matches(|c: char| c.is_xml_whitespace()).len();
+        let mut chars = text[start..].chars().peekable();
+        let mut end = start;
+        while let Some(c) = chars.next_if(is_punctuation) {
+            end += c.len_utf8();
+        }
+        end += chars.next().map_or(0, char::len_utf8);
+        while let Some(c) = chars.next_if(is_punctuation) {
+            end += c.len_utf8();
+        }
+
+        let mut letter_style = self.inline_style(node, &props, &style);
+        letter_style.float = props.get("float").and_then(|value| parse_float(value));
+        if letter_style.float.is_some() {
+            letter_style.margin = parse_edge(props.get("margin-top").map(String::as_str),
+                                             props.get("margin-right").map(String::as_str),
+                                             props.get("margin-bottom").map(String::as_str),
+                                             props.get("margin-left").map(String::as_str),
+                                             letter_style.font_size, self.font_size, style.width, self.dpi);
+        }
+
+        let mut materials = Vec::with_capacity(3);
+        if start > 0 {
+            materials.push(InlineMaterial::Text(TextMaterial {
+                offset,
+                text: text[..start].to_string(),
+                style: style.clone(),
+            }));
+        }
+        materials.push(InlineMaterial::Text(TextMaterial {
+            offset: offset + start,
+            text: transform_text(&text[start..end], letter_style.text_transform),
+            style: letter_style,
+        }));
+        if end < text.len() {
+            materials.push(InlineMaterial::Text(TextMaterial {
+                offset: offset + end,
+                text: text[end..].to_string(),
+                style,
+            }));
+        }
+        inlines.splice(index..index, materials);
+    }
+
+    // Imitates the small capitals with reduced capitals when the font doesn't provide them.
+    fn synthesize_small_caps<'i>(&mut self, inlines: &'i [InlineMaterial]) -> Cow<'i, [InlineMaterial]> {
+        if !inlines.iter().any(|mater| matches!(mater, InlineMaterial::Text(TextMaterial { style, .. }) if style.small_caps)) {
+            return Cow::Borrowed(inlines);
+        }
+
+        let mut result = Vec::with_capacity(inlines.len());
+
+        for mater in inlines {
+            match mater {
+                InlineMaterial::Text(TextMaterial { offset, text, style }) if style.small_caps => {
+                    let font = self.fonts.as_mut().unwrap()
+                                   .get_mut(style.font_kind, style.font_style, style.font_weight);
+                    font.set_size((style.font_size * 64.0) as u32, self.dpi);
+                    if font.has_feature("smcp", "abcdefghijklmnopqrstuvwxyz") {
+                        result.push(mater.clone());
+                        continue;
+                    }
+                    let (lowercase, uppercase) = font.x_heights;
+                    let ratio = if lowercase > 0 && uppercase > lowercase {
+                        lowercase as f32 / uppercase as f32
+                    } else {
+                        0.75
+                    };
+                    let mut start = 0;
+                    while start < text.len() {
+                        let is_lowercase = text[start..].chars().next().is_some_and(char::is_lowercase);
+                        let end = text[start..].find(|c: char| c.is_lowercase() != is_lowercase)
+                                               .map_or(text.len(), |index| start + index);
+                        let mut style = style.clone();
+                        style.small_caps = false;
+                        let text = if is_lowercase {
+                            style.font_size *= ratio;
+                            text[start..end].to_uppercase()
+                        } else {
+                            text[start..end].to_string()
+                        };
+                        result.push(InlineMaterial::Text(TextMaterial { offset: offset + start, text, style }));
+                        start = end;
+                    }
+                },
+                _ => result.push(mater.clone()),
+            }
+        }
+
+        Cow::Owned(result)
+    }
+
+    fn make_paragraph_items(&mut self, inlines: &[InlineMaterial], parent_style: &StyleData, line_width: i32, resource_fetcher: &mut dyn ResourceFetcher) -> (Vec<ParagraphItem<ParagraphElement>>, Vec<FloatElement>) {
         let mut items = Vec::new();
         let mut floats = Vec::new();
         let big_stretch = 3 * {
@@ -752,26 +1413,32 @@ impl Engine {
             items.push(ParagraphItem::Glue { width: 0, stretch: big_stretch, shrink: 0 });
         }
 
+        let base_level = parent_style.direction.bidi_level();
+        let bidi_levels = paragraph_bidi_levels(inlines, parent_style.direction);
+
         for (index, mater) in inlines.iter().enumerate() {
             match mater {
-                InlineMaterial::Image(ImageMaterial { offset, path, style }) => {
+                InlineMaterial::Image(ImageMaterial { offset, path, svg, style }) => {
                     let (mut width, mut height) = (style.width, style.height);
                     let mut scale = 1.0;
                     let dpi = self.dpi;
 
-                    if let Ok(buf) = resource_fetcher.fetch(path) {
-                        if let Some(doc) = PdfOpener::new().and_then(|opener| opener.open_memory(path, &buf)) {
-                            if let Some((w, h)) = doc.dims(0) {
-                                if width == 0 && height == 0 {
-                                    width = pt_to_px(w, dpi).round() as i32;
-                                    height = pt_to_px(h, dpi).round() as i32;
-                                } else if width != 0 {
-                                    height = (width as f32 * h / w).round() as i32;
-                                } else if height != 0 {
-                                    width = (height as f32 * w / h).round() as i32;
-                                }
-                                scale = width as f32 / w;
+                    if let Some(doc) = self.open_image(path, svg.as_deref(), resource_fetcher) {
+                        if let Some((w, h)) = doc.dims() {
+                            if width == 0 && height == 0 {
+                                width = pt_to_px(w, dpi).round() as i32;
+                                height = pt_to_px(h, dpi).round() as i32;
+                            } else if width != 0 {
+                                height = (width as f32 * h / w).round() as i32;
+                            } else if height != 0 {
+                                width = (height as f32 * w / h).round() as i32;
                             }
+                            scale = width as f32 / w;
+                        }
+
+                        // Images are upright on vertical pages.
+                        if style.writing_mode.is_vertical() {
+                            std::mem::swap(&mut width, &mut height);
                         }
 
                         if width * height > 0 {
@@ -785,6 +1452,7 @@ impl Engine {
                                     margin: style.margin,
                                     float: style.float,
                                     path: path.clone(),
+                                    svg: svg.clone(),
                                     uri: style.uri.clone(),
                             };
                             if style.float.is_none() {
@@ -793,11 +1461,60 @@ impl Engine {
                                     data: ParagraphElement::Image(element),
                                 });
                             } else {
-                                floats.push(element);
+                                floats.push(FloatElement::Image(element));
                             }
                         }
                     }
                 },
+                InlineMaterial::Math(MathMaterial { offset, math, style }) => {
+                    let is_block = style.display == Display::Block;
+                    let mut math = MathLayout::new(self.fonts.as_mut().unwrap(), style.font_kind,
+                                                   style.font_size, self.dpi).layout(math, is_block);
+                    // Displayed formulas are centered.
+                    if is_block && math.width < line_width {
+                        math.translate((line_width - math.width) / 2, 0);
+                        math.width = line_width;
+                    }
+                    items.push(ParagraphItem::Box {
+                        width: math.width,
+                        data: ParagraphElement::Math(MathElement {
+                            offset: *offset,
+                            math,
+                            font_kind: style.font_kind,
+                            vertical_align: style.vertical_align,
+                            color: style.color,
+                            uri: style.uri.clone(),
+                        }),
+                    });
+                },
+                InlineMaterial::Ruby(RubyMaterial { offset, text, annotation, style }) => {
+                    let base = self.make_text_element(*offset, text, style);
+                    let (base_ascender, base_descender) = self.font_extents(style);
+                    let annotation_element = self.make_text_element(annotation.offset, &annotation.text, &annotation.style);
+                    let (annotation_ascender, annotation_descender) = self.font_extents(&annotation.style);
+                    let width = base.plan.width.max(annotation_element.plan.width);
+                    // The descender of the annotation rests on the ascender of the base.
+                    let rise = base_ascender - annotation_descender;
+                    let x = (width - annotation_element.plan.width) / 2;
+                    items.push(ParagraphItem::Box {
+                        width,
+                        data: ParagraphElement::Ruby(RubyElement {
+                            offset: *offset,
+                            base_position: pt!((width - base.plan.width) / 2, 0),
+                            annotation_position: pt!(x, -rise),
+                            annotation_rect: rect![pt!(x, -rise - annotation_ascender),
+                                                   pt!(x + annotation_element.plan.width, -base_ascender)],
+                            ascent: rise + annotation_ascender,
+                            descent: -base_descender,
+                            vertical_align: style.vertical_align,
+                            base: Box::new(base),
+                            annotation: Box::new(annotation_element),
+                        }),
+                    });
+                },
+                InlineMaterial::Text(TextMaterial { offset, text, style }) if style.float.is_some() => {
+                    floats.push(FloatElement::Text(self.make_floating_text(*offset, text, style)));
+                },
                 InlineMaterial::Text(TextMaterial { offset, text, style }) => {
                     let font_size = (style.font_size * 64.0) as u32;
                     let space_plan = {
@@ -814,39 +1531,55 @@ impl Engine {
                             if let Some((i, c)) = chunk.char_indices().next_back() {
                                 let j = i + if c.is_whitespace() { 0 } else { c.len_utf8() };
                                 if j > 0 {
-                                    let buf = &text[start_index..start_index+j];
-                                    let local_offset = offset + start_index;
-                                    let mut plan = {
-                                        let font = self.fonts.as_mut().unwrap()
-                                                       .get_mut(style.font_kind,
-                                                                style.font_style,
-                                                                style.font_weight);
-                                        font.set_size(font_size, self.dpi);
-                                        font.plan(buf, None, style.font_features.as_deref())
-                                    };
-                                    plan.space_out(style.letter_spacing);
-
-                                    items.push(ParagraphItem::Box {
-                                        width: plan.width,
-                                        data: ParagraphElement::Text(TextElement {
-                                            offset: local_offset,
-                                            language: style.language.clone(),
-                                            text: buf.to_string(),
-                                            plan,
-                                            font_features: style.font_features.clone(),
-                                            font_kind: style.font_kind,
-                                            font_style: style.font_style,
-                                            font_weight: style.font_weight,
-                                            vertical_align: style.vertical_align,
-                                            letter_spacing: style.letter_spacing,
-                                            font_size,
-                                            color: style.color,
-                                            uri: style.uri.clone(),
-                                        }),
-                                    });
+                                    for (run_start, run_end, bidi_level) in level_runs(&bidi_levels[index], start_index, start_index+j, base_level) {
+                                        for (orientation_start, orientation_end, orientation) in orientation_runs(&text[run_start..run_end], style.writing_mode) {
+                                            let buf = &text[run_start+orientation_start..run_start+orientation_end];
+                                            let local_offset = offset + run_start + orientation_start;
+                                            let font_features = if orientation == GlyphOrientation::Upright {
+                                                let mut features = style.font_features.clone().unwrap_or_default();
+                                                features.push("vert".to_string());
+                                                Some(features)
+                                            } else {
+                                                style.font_features.clone()
+                                            };
+                                            let mut plan = {
+                                                let font = self.fonts.as_mut().unwrap()
+                                                               .get_mut(style.font_kind,
+                                                                        style.font_style,
+                                                                        style.font_weight);
+                                                font.set_size(font_size, self.dpi);
+                                                font.plan(buf, None, font_features.as_deref())
+                                            };
+                                            plan.space_out(style.letter_spacing);
+                                            if bidi_level % 2 == 1 {
+                                                plan.reverse();
+                                            }
+
+                                            items.push(ParagraphItem::Box {
+                                                width: plan.width,
+                                                data: ParagraphElement::Text(TextElement {
+                                                    offset: local_offset,
+                                                    language: style.language.clone(),
+                                                    text: buf.to_string(),
+                                                    plan,
+                                                    font_features,
+                                                    font_kind: style.font_kind,
+                                                    font_style: style.font_style,
+                                                    font_weight: style.font_weight,
+                                                    vertical_align: style.vertical_align,
+                                                    letter_spacing: style.letter_spacing,
+                                                    font_size,
+                                                    color: style.color,
+                                                    uri: style.uri.clone(),
+                                                    bidi_level,
+                                                    orientation,
+                                                }),
+                                            });
+                                        }
+                                    }
                                 }
                                 if c.is_whitespace() {
-                                    if c == '\n' && parent_style.retain_whitespace {
+                                    if c == '\n' && parent_style.retain_newlines {
                                         let stretch = if parent_style.text_align == TextAlign::Center { big_stretch } else { line_width };
 
                                         items.push(ParagraphItem::Penalty { penalty: INFINITE_PENALTY, width: 0, flagged: false });
@@ -899,7 +1632,7 @@ impl Engine {
                                         WordSpacing::Ratio(r) => (r * width as f32) as i32,
                                     } + style.letter_spacing;
 
-                                    let is_unbreakable = c == '\u{00A0}' || c == '\u{202F}' || c == '\u{2007}';
+                                    let is_unbreakable = c == '\u{00A0}' || c == '\u{202F}' || c == '\u{2007}' || style.no_wrap;
 
                                     if (is_unbreakable || (parent_style.retain_whitespace && c.is_xml_whitespace())) &&
                                        (last_c == Some('\n') || last_c.is_none()) {
@@ -938,7 +1671,7 @@ impl Engine {
                                             }
                                         },
                                     }
-                                } else if end_index < text.len() {
+                                } else if end_index < text.len() && !style.no_wrap {
                                     let penalty = if c == '-' { self.hyphen_penalty } else { 0 };
                                     let flagged = penalty > 0;
                                     if matches!(parent_style.text_align, TextAlign::Justify | TextAlign::Center) {
@@ -994,17 +1727,106 @@ impl Engine {
         (items, floats)
     }
 
+    fn make_text_element(&mut self, offset: usize, text: &str, style: &StyleData) -> TextElement {
+        let font_size = (style.font_size * 64.0) as u32;
+        let orientation = GlyphOrientation::from_text(text, style.writing_mode);
+        let font_features = if orientation == GlyphOrientation::Upright {
+            let mut features = style.font_features.clone().unwrap_or_default();
+            features.push("vert".to_string());
+            Some(features)
+        } else {
+            style.font_features.clone()
+        };
+        let font = self.fonts.as_mut().unwrap()
+                       .get_mut(style.font_kind, style.font_style, style.font_weight);
+        font.set_size(font_size, self.dpi);
+        let mut plan = font.plan(text, None, font_features.as_deref());
+        plan.space_out(style.letter_spacing);
+        TextElement {
+            offset,
+            language: style.language.clone(),
+            text: text.to_string(),
+            plan,
+            font_features,
+            font_kind: style.font_kind,
+            font_style: style.font_style,
+            font_weight: style.font_weight,
+            font_size,
+            letter_spacing: style.letter_spacing,
+            vertical_align: style.vertical_align,
+            color: style.color,
+            uri: style.uri.clone(),
+            bidi_level: style.direction.bidi_level(),
+            orientation,
+        }
+    }
+
+    // Returns the ascender and the descender of the font of `style`.
+    fn font_extents(&mut self, style: &StyleData) -> (i32, i32) {
+        let font = self.fonts.as_mut().unwrap()
+                       .get_mut(style.font_kind, style.font_style, style.font_weight);
+        font.set_size((style.font_size * 64.0) as u32, self.dpi);
+        (font.ascender(), font.descender())
+    }
+
+    fn make_floating_text(&mut self, offset: usize, text: &str, style: &StyleData) -> FloatingText {
+        let font_size = (style.font_size * 64.0) as u32;
+        let font = self.fonts.as_mut().unwrap()
+                       .get_mut(style.font_kind, style.font_style, style.font_weight);
+        font.set_size(font_size, self.dpi);
+        let plan = font.plan(text, None, style.font_features.as_deref());
+        // The top of the letter is aligned with the top of the first line.
+        let (ascent, descent) = text.chars().map(|c| font.vertical_extents(c))
+                                    .fold((0, 0), |(a, d), (top, bottom)| (a.max(top), d.max(bottom)));
+        FloatingText {
+            element: TextElement {
+                offset,
+                language: style.language.clone(),
+                text: text.to_string(),
+                plan,
+                font_features: style.font_features.clone(),
+                font_kind: style.font_kind,
+                font_style: style.font_style,
+                font_weight: style.font_weight,
+                font_size,
+                letter_spacing: style.letter_spacing,
+                vertical_align: style.vertical_align,
+                color: style.color,
+                uri: style.uri.clone(),
+                bidi_level: style.direction.bidi_level(),
+                orientation: GlyphOrientation::Horizontal,
+            },
+            ascent,
+            descent,
+            margin: style.margin,
+            float: style.float.unwrap_or(Float::Left),
+        }
+    }
+
     fn place_paragraphs(&mut self, inlines: &[InlineMaterial], style: &StyleData, root_data: &RootData, markers: &[usize], resource_fetcher: &mut dyn ResourceFetcher, draw_state: &mut DrawState, rects: &mut Vec<Option<Rectangle>>, display_list: &mut Vec<Page>) {
         let line_width = style.end_x - style.start_x;
+        let inlines = self.synthesize_small_caps(inlines);
+        let inlines = &*inlines;
         let (mut items, floats) = self.make_paragraph_items(inlines, style, line_width, resource_fetcher);
 
         if items.is_empty() {
             return;
         }
 
+        // Right-to-left lines are laid out from left to right and mirrored afterwards.
+        let base_level = style.direction.bidi_level();
+        let text_align = if style.direction == Direction::Rtl {
+            style.text_align.mirror()
+        } else {
+            style.text_align
+        };
+        let is_bidi = base_level > 0 || items.iter().any(|itm| {
+            matches!(itm, ParagraphItem::Box { data: ParagraphElement::Text(TextElement { bidi_level, .. }), .. } if *bidi_level > 0)
+        });
+
         let position = &mut draw_state.position;
 
-        let text_indent = if style.text_align == TextAlign::Center {
+        let text_indent = if text_align == TextAlign::Center {
             0
         } else {
             style.text_indent
@@ -1035,21 +1857,25 @@ impl Engine {
         let page_index = display_list.len();
 
         for mut element in floats.into_iter() {
-            let horiz_margin = element.margin.left + element.margin.right;
-            let vert_margin = element.margin.top + element.margin.bottom;
-            let mut width = element.width;
-            let mut height = element.height;
+            let (margin, float, mut width, mut height) = match element {
+                FloatElement::Image(ref element) => (element.margin, element.float, element.width, element.height),
+                FloatElement::Text(ref text) => (text.margin, Some(text.float), text.element.plan.width, text.ascent + text.descent),
+            };
+            let horiz_margin = margin.left + margin.right;
+            let vert_margin = margin.top + margin.bottom;
 
             let max_width = line_width / 3;
-            if width + horiz_margin > max_width {
-                let ratio = (max_width - horiz_margin) as f32 / width as f32;
-                element.scale *= ratio;
-                width = max_width - horiz_margin;
-                height = (ratio * height as f32).round() as i32;
+            if let FloatElement::Image(ref mut element) = element {
+                if width + horiz_margin > max_width {
+                    let ratio = (max_width - horiz_margin) as f32 / width as f32;
+                    element.scale *= ratio;
+                    width = max_width - horiz_margin;
+                    height = (ratio * height as f32).round() as i32;
+                }
             }
 
             let mut y_min = position.y - space_top;
-            let side = if element.float == Some(Float::Left) { 0 } else { 1 };
+            let side = if float == Some(Float::Left) { 0 } else { 1 };
 
             if let Some(ref mut floating_rects) = draw_state.floats.get_mut(&page_index) {
                 if let Some(orect) = floating_rects.iter().rev()
@@ -1060,15 +1886,17 @@ impl Engine {
             }
 
             let max_height = 2 * (root_data.rect.max.y - space_bottom - y_min) / 3;
-            if height + vert_margin > max_height {
-                let ratio = (max_height - vert_margin) as f32 / height as f32;
-                element.scale *= ratio;
-                height = max_height - vert_margin;
-                width = (ratio * width as f32).round() as i32;
+            if let FloatElement::Image(ref mut element) = element {
+                if height + vert_margin > max_height {
+                    let ratio = (max_height - vert_margin) as f32 / height as f32;
+                    element.scale *= ratio;
+                    height = max_height - vert_margin;
+                    width = (ratio * width as f32).round() as i32;
+                }
             }
 
             if width > 0 && height > 0 {
-                let mut rect = if element.float == Some(Float::Left) {
+                let mut rect = if float == Some(Float::Left) {
                     rect![style.start_x, y_min,
                           style.start_x + width + horiz_margin,
                           y_min + height + vert_margin]
@@ -1080,15 +1908,36 @@ impl Engine {
                 let floating_rects = draw_state.floats.entry(page_index).or_default();
                 floating_rects.push(rect);
 
-                rect.shrink(&element.margin);
-                page.push(DrawCommand::Image(ImageCommand {
-                    offset: element.offset + root_data.start_offset,
-                    position: rect.min,
-                    rect,
-                    scale: element.scale,
-                    path: element.path,
-                    uri: element.uri,
-                }));
+                rect.shrink(&margin);
+                match element {
+                    FloatElement::Image(element) => {
+                        page.push(DrawCommand::Image(ImageCommand {
+                            offset: element.offset + root_data.start_offset,
+                            position: rect.min,
+                            rect,
+                            scale: element.scale,
+                            path: element.path,
+                            svg: element.svg,
+                            uri: element.uri,
+                        }));
+                    },
+                    FloatElement::Text(FloatingText { element, ascent, .. }) => {
+                        page.push(DrawCommand::Text(TextCommand {
+                            offset: element.offset + root_data.start_offset,
+                            position: pt!(rect.min.x, rect.min.y + ascent),
+                            rect,
+                            text: element.text,
+                            plan: element.plan,
+                            uri: element.uri,
+                            font_kind: element.font_kind,
+                            font_style: element.font_style,
+                            font_weight: element.font_weight,
+                            font_size: element.font_size,
+                            color: element.color,
+                            orientation: element.orientation,
+                        }));
+                    },
+                }
             }
         }
 
@@ -1124,7 +1973,7 @@ impl Engine {
         let mut hyph_indices = Vec::new();
         let mut glue_drifts = Vec::new();
 
-        if bps.is_empty() && style.text_align != TextAlign::Center {
+        if bps.is_empty() && text_align != TextAlign::Center {
             if let Some(dictionary) = hyph_lang(style.language.as_ref().map_or(DEFAULT_HYPH_LANG, String::as_str))
                                                .and_then(|lang| HYPHENATION_PATTERNS.get(&lang)) {
                 items = self.hyphenate_paragraph(style, dictionary, items, &mut hyph_indices);
@@ -1184,8 +2033,12 @@ impl Engine {
                 font.set_size(font_size, self.dpi);
                 font.plan(prefix, None, style.font_features.as_deref())
             };
-            let (start_x, _) = para_shape[0];
-            let pt = pt!(start_x - prefix_plan.width, position.y);
+            let (start_x, end_x) = para_shape[0];
+            let pt = if style.direction == Direction::Rtl {
+                pt!(end_x, position.y)
+            } else {
+                pt!(start_x - prefix_plan.width, position.y)
+            };
             let rect = rect![pt + pt!(0, -ascender), pt + pt!(prefix_plan.width, -descender)];
             if let Some(first_offset) = inlines.iter().filter_map(|elt| elt.offset()).next() {
                 page.push(DrawCommand::ExtraText(TextCommand {
@@ -1194,6 +2047,7 @@ impl Engine {
                     rect,
                     text: prefix.to_string(),
                     plan: prefix_plan,
+                    orientation: GlyphOrientation::from_text(prefix, style.writing_mode),
                     uri: None,
                     font_kind: style.font_kind,
                     font_style: style.font_style,
@@ -1217,12 +2071,12 @@ impl Engine {
             let mut epsilon: f32 = 0.0;
             let current_text_indent = if is_first_line { text_indent } else { 0 };
 
-            match style.text_align {
+            match text_align {
                 TextAlign::Right => position.x = end_x - width - current_text_indent,
                 _ => position.x = start_x + current_text_indent,
             }
 
-            if style.text_align == TextAlign::Left || style.text_align == TextAlign::Right {
+            if text_align == TextAlign::Left || text_align == TextAlign::Right {
                 ratio = ratio.min(0.0);
             }
 
@@ -1230,7 +2084,9 @@ impl Engine {
                 last_index += 1;
             }
 
-            let start_command_index = page.len();
+            let mut start_command_index = page.len();
+            // The depth of the formulas below the descender of the line.
+            let mut extra_descent = 0;
 
             for i in last_index..index {
                 match items[i] {
@@ -1264,6 +2120,7 @@ impl Engine {
                                     font_weight: element.font_weight,
                                     font_size: element.font_size,
                                     color: element.color,
+                                    orientation: element.orientation,
                                 }));
                             },
                             ParagraphElement::Image(element) => {
@@ -1296,6 +2153,7 @@ impl Engine {
                                             display_list.push(page);
                                             position.y = root_data.rect.min.y;
                                             page = Vec::new();
+                                            start_command_index = 0;
                                             ratio = ((root_data.rect.max.y - position.y - space_bottom) as f32 / height as f32).min(1.0);
                                         }
                                         height = (height as f32 * ratio).round() as i32;
@@ -1315,9 +2173,9 @@ impl Engine {
                                             position.y -= style.line_height;
                                         }
                                     } else if width < element.width {
-                                        if style.text_align == TextAlign::Center {
+                                        if text_align == TextAlign::Center {
                                             position.x += (element.width - width) / 2;
-                                        } else if style.text_align == TextAlign::Right {
+                                        } else if text_align == TextAlign::Right {
                                             position.x += element.width - width;
                                         }
                                     }
@@ -1347,6 +2205,7 @@ impl Engine {
                                             pt.y = next_baseline - element.height - element.vertical_align;
                                             position.y = next_baseline;
                                             page = start_commands;
+                                            start_command_index = 0;
                                         } else {
                                             for dc in &mut page[start_command_index..] {
                                                 if let Some(pt) = dc.position_mut() {
@@ -1374,9 +2233,103 @@ impl Engine {
                                     rect,
                                     scale,
                                     path: element.path.clone(),
+                                    svg: element.svg.clone(),
                                     uri: element.uri.clone(),
                                 }));
                             },
+                            ParagraphElement::Math(MathElement { offset: element_offset, vertical_align,
+                                                                 math: MathBox { ascent, descent, .. }, .. }) |
+                            ParagraphElement::Ruby(RubyElement { offset: element_offset, vertical_align,
+                                                                 ascent, descent, .. }) => {
+                                while let Some(offset) = markers.get(markers_index) {
+                                    if offset < element_offset {
+                                        page.push(DrawCommand::Marker(root_data.start_offset + *offset));
+                                        markers_index += 1;
+                                    } else {
+                                        break;
+                                    }
+                                }
+
+                                let (ascent, descent) = (*ascent, *descent);
+                                let mut pt = pt!(position.x, position.y - vertical_align);
+
+                                // Move the line down when the formula or the annotation rises above it.
+                                let delta = (position.y - ascender) - (pt.y - ascent);
+                                if delta > 0 {
+                                    let y_max = root_data.rect.max.y - space_bottom;
+                                    if pt.y + delta + descent > y_max && start_command_index > 0 {
+                                        let mut start_commands = page.drain(start_command_index..).collect::<Vec<DrawCommand>>();
+                                        display_list.push(page);
+                                        let next_baseline = root_data.rect.min.y + space_top - ascender + ascent;
+                                        for dc in &mut start_commands {
+                                            dc.shift_vertically(next_baseline - position.y);
+                                        }
+                                        pt.y += next_baseline - position.y;
+                                        position.y = next_baseline;
+                                        page = start_commands;
+                                        start_command_index = 0;
+                                    } else {
+                                        for dc in &mut page[start_command_index..] {
+                                            dc.shift_vertically(delta);
+                                        }
+                                        pt.y += delta;
+                                        position.y += delta;
+                                    }
+                                }
+
+                                extra_descent = extra_descent.max(pt.y + descent - (position.y - descender));
+
+                                let rect = rect![pt + pt!(0, -ascent), pt + pt!(width, descent)];
+                                if let Some(pr) = page_rect.as_mut() {
+                                    pr.absorb(&rect);
+                                } else {
+                                    page_rect = Some(rect);
+                                }
+
+                                match data {
+                                    ParagraphElement::Ruby(element) => {
+                                        let origin = pt + element.base_position;
+                                        let rect = rect![origin + pt!(0, -ascender),
+                                                         origin + pt!(element.base.plan.width, -descender)];
+                                        page.push(DrawCommand::Text(text_command(&element.base, root_data.start_offset, origin, rect)));
+                                        // The annotations aren't part of the text.
+                                        let rect = rect![pt + element.annotation_rect.min, pt + element.annotation_rect.max];
+                                        page.push(DrawCommand::ExtraText(text_command(&element.annotation, root_data.start_offset,
+                                                                                      pt + element.annotation_position, rect)));
+                                    },
+                                    ParagraphElement::Math(element) => {
+                                        for item in &element.math.items {
+                                            match item {
+                                                MathItem::Glyphs(MathGlyphs { offset, text, plan, font_style, font_weight,
+                                                                              font_size, position: origin, rect }) => {
+                                                    page.push(DrawCommand::Text(TextCommand {
+                                                        offset: *offset + root_data.start_offset,
+                                                        position: pt + *origin,
+                                                        rect: rect![pt + rect.min, pt + rect.max],
+                                                        text: text.clone(),
+                                                        plan: plan.clone(),
+                                                        uri: element.uri.clone(),
+                                                        font_kind: element.font_kind,
+                                                        font_style: *font_style,
+                                                        font_weight: *font_weight,
+                                                        font_size: *font_size,
+                                                        color: element.color,
+                                                        orientation: GlyphOrientation::Horizontal,
+                                                    }));
+                                                },
+                                                MathItem::Rule(rect) => {
+                                                    page.push(DrawCommand::Fill(FillCommand {
+                                                        offset: element.offset + root_data.start_offset,
+                                                        rect: rect![pt + rect.min, pt + rect.max],
+                                                        color: element.color,
+                                                    }));
+                                                },
+                                            }
+                                        }
+                                    },
+                                    _ => (),
+                                }
+                            },
                             _ => (),
                         }
 
@@ -1425,8 +2378,20 @@ impl Engine {
                 }
             }
 
+            if is_bidi {
+                let levels: FxHashMap<usize, u8> = items[last_index..index].iter().filter_map(|itm| {
+                    if let ParagraphItem::Box { data: ParagraphElement::Text(element), .. } = itm {
+                        Some((element.offset + root_data.start_offset, element.bidi_level))
+                    } else {
+                        None
+                    }
+                }).collect();
+                reorder_line(&mut page[start_command_index..], &levels, base_level, start_x, end_x);
+            }
+
             last_index = index;
             is_first_line = false;
+            position.y += extra_descent;
 
             if index < items.len() - 1 {
                 position.y += style.line_height;
@@ -1472,6 +2437,9 @@ impl Engine {
             font.plan(chunk, None, element.font_features.as_deref())
         };
         plan.space_out(element.letter_spacing);
+        if element.bidi_level % 2 == 1 {
+            plan.reverse();
+        }
         ParagraphItem::Box {
             width: plan.width,
             data: ParagraphElement::Text(TextElement {
@@ -1488,6 +2456,8 @@ impl Engine {
                 letter_spacing: element.letter_spacing,
                 color: element.color,
                 uri: element.uri.clone(),
+                bidi_level: element.bidi_level,
+                orientation: element.orientation,
             }),
         }
     }
@@ -1581,8 +2551,8 @@ impl Engine {
             if i == bp.index {
                 let mut merged_width = 0;
 
-                if let ParagraphElement::Text(TextElement { ref text, ref mut plan, font_size, font_kind,
-                                                            font_style, font_weight, letter_spacing, ref font_features, .. }) = merged_element {
+                if let ParagraphElement::Text(TextElement { ref text, ref mut plan, font_size, font_kind, font_style,
+                                                            font_weight, letter_spacing, ref font_features, bidi_level, .. }) = merged_element {
                     *plan = {
                         let font = self.fonts.as_mut().unwrap()
                                        .get_mut(font_kind, font_style, font_weight);
@@ -1590,6 +2560,9 @@ impl Engine {
                         font.plan(text, None, font_features.as_ref().map(Vec::as_slice))
                     };
                     plan.space_out(letter_spacing);
+                    if bidi_level % 2 == 1 {
+                        plan.reverse();
+                    }
                     merged_width = plan.width;
                 }
 
@@ -1644,8 +2617,8 @@ impl Engine {
                         end_index = 0;
                     }
                     let mut merged_width = 0;
-                    if let ParagraphElement::Text(TextElement { ref text, ref mut plan, font_size, font_kind,
-                                                                font_style, font_weight, letter_spacing, ref font_features, .. }) = merged_element {
+                    if let ParagraphElement::Text(TextElement { ref text, ref mut plan, font_size, font_kind, font_style,
+                                                                font_weight, letter_spacing, ref font_features, bidi_level, .. }) = merged_element {
                         *plan = {
                             let font = self.fonts.as_mut().unwrap()
                                            .get_mut(font_kind, font_style, font_weight);
@@ -1653,6 +2626,9 @@ impl Engine {
                             font.plan(text, None, font_features.as_ref().map(Vec::as_slice))
                         };
                         plan.space_out(letter_spacing);
+                        if bidi_level % 2 == 1 {
+                            plan.reverse();
+                        }
                         merged_width = plan.width;
                     }
                     merged_items.push(ParagraphItem::Box { width: merged_width, data: merged_element });
@@ -1677,30 +2653,37 @@ impl Engine {
         let height = (self.dims.1 as f32 * scale_factor) as u32;
         let mut fb = Pixmap::try_new(width, height)?;
 
+        for dc in page {
+            if let DrawCommand::Fill(FillCommand { rect, color, .. }) = dc {
+                let rect = rect![Point::from(scale_factor * Vec2::from(rect.min)),
+                                 Point::from(scale_factor * Vec2::from(rect.max))];
+                fb.draw_rectangle(&rect, *color);
+            }
+        }
+
         for dc in page {
             match dc {
-                DrawCommand::Text(TextCommand { position, plan, font_kind,
-                                                font_style, font_weight, font_size, color, .. }) |
+                DrawCommand::Text(TextCommand { position, plan, font_kind, font_style,
+                                                font_weight, font_size, color, orientation, .. }) |
                 DrawCommand::ExtraText(TextCommand { position, plan, font_kind, font_style,
-                                                     font_weight, font_size, color, .. }) => {
+                                                     font_weight, font_size, color, orientation, .. }) => {
                     let font = self.fonts.as_mut().unwrap()
                                    .get_mut(*font_kind, *font_style, *font_weight);
                     let font_size = (scale_factor * *font_size as f32) as u32;
                     let position = Point::from(scale_factor * Vec2::from(*position));
                     let plan = plan.scale(scale_factor);
                     font.set_size(font_size, self.dpi);
-                    font.render(&mut fb, *color, &plan, position);
+                    match orientation {
+                        GlyphOrientation::Horizontal => font.render(&mut fb, *color, &plan, position),
+                        GlyphOrientation::Upright => font.render_vertical(&mut fb, *color, &plan, position, true),
+                        GlyphOrientation::Sideways => font.render_vertical(&mut fb, *color, &plan, position, false),
+                    }
                 },
-                DrawCommand::Image(ImageCommand { position, path, scale, .. }) => {
-                    if let Ok(buf) = resource_fetcher.fetch(path) {
-                        if let Some((pixmap, _)) = PdfOpener::new().and_then(|opener| {
-                            opener.open_memory(path, &buf)
-                        }).and_then(|mut doc| {
-                            doc.pixmap(Location::Exact(0), scale_factor * *scale)
-                        }) {
-                            let position = Point::from(scale_factor * Vec2::from(*position));
-                            fb.draw_pixmap(&pixmap, position);
-                        }
+                DrawCommand::Image(ImageCommand { position, path, svg, scale, .. }) => {
+                    if let Some(pixmap) = self.open_image(path, svg.as_deref(), resource_fetcher)
+                                              .and_then(|mut doc| doc.pixmap(scale_factor * *scale)) {
+                        let position = Point::from(scale_factor * Vec2::from(*position));
+                        fb.draw_pixmap(&pixmap, position);
                     }
                 },
                 _ => (),
@@ -1709,6 +2692,274 @@ impl Engine {
 
         Some(fb)
     }
+
+    // SVG images are only parsed once.
+    fn open_image(&mut self, path: &str, svg: Option<&str>, resource_fetcher: &mut dyn ResourceFetcher) -> Option<ImageDocument> {
+        let key = svg.unwrap_or(path);
+        if let Some(image) = self.svg_images.get(key) {
+            return Some(ImageDocument::Vector(Arc::clone(image)));
+        }
+        let doc = open_image(path, svg, resource_fetcher)?;
+        if let ImageDocument::Vector(ref image) = doc {
+            self.svg_images.insert(key.to_string(), Arc::clone(image));
+        }
+        Some(doc)
+    }
+}
+
+pub enum ImageDocument {
+    Raster(PdfDocument),
+    Vector(Arc<SvgImage>),
+}
+
+impl ImageDocument {
+    pub fn dims(&self) -> Option<(f32, f32)> {
+        match self {
+            ImageDocument::Raster(doc) => doc.dims(0),
+            ImageDocument::Vector(svg) => Some(svg.dims()),
+        }
+    }
+
+    pub fn pixmap(&mut self, scale: f32) -> Option<Pixmap> {
+        match self {
+            ImageDocument::Raster(doc) => doc.pixmap(Location::Exact(0), scale).map(|(pixmap, _)| pixmap),
+            ImageDocument::Vector(svg) => svg.pixmap(scale),
+        }
+    }
+}
+
+// Opens the image at `path` or, if given, the inline SVG image `svg`.
+pub fn open_image(path: &str, svg: Option<&str>, resource_fetcher: &mut dyn ResourceFetcher) -> Option<ImageDocument> {
+    if let Some(text) = svg {
+        return SvgImage::new(text.as_bytes(), Path::new(path), resource_fetcher)
+                        .map(|svg| ImageDocument::Vector(Arc::new(svg)));
+    }
+
+    let buf = resource_fetcher.fetch(path).ok()?;
+
+    if is_svg(path, &buf) {
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        SvgImage::new(&buf, dir, resource_fetcher)
+                .map(|svg| ImageDocument::Vector(Arc::new(svg)))
+    } else {
+        PdfOpener::new().and_then(|opener| opener.open_memory(path, &buf))
+                        .map(ImageDocument::Raster)
+    }
+}
+
+fn absorb_page_rect(rects: &mut Vec<Option<Rectangle>>, index: usize, rect: &Rectangle) {
+    if rects.len() <= index {
+        rects.resize(index + 1, None);
+    }
+    if let Some(page_rect) = rects[index].as_mut() {
+        page_rect.absorb(rect);
+    } else {
+        rects[index] = Some(*rect);
+    }
+}
+
+pub fn writing_mode(node: NodeRef, stylesheet: &StyleSheet) -> Option<WritingMode> {
+    let props = specified_values(node, stylesheet);
+    ["writing-mode", "-epub-writing-mode", "-webkit-writing-mode"].iter()
+        .filter_map(|name| props.get(*name))
+        .find_map(|value| parse_writing_mode(value))
+}
+
+// Returns the embedding levels of the bytes of each text material,
+// or nothing if the paragraph is entirely left-to-right.
+fn paragraph_bidi_levels(inlines: &[InlineMaterial], direction: Direction) -> Vec<Vec<u8>> {
+    let mut text = String::new();
+    let mut ranges = Vec::with_capacity(inlines.len());
+
+    for mater in inlines {
+        match mater {
+            InlineMaterial::Text(TextMaterial { text: content, style, .. }) => {
+                let isolate = style.direction != direction;
+                if isolate {
+                    text.push(if style.direction == Direction::Rtl { '\u{2067}' } else { '\u{2066}' });
+                }
+                let start = text.len();
+                text.push_str(content);
+                ranges.push(Some(start..text.len()));
+                if isolate {
+                    text.push('\u{2069}');
+                }
+            },
+            InlineMaterial::Image(..) | InlineMaterial::Math(..) | InlineMaterial::Ruby(..) => {
+                text.push('\u{FFFC}');
+                ranges.push(None);
+            },
+            _ => ranges.push(None),
+        }
+    }
+
+    let default_level = if direction == Direction::Rtl { Level::rtl() } else { Level::ltr() };
+    let bidi_info = BidiInfo::new(&text, Some(default_level));
+
+    if direction == Direction::Ltr && !bidi_info.has_rtl() {
+        return vec![Vec::new(); inlines.len()];
+    }
+
+    ranges.into_iter().map(|range| {
+        range.map(|r| bidi_info.levels[r].iter().map(|level| level.number()).collect())
+             .unwrap_or_default()
+    }).collect()
+}
+
+// Splits the given byte range into runs of identical embedding levels.
+fn level_runs(levels: &[u8], start: usize, end: usize, base_level: u8) -> Vec<(usize, usize, u8)> {
+    if levels.is_empty() {
+        return vec![(start, end, base_level)];
+    }
+
+    let mut runs = Vec::new();
+    let mut run_start = start;
+
+    for i in start+1..end {
+        if levels[i] != levels[run_start] {
+            runs.push((run_start, i, levels[run_start]));
+            run_start = i;
+        }
+    }
+
+    runs.push((run_start, end, levels[run_start]));
+    runs
+}
+
+// Mirrors the horizontal positions of the given commands within the interval [a, b].
+fn mirror_commands(commands: &mut [DrawCommand], indices: &[usize], a: i32, b: i32) {
+    for &i in indices {
+        if let Some(rect) = commands[i].rect() {
+            let dx = a + b - rect.max.x - rect.min.x;
+            commands[i].shift(dx);
+        }
+    }
+}
+
+// Applies the rule L2 of the Unicode bidirectional algorithm to a line of commands
+// laid out in logical order: the sequences of levels greater than or equal to a given
+// level are reversed, from the highest level to the lowest odd level.
+fn reorder_line(commands: &mut [DrawCommand], levels: &FxHashMap<usize, u8>, base_level: u8, start_x: i32, end_x: i32) {
+    let placed: Vec<(usize, u8)> = commands.iter().enumerate()
+                                           .filter(|(_, dc)| dc.rect().is_some())
+                                           .map(|(i, dc)| (i, levels.get(&dc.offset()).cloned().unwrap_or(base_level)))
+                                           .collect();
+    let max_level = placed.iter().map(|(_, l)| *l).max().unwrap_or(base_level);
+
+    for level in (base_level+1..=max_level).rev() {
+        let mut k = 0;
+        while k < placed.len() {
+            if placed[k].1 < level {
+                k += 1;
+                continue;
+            }
+            let start = k;
+            while k < placed.len() && placed[k].1 >= level {
+                k += 1;
+            }
+            let indices: Vec<usize> = placed[start..k].iter().map(|(i, _)| *i).collect();
+            let a = indices.iter().filter_map(|&i| commands[i].rect()).map(|r| r.min.x).min().unwrap();
+            let b = indices.iter().filter_map(|&i| commands[i].rect()).map(|r| r.max.x).max().unwrap();
+            mirror_commands(commands, &indices, a, b);
+        }
+    }
+
+    if base_level % 2 == 1 {
+        let indices: Vec<usize> = placed.iter().map(|(i, _)| *i).collect();
+        mirror_commands(commands, &indices, start_x, end_x);
+    }
+}
+
+fn transform_text(text: &str, text_transform: TextTransform) -> String {
+    match text_transform {
+        TextTransform::None => text.to_string(),
+        TextTransform::Uppercase => text.to_uppercase(),
+        TextTransform::Lowercase => text.to_lowercase(),
+        TextTransform::Capitalize => {
+            let mut result = String::with_capacity(text.len());
+            let mut is_word_start = true;
+            for c in text.chars() {
+                if is_word_start && c.is_alphabetic() {
+                    result.extend(c.to_uppercase());
+                } else {
+                    result.push(c);
+                }
+                is_word_start = !(c.is_alphanumeric() || c == '\'' || c == '’');
+            }
+            result
+        },
+    }
+}
+
+fn text_command(element: &TextElement, start_offset: usize, position: Point, rect: Rectangle) -> TextCommand {
+    TextCommand {
+        offset: element.offset + start_offset,
+        position,
+        rect,
+        text: element.text.clone(),
+        plan: element.plan.clone(),
+        uri: element.uri.clone(),
+        font_kind: element.font_kind,
+        font_style: element.font_style,
+        font_weight: element.font_weight,
+        font_size: element.font_size,
+        color: element.color,
+        orientation: element.orientation,
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split(|c: char| c.is_xml_whitespace())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+// Pairs the base texts of a ruby element with their annotations.
+// The bases without annotations are returned with `None`.
+fn ruby_pairs(node: NodeRef) -> Vec<(usize, String, Option<NodeRef>)> {
+    fn flush<'a>(bases: &mut Vec<(usize, String)>, annotations: &mut Vec<NodeRef<'a>>, pairs: &mut Vec<(usize, String, Option<NodeRef<'a>>)>) {
+        for (i, (offset, text)) in bases.drain(..).enumerate() {
+            pairs.push((offset, text, annotations.get(i).copied()));
+        }
+        annotations.clear();
+    }
+
+    let mut pairs = Vec::new();
+    let mut bases: Vec<(usize, String)> = Vec::new();
+    let mut annotations = Vec::new();
+    let mut is_open = false;
+
+    for child in node.children() {
+        match child.tag_name() {
+            Some("rt") => annotations.push(child),
+            Some("rtc") => {
+                let len = annotations.len();
+                annotations.extend(child.children().filter(|c| c.tag_name() == Some("rt")));
+                if annotations.len() == len {
+                    annotations.push(child);
+                }
+            },
+            Some("rp") => (),
+            name => {
+                let text = collapse_whitespace(&decode_entities(&child.text()));
+                if text.is_empty() {
+                    continue;
+                }
+                if !annotations.is_empty() {
+                    flush(&mut bases, &mut annotations, &mut pairs);
+                }
+                match bases.last_mut() {
+                    Some((_, base)) if is_open && name != Some("rb") => base.push_str(&text),
+                    _ => bases.push((child.offset(), text)),
+                }
+                is_open = name != Some("rb");
+            },
+        }
+    }
+
+    flush(&mut bases, &mut annotations, &mut pairs);
+    pairs
 }
 
 fn format_list_prefix(kind: ListStyleType, index: usize) -> Option<String> {
@@ -1760,8 +3011,30 @@ fn default_fonts() -> Result<Fonts, Error> {
         },
         cursive: opener.open("fonts/Parisienne-Regular.ttf")?,
         fantasy: opener.open("fonts/Delius-Regular.ttf")?,
+        embedded: Vec::new(),
     };
     fonts.monospace.bold.set_variations(&["wght=600"]);
     fonts.monospace.bold_italic.set_variations(&["wght=600"]);
     Ok(fonts)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ruby_pairs;
+    use super::super::xml::XmlParser;
+
+    #[test]
+    fn ruby() {
+        let xml = XmlParser::new("<ruby>漢<rp>(</rp><rt>かん</rt><rp>)</rp>字<rt>じ</rt>\n</ruby>").parse();
+        let pairs = ruby_pairs(xml.root().first_child().unwrap());
+        let texts = pairs.iter().map(|(_, text, annot)| (text.as_str(), annot.map(|n| n.text())))
+                         .collect::<Vec<_>>();
+        assert_eq!(texts, vec![("漢", Some("かん".to_string())), ("字", Some("じ".to_string()))]);
+
+        let xml = XmlParser::new("<ruby><rb>A</rb><rb>B</rb><rtc><rt>a</rt><rt>b</rt></rtc><rb>C</rb></ruby>").parse();
+        let pairs = ruby_pairs(xml.root().first_child().unwrap());
+        let texts = pairs.iter().map(|(_, text, annot)| (text.as_str(), annot.map(|n| n.text())))
+                         .collect::<Vec<_>>();
+        assert_eq!(texts, vec![("A", Some("a".to_string())), ("B", Some("b".to_string())), ("C", None)]);
+    }
+}
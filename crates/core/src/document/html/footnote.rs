@@ -0,0 +1,113 @@
+use percent_encoding::percent_decode_str;
+use crate::helpers::decode_entities;
+use super::dom::NodeRef;
+
+const NOTE_TYPES: [&str; 4] = ["footnote", "endnote", "rearnote", "note"];
+const NOTE_ROLES: [&str; 2] = ["doc-footnote", "doc-endnote"];
+const NOTE_CLASSES: [&str; 3] = ["footnote", "endnote", "footnote-definition"];
+const NOTES_TYPES: [&str; 3] = ["footnotes", "endnotes", "rearnotes"];
+const NOTES_ROLES: [&str; 1] = ["doc-endnotes"];
+const NOTES_CLASSES: [&str; 3] = ["footnotes", "endnotes", "notes"];
+
+fn has_token(node: NodeRef, name: &str, tokens: &[&str]) -> bool {
+    node.attribute(name)
+        .is_some_and(|value| value.split_whitespace().any(|v| tokens.contains(&v)))
+}
+
+fn is_noteref(link: NodeRef) -> bool {
+    has_token(link, "epub:type", &["noteref"]) || has_token(link, "role", &["doc-noteref"])
+}
+
+fn is_note(node: NodeRef) -> bool {
+    node.tag_name() == Some("aside") ||
+    has_token(node, "epub:type", &NOTE_TYPES) ||
+    has_token(node, "role", &NOTE_ROLES) ||
+    node.classes().any(|class| NOTE_CLASSES.contains(&class))
+}
+
+fn is_notes(node: NodeRef) -> bool {
+    has_token(node, "epub:type", &NOTES_TYPES) ||
+    has_token(node, "role", &NOTES_ROLES) ||
+    node.classes().any(|class| NOTES_CLASSES.contains(&class))
+}
+
+fn decode_uri(uri: &str) -> String {
+    percent_decode_str(&decode_entities(uri)).decode_utf8_lossy().into_owned()
+}
+
+// Returns the last link to `uri` that starts at or before `offset`.
+pub fn find_link<'a>(root: NodeRef<'a>, offset: usize, uri: &str) -> Option<NodeRef<'a>> {
+    root.descendants()
+        .filter(|node| node.tag_name() == Some("a") && node.offset() <= offset &&
+                       node.attribute("href").map(decode_uri).as_deref() == Some(uri))
+        .last()
+}
+
+// Returns the node holding the note when `link` points to one.
+// The target of a note reference is often a bare anchor inside or before the note's paragraph.
+pub fn note_node<'a>(link: NodeRef, target: NodeRef<'a>) -> Option<NodeRef<'a>> {
+    if !is_noteref(link) && !is_note(target) &&
+       !target.ancestor_elements().any(|anc| is_note(anc) || is_notes(anc)) {
+        return None;
+    }
+
+    if target.is_block() {
+        return Some(target);
+    }
+
+    if target.text().trim().is_empty() {
+        if let Some(next) = target.next_sibling_element().filter(NodeRef::is_block) {
+            return Some(next);
+        }
+    }
+
+    target.ancestor_elements()
+          .find(|anc| anc.is_block() && !matches!(anc.tag_name(), Some("body" | "html")))
+          .or(Some(target))
+}
+
+// Wraps the note in a document that inherits the language and direction of the original one.
+pub fn note_html(node: NodeRef) -> String {
+    let mut buf = String::from("<html");
+
+    if let Some(html) = node.ancestor_elements().find(|anc| anc.tag_name() == Some("html")) {
+        for name in ["xml:lang", "dir"] {
+            if let Some(value) = html.attribute(name) {
+                buf.push_str(&format!(" {}=\"{}\"", name, value));
+            }
+        }
+    }
+
+    buf.push_str("><body>");
+    node.write_xml(&mut buf);
+    buf.push_str("</body></html>");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::xml::XmlParser;
+
+    #[test]
+    fn test_footnotes() {
+        let text = "<html xml:lang=\"en\"><body><p>Text<a epub:type=\"noteref\" href=\"#n1\">1</a> \
+                    and <a href=\"#c2\">chapter</a><a href=\"notes.xhtml#n%202\">2</a>.</p>\
+                    <section id=\"c2\"><p>Chapter 2</p></section>\
+                    <aside id=\"n1\" epub:type=\"footnote\"><p>A <em>note</em>.<br/></p></aside>\
+                    <div class=\"notes\"><a id=\"n 2\"/><p>Another note.</p></div></body></html>";
+        let tree = XmlParser::new(text).parse();
+        let root = tree.root();
+        let link = find_link(root, text.find(">1<").unwrap(), "#n1").unwrap();
+        let note = note_node(link, root.find_by_id("n1").unwrap()).unwrap();
+        let html = note_html(note);
+        assert!(html.starts_with("<html xml:lang=\"en\"><body><aside "));
+        assert!(html.contains(" epub:type=\"footnote\""));
+        assert!(html.ends_with("><p>A <em>note</em>.<br/></p></aside></body></html>"));
+        let link = find_link(root, text.find(">chapter<").unwrap(), "#c2").unwrap();
+        assert!(note_node(link, root.find_by_id("c2").unwrap()).is_none());
+        let link = find_link(root, text.find(">2<").unwrap(), "notes.xhtml#n 2").unwrap();
+        let note = note_node(link, root.find_by_id("n 2").unwrap()).unwrap();
+        assert_eq!(note.text(), "Another note.");
+    }
+}
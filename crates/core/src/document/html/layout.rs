@@ -1,6 +1,7 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::fmt::Debug;
+use std::sync::Arc;
 use fxhash::FxHashMap;
 use lazy_static::lazy_static;
 use kl_hyphenate::{Standard, Language, Load};
@@ -8,6 +9,7 @@ use crate::geom::{Point, Rectangle, Edge};
 use crate::font::{FontFamily, Font, RenderPlan};
 pub use crate::metadata::TextAlign;
 use crate::color::BLACK;
+use super::mathml::{MathNode, MathBox};
 
 pub const DEFAULT_HYPH_LANG: &str = "en";
 
@@ -23,10 +25,6 @@ pub struct DrawState {
     pub position: Point,
     pub floats: FxHashMap<usize, Vec<Rectangle>>,
     pub prefix: Option<String>,
-    pub min_column_widths: Vec<i32>,
-    pub max_column_widths: Vec<i32>,
-    pub column_widths: Vec<i32>,
-    pub center_table: bool,
 }
 
 impl Default for DrawState {
@@ -35,10 +33,6 @@ impl Default for DrawState {
             position: Point::default(),
             floats: FxHashMap::default(),
             prefix: None,
-            min_column_widths: Vec::new(),
-            max_column_widths: Vec::new(),
-            column_widths: Vec::new(),
-            center_table: false,
         }
     }
 }
@@ -54,15 +48,22 @@ pub struct StyleData {
     pub start_x: i32,
     pub end_x: i32,
     pub retain_whitespace: bool,
+    // The spaces are collapsed but the line breaks are kept by `pre-line`.
+    pub retain_newlines: bool,
+    pub no_wrap: bool,
     pub text_align: TextAlign,
     pub text_indent: i32,
     pub line_height: i32,
+    pub direction: Direction,
+    pub writing_mode: WritingMode,
     pub language: Option<String>,
     pub font_kind: FontKind,
     pub font_style: FontStyle,
     pub font_weight: FontWeight,
     pub font_size: f32,
     pub font_features: Option<Vec<String>>,
+    pub small_caps: bool,
+    pub text_transform: TextTransform,
     pub color: u8,
     pub letter_spacing: i32,
     pub word_spacing: WordSpacing,
@@ -78,12 +79,147 @@ pub enum WordSpacing {
     Ratio(f32),
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextTransform {
+    None,
+    Uppercase,
+    Lowercase,
+    Capitalize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    NoWrap,
+    Pre,
+    PreWrap,
+    PreLine,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Float {
     Left,
     Right,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+impl Direction {
+    pub fn bidi_level(self) -> u8 {
+        match self {
+            Direction::Ltr => 0,
+            Direction::Rtl => 1,
+        }
+    }
+}
+
+pub fn language_direction(name: &str) -> Direction {
+    let primary = name.split(['-', '_']).next().unwrap_or_default();
+    match primary.to_lowercase().as_str() {
+        "ar" | "arc" | "ckb" | "dv" | "fa" | "he" | "iw" | "ks" | "ps" | "sd" | "ug" | "ur" | "yi" => Direction::Rtl,
+        _ => Direction::Ltr,
+    }
+}
+
+// Vertical pages are laid out horizontally in a rotated coordinate system:
+// the logical x axis is the inline axis, and the logical y axis is the block axis.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WritingMode {
+    HorizontalTb,
+    VerticalRl,
+    VerticalLr,
+}
+
+impl WritingMode {
+    pub fn is_vertical(self) -> bool {
+        self != WritingMode::HorizontalTb
+    }
+
+    // Converts a physical rectangle of a page of the given width into logical coordinates.
+    pub fn logical_rect(self, rect: Rectangle, width: i32) -> Rectangle {
+        match self {
+            WritingMode::HorizontalTb => rect,
+            WritingMode::VerticalRl => rect![rect.min.y, width - rect.max.x, rect.max.y, width - rect.min.x],
+            WritingMode::VerticalLr => rect![rect.min.y, rect.min.x, rect.max.y, rect.max.x],
+        }
+    }
+
+    // Converts a logical rectangle into physical coordinates.
+    pub fn physical_rect(self, rect: Rectangle, width: i32) -> Rectangle {
+        match self {
+            WritingMode::HorizontalTb => rect,
+            WritingMode::VerticalRl => rect![width - rect.max.y, rect.min.x, width - rect.min.y, rect.max.x],
+            WritingMode::VerticalLr => rect![rect.min.y, rect.min.x, rect.max.y, rect.max.x],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GlyphOrientation {
+    Horizontal,
+    // Stacked glyphs, centered on the column.
+    Upright,
+    // Glyphs rotated by a quarter turn clockwise.
+    Sideways,
+}
+
+impl GlyphOrientation {
+    pub fn from_text(text: &str, writing_mode: WritingMode) -> GlyphOrientation {
+        if !writing_mode.is_vertical() {
+            GlyphOrientation::Horizontal
+        } else if text.chars().next().is_some_and(is_upright_char) {
+            GlyphOrientation::Upright
+        } else {
+            GlyphOrientation::Sideways
+        }
+    }
+}
+
+// Characters that are displayed upright in vertical text (cf. UAX #50).
+pub fn is_upright_char(c: char) -> bool {
+    matches!(c as u32,
+             0x1100..=0x11FF | 0x2E80..=0x2FFF | 0x3000..=0x303F | 0x3040..=0x30FF |
+             0x3100..=0x31FF | 0x3200..=0x4DBF | 0x4E00..=0x9FFF | 0xA960..=0xA97F |
+             0xAC00..=0xD7FF | 0xF900..=0xFAFF | 0xFE10..=0xFE1F | 0xFE30..=0xFE4F |
+             0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x20000..=0x3FFFF)
+}
+
+// Combining marks, joiners and variation selectors keep the orientation of the preceding character.
+fn is_attached_char(c: char) -> bool {
+    matches!(c as u32,
+             0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x200C..=0x200D |
+             0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F | 0xE0100..=0xE01EF)
+}
+
+// Splits the given text into runs of identical glyph orientations.
+// Returns the byte ranges of the runs, relative to the start of the text.
+pub fn orientation_runs(text: &str, writing_mode: WritingMode) -> Vec<(usize, usize, GlyphOrientation)> {
+    if !writing_mode.is_vertical() || text.is_empty() {
+        return vec![(0, text.len(), GlyphOrientation::from_text(text, writing_mode))];
+    }
+
+    let mut runs: Vec<(usize, usize, GlyphOrientation)> = Vec::new();
+
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        let orientation = if is_upright_char(c) {
+            GlyphOrientation::Upright
+        } else {
+            GlyphOrientation::Sideways
+        };
+        match runs.last_mut() {
+            Some(run) if run.2 == orientation || is_attached_char(c) => run.1 = end,
+            _ => runs.push((i, end, orientation)),
+        }
+    }
+
+    runs
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Display {
     Block,
@@ -107,6 +243,19 @@ pub enum ListStyleType {
     None,
 }
 
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Border {
+    pub width: Edge,
+    pub color: u8,
+}
+
+impl Border {
+    pub fn is_empty(&self) -> bool {
+        let Edge { top, right, bottom, left } = self.width;
+        top == 0 && right == 0 && bottom == 0 && left == 0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ChildArtifact {
     pub sibling_style: SiblingStyle,
@@ -178,15 +327,21 @@ impl Default for StyleData {
             start_x: 0,
             end_x: 0,
             retain_whitespace: false,
+            retain_newlines: false,
+            no_wrap: false,
             text_align: TextAlign::Left,
             text_indent: 0,
             line_height: 0,
+            direction: Direction::Ltr,
+            writing_mode: WritingMode::HorizontalTb,
             language: None,
             font_kind: FontKind::Serif,
             font_style: FontStyle::Normal,
             font_weight: FontWeight::Normal,
             font_size: 0.0,
             font_features: None,
+            small_caps: false,
+            text_transform: TextTransform::None,
             color: BLACK,
             letter_spacing: 0,
             word_spacing: WordSpacing::Normal,
@@ -201,6 +356,8 @@ impl Default for StyleData {
 pub enum InlineMaterial {
     Text(TextMaterial),
     Image(ImageMaterial),
+    Math(MathMaterial),
+    Ruby(RubyMaterial),
     Glue(GlueMaterial),
     Penalty(PenaltyMaterial),
     Box(i32),
@@ -211,7 +368,9 @@ impl InlineMaterial {
     pub fn offset(&self) -> Option<usize> {
         match self {
             InlineMaterial::Text(TextMaterial { offset, .. }) |
-            InlineMaterial::Image(ImageMaterial { offset, .. }) => Some(*offset),
+            InlineMaterial::Image(ImageMaterial { offset, .. }) |
+            InlineMaterial::Math(MathMaterial { offset, .. }) |
+            InlineMaterial::Ruby(RubyMaterial { offset, .. }) => Some(*offset),
             _ => None,
         }
     }
@@ -235,6 +394,24 @@ pub struct TextMaterial {
 pub struct ImageMaterial {
     pub offset: usize,
     pub path: String,
+    // The markup of an inline SVG image, `path` is then the directory of the document.
+    pub svg: Option<Arc<str>>,
+    pub style: StyleData,
+}
+
+#[derive(Debug, Clone)]
+pub struct MathMaterial {
+    pub offset: usize,
+    pub math: MathNode,
+    pub style: StyleData,
+}
+
+// A base text and its annotation.
+#[derive(Debug, Clone)]
+pub struct RubyMaterial {
+    pub offset: usize,
+    pub text: String,
+    pub annotation: TextMaterial,
     pub style: StyleData,
 }
 
@@ -259,15 +436,17 @@ pub enum FontKind {
     Monospace,
     Cursive,
     Fantasy,
+    // An index into the embedded fonts.
+    Embedded(usize),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FontStyle {
     Normal,
     Italic,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FontWeight {
     Normal,
     Bold,
@@ -279,6 +458,48 @@ pub struct Fonts {
     pub monospace: FontFamily,
     pub cursive: Font,
     pub fantasy: Font,
+    pub embedded: Vec<EmbeddedFamily>,
+}
+
+// A font family provided by a document.
+pub struct EmbeddedFamily {
+    pub name: String,
+    // The faces are indexed by style and weight: regular, bold, italic and bold italic.
+    faces: [Option<Font>; 4],
+    // The faces read their data from these buffers, so they need to be dropped first.
+    data: Vec<Vec<u8>>,
+}
+
+impl EmbeddedFamily {
+    pub fn new(name: String) -> EmbeddedFamily {
+        EmbeddedFamily {
+            name,
+            faces: [None, None, None, None],
+            data: Vec::new(),
+        }
+    }
+
+    fn face_index(font_style: FontStyle, font_weight: FontWeight) -> usize {
+        2 * (font_style as usize) + font_weight as usize
+    }
+
+    pub fn has_face(&self, font_style: FontStyle, font_weight: FontWeight) -> bool {
+        self.faces[Self::face_index(font_style, font_weight)].is_some()
+    }
+
+    pub fn insert(&mut self, font_style: FontStyle, font_weight: FontWeight, font: Font, data: Vec<u8>) {
+        self.faces[Self::face_index(font_style, font_weight)] = Some(font);
+        self.data.push(data);
+    }
+
+    // Falls back to the closest available face.
+    fn get_mut(&mut self, font_style: FontStyle, font_weight: FontWeight) -> &mut Font {
+        let index = Self::face_index(font_style, font_weight);
+        let index = [index, index ^ 1, index ^ 2, index ^ 3].into_iter()
+                                                             .find(|&i| self.faces[i].is_some())
+                                                             .unwrap_or(index);
+        self.faces[index].as_mut().unwrap()
+    }
 }
 
 impl Fonts {
@@ -310,6 +531,7 @@ impl Fonts {
             },
             FontKind::Cursive => &mut self.cursive,
             FontKind::Fantasy => &mut self.fantasy,
+            FontKind::Embedded(index) => self.embedded[index].get_mut(font_style, font_weight),
         }
     }
 }
@@ -318,6 +540,8 @@ impl Fonts {
 pub enum ParagraphElement {
     Text(TextElement),
     Image(ImageElement),
+    Math(MathElement),
+    Ruby(RubyElement),
     Nothing,
 }
 
@@ -336,6 +560,8 @@ pub struct TextElement {
     pub vertical_align: i32,
     pub color: u8,
     pub uri: Option<String>,
+    pub bidi_level: u8,
+    pub orientation: GlyphOrientation,
 }
 
 #[derive(Debug, Clone)]
@@ -349,6 +575,47 @@ pub struct ImageElement {
     pub margin: Edge,
     pub float: Option<Float>,
     pub path: String,
+    pub svg: Option<Arc<str>>,
+    pub uri: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum FloatElement {
+    Image(ImageElement),
+    Text(FloatingText),
+}
+
+// A floating first letter.
+#[derive(Debug, Clone)]
+pub struct FloatingText {
+    pub element: TextElement,
+    pub ascent: i32,
+    pub descent: i32,
+    pub margin: Edge,
+    pub float: Float,
+}
+
+// The positions and rectangles are relative to the origin of the box.
+#[derive(Debug, Clone)]
+pub struct RubyElement {
+    pub offset: usize,
+    pub base: Box<TextElement>,
+    pub base_position: Point,
+    pub annotation: Box<TextElement>,
+    pub annotation_position: Point,
+    pub annotation_rect: Rectangle,
+    pub ascent: i32,
+    pub descent: i32,
+    pub vertical_align: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MathElement {
+    pub offset: usize,
+    pub math: MathBox,
+    pub font_kind: FontKind,
+    pub vertical_align: i32,
+    pub color: u8,
     pub uri: Option<String>,
 }
 
@@ -357,6 +624,7 @@ pub enum DrawCommand {
     Text(TextCommand),
     ExtraText(TextCommand),
     Image(ImageCommand),
+    Fill(FillCommand),
     Marker(usize),
 }
 
@@ -373,6 +641,7 @@ pub struct TextCommand {
     pub color: u8,
     pub uri: Option<String>,
     pub rect: Rectangle,
+    pub orientation: GlyphOrientation,
 }
 
 #[derive(Debug, Clone)]
@@ -381,16 +650,25 @@ pub struct ImageCommand {
     pub position: Point,
     pub scale: f32,
     pub path: String,
+    pub svg: Option<Arc<str>>,
     pub uri: Option<String>,
     pub rect: Rectangle,
 }
 
+#[derive(Debug, Clone)]
+pub struct FillCommand {
+    pub offset: usize,
+    pub rect: Rectangle,
+    pub color: u8,
+}
+
 impl DrawCommand {
     pub fn offset(&self) -> usize {
         match *self {
             DrawCommand::Text(TextCommand { offset, .. }) => offset,
             DrawCommand::ExtraText(TextCommand { offset, .. }) => offset,
             DrawCommand::Image(ImageCommand { offset, .. }) => offset,
+            DrawCommand::Fill(FillCommand { offset, .. }) => offset,
             DrawCommand::Marker(offset) => offset,
         }
     }
@@ -400,10 +678,75 @@ impl DrawCommand {
             DrawCommand::Text(TextCommand { rect, .. }) => Some(rect),
             DrawCommand::ExtraText(TextCommand { rect, .. }) => Some(rect),
             DrawCommand::Image(ImageCommand { rect, .. }) => Some(rect),
+            DrawCommand::Fill(FillCommand { rect, .. }) => Some(rect),
             _ => None,
         }
     }
 
+    pub fn shift(&mut self, dx: i32) {
+        match *self {
+            DrawCommand::Text(TextCommand { ref mut position, ref mut rect, .. }) |
+            DrawCommand::ExtraText(TextCommand { ref mut position, ref mut rect, .. }) |
+            DrawCommand::Image(ImageCommand { ref mut position, ref mut rect, .. }) => {
+                position.x += dx;
+                rect.min.x += dx;
+                rect.max.x += dx;
+            },
+            DrawCommand::Fill(FillCommand { ref mut rect, .. }) => {
+                rect.min.x += dx;
+                rect.max.x += dx;
+            },
+            _ => (),
+        }
+    }
+
+    pub fn shift_vertically(&mut self, dy: i32) {
+        match *self {
+            DrawCommand::Text(TextCommand { ref mut position, ref mut rect, .. }) |
+            DrawCommand::ExtraText(TextCommand { ref mut position, ref mut rect, .. }) |
+            DrawCommand::Image(ImageCommand { ref mut position, ref mut rect, .. }) => {
+                position.y += dy;
+                rect.min.y += dy;
+                rect.max.y += dy;
+            },
+            DrawCommand::Fill(FillCommand { ref mut rect, .. }) => {
+                rect.min.y += dy;
+                rect.max.y += dy;
+            },
+            _ => (),
+        }
+    }
+
+    // Moves a command laid out in the logical coordinates of a vertical page
+    // to the physical coordinates of a page of the given width.
+    pub fn make_vertical(&mut self, writing_mode: WritingMode, width: i32) {
+        match *self {
+            DrawCommand::Text(TextCommand { ref mut position, ref mut rect, orientation, .. }) |
+            DrawCommand::ExtraText(TextCommand { ref mut position, ref mut rect, orientation, .. }) => {
+                let logical_rect = *rect;
+                *rect = writing_mode.physical_rect(logical_rect, width);
+                // The position is the top of the column, its abscissa is the baseline
+                // of sideways glyphs and the center of upright glyphs.
+                position.x = if orientation == GlyphOrientation::Upright {
+                    (rect.min.x + rect.max.x) / 2
+                } else if writing_mode == WritingMode::VerticalRl {
+                    width - position.y
+                } else {
+                    rect.min.x + rect.max.x - position.y
+                };
+                position.y = logical_rect.min.x;
+            },
+            DrawCommand::Image(ImageCommand { ref mut position, ref mut rect, .. }) => {
+                *rect = writing_mode.physical_rect(*rect, width);
+                *position = rect.min;
+            },
+            DrawCommand::Fill(FillCommand { ref mut rect, .. }) => {
+                *rect = writing_mode.physical_rect(*rect, width);
+            },
+            _ => (),
+        }
+    }
+
     pub fn position_mut(&mut self) -> Option<&mut Point> {
         match *self {
             DrawCommand::Text(TextCommand { ref mut position, .. }) => Some(position),
@@ -437,6 +780,36 @@ mod tests {
         assert_eq!(hyph_lang("de-CH-uuu"), Some(Language::GermanSwiss));
         assert_eq!(hyph_lang("y"), None);
     }
+
+    #[test]
+    fn test_vertical_rects() {
+        let rect = rect![20, 30, 580, 770];
+        for writing_mode in [WritingMode::VerticalRl, WritingMode::VerticalLr] {
+            let logical_rect = writing_mode.logical_rect(rect, 600);
+            assert_eq!(writing_mode.physical_rect(logical_rect, 600), rect);
+        }
+        assert_eq!(WritingMode::VerticalRl.physical_rect(rect![30, 20, 60, 40], 600),
+                   rect![560, 30, 580, 60]);
+    }
+
+    #[test]
+    fn test_orientation_runs() {
+        assert_eq!(orientation_runs("東京Tokyo", WritingMode::VerticalRl),
+                   vec![(0, 6, GlyphOrientation::Upright), (6, 11, GlyphOrientation::Sideways)]);
+        assert_eq!(orientation_runs("Towerです", WritingMode::VerticalLr),
+                   vec![(0, 5, GlyphOrientation::Sideways), (5, 11, GlyphOrientation::Upright)]);
+        assert_eq!(orientation_runs("Cafe\u{301}", WritingMode::VerticalRl),
+                   vec![(0, 6, GlyphOrientation::Sideways)]);
+        assert_eq!(orientation_runs("東京Tokyo", WritingMode::HorizontalTb),
+                   vec![(0, 11, GlyphOrientation::Horizontal)]);
+    }
+
+    #[test]
+    fn test_language_direction() {
+        assert_eq!(language_direction("ar-EG"), Direction::Rtl);
+        assert_eq!(language_direction("HE"), Direction::Rtl);
+        assert_eq!(language_direction("en-US"), Direction::Ltr);
+    }
 }
 
 pub fn hyph_lang(name: &str) -> Option<Language> {
@@ -0,0 +1,714 @@
+use crate::font::{Font, RenderPlan};
+use crate::geom::{Point, Rectangle};
+use crate::helpers::decode_entities;
+use super::dom::NodeRef;
+use super::layout::{Fonts, FontKind, FontStyle, FontWeight};
+
+// The size ratio between two consecutive script levels.
+const SCRIPT_RATIO: f32 = 0.71;
+// The size ratio of large operators in displayed formulas.
+const LARGE_OPERATOR_RATIO: f32 = 1.4;
+const MAX_SCRIPT_LEVEL: u8 = 2;
+
+const FENCES: [&str; 14] = ["(", ")", "[", "]", "{", "}", "|", "‖", "⟨", "⟩", "⌈", "⌉", "⌊", "⌋"];
+const RELATIONS: [&str; 30] = ["=", "<", ">", "≤", "≥", "≠", "≈", "≡", "∼", "≃", "≅", "∝",
+                               "→", "←", "↔", "⇒", "⇐", "⇔", "↦", "∈", "∉", "∋", "⊂", "⊃",
+                               "⊆", "⊇", ":=", "≪", "≫", "⊥"];
+const BINARY_OPERATORS: [&str; 18] = ["+", "-", "−", "±", "∓", "×", "÷", "·", "⋅", "∗", "∘",
+                                      "∧", "∨", "∩", "∪", "⊕", "⊗", "⊖"];
+const LARGE_OPERATORS: [&str; 13] = ["∑", "∏", "∐", "⋃", "⋂", "⋁", "⋀", "⨁", "⨂",
+                                     "∫", "∬", "∭", "∮"];
+const BARS: [&str; 6] = ["¯", "‾", "_", "―", "─", "⎯"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MathNode {
+    Token(MathToken),
+    // A width, in ems.
+    Space(f32),
+    Row(Vec<MathNode>),
+    Fraction {
+        numerator: Box<MathNode>,
+        denominator: Box<MathNode>,
+        rule: bool,
+    },
+    Scripts {
+        base: Box<MathNode>,
+        subscript: Option<Box<MathNode>>,
+        superscript: Option<Box<MathNode>>,
+    },
+    Limits {
+        base: Box<MathNode>,
+        under: Option<Box<MathNode>>,
+        over: Option<Box<MathNode>>,
+        accent: bool,
+    },
+    Radical {
+        offset: usize,
+        base: Box<MathNode>,
+        index: Option<Box<MathNode>>,
+    },
+    Table(Vec<Vec<MathNode>>),
+    Phantom(Box<MathNode>),
+}
+
+impl Default for MathNode {
+    fn default() -> Self {
+        MathNode::Row(Vec::new())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TokenKind {
+    Identifier,
+    Number,
+    Operator,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MathToken {
+    pub offset: usize,
+    pub text: String,
+    pub kind: TokenKind,
+    pub font_style: FontStyle,
+    pub font_weight: FontWeight,
+    // The spaces around an operator, in ems.
+    pub lspace: f32,
+    pub rspace: f32,
+    pub stretchy: bool,
+    pub large: bool,
+}
+
+impl MathToken {
+    fn operator(text: &str, offset: usize) -> MathToken {
+        let (lspace, rspace) = operator_spacing(text);
+        MathToken {
+            offset,
+            text: text.to_string(),
+            kind: TokenKind::Operator,
+            font_style: FontStyle::Normal,
+            font_weight: FontWeight::Normal,
+            lspace,
+            rspace,
+            stretchy: FENCES.contains(&text),
+            large: LARGE_OPERATORS.contains(&text),
+        }
+    }
+}
+
+fn operator_spacing(text: &str) -> (f32, f32) {
+    if RELATIONS.contains(&text) {
+        (0.28, 0.28)
+    } else if BINARY_OPERATORS.contains(&text) {
+        (0.22, 0.22)
+    } else if LARGE_OPERATORS.contains(&text) || text == "," || text == ";" {
+        (0.0, 0.17)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+fn parse_space(value: &str) -> Option<f32> {
+    let value = value.trim();
+    match value {
+        "veryverythinmathspace" => Some(1.0 / 18.0),
+        "verythinmathspace" => Some(2.0 / 18.0),
+        "thinmathspace" => Some(3.0 / 18.0),
+        "mediummathspace" => Some(4.0 / 18.0),
+        "thickmathspace" => Some(5.0 / 18.0),
+        "verythickmathspace" => Some(6.0 / 18.0),
+        "veryverythickmathspace" => Some(7.0 / 18.0),
+        _ => {
+            let index = value.find(|c: char| c.is_ascii_alphabetic() || c == '%')
+                             .unwrap_or(value.len());
+            let number = value[..index].parse::<f32>().ok()?;
+            match &value[index..] {
+                "em" | "" => Some(number),
+                "ex" => Some(number / 2.0),
+                _ => None,
+            }
+        },
+    }
+}
+
+fn parse_token(node: NodeRef, kind: TokenKind) -> Option<MathToken> {
+    let text = decode_entities(&node.text()).chars()
+                   .filter(|c| !matches!(c, '\u{2061}'..='\u{2064}'))
+                   .collect::<String>();
+    let text = text.trim();
+
+    if text.is_empty() {
+        return None;
+    }
+
+    let offset = node.children().find(|child| !child.is_element())
+                     .map_or(node.offset(), |child| child.offset());
+    let mut token = MathToken::operator(text, offset);
+    token.kind = kind;
+
+    if kind == TokenKind::Operator {
+        if let Some(value) = node.attribute("stretchy") {
+            token.stretchy = value == "true";
+        }
+        if let Some(value) = node.attribute("largeop") {
+            token.large = value == "true";
+        }
+        if let Some(space) = node.attribute("lspace").and_then(parse_space) {
+            token.lspace = space;
+        }
+        if let Some(space) = node.attribute("rspace").and_then(parse_space) {
+            token.rspace = space;
+        }
+    } else {
+        token.lspace = 0.0;
+        token.rspace = 0.0;
+        token.stretchy = false;
+        token.large = false;
+        // Single letter identifiers are italic, except upper case Greek letters.
+        let mut chars = text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if kind == TokenKind::Identifier && c.is_alphabetic() && !('Α'..='Ω').contains(&c) {
+                token.font_style = FontStyle::Italic;
+            }
+        }
+    }
+
+    match node.attribute("mathvariant") {
+        Some("normal") => {
+            token.font_style = FontStyle::Normal;
+        },
+        Some("bold") => {
+            token.font_style = FontStyle::Normal;
+            token.font_weight = FontWeight::Bold;
+        },
+        Some("italic") => {
+            token.font_style = FontStyle::Italic;
+        },
+        Some("bold-italic") => {
+            token.font_style = FontStyle::Italic;
+            token.font_weight = FontWeight::Bold;
+        },
+        _ => (),
+    }
+
+    Some(token)
+}
+
+// Converts a MathML element into a tree that can be laid out independently of the document.
+pub fn parse_math(node: NodeRef) -> MathNode {
+    let name = node.tag_name().unwrap_or_default();
+    let kind = match name {
+        "mi" => Some(TokenKind::Identifier),
+        "mn" => Some(TokenKind::Number),
+        "mo" => Some(TokenKind::Operator),
+        "mtext" | "ms" => Some(TokenKind::Text),
+        _ => None,
+    };
+
+    if let Some(kind) = kind {
+        return parse_token(node, kind).map(MathNode::Token)
+                                      .unwrap_or_default();
+    }
+
+    let mut args = node.children().filter(|child| child.is_element()).map(parse_math);
+    let mut next = || Box::new(args.next().unwrap_or_default());
+
+    match name {
+        "mfrac" => {
+            let rule = node.attribute("linethickness").is_none_or(|value| {
+                let number = value.trim().trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%');
+                number.parse::<f32>().ok().is_none_or(|thickness| thickness > 0.0)
+            });
+            MathNode::Fraction { numerator: next(), denominator: next(), rule }
+        },
+        "msub" => MathNode::Scripts { base: next(), subscript: Some(next()), superscript: None },
+        "msup" => MathNode::Scripts { base: next(), subscript: None, superscript: Some(next()) },
+        "msubsup" | "mmultiscripts" => MathNode::Scripts { base: next(), subscript: Some(next()), superscript: Some(next()) },
+        "munder" | "mover" | "munderover" => {
+            let base = next();
+            let under = if name == "mover" { None } else { Some(next()) };
+            let over = if name == "munder" { None } else { Some(next()) };
+            let accent = node.attribute(if name == "munder" { "accentunder" } else { "accent" }) == Some("true");
+            MathNode::Limits { base, under, over, accent }
+        },
+        "msqrt" => MathNode::Radical { offset: node.offset(), base: Box::new(MathNode::Row(args.collect())), index: None },
+        "mroot" => MathNode::Radical { offset: node.offset(), base: next(), index: Some(next()) },
+        "mtable" => MathNode::Table(args.map(|row| match row {
+            MathNode::Row(cells) => cells,
+            cell => vec![cell],
+        }).collect()),
+        "mphantom" => MathNode::Phantom(Box::new(MathNode::Row(args.collect()))),
+        "mspace" => MathNode::Space(node.attribute("width").and_then(parse_space).unwrap_or(0.0)),
+        "mfenced" => {
+            let offset = node.offset();
+            let open = node.attribute("open").unwrap_or("(");
+            let close = node.attribute("close").unwrap_or(")");
+            let separators = node.attribute("separators").unwrap_or(",")
+                                 .chars().filter(|c| !c.is_whitespace()).collect::<Vec<char>>();
+            let mut nodes = vec![MathNode::Token(MathToken::operator(open, offset))];
+            for (index, arg) in args.enumerate() {
+                if index > 0 {
+                    if let Some(sep) = separators.get(index - 1).or_else(|| separators.last()) {
+                        nodes.push(MathNode::Token(MathToken::operator(&sep.to_string(), offset)));
+                    }
+                }
+                nodes.push(arg);
+            }
+            nodes.push(MathNode::Token(MathToken::operator(close, offset)));
+            MathNode::Row(nodes)
+        },
+        "semantics" | "maction" => *next(),
+        "annotation" | "annotation-xml" | "mprescripts" | "none" => MathNode::default(),
+        _ => MathNode::Row(args.collect()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MathGlyphs {
+    pub offset: usize,
+    pub text: String,
+    pub plan: RenderPlan,
+    pub font_style: FontStyle,
+    pub font_weight: FontWeight,
+    pub font_size: u32,
+    pub position: Point,
+    pub rect: Rectangle,
+}
+
+#[derive(Debug, Clone)]
+pub enum MathItem {
+    Glyphs(MathGlyphs),
+    Rule(Rectangle),
+}
+
+// The items are positioned relatively to the origin of the box, on its baseline.
+#[derive(Debug, Clone, Default)]
+pub struct MathBox {
+    pub width: i32,
+    pub ascent: i32,
+    pub descent: i32,
+    pub items: Vec<MathItem>,
+}
+
+impl MathBox {
+    pub fn translate(&mut self, dx: i32, dy: i32) {
+        let delta = pt!(dx, dy);
+        for item in &mut self.items {
+            match item {
+                MathItem::Glyphs(MathGlyphs { position, rect, .. }) => {
+                    *position += delta;
+                    *rect = rect![rect.min + delta, rect.max + delta];
+                },
+                MathItem::Rule(rect) => {
+                    *rect = rect![rect.min + delta, rect.max + delta];
+                },
+            }
+        }
+    }
+
+    fn append(&mut self, mut other: MathBox, dx: i32, dy: i32) {
+        other.translate(dx, dy);
+        self.width = self.width.max(dx + other.width);
+        self.ascent = self.ascent.max(other.ascent - dy);
+        self.descent = self.descent.max(other.descent + dy);
+        self.items.append(&mut other.items);
+    }
+
+    fn push_rule(&mut self, rect: Rectangle) {
+        self.ascent = self.ascent.max(-rect.min.y);
+        self.descent = self.descent.max(rect.max.y);
+        self.items.push(MathItem::Rule(rect));
+    }
+}
+
+struct Metrics {
+    em: i32,
+    x_height: i32,
+    // The height of the math axis, on which fractions are centered.
+    axis: i32,
+    rule: i32,
+}
+
+pub struct MathLayout<'a> {
+    fonts: &'a mut Fonts,
+    font_kind: FontKind,
+    font_size: f32,
+    dpi: u16,
+}
+
+impl<'a> MathLayout<'a> {
+    pub fn new(fonts: &'a mut Fonts, font_kind: FontKind, font_size: f32, dpi: u16) -> MathLayout<'a> {
+        MathLayout { fonts, font_kind, font_size, dpi }
+    }
+
+    pub fn layout(&mut self, node: &MathNode, display: bool) -> MathBox {
+        self.layout_node(node, 0, display)
+    }
+
+    fn font(&mut self, font_style: FontStyle, font_weight: FontWeight, font_size: u32) -> &mut Font {
+        let font = self.fonts.get_mut(self.font_kind, font_style, font_weight);
+        font.set_size(font_size, self.dpi);
+        font
+    }
+
+    fn font_size(&self, level: u8, scale: f32) -> u32 {
+        let ratio = SCRIPT_RATIO.powi(level.min(MAX_SCRIPT_LEVEL) as i32);
+        (scale * ratio * self.font_size * 64.0) as u32
+    }
+
+    fn metrics(&mut self, level: u8) -> Metrics {
+        let font_size = self.font_size(level, 1.0);
+        let font = self.font(FontStyle::Normal, FontWeight::Normal, font_size);
+        let em = font.em() as i32;
+        let x_height = font.x_heights.0 as i32;
+        Metrics {
+            em,
+            x_height,
+            axis: x_height / 2,
+            rule: (em as f32 / 18.0).round().max(1.0) as i32,
+        }
+    }
+
+    fn layout_node(&mut self, node: &MathNode, level: u8, display: bool) -> MathBox {
+        match node {
+            MathNode::Token(token) => {
+                let scale = if display && token.large { LARGE_OPERATOR_RATIO } else { 1.0 };
+                self.layout_token(token, level, scale)
+            },
+            MathNode::Space(width) => {
+                let em = self.metrics(level).em;
+                MathBox { width: (width * em as f32).round() as i32, .. Default::default() }
+            },
+            MathNode::Row(nodes) => self.layout_row(nodes, level, display),
+            MathNode::Fraction { numerator, denominator, rule } => {
+                self.layout_fraction(numerator, denominator, *rule, level, display)
+            },
+            MathNode::Scripts { base, subscript, superscript } => {
+                let base = self.layout_node(base, level, display);
+                self.layout_scripts(base, subscript.as_deref(), superscript.as_deref(), level)
+            },
+            MathNode::Limits { base, under, over, accent } => {
+                // The limits of large operators are placed like scripts within the text.
+                if !display && !accent && matches!(base.as_ref(), MathNode::Token(token) if token.large) {
+                    let base = self.layout_node(base, level, display);
+                    return self.layout_scripts(base, under.as_deref(), over.as_deref(), level);
+                }
+                self.layout_limits(base, under.as_deref(), over.as_deref(), *accent, level, display)
+            },
+            MathNode::Radical { offset, base, index } => {
+                self.layout_radical(*offset, base, index.as_deref(), level, display)
+            },
+            MathNode::Table(rows) => self.layout_table(rows, level),
+            MathNode::Phantom(node) => {
+                let mut phantom = self.layout_node(node, level, display);
+                phantom.items.clear();
+                phantom
+            },
+        }
+    }
+
+    fn layout_token(&mut self, token: &MathToken, level: u8, scale: f32) -> MathBox {
+        let font_size = self.font_size(level, scale);
+        let font = self.font(token.font_style, token.font_weight, font_size);
+        let plan = font.plan(&token.text, None, None);
+        let (ascent, descent) = token.text.chars()
+                                     .filter(|c| !c.is_whitespace())
+                                     .map(|c| font.vertical_extents(c))
+                                     .reduce(|(a, b), (c, d)| (a.max(c), b.max(d)))
+                                     .unwrap_or((0, 0));
+        let width = plan.width;
+        MathBox {
+            width,
+            ascent,
+            descent,
+            items: vec![MathItem::Glyphs(MathGlyphs {
+                offset: token.offset,
+                text: token.text.clone(),
+                plan,
+                font_style: token.font_style,
+                font_weight: token.font_weight,
+                font_size,
+                position: pt!(0, 0),
+                rect: rect![0, -ascent, width, descent],
+            })],
+        }
+    }
+
+    // Scales a stretchy operator so that it covers the given extents, centered on the axis.
+    fn layout_stretchy(&mut self, token: &MathToken, level: u8, ascent: i32, descent: i32) -> MathBox {
+        let axis = self.metrics(level).axis;
+        let natural = self.layout_token(token, level, 1.0);
+        let height = natural.ascent + natural.descent;
+        let target = 2 * (ascent - axis).max(descent + axis);
+
+        if height <= 0 || target <= height {
+            return natural;
+        }
+
+        let mut stretched = self.layout_token(token, level, target as f32 / height as f32);
+        let dy = (stretched.ascent - stretched.descent) / 2 - axis;
+        stretched.translate(0, dy);
+        stretched.ascent -= dy;
+        stretched.descent += dy;
+        stretched
+    }
+
+    fn layout_row(&mut self, nodes: &[MathNode], level: u8, display: bool) -> MathBox {
+        let mut boxes = nodes.iter().map(|node| match node {
+            MathNode::Token(token) if token.stretchy => None,
+            _ => Some(self.layout_node(node, level, display)),
+        }).collect::<Vec<Option<MathBox>>>();
+
+        let (ascent, descent) = boxes.iter().flatten()
+                                     .fold((0, 0), |(a, d), b| (a.max(b.ascent), d.max(b.descent)));
+
+        let em = self.metrics(level).em as f32;
+        let space_ratio = if level == 0 { 1.0 } else { 0.5 };
+        let mut row = MathBox::default();
+        let mut x = 0;
+
+        for (index, node) in nodes.iter().enumerate() {
+            let child = match boxes[index].take() {
+                Some(child) => child,
+                None => match node {
+                    MathNode::Token(token) => self.layout_stretchy(token, level, ascent, descent),
+                    _ => continue,
+                },
+            };
+            // Leading operators, such as the minus sign of a negative number, aren't spaced.
+            let (lspace, rspace) = match node {
+                MathNode::Token(token) if index > 0 || nodes.len() == 1 => (token.lspace, token.rspace),
+                _ => (0.0, 0.0),
+            };
+            x += (space_ratio * lspace * em).round() as i32;
+            let width = child.width;
+            row.append(child, x, 0);
+            x += width + (space_ratio * rspace * em).round() as i32;
+        }
+
+        row.width = x;
+        row
+    }
+
+    fn layout_fraction(&mut self, numerator: &MathNode, denominator: &MathNode, rule: bool, level: u8, display: bool) -> MathBox {
+        let inner_level = if display { level } else { level + 1 };
+        let numerator = self.layout_node(numerator, inner_level, false);
+        let denominator = self.layout_node(denominator, inner_level, false);
+        let metrics = self.metrics(level);
+        let thickness = if rule { metrics.rule } else { 0 };
+        let gap = if display { 3 * metrics.rule } else { metrics.rule };
+        let padding = metrics.em / 10;
+        let width = numerator.width.max(denominator.width) + 2 * padding;
+        let top = -metrics.axis - thickness / 2;
+        let bottom = top + thickness;
+
+        let mut fraction = MathBox { width, .. Default::default() };
+        let (dx, dy) = ((width - numerator.width) / 2, top - gap - numerator.descent);
+        fraction.append(numerator, dx, dy);
+        let (dx, dy) = ((width - denominator.width) / 2, bottom + gap + denominator.ascent);
+        fraction.append(denominator, dx, dy);
+
+        if rule {
+            fraction.push_rule(rect![0, top, width, bottom]);
+        }
+
+        fraction
+    }
+
+    fn layout_scripts(&mut self, base: MathBox, subscript: Option<&MathNode>, superscript: Option<&MathNode>, level: u8) -> MathBox {
+        let metrics = self.metrics(level);
+        let subscript = subscript.map(|node| self.layout_node(node, level + 1, false));
+        let superscript = superscript.map(|node| self.layout_node(node, level + 1, false));
+        let x = base.width + metrics.em / 20;
+        let mut sup_shift = superscript.as_ref().map_or(0, |sup| {
+            (base.ascent - sup.ascent / 2).max(3 * metrics.x_height / 4)
+        });
+        let mut sub_shift = subscript.as_ref().map_or(0, |sub| {
+            (base.descent + sub.ascent / 4).max(metrics.em / 5)
+        });
+
+        if let (Some(sup), Some(sub)) = (superscript.as_ref(), subscript.as_ref()) {
+            let gap = (sup_shift - sup.descent) - (sub.ascent - sub_shift);
+            let min_gap = 4 * metrics.rule;
+            if gap < min_gap {
+                sub_shift += min_gap - gap;
+            }
+        } else if superscript.is_some() {
+            sup_shift = sup_shift.max(metrics.x_height / 2);
+        }
+
+        let mut scripts = base;
+        if let Some(sub) = subscript {
+            scripts.append(sub, x, sub_shift);
+        }
+        if let Some(sup) = superscript {
+            scripts.append(sup, x, -sup_shift);
+        }
+        scripts
+    }
+
+    fn layout_limits(&mut self, base: &MathNode, under: Option<&MathNode>, over: Option<&MathNode>, accent: bool, level: u8, display: bool) -> MathBox {
+        let base = self.layout_node(base, level, display);
+        let metrics = self.metrics(level);
+        let inner_level = if accent { level } else { level + 1 };
+        let gap = 2 * metrics.rule;
+        let mut limit = |node: &MathNode| match node {
+            MathNode::Token(token) if BARS.contains(&token.text.as_str()) => {
+                let mut bar = MathBox { width: base.width, .. Default::default() };
+                bar.push_rule(rect![0, -metrics.rule, base.width, 0]);
+                bar
+            },
+            _ => self.layout_node(node, inner_level, false),
+        };
+        let under = under.map(&mut limit);
+        let over = over.map(&mut limit);
+        let width = base.width.max(under.as_ref().map_or(0, |b| b.width))
+                              .max(over.as_ref().map_or(0, |b| b.width));
+        let (base_ascent, base_descent, base_width) = (base.ascent, base.descent, base.width);
+
+        let mut limits = MathBox { width, .. Default::default() };
+        limits.append(base, (width - base_width) / 2, 0);
+        if let Some(over) = over {
+            let (dx, dy) = ((width - over.width) / 2, -(base_ascent + gap + over.descent));
+            limits.append(over, dx, dy);
+        }
+        if let Some(under) = under {
+            let (dx, dy) = ((width - under.width) / 2, base_descent + gap + under.ascent);
+            limits.append(under, dx, dy);
+        }
+        limits
+    }
+
+    fn layout_radical(&mut self, offset: usize, base: &MathNode, index: Option<&MathNode>, level: u8, display: bool) -> MathBox {
+        let base = self.layout_node(base, level, display);
+        let metrics = self.metrics(level);
+        let thickness = metrics.rule;
+        let gap = thickness + metrics.x_height / 4;
+        let padding = metrics.em / 20;
+        let top = -(base.ascent + gap + thickness);
+        let target = base.ascent + gap + thickness + base.descent;
+
+        let sign = MathToken::operator("√", offset);
+        let mut radical = self.layout_token(&sign, level, 1.0);
+        let height = radical.ascent + radical.descent;
+        if height > 0 && target > height {
+            radical = self.layout_token(&sign, level, target as f32 / height as f32);
+        }
+        // The top of the sign meets the rule.
+        let dy = top + radical.ascent;
+
+        let mut root = MathBox::default();
+        let mut x = 0;
+
+        if let Some(index) = index {
+            let index = self.layout_node(index, level + 2, false);
+            let dy = dy + radical.descent - 3 * (radical.ascent + radical.descent) / 5 - index.descent;
+            x = (index.width - radical.width / 2).max(0);
+            root.append(index, 0, dy);
+        }
+
+        let sign_width = radical.width;
+        root.append(radical, x, dy);
+        x += sign_width;
+        let base_width = base.width;
+        root.append(base, x + padding, 0);
+        root.push_rule(rect![x, top, x + base_width + 2 * padding, top + thickness]);
+        root.width = x + base_width + 2 * padding;
+        root
+    }
+
+    fn layout_table(&mut self, rows: &[Vec<MathNode>], level: u8) -> MathBox {
+        let metrics = self.metrics(level);
+        let cells = rows.iter().map(|row| {
+            row.iter().map(|cell| self.layout_node(cell, level, false)).collect::<Vec<MathBox>>()
+        }).collect::<Vec<Vec<MathBox>>>();
+        let columns_count = cells.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns_count];
+        for row in &cells {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.width);
+            }
+        }
+        let extents = cells.iter().map(|row| {
+            row.iter().fold((0, 0), |(a, d), cell| (a.max(cell.ascent), d.max(cell.descent)))
+        }).collect::<Vec<(i32, i32)>>();
+        let column_gap = 4 * metrics.em / 5;
+        let row_gap = metrics.em / 3;
+        let height = extents.iter().map(|(a, d)| a + d).sum::<i32>() +
+                     row_gap * (extents.len() as i32 - 1).max(0);
+
+        let mut table = MathBox::default();
+        let mut y = -metrics.axis - height / 2;
+
+        for (row, (ascent, descent)) in cells.into_iter().zip(extents) {
+            let mut x = 0;
+            for (cell, width) in row.into_iter().zip(&widths) {
+                let cell_width = cell.width;
+                table.append(cell, x + (width - cell_width) / 2, y + ascent);
+                x += width + column_gap;
+            }
+            y += ascent + descent + row_gap;
+        }
+
+        table.width = widths.iter().sum::<i32>() + column_gap * (columns_count as i32 - 1).max(0);
+        table.ascent = table.ascent.max(metrics.axis + height / 2);
+        table.descent = table.descent.max(height - metrics.axis - height / 2);
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::xml::XmlParser;
+
+    fn token(node: &MathNode) -> &MathToken {
+        match node {
+            MathNode::Token(token) => token,
+            _ => panic!("not a token: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_math() {
+        let text = "<math><mrow><mi>x</mi><mo>=</mo>\
+                    <mfrac><mrow><mo>&#x2212;</mo><mi>b</mi></mrow><msup><mi>a</mi><mn>2</mn></msup></mfrac>\
+                    <mo>&InvisibleTimes;</mo><msqrt><mi>sin</mi></msqrt></mrow></math>";
+        let tree = XmlParser::new(text).parse();
+        let math = parse_math(tree.root().find("math").unwrap());
+        let MathNode::Row(children) = math else { panic!() };
+        let MathNode::Row(nodes) = &children[0] else { panic!() };
+        assert_eq!(nodes.len(), 5);
+        assert_eq!(token(&nodes[0]).font_style, FontStyle::Italic);
+        assert_eq!(token(&nodes[0]).offset, text.find("x<").unwrap());
+        assert_eq!(token(&nodes[1]).lspace, 0.28);
+        let MathNode::Fraction { numerator, denominator, rule: true } = &nodes[2] else { panic!() };
+        let MathNode::Row(numerator) = numerator.as_ref() else { panic!() };
+        assert_eq!(token(&numerator[0]).text, "−");
+        assert!(matches!(denominator.as_ref(), MathNode::Scripts { subscript: None, superscript: Some(..), .. }));
+        assert_eq!(nodes[3], MathNode::default());
+        let MathNode::Radical { base, index: None, .. } = &nodes[4] else { panic!() };
+        let MathNode::Row(base) = base.as_ref() else { panic!() };
+        assert_eq!(token(&base[0]).font_style, FontStyle::Normal);
+    }
+
+    #[test]
+    fn test_parse_fenced_matrix() {
+        let text = "<math><mfenced open=\"[\" close=\"]\"><mtable>\
+                    <mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr>\
+                    <mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr>\
+                    </mtable></mfenced><mfrac linethickness=\"0\"><mi>n</mi><mi>k</mi></mfrac></math>";
+        let tree = XmlParser::new(text).parse();
+        let MathNode::Row(children) = parse_math(tree.root().find("math").unwrap()) else { panic!() };
+        let MathNode::Row(nodes) = &children[0] else { panic!() };
+        assert_eq!(nodes.len(), 3);
+        assert!(token(&nodes[0]).stretchy);
+        assert_eq!(token(&nodes[2]).text, "]");
+        let MathNode::Table(rows) = &nodes[1] else { panic!() };
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].len(), 2);
+        assert!(matches!(children[1], MathNode::Fraction { rule: false, .. }));
+        assert_eq!(parse_space("thickmathspace"), Some(5.0 / 18.0));
+        assert_eq!(parse_space("0.5ex"), Some(0.25));
+    }
+}
@@ -5,6 +5,10 @@ pub mod parse;
 pub mod style;
 pub mod layout;
 pub mod engine;
+pub mod table;
+pub mod footnote;
+pub mod svg;
+pub mod mathml;
 
 use std::io::{Read, Write};
 use std::fs::{self, File};
@@ -13,13 +17,17 @@ use fxhash::FxHashMap;
 use anyhow::Error;
 use crate::framebuffer::Pixmap;
 use crate::helpers::{Normalize, decode_entities};
-use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText};
+use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText, Footnote};
+use crate::document::{chapter, chapter_relative, collect_headings, headings_toc, SYNTHETIC_TOC_DEPTH};
 use crate::unit::pt_to_px;
 use crate::geom::{Boundary, Edge, CycleDir};
 use self::dom::{XmlTree, NodeRef};
 use self::layout::{RootData, StyleData, DrawState, LoopContext};
 use self::layout::{DrawCommand, TextCommand, ImageCommand, TextAlign};
-use self::engine::{Page, Engine, ResourceFetcher};
+use self::layout::{Direction, WritingMode, language_direction};
+use self::parse::parse_direction;
+use self::engine::{Page, Engine, ResourceFetcher, writing_mode};
+use self::footnote::{find_link, note_node, note_html};
 use self::style::StyleSheet;
 use self::css::CssParser;
 use self::xml::XmlParser;
@@ -34,11 +42,14 @@ pub struct HtmlDocument {
     content: XmlTree,
     engine: Engine,
     pages: Vec<Page>,
-    parent: PathBuf,
+    fetcher: Box<dyn ResourceFetcher>,
     size: usize,
     viewer_stylesheet: PathBuf,
     user_stylesheet: PathBuf,
+    extra_css: String,
     ignore_document_css: bool,
+    // The headings of the documents opened by the user act as a table of contents.
+    headings_toc: bool,
 }
 
 impl ResourceFetcher for PathBuf {
@@ -68,11 +79,13 @@ impl HtmlDocument {
             content,
             engine: Engine::new(),
             pages: Vec::new(),
-            parent: parent.to_path_buf(),
+            fetcher: Box::new(parent.to_path_buf()),
             size,
             viewer_stylesheet: PathBuf::from(VIEWER_STYLESHEET),
             user_stylesheet: PathBuf::from(USER_STYLESHEET),
+            extra_css: String::new(),
             ignore_document_css: false,
+            headings_toc: true,
         })
     }
 
@@ -86,11 +99,30 @@ impl HtmlDocument {
             content,
             engine: Engine::new(),
             pages: Vec::new(),
-            parent: PathBuf::default(),
+            fetcher: Box::new(PathBuf::default()),
             size,
             viewer_stylesheet: PathBuf::from(VIEWER_STYLESHEET),
             user_stylesheet: PathBuf::from(USER_STYLESHEET),
+            extra_css: String::new(),
             ignore_document_css: false,
+            headings_toc: false,
+        }
+    }
+
+    // The offsets of the nodes of `content` are expected to point into `text`.
+    pub fn new_from_tree(text: String, content: XmlTree, fetcher: Box<dyn ResourceFetcher>) -> HtmlDocument {
+        HtmlDocument {
+            size: text.len(),
+            text,
+            content,
+            engine: Engine::new(),
+            pages: Vec::new(),
+            fetcher,
+            viewer_stylesheet: PathBuf::from(VIEWER_STYLESHEET),
+            user_stylesheet: PathBuf::from(USER_STYLESHEET),
+            extra_css: String::new(),
+            ignore_document_css: false,
+            headings_toc: false,
         }
     }
 
@@ -173,6 +205,11 @@ impl HtmlDocument {
             stylesheet.append(&mut css, true);
         }
 
+        if !self.extra_css.is_empty() {
+            let mut css = CssParser::new(&self.extra_css).parse();
+            stylesheet.append(&mut css, true);
+        }
+
         if !self.ignore_document_css {
             let mut inner_css = StyleSheet::new();
 
@@ -181,9 +218,10 @@ impl HtmlDocument {
                     if child.tag_name() == Some("link") && child.attribute("rel") == Some("stylesheet") {
                         if let Some(href) = child.attribute("href") {
                             if let Some(name) = spine_dir.join(href).normalize().to_str() {
-                                if let Ok(buf) = self.parent.fetch(name) {
+                                if let Ok(buf) = self.fetcher.fetch(name) {
                                     if let Ok(text) = String::from_utf8(buf) {
                                         let mut css = CssParser::new(&text).parse();
+                                        css.resolve_font_sources(Path::new(name).parent().unwrap_or(&spine_dir));
                                         inner_css.append(&mut css, false);
                                     }
                                 }
@@ -199,21 +237,36 @@ impl HtmlDocument {
             stylesheet.append(&mut inner_css, true);
         }
 
+        self.engine.load_font_faces(&stylesheet.font_faces, self.fetcher.as_mut());
+
         let mut pages = Vec::new();
 
-        let mut rect = self.engine.rect();
-        rect.shrink(&self.engine.margin);
+        let writing_mode = self.content.root().find("body")
+                               .and_then(|body| writing_mode(body, &stylesheet))
+                               .or_else(|| self.content.root().find("html")
+                                               .and_then(|html| writing_mode(html, &stylesheet)))
+                               .unwrap_or(WritingMode::HorizontalTb);
+        let rect = self.engine.content_rect(writing_mode);
 
         let language = self.content.root()
                            .find("html")
                            .and_then(|html| html.attribute("xml:lang"))
                            .map(String::from);
 
+        let direction = self.direction();
+        let text_align = if direction == Direction::Rtl {
+            self.engine.text_align.mirror()
+        } else {
+            self.engine.text_align
+        };
+
         let style = StyleData {
             language,
+            direction,
+            writing_mode,
             font_size: self.engine.font_size,
             line_height: pt_to_px(self.engine.line_height * self.engine.font_size, self.engine.dpi).round() as i32,
-            text_align: self.engine.text_align,
+            text_align,
             start_x: rect.min.x,
             end_x: rect.max.x,
             width: rect.max.x - rect.min.x,
@@ -234,7 +287,8 @@ impl HtmlDocument {
 
         pages.push(Vec::new());
 
-        self.engine.build_display_list(self.content.root(), &style, &loop_context, &stylesheet, &root_data, &mut self.parent, &mut draw_state, &mut pages);
+        self.engine.build_display_list(self.content.root(), &style, &loop_context, &stylesheet, &root_data, self.fetcher.as_mut(), &mut draw_state, &mut pages);
+        self.engine.make_vertical(&mut pages, writing_mode);
 
         pages.retain(|page| !page.is_empty());
 
@@ -263,6 +317,15 @@ impl HtmlDocument {
     pub fn year(&self) -> Option<String> {
         self.metadata("date").map(|s| s.chars().take(4).collect())
     }
+
+    pub fn direction(&self) -> Direction {
+        self.content.root()
+            .find("html")
+            .and_then(|html| html.attribute("dir"))
+            .and_then(parse_direction)
+            .or_else(|| self.language().map(|lang| language_direction(&lang)))
+            .unwrap_or(Direction::Ltr)
+    }
 }
 
 impl Document for HtmlDocument {
@@ -275,16 +338,30 @@ impl Document for HtmlDocument {
         self.size
     }
 
+    // The headings of the first three levels act as a table of contents.
     fn toc(&mut self) -> Option<Vec<TocEntry>> {
-        None
+        if !self.headings_toc {
+            return None;
+        }
+
+        let mut nodes = Vec::new();
+        collect_headings(self.content.root(), SYNTHETIC_TOC_DEPTH, &mut nodes);
+        let headings: Vec<(usize, String, Location)> = nodes.into_iter()
+            .map(|(level, title, node)| (level, title, Location::Exact(node.offset())))
+            .collect();
+        if headings.is_empty() {
+            None
+        } else {
+            Some(headings_toc(&headings))
+        }
     }
 
-    fn chapter<'a>(&mut self, _offset: usize, _toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
-        None
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        chapter(offset, self.pages_count(), toc)
     }
 
-    fn chapter_relative<'a>(&mut self, _offset: usize, _dir: CycleDir, _toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
-        None
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        chapter_relative(offset, dir, toc)
     }
 
     fn resolve_location(&mut self, loc: Location) -> Option<usize> {
@@ -376,7 +453,7 @@ impl Document for HtmlDocument {
         let offset = self.resolve_location(loc)?;
         let page_index = self.page_index(offset)?;
         let page = self.pages[page_index].clone();
-        let pixmap = self.engine.render_page(&page, scale, &mut self.parent)?;
+        let pixmap = self.engine.render_page(&page, scale, self.fetcher.as_mut())?;
 
         Some((pixmap, offset))
     }
@@ -421,6 +498,21 @@ impl Document for HtmlDocument {
         self.pages.clear();
     }
 
+    fn set_ignore_embedded_fonts(&mut self, ignore: bool) {
+        self.engine.set_ignore_embedded_fonts(ignore);
+        self.pages.clear();
+    }
+
+    fn set_hide_ruby(&mut self, hide: bool) {
+        self.engine.set_hide_ruby(hide);
+        self.pages.clear();
+    }
+
+    fn set_extra_css(&mut self, css: &str) {
+        self.extra_css = css.to_string();
+        self.pages.clear();
+    }
+
     fn title(&self) -> Option<String> {
         self.content.root()
             .find("head")
@@ -445,6 +537,18 @@ impl Document for HtmlDocument {
             .map_err(Into::into)
     }
 
+    fn footnote(&mut self, offset: usize, uri: &str) -> Option<Footnote> {
+        let root = self.content.root();
+        let link = find_link(root, offset, uri)?;
+        let frag_index = uri.find('#')?;
+        let target = root.find_by_id(&uri[frag_index+1..])?;
+        let note = note_node(link, target)?;
+        Some(Footnote {
+            text: note_html(note),
+            location: target.offset(),
+        })
+    }
+
     fn is_reflowable(&self) -> bool {
         true
     }
@@ -452,4 +556,8 @@ impl Document for HtmlDocument {
     fn has_synthetic_page_numbers(&self) -> bool {
         true
     }
+
+    fn is_rtl(&self) -> bool {
+        self.direction() == Direction::Rtl
+    }
 }
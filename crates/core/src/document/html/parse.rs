@@ -1,8 +1,9 @@
 use fxhash::FxHashSet;
 use regex::Regex;
 use super::layout::{FontKind, FontStyle, FontWeight, WordSpacing};
-use super::layout::{TextAlign, Display, Float, ListStyleType};
-use super::layout::{InlineMaterial, GlueMaterial, PenaltyMaterial};
+use super::layout::{TextAlign, Direction, WritingMode, Display, Float, ListStyleType};
+use super::layout::{InlineMaterial, GlueMaterial, PenaltyMaterial, TextTransform, WhiteSpace};
+use super::dom::NodeRef;
 use crate::geom::Edge;
 use crate::unit::{pt_to_px, pc_to_px, mm_to_px, in_to_px};
 use crate::unit::{POINTS_PER_INCH, PICAS_PER_INCH, MILLIMETERS_PER_INCH, CENTIMETERS_PER_INCH};
@@ -148,25 +149,34 @@ pub fn parse_vertical_align(value: &str, em: f32, rem: f32, line_height: i32, dp
 }
 
 pub fn parse_font_weight(value: &str) -> Option<FontWeight> {
-    if value == "normal" {
-        Some(FontWeight::Normal)
-    } else if value == "bold" {
-        Some(FontWeight::Bold)
-    } else {
-        None
+    match value {
+        "normal" | "lighter" => Some(FontWeight::Normal),
+        "bold" | "bolder" => Some(FontWeight::Bold),
+        _ => value.parse::<u16>().ok().map(|weight| {
+            if weight >= 600 {
+                FontWeight::Bold
+            } else {
+                FontWeight::Normal
+            }
+        }),
     }
 }
 
 pub fn parse_font_style(value: &str) -> Option<FontStyle> {
     if value == "normal" {
         Some(FontStyle::Normal)
-    } else if value == "italic" {
+    } else if value == "italic" || value.starts_with("oblique") {
         Some(FontStyle::Italic)
     } else {
         None
     }
 }
 
+// Returns the name of a font family, as used for comparisons.
+pub fn normalize_font_family(value: &str) -> String {
+    value.trim().trim_matches(|c| c == '"' || c == '\'').trim().to_lowercase()
+}
+
 pub fn parse_display(value: &str) -> Option<Display> {
     match value {
         "block" => Some(Display::Block),
@@ -226,6 +236,15 @@ pub fn parse_height(value: &str, em: f32, rem: f32, width: i32, dpi: u16) -> Opt
     }
 }
 
+pub fn parse_border_width(value: &str, em: f32, rem: f32, dpi: u16) -> Option<i32> {
+    match value {
+        "thin" => parse_length("1px", em, rem, dpi),
+        "medium" => parse_length("3px", em, rem, dpi),
+        "thick" => parse_length("5px", em, rem, dpi),
+        _ => parse_length(value, em, rem, dpi),
+    }
+}
+
 fn parse_edge_length(value: &str, em: f32, rem: f32, width: i32, auto_value: i32, dpi: u16) -> i32 {
     if value == "auto" {
         auto_value
@@ -262,12 +281,33 @@ pub fn parse_edge(top_edge: Option<&str>, right_edge: Option<&str>, bottom_edge:
     e
 }
 
-pub fn parse_text_align(value: &str) -> Option<TextAlign> {
+pub fn parse_text_align(value: &str, direction: Direction) -> Option<TextAlign> {
     match value {
         "justify" => Some(TextAlign::Justify),
         "left" => Some(TextAlign::Left),
         "right" => Some(TextAlign::Right),
         "center" => Some(TextAlign::Center),
+        "start" if direction == Direction::Rtl => Some(TextAlign::Right),
+        "end" if direction == Direction::Rtl => Some(TextAlign::Left),
+        "start" => Some(TextAlign::Left),
+        "end" => Some(TextAlign::Right),
+        _ => None,
+    }
+}
+
+pub fn parse_writing_mode(value: &str) -> Option<WritingMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "horizontal-tb" | "lr" | "lr-tb" | "rl" | "rl-tb" => Some(WritingMode::HorizontalTb),
+        "vertical-rl" | "tb" | "tb-rl" => Some(WritingMode::VerticalRl),
+        "vertical-lr" | "tb-lr" => Some(WritingMode::VerticalLr),
+        _ => None,
+    }
+}
+
+pub fn parse_direction(value: &str) -> Option<Direction> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "ltr" => Some(Direction::Ltr),
+        "rtl" => Some(Direction::Rtl),
         _ => None,
     }
 }
@@ -337,6 +377,84 @@ pub fn parse_font_variant(value: &str) -> Vec<String> {
     features.into_iter().map(String::from).collect()
 }
 
+pub fn parse_text_transform(value: &str) -> Option<TextTransform> {
+    match value {
+        "none" => Some(TextTransform::None),
+        "uppercase" => Some(TextTransform::Uppercase),
+        "lowercase" => Some(TextTransform::Lowercase),
+        "capitalize" => Some(TextTransform::Capitalize),
+        _ => None,
+    }
+}
+
+pub fn parse_white_space(value: &str) -> Option<WhiteSpace> {
+    match value {
+        "normal" => Some(WhiteSpace::Normal),
+        "nowrap" => Some(WhiteSpace::NoWrap),
+        "pre" => Some(WhiteSpace::Pre),
+        "pre-wrap" | "break-spaces" => Some(WhiteSpace::PreWrap),
+        "pre-line" => Some(WhiteSpace::PreLine),
+        _ => None,
+    }
+}
+
+// Returns the generated text of the `content` property of a pseudo-element of `node`.
+pub fn parse_content(value: &str, node: NodeRef) -> Option<String> {
+    let mut text = String::new();
+    let mut chars = value.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                while let Some(d) = chars.next() {
+                    if d == c {
+                        break;
+                    } else if d == '\\' {
+                        let mut code = String::new();
+                        while code.len() < 6 && chars.peek().is_some_and(char::is_ascii_hexdigit) {
+                            code.extend(chars.next());
+                        }
+                        if code.is_empty() {
+                            text.extend(chars.next().filter(|&e| e != '\n'));
+                        } else {
+                            text.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+                            if chars.peek().is_some_and(|e| e.is_whitespace()) {
+                                chars.next();
+                            }
+                        }
+                    } else {
+                        text.push(d);
+                    }
+                }
+            },
+            _ if c.is_ascii_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some(d) = chars.next_if(|d| d.is_ascii_alphanumeric() || *d == '-') {
+                    name.push(d);
+                }
+                let mut argument = String::new();
+                if chars.next_if_eq(&'(').is_some() {
+                    argument.extend(chars.by_ref().take_while(|&d| d != ')'));
+                }
+                match name.as_str() {
+                    "none" | "normal" => return None,
+                    "open-quote" => text.push('“'),
+                    "close-quote" => text.push('”'),
+                    "attr" => text.push_str(node.attribute(argument.trim()).unwrap_or_default()),
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
 pub fn parse_color(value: &str) -> Option<u8> {
     if value.starts_with('#') {
         if value.len() < 4 {
@@ -373,13 +491,14 @@ pub fn parse_color(value: &str) -> Option<u8> {
 }
 
 #[inline]
-fn luma(r: f32, g: f32, b: f32) -> f32 {
+pub fn luma(r: f32, g: f32, b: f32) -> f32 {
     r * 0.2126 + g * 0.7152 + b * 0.0722
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::xml::XmlParser;
 
     #[test]
     fn test_parse_color() {
@@ -394,4 +513,32 @@ mod tests {
         assert_eq!(d, Some(18));
         assert_eq!(e, Some(255));
     }
+
+    #[test]
+    fn test_parse_content() {
+        let xml = XmlParser::new("<a title='note'/>").parse();
+        let node = xml.root().first_child().unwrap();
+        assert_eq!(parse_content("\"\\201C\" attr(title) ' \\2014 ' close-quote", node),
+                   Some("\u{201C}note \u{2014}\u{201D}".to_string()));
+        assert_eq!(parse_content("counter(chapter) \". \"", node), Some(". ".to_string()));
+        assert_eq!(parse_content("none", node), None);
+        assert_eq!(parse_content("\"\"", node), None);
+    }
+
+    #[test]
+    fn test_parse_text_align() {
+        assert_eq!(parse_text_align("start", Direction::Ltr), Some(TextAlign::Left));
+        assert_eq!(parse_text_align("start", Direction::Rtl), Some(TextAlign::Right));
+        assert_eq!(parse_text_align("end", Direction::Rtl), Some(TextAlign::Left));
+        assert_eq!(parse_direction("RTL"), Some(Direction::Rtl));
+        assert_eq!(parse_direction("auto"), None);
+    }
+
+    #[test]
+    fn test_parse_writing_mode() {
+        assert_eq!(parse_writing_mode("vertical-rl"), Some(WritingMode::VerticalRl));
+        assert_eq!(parse_writing_mode("tb-rl"), Some(WritingMode::VerticalRl));
+        assert_eq!(parse_writing_mode("vertical-lr"), Some(WritingMode::VerticalLr));
+        assert_eq!(parse_writing_mode("sideways-rl"), None);
+    }
 }
@@ -1,19 +1,26 @@
+use std::path::Path;
 use fxhash::FxHashMap;
+use crate::helpers::Normalize;
 use super::dom::NodeRef;
-use super::css::{CssParser, Rule, Selector, SimpleSelector};
-use super::css::{Combinator, AttributeOperator, PseudoClass};
+use super::css::{CssParser, Rule, Selector, SimpleSelector, FontFace};
+use super::css::{Combinator, AttributeOperator, PseudoClass, PseudoElement};
 
 pub type PropertyMap = FxHashMap<String, String>;
 
 #[derive(Debug, Clone)]
 pub struct StyleSheet {
-    pub rules: Vec<Rule>
+    pub rules: Vec<Rule>,
+    pub font_faces: Vec<FontFace>,
+    // The pseudo-elements targeted by the rules.
+    pub pseudo_elements: Vec<PseudoElement>,
 }
 
 impl StyleSheet {
     pub fn new() -> Self {
         StyleSheet {
             rules: Vec::new(),
+            font_faces: Vec::new(),
+            pseudo_elements: Vec::new(),
         }
     }
 
@@ -22,6 +29,26 @@ impl StyleSheet {
             other.sort();
         }
         self.rules.append(&mut other.rules);
+        self.font_faces.append(&mut other.font_faces);
+        for pseudo_element in other.pseudo_elements.drain(..) {
+            if !self.pseudo_elements.contains(&pseudo_element) {
+                self.pseudo_elements.push(pseudo_element);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn has_pseudo_element(&self, pseudo_element: PseudoElement) -> bool {
+        self.pseudo_elements.contains(&pseudo_element)
+    }
+
+    // Makes the font sources relative to the root of the resources instead of `dir`.
+    pub fn resolve_font_sources(&mut self, dir: &Path) {
+        for source in self.font_faces.iter_mut().flat_map(|font_face| font_face.sources.iter_mut()) {
+            if !source.starts_with("data:") {
+                *source = dir.join(&source).normalize().to_string_lossy().into_owned();
+            }
+        }
     }
 
     pub fn sort(&mut self) {
@@ -30,11 +57,20 @@ impl StyleSheet {
 }
 
 pub fn specified_values(node: NodeRef, stylesheet: &StyleSheet) -> PropertyMap {
+    cascade(node, stylesheet, None)
+}
+
+pub fn pseudo_element_values(node: NodeRef, stylesheet: &StyleSheet, pseudo_element: PseudoElement) -> PropertyMap {
+    cascade(node, stylesheet, Some(pseudo_element))
+}
+
+fn cascade(node: NodeRef, stylesheet: &StyleSheet, pseudo_element: Option<PseudoElement>) -> PropertyMap {
     let mut props = FxHashMap::default();
     let mut important = Vec::new();
 
     for rule in stylesheet.rules.iter()
-                          .filter(|rule| rule.selector.matches(node)) {
+                          .filter(|rule| rule.selector.pseudo_element == pseudo_element &&
+                                         rule.selector.matches(node)) {
         for declaration in &rule.declarations {
             if declaration.important {
                 important.push([&declaration.name, &declaration.value]);
@@ -44,7 +80,7 @@ pub fn specified_values(node: NodeRef, stylesheet: &StyleSheet) -> PropertyMap {
         }
     }
 
-    let local_declarations = node.attribute("style").map(|text| {
+    let local_declarations = node.attribute("style").filter(|_| pseudo_element.is_none()).map(|text| {
         CssParser::new(text).parse_declarations()
     }).unwrap_or_default();
 
@@ -148,6 +184,9 @@ impl AttributeOperator {
             AttributeOperator::StartsWith(v) => v == value ||
                                                (value.starts_with(v) &&
                                                 value[v.len()..].starts_with('-')),
+            AttributeOperator::Prefix(v) => !v.is_empty() && value.starts_with(v.as_str()),
+            AttributeOperator::Suffix(v) => !v.is_empty() && value.ends_with(v.as_str()),
+            AttributeOperator::Substring(v) => !v.is_empty() && value.contains(v.as_str()),
         }
     }
 }
@@ -157,54 +196,84 @@ impl PseudoClass {
         match self {
             PseudoClass::FirstChild => node.previous_sibling_element().is_none(),
             PseudoClass::LastChild => node.next_sibling_element().is_none(),
+            PseudoClass::NthChild(a, b) => nth_matches(*a, *b, node.previous_sibling_elements().count() + 1),
+            PseudoClass::NthLastChild(a, b) => nth_matches(*a, *b, node.next_sibling_elements().count() + 1),
+            PseudoClass::Not(selectors) => selectors.iter().all(|selec| !selec.matches(node)),
+        }
+    }
+}
+
+// Checks whether the one-based `index` can be written as `a*n + b` for some non-negative `n`.
+fn nth_matches(a: i32, b: i32, index: usize) -> bool {
+    let delta = index as i32 - b;
+    if a == 0 {
+        delta == 0
+    } else {
+        delta % a == 0 && delta / a >= 0
+    }
+}
+
+const EDGES: [&str; 4] = ["top", "right", "bottom", "left"];
+const BORDER_STYLES: [&str; 10] = ["none", "hidden", "dotted", "dashed", "solid",
+                                   "double", "groove", "ridge", "inset", "outset"];
+
+// Returns the top, right, bottom and left values of an edge shorthand.
+fn edge_values(value: &str) -> Option<[&str; 4]> {
+    let values = value.split_whitespace().collect::<Vec<&str>>();
+    match values.len() {
+        1 => Some([values[0], values[0], values[0], values[0]]),
+        2 => Some([values[0], values[1], values[0], values[1]]),
+        3 => Some([values[0], values[1], values[2], values[1]]),
+        4 => Some([values[0], values[1], values[2], values[3]]),
+        _ => None,
+    }
+}
+
+// Returns the width, style and color of a border shorthand.
+fn border_values(value: &str) -> [String; 3] {
+    let mut result = ["medium".to_string(), "none".to_string(), "currentcolor".to_string()];
+    for token in value.split_whitespace() {
+        if BORDER_STYLES.contains(&token) {
+            result[1] = token.to_string();
+        } else if token.starts_with(|c: char| c.is_ascii_digit() || c == '.') ||
+                  matches!(token, "thin" | "medium" | "thick") {
+            result[0] = token.to_string();
+        } else {
+            result[2] = token.to_string();
         }
     }
+    result
 }
 
 fn expand_and_insert(name: &str, value: &str, props: &mut PropertyMap) {
     match name {
         "margin" | "padding" => {
-            let values = value.split_whitespace().collect::<Vec<&str>>();
-            match values.len() {
-                1 => {
-                    props.insert(format!("{}-top", name), value.to_string());
-                    props.insert(format!("{}-right", name), value.to_string());
-                    props.insert(format!("{}-bottom", name), value.to_string());
-                    props.insert(format!("{}-left", name), value.to_string());
-                },
-                2 => {
-                    let vertical = values[0];
-                    let horizontal = values[1];
-                    props.insert(format!("{}-top", name), vertical.to_string());
-                    props.insert(format!("{}-right", name), horizontal.to_string());
-                    props.insert(format!("{}-bottom", name), vertical.to_string());
-                    props.insert(format!("{}-left", name), horizontal.to_string());
-                },
-                3 => {
-                    let top = values[0];
-                    let horizontal = values[1];
-                    let bottom = values[2];
-                    props.insert(format!("{}-top", name), top.to_string());
-                    props.insert(format!("{}-right", name), horizontal.to_string());
-                    props.insert(format!("{}-bottom", name), bottom.to_string());
-                    props.insert(format!("{}-left", name), horizontal.to_string());
-                },
-                4 => {
-                    let top = values[0];
-                    let right = values[1];
-                    let bottom = values[2];
-                    let left = values[3];
-                    props.insert(format!("{}-top", name), top.to_string());
-                    props.insert(format!("{}-right", name), right.to_string());
-                    props.insert(format!("{}-bottom", name), bottom.to_string());
-                    props.insert(format!("{}-left", name), left.to_string());
-                },
-                _ => (),
+            if let Some(values) = edge_values(value) {
+                for (edge, value) in EDGES.iter().zip(values.iter()) {
+                    props.insert(format!("{}-{}", name, edge), value.to_string());
+                }
+            }
+        },
+        "border-width" | "border-style" | "border-color" => {
+            if let Some(values) = edge_values(value) {
+                let kind = &name[7..];
+                for (edge, value) in EDGES.iter().zip(values.iter()) {
+                    props.insert(format!("border-{}-{}", edge, kind), value.to_string());
+                }
+            }
+        },
+        "border" | "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            let values = border_values(value);
+            let edges = match name.strip_prefix("border-") {
+                Some(edge) => vec![edge],
+                None => EDGES.to_vec(),
+            };
+            for edge in edges {
+                for (kind, value) in ["width", "style", "color"].iter().zip(values.iter()) {
+                    props.insert(format!("border-{}-{}", edge, kind), value.clone());
+                }
             }
         },
-        // TODO: border -> border-{top,right,bottom,left}-{width,style,color}
-        // border-left -> border-left-{width,style,color}
-        // border-style -> border-{top,right,bottom,left}-style
         _ => {
             props.insert(name.to_string(), value.to_string());
         }
@@ -213,8 +282,8 @@ fn expand_and_insert(name: &str, value: &str, props: &mut PropertyMap) {
 
 #[cfg(test)]
 mod tests {
-    use super::specified_values;
-    use super::super::css::CssParser;
+    use super::{specified_values, pseudo_element_values};
+    use super::super::css::{CssParser, PseudoElement};
     use super::super::xml::XmlParser;
 
     #[test]
@@ -232,4 +301,43 @@ mod tests {
                                                 ("c".to_string(), "7".to_string())].iter().cloned().collect());
         assert_eq!(specified_values(n2, &css), [("b".to_string(), "5".to_string())].iter().cloned().collect());
     }
+
+    #[test]
+    fn pseudo_style() {
+        let xml = XmlParser::new("<div><p id='a' lang='en-US'/><h1/><p class='b' style='c: 1'/><p/></div>").parse();
+        let mut css = CssParser::new("p:nth-child(odd) { a: 1 }\
+                                      p:not(#a, .c) { b: 2 }\
+                                      [lang^=en] { c: 3 }\
+                                      h1 ~ p:nth-last-child(-n+2) { d: 4 }\
+                                      p::before { content: 'x' }").parse();
+        css.sort();
+        let nodes = xml.root().first_child().unwrap().children().collect::<Vec<_>>();
+        let names = |node| {
+            let mut names = specified_values(node, &css).into_keys().collect::<Vec<String>>();
+            names.sort();
+            names
+        };
+        assert_eq!(names(nodes[0]), vec!["a", "c"]);
+        assert_eq!(names(nodes[1]), Vec::<String>::new());
+        assert_eq!(names(nodes[2]), vec!["a", "b", "c", "d"]);
+        assert_eq!(names(nodes[3]), vec!["b", "d"]);
+        let props = pseudo_element_values(nodes[3], &css, PseudoElement::Before);
+        assert_eq!(props.get("content").map(String::as_str), Some("'x'"));
+        assert!(pseudo_element_values(nodes[3], &css, PseudoElement::After).is_empty());
+    }
+
+    #[test]
+    fn border_style() {
+        let xml = XmlParser::new("<td style='border-bottom: 2px dashed'/>").parse();
+        let mut css = CssParser::new("td { border: thin solid black; border-width: 1px 3px }").parse();
+        css.sort();
+        let props = specified_values(xml.root().first_child().unwrap(), &css);
+        assert_eq!(props.get("border-top-width").map(String::as_str), Some("1px"));
+        assert_eq!(props.get("border-right-width").map(String::as_str), Some("3px"));
+        assert_eq!(props.get("border-left-style").map(String::as_str), Some("solid"));
+        assert_eq!(props.get("border-left-color").map(String::as_str), Some("black"));
+        assert_eq!(props.get("border-bottom-width").map(String::as_str), Some("2px"));
+        assert_eq!(props.get("border-bottom-style").map(String::as_str), Some("dashed"));
+        assert_eq!(props.get("border-bottom-color").map(String::as_str), Some("currentcolor"));
+    }
 }
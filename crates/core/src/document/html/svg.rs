@@ -0,0 +1,160 @@
+use std::path::Path;
+use std::sync::Arc;
+use fxhash::FxHashMap;
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use resvg::{usvg, tiny_skia};
+use resvg::usvg::fontdb;
+use crate::framebuffer::Pixmap;
+use crate::helpers::{Normalize, decode_entities};
+use super::dom::NodeRef;
+use super::engine::ResourceFetcher;
+use super::parse::luma;
+use super::xml::XmlParser;
+
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+lazy_static! {
+    // The fonts used to render the texts of SVG images.
+    static ref FONT_DATABASE: Arc<fontdb::Database> = {
+        let mut db = fontdb::Database::new();
+        db.load_fonts_dir("fonts");
+        db.set_serif_family("Noto Serif");
+        db.set_sans_serif_family("Noto Sans");
+        db.set_monospace_family("Source Code Variable");
+        db.set_cursive_family("Parisienne");
+        db.set_fantasy_family("Delius");
+        Arc::new(db)
+    };
+}
+
+pub struct SvgImage {
+    tree: usvg::Tree,
+}
+
+impl SvgImage {
+    // The images referenced by the SVG document are resolved relative to `dir`.
+    pub fn new(buf: &[u8], dir: &Path, resource_fetcher: &mut dyn ResourceFetcher) -> Option<SvgImage> {
+        let images = std::str::from_utf8(buf).ok()
+                        .map(|text| fetch_images(text, dir, resource_fetcher))
+                        .unwrap_or_default();
+        let options = usvg::Options {
+            fontdb: FONT_DATABASE.clone(),
+            image_href_resolver: usvg::ImageHrefResolver {
+                resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+                resolve_string: Box::new(move |href, options| {
+                    let mime = if href.ends_with(".svg") { "image/svg+xml" } else { "text/plain" };
+                    images.get(href).and_then(|data| {
+                        (usvg::ImageHrefResolver::default_data_resolver())(mime, Arc::clone(data), options)
+                    })
+                }),
+            },
+            ..Default::default()
+        };
+        usvg::Tree::from_data(buf, &options).ok()
+             .map(|tree| SvgImage { tree })
+    }
+
+    // The user unit is considered to be one point.
+    pub fn dims(&self) -> (f32, f32) {
+        let size = self.tree.size();
+        (size.width(), size.height())
+    }
+
+    pub fn pixmap(&self, scale: f32) -> Option<Pixmap> {
+        let (width, height) = self.dims();
+        let width = (scale * width).round().max(1.0) as u32;
+        let height = (scale * height).round().max(1.0) as u32;
+        let mut canvas = tiny_skia::Pixmap::new(width, height)?;
+        canvas.fill(tiny_skia::Color::WHITE);
+        resvg::render(&self.tree, tiny_skia::Transform::from_scale(scale, scale), &mut canvas.as_mut());
+        let mut pixmap = Pixmap::try_new(width, height)?;
+        // The canvas is opaque: the premultiplied components are the actual ones.
+        for (dst, src) in pixmap.data_mut().iter_mut().zip(canvas.pixels()) {
+            *dst = luma(src.red() as f32, src.green() as f32, src.blue() as f32).round() as u8;
+        }
+        Some(pixmap)
+    }
+}
+
+pub fn is_svg(path: &str, buf: &[u8]) -> bool {
+    let path = path.to_lowercase();
+    if path.ends_with(".svg") || path.ends_with(".svgz") {
+        return true;
+    }
+    let head = String::from_utf8_lossy(&buf[..buf.len().min(1024)]);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    (head.starts_with("<?xml") || head.starts_with("<!DOCTYPE svg") || head.starts_with("<svg")) &&
+    head.contains("<svg")
+}
+
+// The raster images can't be fetched by usvg.
+fn fetch_images(text: &str, dir: &Path, resource_fetcher: &mut dyn ResourceFetcher) -> FxHashMap<String, Arc<Vec<u8>>> {
+    let root = XmlParser::new(text).parse();
+    let mut images = FxHashMap::default();
+    for node in root.root().descendants().filter(|node| node.tag_name() == Some("image")) {
+        if let Some(href) = node.attribute("xlink:href").or_else(|| node.attribute("href")) {
+            let href = decode_entities(href).into_owned();
+            if href.starts_with("data:") || images.contains_key(&href) {
+                continue;
+            }
+            let path = dir.join(percent_decode_str(&href).decode_utf8_lossy().as_ref()).normalize();
+            if let Some(buf) = path.to_str().and_then(|path| resource_fetcher.fetch(path).ok()) {
+                images.insert(href, Arc::new(buf));
+            }
+        }
+    }
+    images
+}
+
+// Serializes an SVG element found in an HTML document.
+// The namespaces are usually declared by the root of the document.
+pub fn inline_svg(node: NodeRef) -> String {
+    let mut buf = String::new();
+    node.write_xml(&mut buf);
+    let name = node.tag_qualified_name().or_else(|| node.tag_name()).unwrap_or("svg");
+    let svg_attr = name.find(':').map(|index| format!("xmlns:{}", &name[..index]))
+                       .unwrap_or_else(|| "xmlns".to_string());
+    let mut declarations = String::new();
+    if node.attribute(&svg_attr).is_none() {
+        declarations.push_str(&format!(" {}=\"{}\"", svg_attr, SVG_NAMESPACE));
+    }
+    if node.attribute("xmlns:xlink").is_none() {
+        declarations.push_str(&format!(" xmlns:xlink=\"{}\"", XLINK_NAMESPACE));
+    }
+    buf.insert_str(1 + name.len(), &declarations);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    #[test]
+    fn test_inline_svg() {
+        let text = "<html xmlns:xlink=\"http://www.w3.org/1999/xlink\"><body><div>\
+                    <svg viewBox=\"0 0 6 8\"><image width=\"6\" height=\"8\" xlink:href=\"cover.jpg\"/></svg>\
+                    </div></body></html>";
+        let tree = XmlParser::new(text).parse();
+        let svg = inline_svg(tree.root().find("svg").unwrap());
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" \
+                                 xmlns:xlink=\"http://www.w3.org/1999/xlink\" "));
+        assert!(svg.ends_with("/></svg>"));
+        assert!(is_svg("Images/Cover.SVG", b""));
+        assert!(is_svg("cover", svg.as_bytes()));
+        assert!(!is_svg("cover.jpg", b"\xff\xd8\xff\xe0"));
+    }
+
+    #[test]
+    fn test_svg_pixmap() {
+        let text = "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"4\" height=\"2\">\
+                    <rect width=\"2\" height=\"2\" fill=\"black\"/></svg>";
+        let svg = SvgImage::new(text.as_bytes(), Path::new(""), &mut PathBuf::default()).unwrap();
+        assert_eq!(svg.dims(), (4.0, 2.0));
+        let pixmap = svg.pixmap(2.0).unwrap();
+        assert_eq!((pixmap.width, pixmap.height), (8, 4));
+        assert_eq!(&pixmap.data[..8], &[0, 0, 0, 0, 255, 255, 255, 255]);
+    }
+}
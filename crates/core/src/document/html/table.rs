@@ -0,0 +1,291 @@
+use std::ops::Range;
+use super::dom::NodeRef;
+
+// The maximum spans allowed by the HTML specification.
+const MAX_COLSPAN: usize = 1000;
+const MAX_ROWSPAN: usize = 65534;
+
+#[derive(Debug, Copy, Clone)]
+pub struct TableCell<'a> {
+    pub node: NodeRef<'a>,
+    pub row: usize,
+    pub column: usize,
+    pub rowspan: usize,
+    pub colspan: usize,
+}
+
+impl TableCell<'_> {
+    pub fn last_row(&self) -> usize {
+        self.row + self.rowspan - 1
+    }
+
+    pub fn columns(&self) -> Range<usize> {
+        self.column..self.column + self.colspan
+    }
+}
+
+// The cells of a table, placed on a grid according to their spans.
+// The header rows come first and the footer rows last.
+#[derive(Debug, Clone)]
+pub struct TableGrid<'a> {
+    pub caption: Option<NodeRef<'a>>,
+    pub rows: Vec<NodeRef<'a>>,
+    pub header_rows_count: usize,
+    pub columns_count: usize,
+    pub cells: Vec<TableCell<'a>>,
+}
+
+fn is_cell(node: &NodeRef) -> bool {
+    matches!(node.tag_name(), Some("td" | "th"))
+}
+
+fn rows_of<'a>(group: NodeRef<'a>) -> impl Iterator<Item=NodeRef<'a>> {
+    group.children().filter(|child| child.tag_name() == Some("tr"))
+}
+
+fn span(cell: NodeRef, name: &str) -> Option<usize> {
+    cell.attribute(name).and_then(|value| value.trim().parse().ok())
+}
+
+impl<'a> TableGrid<'a> {
+    pub fn new(table: NodeRef<'a>) -> TableGrid<'a> {
+        let mut caption = None;
+        let mut head = Vec::new();
+        let mut body = Vec::new();
+        let mut foot = Vec::new();
+
+        for child in table.children().filter(|child| child.is_element()) {
+            match child.tag_name() {
+                Some("caption") if caption.is_none() => caption = Some(child),
+                Some("thead") => head.extend(rows_of(child)),
+                Some("tbody") => body.extend(rows_of(child)),
+                Some("tfoot") => foot.extend(rows_of(child)),
+                Some("tr") => body.push(child),
+                _ => (),
+            }
+        }
+
+        // Without an explicit header, the leading rows made of header cells act as one.
+        if head.is_empty() {
+            let count = body.iter().take_while(|row| {
+                let mut cells = row.children().filter(is_cell).peekable();
+                cells.peek().is_some() && cells.all(|cell| cell.tag_name() == Some("th"))
+            }).count();
+            if count < body.len() {
+                head.extend(body.drain(..count));
+            }
+        }
+
+        let header_rows_count = head.len();
+        let mut rows = head;
+        rows.append(&mut body);
+        rows.append(&mut foot);
+
+        let mut cells = Vec::new();
+        // The number of rows, including the current one, covered by the cells above.
+        let mut covered: Vec<usize> = Vec::new();
+
+        for (index, row) in rows.iter().enumerate() {
+            let mut column = 0;
+
+            for node in row.children().filter(is_cell) {
+                while covered.get(column).is_some_and(|&count| count > 0) {
+                    column += 1;
+                }
+
+                let colspan = span(node, "colspan").unwrap_or(1).clamp(1, MAX_COLSPAN);
+                let remaining_rows = rows.len() - index;
+                let rowspan = match span(node, "rowspan") {
+                    Some(0) => remaining_rows,
+                    Some(n) => n.min(MAX_ROWSPAN).min(remaining_rows).max(1),
+                    None => 1,
+                };
+
+                if covered.len() < column + colspan {
+                    covered.resize(column + colspan, 0);
+                }
+
+                for count in &mut covered[column..column+colspan] {
+                    *count = rowspan;
+                }
+
+                cells.push(TableCell { node, row: index, column, rowspan, colspan });
+                column += colspan;
+            }
+
+            for count in covered.iter_mut() {
+                *count = count.saturating_sub(1);
+            }
+        }
+
+        TableGrid {
+            caption,
+            rows,
+            header_rows_count,
+            columns_count: covered.len(),
+            cells,
+        }
+    }
+}
+
+// Grows the given columns so that they are at least as wide as `width`,
+// in proportion of their current widths.
+fn spread(widths: &mut [i32], width: i32) {
+    let current: i32 = widths.iter().sum();
+
+    if current >= width || widths.is_empty() {
+        return;
+    }
+
+    let extra = width - current;
+    let count = widths.len() as i32;
+    let mut given = 0;
+
+    for (index, w) in widths.iter_mut().enumerate() {
+        let delta = if index as i32 == count - 1 {
+            extra - given
+        } else if current > 0 {
+            (extra as i64 * *w as i64 / current as i64) as i32
+        } else {
+            extra / count
+        };
+        *w += delta;
+        given += delta;
+    }
+}
+
+// Computes the minimum and maximum widths of the columns from the ones of the cells.
+// Each item of `cells` holds the columns spanned by a cell, its minimum and maximum widths.
+pub fn column_bounds(cells: &[(Range<usize>, i32, i32)], columns_count: usize) -> (Vec<i32>, Vec<i32>) {
+    let mut min_widths = vec![0; columns_count];
+    let mut max_widths = vec![0; columns_count];
+    let mut spanning = Vec::new();
+
+    for (columns, min_width, max_width) in cells {
+        if columns.len() == 1 {
+            let index = columns.start;
+            min_widths[index] = min_widths[index].max(*min_width);
+            max_widths[index] = max_widths[index].max(*max_width);
+        } else if !columns.is_empty() {
+            spanning.push((columns.clone(), *min_width, *max_width));
+        }
+    }
+
+    spanning.sort_by_key(|(columns, ..)| columns.len());
+
+    for (columns, min_width, max_width) in spanning {
+        spread(&mut min_widths[columns.clone()], min_width);
+        spread(&mut max_widths[columns], max_width);
+    }
+
+    for (min_width, max_width) in min_widths.iter().zip(max_widths.iter_mut()) {
+        *max_width = (*max_width).max(*min_width);
+    }
+
+    (min_widths, max_widths)
+}
+
+// Distributes `width` among the columns.
+// The result is narrower than `width` when the columns don't need that much space,
+// and wider when they can't fit in it.
+pub fn column_widths(min_widths: &[i32], max_widths: &[i32], width: i32, stretch: bool) -> Vec<i32> {
+    let min_width: i32 = min_widths.iter().sum();
+    let max_width: i32 = max_widths.iter().sum();
+
+    if min_width >= width {
+        return min_widths.to_vec();
+    }
+
+    if max_width <= width {
+        let mut widths = max_widths.to_vec();
+        if stretch {
+            spread(&mut widths, width);
+        }
+        return widths;
+    }
+
+    // https://www.w3.org/MarkUp/html3/tables.html
+    let factor = (width - min_width) as f32 / (max_width - min_width) as f32;
+    let mut widths: Vec<i32> = min_widths.iter().zip(max_widths.iter())
+                                         .map(|(a, b)| a + ((b - a) as f32 * factor) as i32)
+                                         .collect();
+    let total: i32 = widths.iter().sum();
+    if let Some(w) = widths.iter_mut().max_by_key(|w| **w) {
+        *w += width - total;
+    }
+    widths
+}
+
+// Splits the columns into consecutive groups that fit in `width`.
+pub fn split_columns(min_widths: &[i32], width: i32) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    let mut current = 0;
+
+    for (index, w) in min_widths.iter().enumerate() {
+        if index > start && current + w > width {
+            groups.push(start..index);
+            start = index;
+            current = 0;
+        }
+        current += w;
+    }
+
+    if start < min_widths.len() {
+        groups.push(start..min_widths.len());
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::xml::XmlParser;
+
+    #[test]
+    fn test_grid() {
+        let text = "<table><caption>C</caption><tbody><tr><td rowspan='2'>a</td><td colspan='2'>b</td></tr>\
+                    <tr><td>c</td><td>d</td></tr></tbody><thead><tr><th>x</th><th>y</th><th>z</th></tr></thead>\
+                    <tfoot><tr><td colspan='3'>f</td></tr></tfoot></table>";
+        let tree = XmlParser::new(text).parse();
+        let grid = TableGrid::new(tree.root().find("table").unwrap());
+        assert!(grid.caption.is_some());
+        assert_eq!(grid.rows.len(), 4);
+        assert_eq!(grid.header_rows_count, 1);
+        assert_eq!(grid.columns_count, 3);
+        let positions: Vec<(String, usize, usize, usize, usize)> = grid.cells.iter().map(|cell| {
+            (cell.node.text(), cell.row, cell.column, cell.rowspan, cell.colspan)
+        }).collect();
+        assert_eq!(positions, vec![("x".to_string(), 0, 0, 1, 1), ("y".to_string(), 0, 1, 1, 1),
+                                   ("z".to_string(), 0, 2, 1, 1), ("a".to_string(), 1, 0, 2, 1),
+                                   ("b".to_string(), 1, 1, 1, 2), ("c".to_string(), 2, 1, 1, 1),
+                                   ("d".to_string(), 2, 2, 1, 1), ("f".to_string(), 3, 0, 1, 3)]);
+    }
+
+    #[test]
+    fn test_implicit_header() {
+        let text = "<table><tr><th>x</th></tr><tr><td rowspan='0'>a</td></tr><tr><td>b</td></tr></table>";
+        let tree = XmlParser::new(text).parse();
+        let grid = TableGrid::new(tree.root().find("table").unwrap());
+        assert_eq!(grid.header_rows_count, 1);
+        assert_eq!(grid.cells[1].rowspan, 2);
+        assert_eq!(grid.cells[2].column, 1);
+    }
+
+    #[test]
+    fn test_column_widths() {
+        let cells = vec![(0..1, 10, 50), (1..2, 20, 20), (0..2, 60, 100)];
+        let (min_widths, max_widths) = column_bounds(&cells, 2);
+        assert_eq!(min_widths, vec![20, 40]);
+        assert_eq!(max_widths, vec![71, 40]);
+        assert_eq!(column_widths(&min_widths, &max_widths, 200, false), vec![71, 40]);
+        assert_eq!(column_widths(&min_widths, &max_widths, 222, true), vec![142, 80]);
+        assert_eq!(column_widths(&min_widths, &max_widths, 30, false), vec![20, 40]);
+        let widths = column_widths(&min_widths, &max_widths, 86, false);
+        assert_eq!(widths.iter().sum::<i32>(), 86);
+        assert!(widths[0] > 20 && widths[1] == 40);
+        assert_eq!(split_columns(&[30, 30, 50, 10], 60), vec![0..2, 2..4]);
+        assert_eq!(split_columns(&[80, 10], 60), vec![0..1, 1..2]);
+    }
+}
@@ -2,6 +2,10 @@ pub mod djvu;
 pub mod pdf;
 pub mod epub;
 pub mod html;
+pub mod comic;
+pub mod fb2;
+pub mod text;
+pub mod reflow;
 
 mod djvulibre_sys;
 mod mupdf_sys;
@@ -11,10 +15,11 @@ use std::process::Command;
 use std::path::Path;
 use std::fs::{self, File};
 use std::ffi::OsStr;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::os::unix::fs::FileExt;
 use anyhow::{Error, format_err};
 use regex::Regex;
+use lazy_static::lazy_static;
 use nix::sys::statvfs;
 #[cfg(target_os = "linux")]
 use nix::sys::sysinfo;
@@ -26,11 +31,16 @@ use self::djvu::DjvuOpener;
 use self::pdf::PdfOpener;
 use self::epub::EpubDocument;
 use self::html::HtmlDocument;
+use self::comic::ComicDocument;
+use self::fb2::Fb2Document;
+use self::text::TextDocument;
+use self::html::dom::NodeRef;
 use crate::geom::{Boundary, CycleDir};
 use crate::metadata::{TextAlign, Annotation};
 use crate::framebuffer::Pixmap;
 use crate::settings::INTERNAL_CARD_ROOT;
 use crate::device::CURRENT_DEVICE;
+use crate::helpers::decode_entities;
 
 pub const BYTES_PER_PAGE: f64 = 2048.0;
 
@@ -83,6 +93,21 @@ pub struct TocEntry {
     pub children: Vec<TocEntry>,
 }
 
+// The HTML content of a note and the location of its target.
+#[derive(Debug, Clone)]
+pub struct Footnote {
+    pub text: String,
+    pub location: usize,
+}
+
+// The side of a two-page spread on which a page is meant to be shown.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageSpread {
+    Left,
+    Right,
+    Center,
+}
+
 #[derive(Debug, Clone)]
 pub struct Neighbors {
     pub previous_page: Option<usize>,
@@ -94,6 +119,13 @@ pub trait Document: Send+Sync {
     fn pages_count(&self) -> usize;
 
     fn toc(&mut self) -> Option<Vec<TocEntry>>;
+
+    // Like `toc`, but also builds the tables of contents that are too slow to build
+    // each time the document is opened.
+    fn full_toc(&mut self) -> Option<Vec<TocEntry>> {
+        self.toc()
+    }
+
     fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)>;
     fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry>;
     fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)>;
@@ -111,6 +143,18 @@ pub trait Document: Send+Sync {
     fn set_stretch_tolerance(&mut self, stretch_tolerance: f32);
     fn set_ignore_document_css(&mut self, ignore: bool);
 
+    fn set_ignore_embedded_fonts(&mut self, _ignore: bool) { }
+
+    fn set_hide_ruby(&mut self, _hide: bool) { }
+
+    // Style rules applied after the user stylesheet.
+    fn set_extra_css(&mut self, _css: &str) { }
+
+    // Maps the locations of the print pages to their names.
+    fn page_list(&mut self) -> Option<BTreeMap<usize, String>> {
+        None
+    }
+
     fn title(&self) -> Option<String>;
     fn author(&self) -> Option<String>;
     fn metadata(&self, key: &str) -> Option<String>;
@@ -121,10 +165,42 @@ pub trait Document: Send+Sync {
         false
     }
 
+    fn is_rtl(&self) -> bool {
+        false
+    }
+
+    fn page_spread(&self, _index: usize) -> Option<PageSpread> {
+        None
+    }
+
+    fn is_protected(&self) -> bool {
+        false
+    }
+
+    // Whether any page has text, scanned documents might only have images.
+    fn has_text(&mut self) -> bool {
+        true
+    }
+
+    // Returns whether *password* unlocks the document.
+    fn authenticate(&mut self, _password: &str) -> bool {
+        true
+    }
+
+    // Returns the annotations stored inside the document.
+    fn embedded_annotations(&mut self) -> Vec<Annotation> {
+        Vec::new()
+    }
+
     fn save(&self, _path: &str) -> Result<(), Error> {
         Err(format_err!("this document can't be saved"))
     }
 
+    // Returns the note targeted by the link to `uri` found at `offset`.
+    fn footnote(&mut self, _offset: usize, _uri: &str) -> Option<Footnote> {
+        None
+    }
+
     fn preview_pixmap(&mut self, width: f32, height: f32) -> Option<Pixmap> {
         self.dims(0).and_then(|dims| {
             let scale = (width / dims.0).min(height / dims.1);
@@ -165,6 +241,10 @@ pub trait Document: Send+Sync {
 }
 
 pub fn file_kind<P: AsRef<Path>>(path: P) -> Option<String> {
+    if path.as_ref().is_dir() {
+        return Some("dir".to_string());
+    }
+
     path.as_ref().extension()
         .and_then(OsStr::to_str)
         .map(str::to_lowercase)
@@ -188,6 +268,15 @@ pub fn guess_kind<P: AsRef<Path>>(path: P) -> Result<&'static str, Error> {
         return Ok("pdf");
     } else if &magic == b"AT&T" {
         return Ok("djvu");
+    } else if &magic == b"Rar!" {
+        return Ok("cbr");
+    } else if &magic == b"7z\xBC\xAF" {
+        return Ok("cb7");
+    }
+
+    let mut magic = [0; 5];
+    if file.read_exact_at(&mut magic, 257).is_ok() && &magic == b"ustar" {
+        return Ok("cbt");
     }
 
     Err(format_err!("Unknown file type"))
@@ -233,6 +322,21 @@ pub fn open<P: AsRef<Path>>(path: P) -> Option<Box<dyn Document>> {
                              .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
                              .map(|d| Box::new(d) as Box<dyn Document>).ok()
             },
+            "fb2" => {
+                Fb2Document::new(&path)
+                            .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                            .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
+            "txt" | "md" | "markdown" => {
+                TextDocument::new(&path)
+                             .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                             .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
+            "cbr" | "cb7" | "cbt" | "dir" => {
+                ComicDocument::new(&path)
+                              .map_err(|e| eprintln!("{}: {:#}.", path.as_ref().display(), e))
+                              .map(|d| Box::new(d) as Box<dyn Document>).ok()
+            },
             "djvu" | "djv" => {
                 DjvuOpener::new().and_then(|o| {
                     o.open(path)
@@ -241,7 +345,7 @@ pub fn open<P: AsRef<Path>>(path: P) -> Option<Box<dyn Document>> {
             },
             _ => {
                 PdfOpener::new().and_then(|mut o| {
-                    if matches!(k.as_ref(), "mobi" | "fb2" | "xps" | "txt") {
+                    if matches!(k.as_ref(), "mobi" | "xps") {
                         o.load_user_stylesheet();
                     }
                     o.open(path)
@@ -356,6 +460,28 @@ pub fn bookmarks_as_html(bookmarks: &BTreeSet<usize>, index: usize, synthetic: b
     buf
 }
 
+// Returns the pages, in reading order, of the two-page spread that includes the page
+// at `index`. The sides declared by the document take precedence over the parity of the index.
+pub fn spread_pages<F>(index: usize, pages_count: usize, lone_cover: bool, rtl: bool, side: F) -> (usize, Option<usize>)
+                       where F: Fn(usize) -> Option<PageSpread> {
+    let leading_side = if rtl { PageSpread::Right } else { PageSpread::Left };
+    let leads = |i: usize| match side(i) {
+        Some(PageSpread::Center) => true,
+        Some(s) => s == leading_side,
+        None => i.is_multiple_of(2) != lone_cover,
+    };
+    let paired = |i: usize| i + 1 < pages_count && !(lone_cover && i == 0) &&
+                            side(i) != Some(PageSpread::Center) && leads(i) && !leads(i + 1);
+
+    if paired(index) {
+        (index, Some(index + 1))
+    } else if index > 0 && paired(index - 1) {
+        (index - 1, Some(index))
+    } else {
+        (index, None)
+    }
+}
+
 #[inline]
 fn chapter(index: usize, pages_count: usize, toc: &[TocEntry]) -> Option<(&TocEntry, f32)> {
     let mut chap = None;
@@ -458,6 +584,161 @@ pub fn chapter_from_uri<'a>(target_uri: &str, toc: &'a [TocEntry]) -> Option<&'a
     None
 }
 
+const HEADING_NAMES: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+// Depth of the tables of contents built from headings.
+const SYNTHETIC_TOC_DEPTH: usize = 3;
+// Longer lines are considered as regular text.
+const MAX_HEADING_LENGTH: usize = 80;
+// Minimum ratio between the font size of a heading and the font size of the body text.
+const HEADING_SIZE_RATIO: f32 = 1.15;
+// Lines repeated on more pages are running heads.
+const MAX_HEADING_REPEATS: usize = 2;
+const HEADING_NUMBER: &str = r"[0-9]+|[ivxlcdm]+|(?:twenty|thirty|forty|fifty)(?:-(?:one|two|three|four|five|six|seven|eight|nine))?|one|two|three|four|five|six|seven|eight|nine|ten|eleven|twelve|thirteen|fourteen|fifteen|sixteen|seventeen|eighteen|nineteen|first|second|third|last";
+
+lazy_static! {
+    static ref PART_HEADING: Regex = Regex::new(&format!(r"(?i)^(?:part|book|volume|partie|livre|teil|parte)\s+(?:{})\b", HEADING_NUMBER)).unwrap();
+    static ref CHAPTER_HEADING: Regex = Regex::new(&format!(r"(?i)^(?:(?:chapter|section|chapitre|kapitel|cap[ií]tulo|capitolo)\s+(?:{})\b|(?:prologue|[ée]pilogue|pr[ée]face|foreword|introduction|afterword)\W*$)", HEADING_NUMBER)).unwrap();
+}
+
+pub fn heading_level(node: NodeRef) -> Option<usize> {
+    node.tag_name()
+        .and_then(|name| HEADING_NAMES.iter().position(|&n| n == name))
+        .map(|index| index + 1)
+}
+
+// Collects the non-empty headings up to *max_level*, with their titles.
+pub fn collect_headings<'a>(node: NodeRef<'a>, max_level: usize, headings: &mut Vec<(usize, String, NodeRef<'a>)>) {
+    for child in node.children() {
+        if let Some(level) = heading_level(child) {
+            let title = decode_entities(&child.text()).split_whitespace()
+                                                      .collect::<Vec<&str>>()
+                                                      .join(" ");
+            if level <= max_level && !title.is_empty() {
+                headings.push((level, title, child));
+            }
+        } else {
+            collect_headings(child, max_level, headings);
+        }
+    }
+}
+
+// Builds a table of contents from a flat list of headings: each heading contains
+// the following headings of a higher level.
+pub fn headings_toc(headings: &[(usize, String, Location)]) -> Vec<TocEntry> {
+    nest_headings(headings, &mut 0, 0, &mut 0)
+}
+
+fn nest_headings(headings: &[(usize, String, Location)], position: &mut usize, level: usize, index: &mut usize) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+
+    while let Some((heading_level, title, location)) = headings.get(*position) {
+        if *heading_level <= level {
+            break;
+        }
+        *position += 1;
+        let current_index = *index;
+        *index += 1;
+        let children = nest_headings(headings, position, *heading_level, index);
+        entries.push(TocEntry {
+            title: title.clone(),
+            location: location.clone(),
+            index: current_index,
+            children,
+        });
+    }
+
+    entries
+}
+
+// Recognizes lines such as *Chapter 12*, *Part Two* or *Prologue*, and returns their level.
+pub fn chapter_heading_level(line: &str) -> Option<usize> {
+    let line = line.trim();
+    if line.chars().count() > MAX_HEADING_LENGTH {
+        return None;
+    }
+    if PART_HEADING.is_match(line) {
+        Some(1)
+    } else if CHAPTER_HEADING.is_match(line) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+// A line of text from a fixed-layout document.
+#[derive(Debug, Clone)]
+pub struct StyledLine {
+    pub page: usize,
+    pub text: String,
+    pub size: f32,
+    pub bold: bool,
+    // Whether the line is the only one of its block.
+    pub isolated: bool,
+}
+
+#[inline]
+fn size_key(size: f32) -> i32 {
+    (2.0 * size).round() as i32
+}
+
+// Headings are the short lines set noticeably larger than the body text, or the isolated
+// bold lines. Consecutive lines with the same size form a single heading, and the three
+// largest sizes give the levels.
+pub fn styled_headings(lines: &[StyledLine]) -> Vec<(usize, String, Location)> {
+    let mut sizes: FxHashMap<i32, usize> = FxHashMap::default();
+    for line in lines {
+        *sizes.entry(size_key(line.size)).or_insert(0) += line.text.chars().count();
+    }
+    let body_size = match sizes.iter().max_by_key(|(key, count)| (**count, -**key)) {
+        Some((key, _)) => *key as f32 / 2.0,
+        None => return Vec::new(),
+    };
+
+    let mut candidates: Vec<(usize, String, i32)> = Vec::new();
+    let mut last_index = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let text = line.text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if text.chars().count() > MAX_HEADING_LENGTH || !text.chars().any(char::is_alphabetic) {
+            continue;
+        }
+        let large = line.size >= HEADING_SIZE_RATIO * body_size;
+        let strong = line.bold && line.isolated && line.size >= body_size;
+        if !large && !strong {
+            continue;
+        }
+        let key = size_key(line.size);
+        if let Some((page, title, last_key)) = candidates.last_mut() {
+            if last_index == Some(i.wrapping_sub(1)) && *page == line.page && *last_key == key &&
+               title.chars().count() + text.chars().count() < MAX_HEADING_LENGTH {
+                title.push(' ');
+                title.push_str(&text);
+                last_index = Some(i);
+                continue;
+            }
+        }
+        candidates.push((line.page, text, key));
+        last_index = Some(i);
+    }
+
+    let mut pages: FxHashMap<&str, BTreeSet<usize>> = FxHashMap::default();
+    for (page, title, _) in &candidates {
+        pages.entry(title.as_str()).or_default().insert(*page);
+    }
+
+    let mut keys: Vec<i32> = candidates.iter().map(|(_, _, key)| *key).collect();
+    keys.sort_unstable_by(|a, b| b.cmp(a));
+    keys.dedup();
+
+    candidates.iter().filter_map(|(page, title, key)| {
+        if pages.get(title.as_str()).map_or(0, BTreeSet::len) > MAX_HEADING_REPEATS {
+            return None;
+        }
+        let level = keys.iter().position(|k| k == key)? + 1;
+        (level <= SYNTHETIC_TOC_DEPTH).then(|| (level, title.clone(), Location::Exact(*page)))
+    }).collect()
+}
+
 const CPUINFO_KEYS: [&str; 3] = ["Processor", "Features", "Hardware"];
 const HWINFO_KEYS: [&str; 19] = ["CPU", "PCB", "DisplayPanel", "DisplayCtrl", "DisplayBusWidth",
                                  "DisplayResolution", "FrontLight", "FrontLight_LEDrv", "FL_PWM",
@@ -585,3 +866,66 @@ pub fn sys_info_as_html() -> String {
     buf.push_str("\t\t</table>\n\t</body>\n</html>");
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(page: usize, text: &str, size: f32, bold: bool, isolated: bool) -> StyledLine {
+        StyledLine { page, text: text.to_string(), size, bold, isolated }
+    }
+
+    #[test]
+    fn test_chapter_heading_level() {
+        assert_eq!(chapter_heading_level("Part Two"), Some(1));
+        assert_eq!(chapter_heading_level("CHAPTER XII. The Return"), Some(2));
+        assert_eq!(chapter_heading_level("Chapter twenty-one"), Some(2));
+        assert_eq!(chapter_heading_level("Épilogue"), Some(2));
+        assert_eq!(chapter_heading_level("Chapters are long."), None);
+        assert_eq!(chapter_heading_level("Part of the plan was simple."), None);
+    }
+
+    #[test]
+    fn test_styled_headings() {
+        let mut lines = Vec::new();
+        for page in 0..4 {
+            lines.push(line(page, "A Running Head", 14.0, false, true));
+            lines.push(line(page, "Some regular text that fills the page.", 10.0, false, false));
+            lines.push(line(page, "And some more regular text on the page.", 10.0, false, false));
+        }
+        lines.insert(1, line(0, "The Long", 20.0, true, false));
+        lines.insert(2, line(0, "Title", 20.0, true, false));
+        lines.insert(8, line(2, "A Section", 14.0, false, true));
+        lines.insert(9, line(2, "A Paragraph Title", 10.0, true, true));
+        lines.insert(10, line(2, "42", 16.0, false, true));
+        let headings = styled_headings(&lines);
+        let summary: Vec<(usize, &str)> = headings.iter().map(|(level, title, _)| (*level, title.as_str())).collect();
+        assert_eq!(summary, vec![(1, "The Long Title"), (2, "A Section"), (3, "A Paragraph Title")]);
+        let toc = headings_toc(&headings);
+        assert_eq!(toc.len(), 1);
+        assert_eq!(toc[0].children.len(), 1);
+        assert_eq!(toc[0].children[0].children[0].title, "A Paragraph Title");
+        assert!(matches!(toc[0].children[0].location, Location::Exact(2)));
+    }
+
+    #[test]
+    fn test_spread_pages() {
+        let none = |_| None;
+        assert_eq!(spread_pages(0, 5, false, false, none), (0, Some(1)));
+        assert_eq!(spread_pages(3, 5, false, false, none), (2, Some(3)));
+        assert_eq!(spread_pages(4, 5, false, false, none), (4, None));
+        assert_eq!(spread_pages(0, 5, true, false, none), (0, None));
+        assert_eq!(spread_pages(2, 5, true, false, none), (1, Some(2)));
+        assert_eq!(spread_pages(4, 5, true, true, none), (3, Some(4)));
+
+        // A centered page interrupts the sequence of spreads.
+        let sides = [None, Some(PageSpread::Right), Some(PageSpread::Left), Some(PageSpread::Center),
+                     Some(PageSpread::Right), Some(PageSpread::Left)];
+        let side = |i: usize| sides[i];
+        assert_eq!(spread_pages(0, 6, true, true, side), (0, None));
+        assert_eq!(spread_pages(2, 6, true, true, side), (1, Some(2)));
+        assert_eq!(spread_pages(3, 6, true, true, side), (3, None));
+        assert_eq!(spread_pages(5, 6, true, true, side), (4, Some(5)));
+        assert_eq!(spread_pages(1, 6, true, false, side), (1, None));
+    }
+}
@@ -19,6 +19,12 @@ pub const FZ_TEXT_MEDIABOX_CLIP: libc::c_int = 64;
 pub const FZ_PAGE_BLOCK_TEXT: libc::c_int = 0;
 pub const FZ_PAGE_BLOCK_IMAGE: libc::c_int = 1;
 
+pub const PDF_ANNOT_TEXT: libc::c_int = 0;
+pub const PDF_ANNOT_HIGHLIGHT: libc::c_int = 8;
+pub const PDF_ANNOT_UNDERLINE: libc::c_int = 9;
+pub const PDF_ANNOT_SQUIGGLY: libc::c_int = 10;
+pub const PDF_ANNOT_STRIKE_OUT: libc::c_int = 11;
+
 pub const CACHE_SIZE: libc::size_t = 32 * 1024 * 1024;
 
 pub enum FzContext {}
@@ -38,6 +44,9 @@ pub enum FzLinkSetUriFn {}
 pub enum FzLinkDropLinkFn {}
 pub enum FzSeparations {}
 pub enum FzImage {}
+pub enum RawPdfDocument {}
+pub enum RawPdfPage {}
+pub enum PdfAnnot {}
 
 #[link(name="mupdf")]
 #[link(name="mupdf_wrapper", kind="static")]
@@ -57,6 +66,7 @@ extern {
     pub fn mp_page_number_from_location(ctx: *mut FzContext, doc: *mut FzDocument, loc: FzLocation) -> libc::c_int;
     pub fn fz_lookup_metadata(ctx: *mut FzContext, doc: *mut FzDocument, key: *const libc::c_char, buf: *mut libc::c_char, size: libc::c_int) -> libc::c_int;
     pub fn fz_needs_password(ctx: *mut FzContext, doc: *mut FzDocument) -> libc::c_int;
+    pub fn fz_authenticate_password(ctx: *mut FzContext, doc: *mut FzDocument, password: *const libc::c_char) -> libc::c_int;
     pub fn fz_is_document_reflowable(ctx: *mut FzContext, doc: *mut FzDocument) -> libc::c_int;
     pub fn fz_layout_document(ctx: *mut FzContext, doc: *mut FzDocument, w: libc::c_float, h: libc::c_float, em: libc::c_float);
     pub fn mp_load_outline(ctx: *mut FzContext, doc: *mut FzDocument) -> *mut FzOutline;
@@ -82,6 +92,24 @@ extern {
     pub fn fz_union_rect(a: FzRect, b: FzRect) -> FzRect;
     pub fn fz_rect_from_quad(q: FzQuad) -> FzRect;
     pub fn fz_runetochar(buf: *mut u8, rune: libc::c_int) -> libc::c_int;
+    pub fn fz_font_is_bold(ctx: *mut FzContext, font: *mut FzFont) -> libc::c_int;
+    pub fn pdf_specifics(ctx: *mut FzContext, doc: *mut FzDocument) -> *mut RawPdfDocument;
+    pub fn pdf_page_from_fz_page(ctx: *mut FzContext, page: *mut FzPage) -> *mut RawPdfPage;
+    pub fn pdf_first_annot(ctx: *mut FzContext, page: *mut RawPdfPage) -> *mut PdfAnnot;
+    pub fn pdf_next_annot(ctx: *mut FzContext, annot: *mut PdfAnnot) -> *mut PdfAnnot;
+    pub fn pdf_drop_annot(ctx: *mut FzContext, annot: *mut PdfAnnot);
+    pub fn mp_create_annot(ctx: *mut FzContext, page: *mut FzPage, kind: libc::c_int) -> *mut PdfAnnot;
+    pub fn mp_update_annot(ctx: *mut FzContext, annot: *mut PdfAnnot) -> libc::c_int;
+    pub fn mp_annot_type(ctx: *mut FzContext, annot: *mut PdfAnnot) -> libc::c_int;
+    pub fn mp_bound_annot(ctx: *mut FzContext, annot: *mut PdfAnnot) -> FzRect;
+    pub fn mp_annot_contents(ctx: *mut FzContext, annot: *mut PdfAnnot) -> *const libc::c_char;
+    pub fn mp_annot_modification_date(ctx: *mut FzContext, annot: *mut PdfAnnot) -> i64;
+    pub fn mp_annot_quad_point_count(ctx: *mut FzContext, annot: *mut PdfAnnot) -> libc::c_int;
+    pub fn mp_annot_quad_point(ctx: *mut FzContext, annot: *mut PdfAnnot, i: libc::c_int) -> FzQuad;
+    pub fn mp_set_annot_quad_points(ctx: *mut FzContext, annot: *mut PdfAnnot, n: libc::c_int, qv: *const FzQuad) -> libc::c_int;
+    pub fn mp_set_annot_contents(ctx: *mut FzContext, annot: *mut PdfAnnot, text: *const libc::c_char) -> libc::c_int;
+    pub fn mp_set_annot_modification_date(ctx: *mut FzContext, annot: *mut PdfAnnot, time: i64) -> libc::c_int;
+    pub fn mp_save_document(ctx: *mut FzContext, doc: *mut FzDocument, path: *const libc::c_char) -> libc::c_int;
     pub static fz_identity: FzMatrix;
 }
 
@@ -109,17 +137,17 @@ impl Default for FzRect {
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct FzPoint {
-    x: libc::c_float,
-    y: libc::c_float,
+    pub x: libc::c_float,
+    pub y: libc::c_float,
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct FzQuad {
-    ul: FzPoint,
-    ur: FzPoint,
-    ll: FzPoint,
-    lr: FzPoint,
+    pub ul: FzPoint,
+    pub ur: FzPoint,
+    pub ll: FzPoint,
+    pub lr: FzPoint,
 }
 
 #[derive(Copy, Clone)]
@@ -247,8 +275,8 @@ pub struct FzTextChar {
     color: libc::c_int,
     origin: FzPoint,
     pub quad: FzQuad,
-    size: libc::c_float,
-    font: *mut FzFont,
+    pub size: libc::c_float,
+    pub font: *mut FzFont,
     pub next: *mut FzTextChar,
 }
 
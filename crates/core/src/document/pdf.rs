@@ -5,13 +5,15 @@ use std::ptr;
 use std::slice;
 use std::char;
 use std::rc::Rc;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::io::ErrorKind;
 use std::ffi::{CString, CStr};
 use std::os::unix::ffi::OsStrExt;
+use anyhow::{Error, format_err};
+use chrono::{Local, TimeZone};
 use super::{Document, Location, TextLocation, BoundedText, TocEntry};
-use super::{chapter, chapter_relative};
-use crate::metadata::TextAlign;
+use super::{chapter, chapter_relative, headings_toc, styled_headings, StyledLine};
+use crate::metadata::{TextAlign, Annotation};
 use crate::geom::{Boundary, CycleDir};
 use crate::unit::pt_to_px;
 use crate::framebuffer::Pixmap;
@@ -27,6 +29,17 @@ impl Into<Boundary> for FzRect {
     }
 }
 
+impl From<Boundary> for FzQuad {
+    fn from(rect: Boundary) -> FzQuad {
+        FzQuad {
+            ul: FzPoint { x: rect.min.x, y: rect.min.y },
+            ur: FzPoint { x: rect.max.x, y: rect.min.y },
+            ll: FzPoint { x: rect.min.x, y: rect.max.y },
+            lr: FzPoint { x: rect.max.x, y: rect.max.y },
+        }
+    }
+}
+
 struct PdfContext(*mut FzContext);
 
 pub struct PdfOpener(Rc<PdfContext>);
@@ -34,6 +47,8 @@ pub struct PdfOpener(Rc<PdfContext>);
 pub struct PdfDocument {
     ctx: Rc<PdfContext>,
     doc: *mut FzDocument,
+    // Table of contents built from the headings, when the document has no outline.
+    synthetic_toc: Option<Vec<TocEntry>>,
 }
 
 pub struct PdfPage<'a> {
@@ -43,6 +58,14 @@ pub struct PdfPage<'a> {
     _doc: &'a PdfDocument,
 }
 
+// A text markup or a note annotation.
+struct Markup {
+    kind: libc::c_int,
+    rects: Vec<Boundary>,
+    contents: String,
+    modified: i64,
+}
+
 impl PdfOpener {
     pub fn new() -> Option<PdfOpener> {
         unsafe {
@@ -68,6 +91,7 @@ impl PdfOpener {
                 Some(PdfDocument {
                     ctx: self.0.clone(),
                     doc,
+                    synthetic_toc: None,
                 })
             }
         }
@@ -88,6 +112,7 @@ impl PdfOpener {
                 Some(PdfDocument {
                     ctx: self.0.clone(),
                     doc,
+                    synthetic_toc: None,
                 })
             }
         }
@@ -122,6 +147,63 @@ impl PdfDocument {
         }
     }
 
+    pub fn is_pdf(&self) -> bool {
+        unsafe { !pdf_specifics(self.ctx.0, self.doc).is_null() }
+    }
+
+    // Adds the annotations with static locations as highlights, and returns their count.
+    pub fn add_annotations(&mut self, annotations: &[Annotation]) -> Result<usize, Error> {
+        if !self.is_pdf() {
+            return Err(format_err!("not a PDF document"));
+        }
+
+        let mut count = 0;
+
+        for annot in annotations {
+            let [start, end] = annot.selection;
+            let (first_page, last_page) = match (start, end) {
+                (TextLocation::Static(first_page, _), TextLocation::Static(last_page, _)) => (first_page, last_page),
+                _ => continue,
+            };
+            let modified = Local.from_local_datetime(&annot.modified).single()
+                                .map_or(0, |dt| dt.timestamp());
+
+            for index in first_page..=last_page {
+                let page = self.page(index).ok_or_else(|| format_err!("can't load page {}", index))?;
+                let words = page.words().unwrap_or_default();
+                let rects = line_rects(words.iter()
+                                            .filter(|word| word.location >= start && word.location <= end)
+                                            .map(|word| word.rect));
+                if rects.is_empty() {
+                    continue;
+                }
+                let note = if index == first_page { annot.note.as_str() } else { "" };
+                page.add_highlight(&rects, note, modified)?;
+            }
+
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    // Scans every page once, the result is kept for the following calls.
+    fn headings_toc(&mut self) -> Option<Vec<TocEntry>> {
+        if !self.is_pdf() {
+            return None;
+        }
+
+        if self.synthetic_toc.is_none() {
+            let lines: Vec<StyledLine> = (0..self.pages_count()).filter_map(|index| {
+                self.page(index).and_then(|page| page.styled_lines())
+            }).flatten().collect();
+            self.synthetic_toc = Some(headings_toc(&styled_headings(&lines)));
+        }
+
+        self.synthetic_toc.clone()
+            .filter(|toc| !toc.is_empty())
+    }
+
     fn walk_toc(&self, outline: *mut FzOutline, index: &mut usize) -> Vec<TocEntry> {
         unsafe {
             let mut vec = Vec::new();
@@ -154,10 +236,6 @@ impl PdfDocument {
             vec
         }
     }
-
-    pub fn is_protected(&self) -> bool {
-        unsafe { fz_needs_password(self.ctx.0, self.doc) == 1 }
-    }
 }
 
 impl Document for PdfDocument {
@@ -214,11 +292,13 @@ impl Document for PdfDocument {
         self.page(index).and_then(|page| page.pixmap(scale)).map(|pixmap| (pixmap, index))
     }
 
+    // The table of contents built from the headings is only returned once `full_toc` was called.
     fn toc(&mut self) -> Option<Vec<TocEntry>> {
         unsafe {
             let outline = mp_load_outline(self.ctx.0, self.doc);
             if outline.is_null() {
-                None
+                self.synthetic_toc.clone()
+                    .filter(|toc| !toc.is_empty())
             } else {
                 let mut index = 0;
                 let toc = self.walk_toc(outline, &mut index);
@@ -228,6 +308,11 @@ impl Document for PdfDocument {
         }
     }
 
+    // The text of every page is extracted to find the headings.
+    fn full_toc(&mut self) -> Option<Vec<TocEntry>> {
+        self.toc().or_else(|| self.headings_toc())
+    }
+
     fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
         chapter(offset, self.pages_count(), toc)
     }
@@ -281,6 +366,51 @@ impl Document for PdfDocument {
         unsafe { fz_is_document_reflowable(self.ctx.0, self.doc) == 1 }
     }
 
+    fn is_protected(&self) -> bool {
+        unsafe { fz_needs_password(self.ctx.0, self.doc) == 1 }
+    }
+
+    fn embedded_annotations(&mut self) -> Vec<Annotation> {
+        let mut annotations = Vec::new();
+
+        if !self.is_pdf() {
+            return annotations;
+        }
+
+        for index in 0..self.pages_count() {
+            if let Some(page) = self.page(index) {
+                let markups = page.markups();
+                if markups.is_empty() {
+                    continue;
+                }
+                let words = page.words().unwrap_or_default();
+                annotations.extend(markups.into_iter()
+                                          .filter_map(|markup| markup_annotation(markup, &words)));
+            }
+        }
+
+        annotations
+    }
+
+    fn save(&self, path: &str) -> Result<(), Error> {
+        if !self.is_pdf() {
+            return Err(format_err!("this document can't be saved"));
+        }
+        let c_path = CString::new(path)?;
+        if unsafe { mp_save_document(self.ctx.0, self.doc, c_path.as_ptr()) } < 0 {
+            return Err(format_err!("can't save {}", path));
+        }
+        Ok(())
+    }
+
+    fn authenticate(&mut self, password: &str) -> bool {
+        let c_password = match CString::new(password) {
+            Ok(c_password) => c_password,
+            Err(_) => return false,
+        };
+        unsafe { fz_authenticate_password(self.ctx.0, self.doc, c_password.as_ptr()) != 0 }
+    }
+
     fn layout(&mut self, width: u32, height: u32, font_size: f32, dpi: u16) {
         let em = pt_to_px(font_size, dpi);
         unsafe {
@@ -378,6 +508,64 @@ impl<'a> PdfPage<'a> {
         }
     }
 
+    pub fn styled_lines(&self) -> Option<Vec<StyledLine>> {
+        unsafe {
+            let mut lines = Vec::new();
+            let tp = mp_new_stext_page_from_page(self.ctx.0, self.page, ptr::null());
+            if tp.is_null() {
+                return None;
+            }
+            let mut block = (*tp).first_block;
+
+            while !block.is_null() {
+                if (*block).kind == FZ_PAGE_BLOCK_TEXT {
+                    let text_block = (*block).u.text;
+                    let mut line = text_block.first_line;
+                    let start = lines.len();
+
+                    while !line.is_null() {
+                        let mut chr = (*line).first_char;
+                        let mut text = String::default();
+                        let mut size: f32 = 0.0;
+                        let mut bold = true;
+
+                        while !chr.is_null() {
+                            if let Some(c) = char::from_u32((*chr).c as u32) {
+                                text.push(c);
+                                if !c.is_whitespace() {
+                                    size = size.max((*chr).size);
+                                    bold &= fz_font_is_bold(self.ctx.0, (*chr).font) != 0;
+                                }
+                            }
+                            chr = (*chr).next;
+                        }
+
+                        if !text.trim().is_empty() {
+                            lines.push(StyledLine {
+                                page: self.index,
+                                text,
+                                size,
+                                bold,
+                                isolated: false,
+                            });
+                        }
+
+                        line = (*line).next;
+                    }
+
+                    if lines.len() == start + 1 {
+                        lines[start].isolated = true;
+                    }
+                }
+
+                block = (*block).next;
+            }
+
+            fz_drop_stext_page(self.ctx.0, tp);
+            Some(lines)
+        }
+    }
+
     pub fn words(&self) -> Option<Vec<BoundedText>> {
         unsafe {
             let mut words = Vec::new();
@@ -467,6 +655,87 @@ impl<'a> PdfPage<'a> {
         }
     }
 
+    fn markups(&self) -> Vec<Markup> {
+        let mut markups = Vec::new();
+
+        unsafe {
+            let page = pdf_page_from_fz_page(self.ctx.0, self.page);
+            if page.is_null() {
+                return markups;
+            }
+
+            let mut annot = pdf_first_annot(self.ctx.0, page);
+
+            while !annot.is_null() {
+                let kind = mp_annot_type(self.ctx.0, annot);
+
+                if matches!(kind, PDF_ANNOT_TEXT | PDF_ANNOT_HIGHLIGHT | PDF_ANNOT_UNDERLINE |
+                                  PDF_ANNOT_SQUIGGLY | PDF_ANNOT_STRIKE_OUT) {
+                    let mut rects = Vec::new();
+
+                    if kind != PDF_ANNOT_TEXT {
+                        for i in 0..mp_annot_quad_point_count(self.ctx.0, annot) {
+                            rects.push(fz_rect_from_quad(mp_annot_quad_point(self.ctx.0, annot, i)).into());
+                        }
+                    }
+
+                    if rects.is_empty() {
+                        rects.push(mp_bound_annot(self.ctx.0, annot).into());
+                    }
+
+                    let contents = mp_annot_contents(self.ctx.0, annot);
+                    let contents = if contents.is_null() {
+                        String::default()
+                    } else {
+                        CStr::from_ptr(contents).to_string_lossy().into_owned()
+                    };
+
+                    markups.push(Markup {
+                        kind,
+                        rects,
+                        contents,
+                        modified: mp_annot_modification_date(self.ctx.0, annot),
+                    });
+                }
+
+                annot = pdf_next_annot(self.ctx.0, annot);
+            }
+        }
+
+        markups
+    }
+
+    pub fn add_highlight(&self, rects: &[Boundary], note: &str, modified: i64) -> Result<(), Error> {
+        let quads: Vec<FzQuad> = rects.iter().map(|&rect| rect.into()).collect();
+        let c_note = CString::new(note)?;
+
+        unsafe {
+            let annot = mp_create_annot(self.ctx.0, self.page, PDF_ANNOT_HIGHLIGHT);
+            if annot.is_null() {
+                return Err(format_err!("can't create annotation on page {}", self.index));
+            }
+
+            let mut success = mp_set_annot_quad_points(self.ctx.0, annot, quads.len() as libc::c_int, quads.as_ptr()) == 0;
+
+            if success && !note.is_empty() {
+                success = mp_set_annot_contents(self.ctx.0, annot, c_note.as_ptr()) == 0;
+            }
+
+            if success && modified > 0 {
+                success = mp_set_annot_modification_date(self.ctx.0, annot, modified) == 0;
+            }
+
+            success = success && mp_update_annot(self.ctx.0, annot) >= 0;
+            pdf_drop_annot(self.ctx.0, annot);
+
+            if success {
+                Ok(())
+            } else {
+                Err(format_err!("can't set up annotation on page {}", self.index))
+            }
+        }
+    }
+
     pub fn pixmap(&self, scale: f32) -> Option<Pixmap> {
         unsafe {
             let mat = fz_scale(scale as libc::c_float, scale as libc::c_float);
@@ -529,6 +798,101 @@ impl<'a> PdfPage<'a> {
     }
 }
 
+// Merges the consecutive word rectangles that belong to the same line.
+fn line_rects<I: Iterator<Item=Boundary>>(rects: I) -> Vec<Boundary> {
+    let mut lines: Vec<Boundary> = Vec::new();
+
+    for rect in rects {
+        if let Some(last) = lines.last_mut() {
+            let overlap = last.max.y.min(rect.max.y) - last.min.y.max(rect.min.y);
+            if rect.min.x > last.min.x && overlap > last.height().min(rect.height()) / 2.0 {
+                last.absorb(&rect);
+                continue;
+            }
+        }
+        lines.push(rect);
+    }
+
+    lines
+}
+
+// Notes are attached to the word nearest to their top left corner,
+// text markups cover the words whose centers they contain.
+fn markup_annotation(markup: Markup, words: &[BoundedText]) -> Option<Annotation> {
+    let selected: Vec<&BoundedText> = if markup.kind == PDF_ANNOT_TEXT {
+        if markup.contents.is_empty() {
+            return None;
+        }
+        let corner = markup.rects[0].min;
+        words.iter().min_by(|a, b| {
+            let da = (a.rect.min.x - corner.x).powi(2) + (a.rect.min.y - corner.y).powi(2);
+            let db = (b.rect.min.x - corner.x).powi(2) + (b.rect.min.y - corner.y).powi(2);
+            da.total_cmp(&db)
+        }).into_iter().collect()
+    } else {
+        words.iter().filter(|word| {
+            let x = (word.rect.min.x + word.rect.max.x) / 2.0;
+            let y = (word.rect.min.y + word.rect.max.y) / 2.0;
+            markup.rects.iter().any(|r| x >= r.min.x && x <= r.max.x && y >= r.min.y && y <= r.max.y)
+        }).collect()
+    };
+
+    let (first, last) = (selected.first()?, selected.last()?);
+    let mut text = first.text.clone();
+
+    for word in &selected[1..] {
+        if text.ends_with('\u{00AD}') {
+            text.pop();
+        } else if !text.ends_with('-') {
+            text.push(' ');
+        }
+        text += &word.text;
+    }
+
+    let modified = Some(markup.modified).filter(|&secs| secs > 0)
+                                        .and_then(|secs| Local.timestamp_opt(secs, 0).single())
+                                        .map_or_else(|| Local::now().naive_local(), |dt| dt.naive_local());
+
+    Some(Annotation {
+        selection: [first.location, last.location],
+        note: markup.contents,
+        text,
+        modified,
+    })
+}
+
+// Writes the annotations into a copy of the PDF document, and returns their count.
+pub fn export_annotations<P: AsRef<Path>, Q: AsRef<Path>>(path: P, target: Q, annotations: &[Annotation], password: Option<&str>) -> Result<usize, Error> {
+    let path = path.as_ref();
+    let opener = PdfOpener::new().ok_or_else(|| format_err!("can't create PDF opener"))?;
+    let mut doc = opener.open(path).ok_or_else(|| format_err!("can't open {}", path.display()))?;
+
+    if doc.is_protected() && !password.is_some_and(|password| doc.authenticate(password)) {
+        return Err(format_err!("wrong password"));
+    }
+
+    let count = doc.add_annotations(annotations)?;
+
+    if count == 0 {
+        return Err(format_err!("no annotations with fixed locations"));
+    }
+
+    if let Some(parent) = target.as_ref().parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    doc.save(&target.as_ref().to_string_lossy())?;
+
+    Ok(count)
+}
+
+// The path, within the export directory, of the annotated copy of a PDF document.
+// The given path is relative to the library's home.
+pub fn annotated_path(directory: &Path, path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    directory.join(path).with_file_name(format!("{}-annotated.pdf", stem))
+}
+
 impl Drop for PdfContext {
     fn drop(&mut self) {
         unsafe { fz_drop_context(self.0); }
@@ -546,3 +910,59 @@ impl<'a> Drop for PdfPage<'a> {
         unsafe { fz_drop_page(self.ctx.0, self.page); }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str, index: usize, rect: Boundary) -> BoundedText {
+        BoundedText { text: text.to_string(), rect, location: TextLocation::Static(0, index) }
+    }
+
+    #[test]
+    fn test_annotated_path() {
+        assert_eq!(annotated_path(Path::new("/mnt/onboard/.notes"), Path::new("Papers/On Computable Numbers.pdf")),
+                   PathBuf::from("/mnt/onboard/.notes/Papers/On Computable Numbers-annotated.pdf"));
+        assert_eq!(annotated_path(Path::new(".notes"), Path::new("paper.pdf")),
+                   PathBuf::from(".notes/paper-annotated.pdf"));
+    }
+
+    #[test]
+    fn test_line_rects() {
+        let rects = vec![Boundary::new(vec2!(10.0, 10.0), vec2!(30.0, 20.0)),
+                         Boundary::new(vec2!(35.0, 11.0), vec2!(50.0, 20.0)),
+                         Boundary::new(vec2!(10.0, 25.0), vec2!(25.0, 35.0))];
+        let lines = line_rects(rects.into_iter());
+        assert_eq!(lines.len(), 2);
+        assert_eq!((lines[0].min.x, lines[0].min.y, lines[0].max.x, lines[0].max.y), (10.0, 10.0, 50.0, 20.0));
+        assert_eq!((lines[1].min.x, lines[1].max.x), (10.0, 25.0));
+    }
+
+    #[test]
+    fn test_markup_annotation() {
+        let words = vec![word("Lorem", 0, Boundary::new(vec2!(10.0, 10.0), vec2!(40.0, 20.0))),
+                         word("ip\u{00AD}", 1, Boundary::new(vec2!(45.0, 10.0), vec2!(60.0, 20.0))),
+                         word("sum", 2, Boundary::new(vec2!(10.0, 25.0), vec2!(30.0, 35.0))),
+                         word("dolor", 3, Boundary::new(vec2!(35.0, 25.0), vec2!(60.0, 35.0)))];
+        let highlight = Markup {
+            kind: PDF_ANNOT_HIGHLIGHT,
+            rects: vec![Boundary::new(vec2!(44.0, 9.0), vec2!(61.0, 21.0)),
+                        Boundary::new(vec2!(9.0, 24.0), vec2!(31.0, 36.0))],
+            contents: String::default(),
+            modified: 0,
+        };
+        let annot = markup_annotation(highlight, &words).unwrap();
+        assert_eq!(annot.selection, [TextLocation::Static(0, 1), TextLocation::Static(0, 2)]);
+        assert_eq!(annot.text, "ipsum");
+        assert!(annot.note.is_empty());
+        let note = Markup {
+            kind: PDF_ANNOT_TEXT,
+            rects: vec![Boundary::new(vec2!(33.0, 22.0), vec2!(43.0, 32.0))],
+            contents: "Note".to_string(),
+            modified: 0,
+        };
+        let annot = markup_annotation(note, &words).unwrap();
+        assert_eq!(annot.selection, [TextLocation::Static(0, 3), TextLocation::Static(0, 3)]);
+        assert_eq!(annot.note, "Note");
+    }
+}
@@ -0,0 +1,1329 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+use fxhash::FxHashMap;
+use anyhow::{Error, format_err};
+use super::{Document, Location, TextLocation, TocEntry, BoundedText};
+use super::{chapter, chapter_relative};
+use super::pdf::{PdfOpener, PdfDocument, PdfPage};
+use super::html::HtmlDocument;
+use super::html::engine::{ResourceFetcher, DEFAULT_WIDTH, DEFAULT_HEIGHT};
+use super::html::xml::XmlParser;
+use crate::helpers::encode_entities;
+use crate::framebuffer::Pixmap;
+use crate::metadata::TextAlign;
+use crate::geom::{Boundary, CycleDir};
+
+// Scale at which the figures and the words of scanned pages are rendered.
+const RENDER_SCALE: f32 = 3.0;
+// Scale at which scanned pages are segmented.
+const SCAN_SCALE: f32 = 2.0;
+// Darker pixels are considered ink.
+const INK_THRESHOLD: u8 = 160;
+// Lines at least this many times taller than the median line are headings.
+const HEADING_RATIO: f32 = 1.3;
+// Ratio between the gaps separating the words of a scanned line and its height.
+const WORD_GAP_RATIO: f32 = 0.2;
+// Ratio between the line box of the original text and the height of its lines.
+const LINE_BOX_RATIO: f32 = 1.2;
+// The locations of the page at index `i` start at `i * PAGE_SIZE`: the HTML of a page
+// is expected to be shorter.
+const PAGE_SIZE: usize = 1 << 18;
+// Number of converted pages kept in memory.
+const CACHE_SIZE: usize = 3;
+
+// The pages of a PDF document, converted to HTML so that they can be reflowed.
+// The text blocks and figures of each page are sorted in reading order, and the
+// ink blobs of scanned pages are grouped into words rendered as images.
+// The pages are converted when they're first displayed.
+pub struct ReflowDocument {
+    source: Arc<Mutex<Source>>,
+    pages_count: usize,
+    lines: FxHashMap<usize, Vec<Line>>,
+    chunks: FxHashMap<usize, Chunk>,
+    settings: Settings,
+    toc: Vec<TocEntry>,
+    title: Option<String>,
+    author: Option<String>,
+}
+
+struct Source {
+    doc: PdfDocument,
+    // The last page rendered for its regions.
+    pixmap: Option<(usize, Pixmap)>,
+}
+
+// A page converted to HTML.
+struct Chunk {
+    html: HtmlDocument,
+    // The offsets of the words of the page within the HTML, with their indices in the page.
+    words: Vec<(usize, usize)>,
+}
+
+// The settings given to every converted page.
+#[derive(Default)]
+struct Settings {
+    layout: Option<(u32, u32, f32, u16)>,
+    font_family: Option<(String, String)>,
+    margin_width: Option<i32>,
+    text_align: Option<TextAlign>,
+    line_height: Option<f32>,
+    hyphen_penalty: Option<i32>,
+    stretch_tolerance: Option<f32>,
+    ignore_document_css: bool,
+    ignore_embedded_fonts: bool,
+    hide_ruby: bool,
+    extra_css: String,
+}
+
+impl Settings {
+    fn apply(&self, html: &mut HtmlDocument) {
+        // The margin width depends on the DPI.
+        if let Some((width, height, font_size, dpi)) = self.layout {
+            html.layout(width, height, font_size, dpi);
+        }
+        if let Some((ref family_name, ref search_path)) = self.font_family {
+            html.set_font_family(family_name, search_path);
+        }
+        if let Some(width) = self.margin_width {
+            html.set_margin_width(width);
+        }
+        if let Some(text_align) = self.text_align {
+            html.set_text_align(text_align);
+        }
+        if let Some(line_height) = self.line_height {
+            html.set_line_height(line_height);
+        }
+        if let Some(hyphen_penalty) = self.hyphen_penalty {
+            html.set_hyphen_penalty(hyphen_penalty);
+        }
+        if let Some(stretch_tolerance) = self.stretch_tolerance {
+            html.set_stretch_tolerance(stretch_tolerance);
+        }
+        html.set_ignore_document_css(self.ignore_document_css);
+        html.set_ignore_embedded_fonts(self.ignore_embedded_fonts);
+        html.set_hide_ruby(self.hide_ruby);
+        html.set_extra_css(&self.extra_css);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    // The text of a word and its index in the page.
+    Text(String, usize),
+    // An ink blob of a scanned page.
+    Glyph,
+    Figure,
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    rect: Boundary,
+    content: Content,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    rect: Boundary,
+    items: Vec<Item>,
+}
+
+impl Line {
+    fn new(items: Vec<Item>) -> Line {
+        let mut rect = items[0].rect;
+        for item in &items[1..] {
+            rect.absorb(&item.rect);
+        }
+        Line { rect, items }
+    }
+
+    fn is_figure(&self) -> bool {
+        self.items.len() == 1 && matches!(self.items[0].content, Content::Figure)
+    }
+
+    fn has_text(&self) -> bool {
+        self.items.iter().any(|item| matches!(item.content, Content::Text(..)))
+    }
+
+    fn is_page_number(&self) -> bool {
+        let mut digits = 0;
+        for item in &self.items {
+            if let Content::Text(ref text, _) = item.content {
+                if !text.chars().all(|c| c.is_ascii_digit()) {
+                    return false;
+                }
+                digits += text.len();
+            } else {
+                return false;
+            }
+        }
+        digits > 0 && digits <= 4
+    }
+
+    fn starts_lowercase(&self) -> bool {
+        match self.items.first().map(|item| &item.content) {
+            Some(Content::Text(text, _)) => text.chars().next().is_some_and(char::is_lowercase),
+            _ => false,
+        }
+    }
+
+    fn ends_sentence(&self) -> bool {
+        match self.items.last().map(|item| &item.content) {
+            Some(Content::Text(text, _)) => {
+                let text = text.trim_end_matches(['"', '\'', ')', '”', '’', '»']);
+                text.ends_with(['.', '!', '?', ':'])
+            },
+            _ => true,
+        }
+    }
+}
+
+fn median(mut values: Vec<f32>) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    Some(values[values.len() / 2])
+}
+
+fn vertical_distance(a: &Boundary, b: &Boundary) -> f32 {
+    (a.min.y - b.max.y).max(b.min.y - a.max.y).max(0.0)
+}
+
+// Groups the items into rows of vertically overlapping items, from top to bottom.
+fn rows(mut items: Vec<Item>) -> Vec<(Boundary, Vec<Item>)> {
+    items.sort_by(|a, b| a.rect.min.y.total_cmp(&b.rect.min.y));
+    let mut rows: Vec<(Boundary, Vec<Item>)> = Vec::new();
+
+    for item in items {
+        let row = rows.iter_mut().rev().find(|(rect, _)| {
+            let overlap = rect.max.y.min(item.rect.max.y) - rect.min.y.max(item.rect.min.y);
+            overlap >= 0.5 * rect.height().min(item.rect.height())
+        });
+        if let Some((rect, row_items)) = row {
+            rect.absorb(&item.rect);
+            row_items.push(item);
+        } else {
+            rows.push((item.rect, vec![item]));
+        }
+    }
+
+    // Specks, like the dots and the accents of scanned letters, join the closest row.
+    if let Some(height) = median(rows.iter().map(|(rect, _)| rect.height()).collect()) {
+        let mut i = 0;
+        while i < rows.len() {
+            if rows.len() > 1 && rows[i].0.height() < 0.5 * height {
+                let j = if i == 0 {
+                    1
+                } else if i == rows.len() - 1 ||
+                          vertical_distance(&rows[i].0, &rows[i-1].0) <= vertical_distance(&rows[i].0, &rows[i+1].0) {
+                    i - 1
+                } else {
+                    i + 1
+                };
+                if vertical_distance(&rows[i].0, &rows[j].0) < 0.5 * height {
+                    let (rect, items) = rows.remove(i);
+                    let j = if j > i { j - 1 } else { j };
+                    rows[j].0.absorb(&rect);
+                    rows[j].1.extend(items);
+                    continue;
+                }
+            }
+            i += 1;
+        }
+    }
+
+    rows
+}
+
+// The intervals of *[min_x, max_x]*, at least *min_gap* wide, that aren't covered by any item.
+fn free_intervals(items: &[Item], min_x: f32, max_x: f32, min_gap: f32) -> Vec<(f32, f32)> {
+    let mut spans: Vec<(f32, f32)> = items.iter().map(|item| (item.rect.min.x, item.rect.max.x)).collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut intervals = Vec::new();
+    let mut x = min_x;
+
+    for (start, end) in spans {
+        if start - x >= min_gap {
+            intervals.push((x, start));
+        }
+        x = x.max(end);
+    }
+
+    if max_x - x >= min_gap {
+        intervals.push((x, max_x));
+    }
+
+    intervals
+}
+
+fn intersect_intervals(a: &[(f32, f32)], b: &[(f32, f32)], min_gap: f32) -> Vec<(f32, f32)> {
+    let mut intervals = Vec::new();
+    for &(s1, e1) in a {
+        for &(s2, e2) in b {
+            let (start, end) = (s1.max(s2), e1.min(e2));
+            if end - start >= min_gap {
+                intervals.push((start, end));
+            }
+        }
+    }
+    intervals
+}
+
+// A band starts with a row that has items on both sides of a free interval, and
+// goes on while the following rows leave a part of this interval free. The widest
+// remaining interval is the gutter between two columns, and each side is then
+// processed on its own. A title spanning several columns breaks the bands, hence
+// the columns above and below it are read separately.
+fn cut(items: Vec<Item>, min_gap: f32, lines: &mut Vec<Line>) {
+    if items.is_empty() {
+        return;
+    }
+
+    let min_x = items.iter().map(|item| item.rect.min.x).fold(f32::INFINITY, f32::min);
+    let max_x = items.iter().map(|item| item.rect.max.x).fold(f32::NEG_INFINITY, f32::max);
+    let interior = |gaps: Vec<(f32, f32)>| -> Vec<(f32, f32)> {
+        gaps.into_iter().filter(|(start, end)| *start > min_x && *end < max_x).collect()
+    };
+    let mut band: Vec<Vec<Item>> = Vec::new();
+    let mut gaps = Vec::new();
+
+    for (_, row) in rows(items) {
+        let row_gaps = free_intervals(&row, min_x, max_x, min_gap);
+        if band.is_empty() {
+            gaps = interior(row_gaps);
+        } else {
+            let common = intersect_intervals(&gaps, &row_gaps, min_gap);
+            if common.is_empty() {
+                split_band(band, &gaps, min_gap, lines);
+                band = Vec::new();
+                gaps = interior(row_gaps);
+            } else {
+                gaps = common;
+            }
+        }
+        band.push(row);
+    }
+
+    split_band(band, &gaps, min_gap, lines);
+}
+
+fn split_band(band: Vec<Vec<Item>>, gaps: &[(f32, f32)], min_gap: f32, lines: &mut Vec<Line>) {
+    // The wide spaces of a single row don't make a gutter.
+    let gutter = gaps.iter()
+                     .filter(|_| band.len() > 1)
+                     .max_by(|a, b| (a.1 - a.0).total_cmp(&(b.1 - b.0)));
+
+    if let Some(&(start, end)) = gutter {
+        let middle = (start + end) / 2.0;
+        let (left, right): (Vec<Item>, Vec<Item>) = band.into_iter().flatten()
+                                                        .partition(|item| item.rect.max.x <= middle);
+        cut(left, min_gap, lines);
+        cut(right, min_gap, lines);
+    } else {
+        for mut row in band {
+            row.sort_by(|a, b| a.rect.min.x.total_cmp(&b.rect.min.x));
+            lines.push(Line::new(row));
+        }
+    }
+}
+
+// Returns the lines of the given items, in reading order.
+fn reading_order(items: Vec<Item>, min_gap: f32) -> Vec<Line> {
+    let mut lines = Vec::new();
+    cut(items, min_gap, &mut lines);
+    lines
+}
+
+// Merges the glyphs of a scanned line into words spanning the height of the line.
+fn glyph_words(line: Line) -> Line {
+    let gap = WORD_GAP_RATIO * line.rect.height();
+    let mut items: Vec<Item> = Vec::new();
+
+    for item in line.items {
+        if let Some(last) = items.last_mut() {
+            if matches!(last.content, Content::Glyph) && matches!(item.content, Content::Glyph) &&
+               item.rect.min.x - last.rect.max.x < gap {
+                last.rect.absorb(&item.rect);
+                continue;
+            }
+        }
+        items.push(item);
+    }
+
+    for item in &mut items {
+        if let Content::Glyph = item.content {
+            item.rect.min.y = line.rect.min.y;
+            item.rect.max.y = line.rect.max.y;
+        }
+    }
+
+    Line { rect: line.rect, items }
+}
+
+// The bounding boxes, in pixels, of the connected ink blobs of a scanned page.
+fn ink_boxes(pixmap: &Pixmap) -> Vec<Boundary> {
+    let (width, height) = (pixmap.width as usize, pixmap.height as usize);
+    let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+    let mut row_starts = Vec::with_capacity(height + 1);
+
+    for y in 0..height {
+        row_starts.push(runs.len());
+        let row = &pixmap.data[y * width..(y + 1) * width];
+        let mut x = 0;
+        while x < width {
+            if row[x] < INK_THRESHOLD {
+                let start = x;
+                while x < width && row[x] < INK_THRESHOLD {
+                    x += 1;
+                }
+                runs.push((y, start, x));
+            } else {
+                x += 1;
+            }
+        }
+    }
+
+    row_starts.push(runs.len());
+
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let mut parents: Vec<usize> = (0..runs.len()).collect();
+
+    // Runs of consecutive rows touching each other, diagonally included, are connected.
+    for y in 1..height {
+        let (mut i, mut j) = (row_starts[y - 1], row_starts[y]);
+        while i < row_starts[y] && j < row_starts[y + 1] {
+            let (_, a0, a1) = runs[i];
+            let (_, b0, b1) = runs[j];
+            if a0 <= b1 && b0 <= a1 {
+                let (ri, rj) = (find(&mut parents, i), find(&mut parents, j));
+                if ri != rj {
+                    parents[ri.max(rj)] = ri.min(rj);
+                }
+            }
+            if a1 < b1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+
+    let mut boxes: Vec<Option<Boundary>> = vec![None; runs.len()];
+
+    for (k, &(y, x0, x1)) in runs.iter().enumerate() {
+        let rect = bndr!(x0 as f32, y as f32, x1 as f32, (y + 1) as f32);
+        let root = find(&mut parents, k);
+        if let Some(bnd) = boxes[root].as_mut() {
+            bnd.absorb(&rect);
+        } else {
+            boxes[root] = Some(rect);
+        }
+    }
+
+    // Noise is dropped, and so are the blobs touching the edges of the page:
+    // they're usually the shadows of the scanner.
+    boxes.into_iter().flatten().filter(|bnd| {
+        bnd.width() * bnd.height() > 2.0 &&
+        bnd.min.x > 0.0 && bnd.min.y > 0.0 &&
+        bnd.max.x < width as f32 && bnd.max.y < height as f32
+    }).collect()
+}
+
+fn scanned_items(boxes: Vec<Boundary>) -> Vec<Item> {
+    let height = median(boxes.iter().map(Boundary::height).collect()).unwrap_or(0.0);
+    boxes.into_iter().map(|rect| {
+        let content = if rect.height() > 4.0 * height {
+            Content::Figure
+        } else {
+            Content::Glyph
+        };
+        Item { rect, content }
+    }).collect()
+}
+
+fn page_lines(page: &PdfPage) -> Vec<Line> {
+    let words: Vec<BoundedText> = page.words().unwrap_or_default().into_iter()
+                                      .filter(|word| word.rect.width() > 0.0 && word.rect.height() > 0.0)
+                                      .collect();
+
+    if words.is_empty() {
+        let boxes = if let Some(pixmap) = page.pixmap(SCAN_SCALE) {
+            ink_boxes(&pixmap).into_iter().map(|bnd| bnd / SCAN_SCALE).collect()
+        } else {
+            Vec::new()
+        };
+        let items = scanned_items(boxes);
+        let height = median(items.iter().map(|item| item.rect.height()).collect()).unwrap_or(0.0);
+        return reading_order(items, 2.0 * height).into_iter().map(glyph_words).collect();
+    }
+
+    let height = median(words.iter().map(|word| word.rect.height()).collect()).unwrap_or(0.0);
+
+    // The images overlaid by text are the scans of OCRed pages.
+    let figures: Vec<Item> = page.images().unwrap_or_default().into_iter()
+                                 .filter(|img| img.width() >= 1.0 && img.height() >= 1.0 &&
+                                               !words.iter().any(|word| img.contains(&word.rect)))
+                                 .map(|rect| Item { rect, content: Content::Figure })
+                                 .collect();
+
+    let mut items: Vec<Item> = words.into_iter()
+                                    .enumerate()
+                                    .map(|(i, word)| {
+                                        let index = match word.location {
+                                            TextLocation::Static(_, index) => index,
+                                            TextLocation::Dynamic(_) => i,
+                                        };
+                                        Item { rect: word.rect, content: Content::Text(word.text, index) }
+                                    })
+                                    .collect();
+    items.extend(figures);
+
+    reading_order(items, 0.8 * height)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BlockKind {
+    Paragraph,
+    Heading(u8),
+    Figure,
+}
+
+struct LastLine {
+    rect: Boundary,
+    kind: BlockKind,
+    page: usize,
+    ends_sentence: bool,
+}
+
+// Metrics of the body text of a page.
+#[derive(Debug, Copy, Clone)]
+struct Metrics {
+    height: f32,
+    spacing: f32,
+}
+
+struct HtmlBuilder {
+    html: String,
+    regions: Vec<(usize, Boundary)>,
+    pages: Vec<usize>,
+    // The offsets of the page markers, with the opening tags of their blocks.
+    markers: Vec<(usize, String)>,
+    words: Vec<(usize, usize)>,
+    page: usize,
+    marker: Option<usize>,
+    block: Option<BlockKind>,
+    opening: String,
+    last: Option<LastLine>,
+}
+
+impl HtmlBuilder {
+    fn new(title: Option<&str>) -> HtmlBuilder {
+        let mut html = String::from("<html><head>");
+        if let Some(title) = title {
+            html.push_str("<title>");
+            html.push_str(&encode_entities(title));
+            html.push_str("</title>");
+        }
+        html.push_str("</head><body>");
+
+        HtmlBuilder {
+            html,
+            regions: Vec::new(),
+            pages: Vec::new(),
+            markers: Vec::new(),
+            words: Vec::new(),
+            page: 0,
+            marker: None,
+            block: None,
+            opening: String::new(),
+            last: None,
+        }
+    }
+
+    fn add_page(&mut self, index: usize, mut lines: Vec<Line>) {
+        self.flush_empty_page();
+        self.page = index;
+        self.marker = Some(index);
+
+        if lines.first().is_some_and(Line::is_page_number) {
+            lines.remove(0);
+        }
+        if lines.last().is_some_and(Line::is_page_number) {
+            lines.pop();
+        }
+
+        let height = median(lines.iter().filter(|line| !line.is_figure())
+                                 .map(|line| line.rect.height()).collect())
+                           .unwrap_or(10.0);
+        let spacing = median(lines.windows(2).filter_map(|pair| {
+                                 let (a, b) = (&pair[0].rect, &pair[1].rect);
+                                 let d = b.min.y - a.min.y;
+                                 if d > 0.0 && d < 3.0 * height && a.min.x < b.max.x && b.min.x < a.max.x {
+                                     Some(d)
+                                 } else {
+                                     None
+                                 }
+                             }).collect())
+                            .unwrap_or(LINE_BOX_RATIO * height);
+        let metrics = Metrics { height, spacing };
+
+        for line in lines {
+            self.add_line(line, metrics);
+        }
+    }
+
+    fn add_line(&mut self, line: Line, metrics: Metrics) {
+        let ratio = line.rect.height() / metrics.height;
+        let kind = if line.is_figure() {
+            BlockKind::Figure
+        } else if line.has_text() && ratio >= HEADING_RATIO {
+            BlockKind::Heading(if ratio >= 1.8 { 1 } else { 2 })
+        } else {
+            BlockKind::Paragraph
+        };
+
+        if self.continues(&line, kind, metrics) {
+            let joined = matches!(line.items.first().map(|item| &item.content), Some(Content::Text(..))) &&
+                         line.starts_lowercase() && self.html.ends_with(['-', '\u{00AD}']);
+            if joined {
+                let c = self.html.pop();
+                if c == Some('\u{00AD}') {
+                    self.html.pop();
+                }
+            } else {
+                self.html.push(' ');
+            }
+        } else {
+            self.close_block();
+            self.opening = match kind {
+                BlockKind::Paragraph if line.has_text() && ratio < 0.9 => {
+                    format!("<p style=\"font-size: {}%\">", (100.0 * ratio).round())
+                },
+                BlockKind::Paragraph => "<p>".to_string(),
+                BlockKind::Heading(level) => format!("<h{}>", level),
+                BlockKind::Figure => "<div style=\"text-align: center\">".to_string(),
+            };
+            self.html.push_str(&self.opening);
+            self.block = Some(kind);
+        }
+
+        self.flush_marker();
+
+        for (i, item) in line.items.iter().enumerate() {
+            if i > 0 {
+                self.html.push(' ');
+            }
+            match item.content {
+                Content::Text(ref text, index) => {
+                    self.words.push((self.html.len(), index));
+                    self.html.push_str(&encode_entities(text));
+                },
+                Content::Figure if kind == BlockKind::Figure => self.push_image(item.rect, None),
+                Content::Glyph | Content::Figure => {
+                    self.push_image(item.rect, Some(LINE_BOX_RATIO * item.rect.height() / metrics.height));
+                },
+            }
+        }
+
+        self.last = Some(LastLine {
+            rect: line.rect,
+            kind,
+            page: self.page,
+            ends_sentence: line.ends_sentence(),
+        });
+    }
+
+    fn continues(&self, line: &Line, kind: BlockKind, metrics: Metrics) -> bool {
+        let last = if let Some(last) = self.last.as_ref() {
+            last
+        } else {
+            return false;
+        };
+
+        if kind != last.kind || kind == BlockKind::Figure {
+            return false;
+        }
+
+        let ratio = line.rect.height() / last.rect.height();
+        if !(0.8..=1.25).contains(&ratio) {
+            return false;
+        }
+
+        let Metrics { height, spacing } = metrics;
+        let is_below = self.page == last.page &&
+                       line.rect.min.y >= last.rect.max.y - 0.5 * height &&
+                       line.rect.min.x < last.rect.max.x && last.rect.min.x < line.rect.max.x;
+
+        if is_below {
+            let leading = (spacing - height).max(0.0);
+            if line.rect.min.y - last.rect.max.y > leading + 0.4 * height {
+                return false;
+            }
+            if kind == BlockKind::Paragraph &&
+               (line.rect.min.x > last.rect.min.x + 0.8 * height ||
+                last.rect.max.x < line.rect.max.x - 2.0 * height) {
+                return false;
+            }
+            true
+        } else {
+            // The line starts a new column or a new page.
+            kind == BlockKind::Paragraph && (!last.ends_sentence || line.starts_lowercase())
+        }
+    }
+
+    fn push_image(&mut self, rect: Boundary, height: Option<f32>) {
+        let index = self.regions.len();
+        self.regions.push((self.page, rect));
+        if let Some(height) = height {
+            self.html.push_str(&format!("<img src=\"region-{}.png\" style=\"height: {:.2}em\"/>", index, height));
+        } else {
+            self.html.push_str(&format!("<img src=\"region-{}.png\"/>", index));
+        }
+    }
+
+    // The location of a page is the offset of its first line.
+    fn flush_marker(&mut self) {
+        if let Some(index) = self.marker.take() {
+            self.markers.push((self.html.len(), self.opening.clone()));
+            self.html.push_str(&format!("<a id=\"page-{}\"></a>", index + 1));
+            self.pages.push(self.html.len());
+        }
+    }
+
+    fn flush_empty_page(&mut self) {
+        if self.marker.is_some() {
+            if self.block.is_some() {
+                self.flush_marker();
+            } else {
+                self.opening = "<p>".to_string();
+                self.html.push_str(&self.opening);
+                self.flush_marker();
+                self.html.push_str("</p>");
+            }
+        }
+    }
+
+    fn close_block(&mut self) {
+        match self.block.take() {
+            Some(BlockKind::Paragraph) => self.html.push_str("</p>"),
+            Some(BlockKind::Heading(level)) => self.html.push_str(&format!("</h{}>", level)),
+            Some(BlockKind::Figure) => self.html.push_str("</div>"),
+            None => (),
+        }
+    }
+
+    fn close(&mut self) {
+        self.flush_empty_page();
+        self.close_block();
+        self.html.push_str("</body></html>");
+    }
+}
+
+// The paragraphs that continue from the previous page aren't indented.
+fn continued(opening: &str) -> String {
+    if opening == "<p>" {
+        "<p style=\"text-indent: 0\">".to_string()
+    } else if let Some(rest) = opening.strip_prefix("<p style=\"") {
+        format!("<p style=\"text-indent: 0; {}", rest)
+    } else {
+        opening.to_string()
+    }
+}
+
+struct PageHtml {
+    text: String,
+    regions: Vec<(usize, Boundary)>,
+    words: Vec<(usize, usize)>,
+}
+
+// Converts the page at `index` into HTML. The lines of the previous page are used to
+// tell whether the first paragraph continues from it.
+fn page_html(title: Option<&str>, previous: Option<Vec<Line>>, index: usize, lines: Vec<Line>) -> PageHtml {
+    let mut builder = HtmlBuilder::new(title);
+    let header_len = builder.html.len();
+
+    if let Some(lines) = previous {
+        builder.add_page(index - 1, lines);
+    }
+
+    builder.add_page(index, lines);
+    builder.close();
+
+    let (start, opening) = builder.markers.last()
+                                  .map(|(start, opening)| (*start, opening.as_str()))
+                                  .unwrap_or((header_len, ""));
+    let mut text = builder.html[..header_len].to_string();
+
+    // The marker of a page follows the opening tag of its first block, unless that
+    // block started on the previous page.
+    if builder.html[..start].ends_with(opening) {
+        text.push_str(opening);
+    } else {
+        text.push_str(&continued(opening));
+    }
+
+    let words = builder.words.iter()
+                      .filter(|(offset, _)| *offset >= start)
+                      .map(|(offset, index)| (offset - start + text.len(), *index))
+                      .collect();
+    text.push_str(&builder.html[start..]);
+
+    PageHtml { text, regions: builder.regions, words }
+}
+
+// Renders the regions of the pages referenced by a converted page.
+struct RegionFetcher {
+    source: Arc<Mutex<Source>>,
+    regions: Vec<(usize, Boundary)>,
+}
+
+impl ResourceFetcher for RegionFetcher {
+    fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
+        let (index, rect) = name.strip_prefix("region-")
+                                .and_then(|name| name.strip_suffix(".png"))
+                                .and_then(|index| index.parse::<usize>().ok())
+                                .and_then(|index| self.regions.get(index).cloned())
+                                .ok_or_else(|| format_err!("unknown region {}", name))?;
+
+        let mut source = self.source.lock().map_err(|_| format_err!("poisoned source"))?;
+
+        // The regions of a page are usually requested in a row.
+        if source.pixmap.as_ref().map(|(i, _)| *i) != Some(index) {
+            let pixmap = source.doc.page(index)
+                               .and_then(|page| page.pixmap(RENDER_SCALE))
+                               .ok_or_else(|| format_err!("can't render page {}", index))?;
+            source.pixmap = Some((index, pixmap));
+        }
+
+        let (_, pixmap) = source.pixmap.as_ref().unwrap();
+        encode_region(pixmap, &rect, RENDER_SCALE)
+    }
+}
+
+fn encode_region(pixmap: &Pixmap, rect: &Boundary, scale: f32) -> Result<Vec<u8>, Error> {
+    let x0 = ((scale * rect.min.x).floor() as u32).min(pixmap.width);
+    let y0 = ((scale * rect.min.y).floor() as u32).min(pixmap.height);
+    let x1 = ((scale * rect.max.x).ceil() as u32).clamp(x0, pixmap.width);
+    let y1 = ((scale * rect.max.y).ceil() as u32).clamp(y0, pixmap.height);
+    let (width, height) = (x1 - x0, y1 - y0);
+
+    if width == 0 || height == 0 {
+        return Err(format_err!("empty region"));
+    }
+
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in y0..y1 {
+        let start = (y * pixmap.width + x0) as usize;
+        data.extend_from_slice(&pixmap.data[start..start + width as usize]);
+    }
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut buf, width, height);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_color(png::ColorType::Grayscale);
+        // Lets the decoder report the original size of the region, in points.
+        let ppu = (72.0 * scale / 0.0254).round() as u32;
+        encoder.set_pixel_dims(Some(png::PixelDimensions { xppu: ppu, yppu: ppu, unit: png::Unit::Meter }));
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+    }
+
+    Ok(buf)
+}
+
+fn relocate_toc(toc: &[TocEntry]) -> Vec<TocEntry> {
+    toc.iter().map(|entry| {
+        let index = if let Location::Exact(index) = entry.location { index } else { 0 };
+        TocEntry {
+            title: entry.title.clone(),
+            location: Location::Exact(index.saturating_mul(PAGE_SIZE)),
+            index: entry.index,
+            children: relocate_toc(&entry.children),
+        }
+    }).collect()
+}
+
+// Keeps the entries closest to `index`, to make room for it.
+fn evict<T>(cache: &mut FxHashMap<usize, T>, index: usize) {
+    while cache.len() >= CACHE_SIZE {
+        if let Some(farthest) = cache.keys().max_by_key(|i| i.abs_diff(index)).cloned() {
+            cache.remove(&farthest);
+        }
+    }
+}
+
+fn split_location(location: usize) -> (usize, usize) {
+    (location / PAGE_SIZE, location % PAGE_SIZE)
+}
+
+fn join_location(index: usize, offset: usize) -> usize {
+    index * PAGE_SIZE + offset.min(PAGE_SIZE - 1)
+}
+
+// The offset of the given word, or of the closest following word, within the HTML of its page.
+fn word_offset(words: &[(usize, usize)], word: usize) -> usize {
+    words.iter()
+         .filter(|(_, i)| *i >= word)
+         .min_by_key(|(_, i)| *i)
+         .or_else(|| words.iter().max_by_key(|(_, i)| *i))
+         .map(|(offset, _)| *offset)
+         .unwrap_or(0)
+}
+
+// The index of the word at the given offset, or of the closest preceding word.
+fn offset_word(words: &[(usize, usize)], offset: usize) -> usize {
+    words.iter()
+         .take_while(|(o, _)| *o <= offset)
+         .last()
+         .or_else(|| words.first())
+         .map(|(_, i)| *i)
+         .unwrap_or(0)
+}
+
+fn relocate_text(mut text: BoundedText, index: usize) -> BoundedText {
+    if let TextLocation::Dynamic(offset) = text.location {
+        text.location = TextLocation::Dynamic(join_location(index, offset));
+    }
+    text
+}
+
+impl ReflowDocument {
+    pub fn new<P: AsRef<Path>>(path: P, password: Option<&str>) -> Result<ReflowDocument, Error> {
+        let path = path.as_ref();
+        let opener = PdfOpener::new().ok_or_else(|| format_err!("can't create PDF opener"))?;
+        let mut doc = opener.open(path).ok_or_else(|| format_err!("can't open {}", path.display()))?;
+        if doc.is_protected() && !password.is_some_and(|password| doc.authenticate(password)) {
+            return Err(format_err!("wrong password"));
+        }
+        let pages_count = doc.pages_count();
+        if pages_count > usize::MAX / PAGE_SIZE {
+            return Err(format_err!("too many pages: {}", pages_count));
+        }
+        let title = doc.title().filter(|title| !title.is_empty());
+        let author = doc.author().filter(|author| !author.is_empty());
+        let toc = doc.toc().map(|toc| relocate_toc(&toc)).unwrap_or_default();
+
+        Ok(ReflowDocument {
+            source: Arc::new(Mutex::new(Source { doc, pixmap: None })),
+            pages_count,
+            lines: FxHashMap::default(),
+            chunks: FxHashMap::default(),
+            settings: Settings::default(),
+            toc,
+            title,
+            author,
+        })
+    }
+
+    fn page_lines(&mut self, index: usize) -> Vec<Line> {
+        if let Some(lines) = self.lines.get(&index) {
+            return lines.clone();
+        }
+
+        let lines = self.source.lock().ok()
+                        .and_then(|source| source.doc.page(index).map(|page| page_lines(&page)))
+                        .unwrap_or_default();
+        evict(&mut self.lines, index);
+        self.lines.insert(index, lines.clone());
+        lines
+    }
+
+    fn chunk(&mut self, index: usize) -> Option<&mut Chunk> {
+        if index >= self.pages_count {
+            return None;
+        }
+
+        if !self.chunks.contains_key(&index) {
+            let previous = index.checked_sub(1).map(|index| self.page_lines(index));
+            let lines = self.page_lines(index);
+            let PageHtml { text, regions, words } = page_html(self.title.as_deref(), previous, index, lines);
+            let mut content = XmlParser::new(&text).parse();
+            content.wrap_lost_inlines();
+            let fetcher = RegionFetcher { source: Arc::clone(&self.source), regions };
+            let mut html = HtmlDocument::new_from_tree(text, content, Box::new(fetcher));
+            self.settings.apply(&mut html);
+            evict(&mut self.chunks, index);
+            self.chunks.insert(index, Chunk { html, words });
+        }
+
+        self.chunks.get_mut(&index)
+    }
+
+    // The first location of the next page that has content.
+    fn next_page(&mut self, index: usize) -> Option<usize> {
+        (index + 1..self.pages_count).find_map(|index| {
+            self.chunk(index)
+                .and_then(|chunk| chunk.html.resolve_location(Location::Exact(0)))
+                .map(|offset| join_location(index, offset))
+        })
+    }
+
+    // The location of the last screen of the previous page that has content.
+    fn previous_page(&mut self, index: usize) -> Option<usize> {
+        (0..index).rev().find_map(|index| {
+            self.chunk(index)
+                .and_then(|chunk| chunk.html.resolve_location(Location::Exact(PAGE_SIZE - 1)))
+                .map(|offset| join_location(index, offset))
+        })
+    }
+
+    // Maps a location of the original document to the reflowed document.
+    pub fn reflowed_location(&mut self, loc: TextLocation) -> Option<TextLocation> {
+        let TextLocation::Static(index, word) = loc else {
+            return None;
+        };
+        let chunk = self.chunk(index)?;
+        Some(TextLocation::Dynamic(join_location(index, word_offset(&chunk.words, word))))
+    }
+
+    // Maps a location of the reflowed document to the original document.
+    pub fn original_location(&mut self, loc: TextLocation) -> Option<TextLocation> {
+        let TextLocation::Dynamic(location) = loc else {
+            return None;
+        };
+        let (index, offset) = split_location(location);
+        let chunk = self.chunk(index)?;
+        Some(TextLocation::Static(index, offset_word(&chunk.words, offset)))
+    }
+}
+
+impl Document for ReflowDocument {
+    fn dims(&self, _index: usize) -> Option<(f32, f32)> {
+        let (width, height) = self.settings.layout
+                                  .map(|(width, height, ..)| (width, height))
+                                  .unwrap_or((DEFAULT_WIDTH, DEFAULT_HEIGHT));
+        Some((width as f32, height as f32))
+    }
+
+    fn pages_count(&self) -> usize {
+        self.pages_count * PAGE_SIZE
+    }
+
+    fn toc(&mut self) -> Option<Vec<TocEntry>> {
+        if self.toc.is_empty() {
+            None
+        } else {
+            Some(self.toc.clone())
+        }
+    }
+
+    fn full_toc(&mut self) -> Option<Vec<TocEntry>> {
+        if self.toc.is_empty() {
+            let toc = self.source.lock().ok()?.doc.full_toc()?;
+            self.toc = relocate_toc(&toc);
+        }
+        self.toc()
+    }
+
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        chapter(offset, self.pages_count(), toc)
+    }
+
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        chapter_relative(offset, dir, toc)
+    }
+
+    fn resolve_location(&mut self, loc: Location) -> Option<usize> {
+        match loc {
+            Location::Exact(location) => {
+                let (index, offset) = split_location(location);
+                let index = index.min(self.pages_count.checked_sub(1)?);
+                let offset = self.chunk(index)?.html.resolve_location(Location::Exact(offset));
+                // The pages without content are skipped.
+                offset.map(|offset| join_location(index, offset))
+                      .or_else(|| self.next_page(index))
+                      .or_else(|| self.previous_page(index))
+            },
+            Location::Next(location) => {
+                let (index, offset) = split_location(location);
+                let offset = self.chunk(index)?.html.resolve_location(Location::Next(offset));
+                offset.map(|offset| join_location(index, offset))
+                      .or_else(|| self.next_page(index))
+            },
+            Location::Previous(location) => {
+                let (index, offset) = split_location(location);
+                let offset = self.chunk(index)?.html.resolve_location(Location::Previous(offset));
+                offset.map(|offset| join_location(index, offset))
+                      .or_else(|| self.previous_page(index))
+            },
+            Location::LocalUri(..) | Location::Uri(..) => None,
+        }
+    }
+
+    fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        let location = self.resolve_location(loc)?;
+        let (index, offset) = split_location(location);
+        let (words, _) = self.chunk(index)?.html.words(Location::Exact(offset))?;
+        Some((words.into_iter().map(|word| relocate_text(word, index)).collect(), location))
+    }
+
+    fn lines(&mut self, _loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        None
+    }
+
+    fn links(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        let location = self.resolve_location(loc)?;
+        let (index, offset) = split_location(location);
+        let (links, _) = self.chunk(index)?.html.links(Location::Exact(offset))?;
+        Some((links.into_iter().map(|link| relocate_text(link, index)).collect(), location))
+    }
+
+    fn images(&mut self, loc: Location) -> Option<(Vec<Boundary>, usize)> {
+        let location = self.resolve_location(loc)?;
+        let (index, offset) = split_location(location);
+        let (images, _) = self.chunk(index)?.html.images(Location::Exact(offset))?;
+        Some((images, location))
+    }
+
+    fn pixmap(&mut self, loc: Location, scale: f32) -> Option<(Pixmap, usize)> {
+        let location = self.resolve_location(loc)?;
+        let (index, offset) = split_location(location);
+        let (pixmap, _) = self.chunk(index)?.html.pixmap(Location::Exact(offset), scale)?;
+        Some((pixmap, location))
+    }
+
+    fn layout(&mut self, width: u32, height: u32, font_size: f32, dpi: u16) {
+        self.settings.layout = Some((width, height, font_size, dpi));
+        for chunk in self.chunks.values_mut() {
+            chunk.html.layout(width, height, font_size, dpi);
+        }
+    }
+
+    fn set_font_family(&mut self, family_name: &str, search_path: &str) {
+        self.settings.font_family = Some((family_name.to_string(), search_path.to_string()));
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_font_family(family_name, search_path);
+        }
+    }
+
+    fn set_margin_width(&mut self, width: i32) {
+        self.settings.margin_width = Some(width);
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_margin_width(width);
+        }
+    }
+
+    fn set_text_align(&mut self, text_align: TextAlign) {
+        self.settings.text_align = Some(text_align);
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_text_align(text_align);
+        }
+    }
+
+    fn set_line_height(&mut self, line_height: f32) {
+        self.settings.line_height = Some(line_height);
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_line_height(line_height);
+        }
+    }
+
+    fn set_hyphen_penalty(&mut self, hyphen_penalty: i32) {
+        self.settings.hyphen_penalty = Some(hyphen_penalty);
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_hyphen_penalty(hyphen_penalty);
+        }
+    }
+
+    fn set_stretch_tolerance(&mut self, stretch_tolerance: f32) {
+        self.settings.stretch_tolerance = Some(stretch_tolerance);
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_stretch_tolerance(stretch_tolerance);
+        }
+    }
+
+    fn set_ignore_document_css(&mut self, ignore: bool) {
+        self.settings.ignore_document_css = ignore;
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_ignore_document_css(ignore);
+        }
+    }
+
+    fn set_ignore_embedded_fonts(&mut self, ignore: bool) {
+        self.settings.ignore_embedded_fonts = ignore;
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_ignore_embedded_fonts(ignore);
+        }
+    }
+
+    fn set_hide_ruby(&mut self, hide: bool) {
+        self.settings.hide_ruby = hide;
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_hide_ruby(hide);
+        }
+    }
+
+    fn set_extra_css(&mut self, css: &str) {
+        self.settings.extra_css = css.to_string();
+        for chunk in self.chunks.values_mut() {
+            chunk.html.set_extra_css(css);
+        }
+    }
+
+    fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    fn author(&self) -> Option<String> {
+        self.author.clone()
+    }
+
+    fn metadata(&self, _key: &str) -> Option<String> {
+        None
+    }
+
+    fn page_list(&mut self) -> Option<BTreeMap<usize, String>> {
+        Some((0..self.pages_count).map(|index| (index * PAGE_SIZE, (index + 1).to_string()))
+                                  .collect())
+    }
+
+    fn is_reflowable(&self) -> bool {
+        true
+    }
+
+    fn has_synthetic_page_numbers(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str, x0: f32, y0: f32, x1: f32, y1: f32) -> Item {
+        Item { rect: bndr!(x0, y0, x1, y1), content: Content::Text(text.to_string(), 0) }
+    }
+
+    fn line_text(line: &Line) -> String {
+        line.items.iter().filter_map(|item| match item.content {
+            Content::Text(ref text, _) => Some(text.as_str()),
+            _ => None,
+        }).collect::<Vec<&str>>().join(" ")
+    }
+
+    #[test]
+    fn test_two_columns() {
+        let mut items = vec![word("Title", 200.0, 40.0, 400.0, 60.0)];
+        // The baselines of both columns are aligned.
+        for i in 0..5 {
+            let y = 100.0 + 12.0 * i as f32;
+            let x = 10.0 * i as f32;
+            items.push(word(&format!("l{}", i), 100.0, y, 150.0 + x, y + 10.0));
+            items.push(word("left", 160.0 + x, y, 290.0, y + 10.0));
+            items.push(word(&format!("r{}", i), 310.0, y, 360.0 + x, y + 10.0));
+            items.push(word("right", 370.0 + x, y, 500.0, y + 10.0));
+        }
+        items.push(word("Footnote", 100.0, 200.0, 500.0, 210.0));
+        items.reverse();
+
+        let lines = reading_order(items, 8.0);
+        let texts: Vec<String> = lines.iter().map(line_text).collect();
+        assert_eq!(texts, vec!["Title",
+                               "l0 left", "l1 left", "l2 left", "l3 left", "l4 left",
+                               "r0 right", "r1 right", "r2 right", "r3 right", "r4 right",
+                               "Footnote"]);
+    }
+
+    #[test]
+    fn test_paragraphs() {
+        let lines = vec![
+            Line::new(vec![word("First", 100.0, 100.0, 150.0, 110.0), word("para-", 160.0, 100.0, 300.0, 110.0)]),
+            Line::new(vec![word("graph", 100.0, 112.0, 150.0, 122.0), word("ends.", 160.0, 112.0, 200.0, 122.0)]),
+            Line::new(vec![word("Second", 120.0, 124.0, 200.0, 134.0), word("one", 210.0, 124.0, 300.0, 134.0)]),
+            Line::new(vec![word("42", 190.0, 300.0, 210.0, 310.0)]),
+        ];
+        let mut builder = HtmlBuilder::new(Some("A & B"));
+        builder.add_page(0, lines);
+        let lines = vec![
+            Line::new(vec![word("continues", 100.0, 50.0, 200.0, 60.0), word("here", 210.0, 50.0, 300.0, 60.0)]),
+            Line::new(vec![word("and", 100.0, 62.0, 200.0, 72.0), word("ends.", 210.0, 62.0, 250.0, 72.0)]),
+            Line::new(vec![word("Heading", 100.0, 80.0, 300.0, 100.0)]),
+        ];
+        builder.add_page(1, lines);
+        builder.add_page(2, Vec::new());
+        builder.close();
+        let HtmlBuilder { html, pages, .. } = builder;
+
+        assert!(html.contains("<title>A &amp; B</title>"));
+        assert!(html.contains("<p><a id=\"page-1\"></a>First paragraph ends.</p><p>Second one \
+                               <a id=\"page-2\"></a>continues here and ends.</p>\
+                               <h1>Heading<a id=\"page-3\"></a></h1>"));
+        assert_eq!(pages.len(), 3);
+        assert!(html[pages[1]..].starts_with("continues"));
+    }
+
+    #[test]
+    fn test_page_html() {
+        let previous = vec![
+            Line::new(vec![word("First", 100.0, 100.0, 150.0, 110.0), word("one", 160.0, 100.0, 300.0, 110.0)]),
+        ];
+        let lines = vec![
+            Line::new(vec![word("continues", 100.0, 50.0, 200.0, 60.0), word("here.", 210.0, 50.0, 300.0, 60.0)]),
+            Line::new(vec![word("Next", 120.0, 80.0, 300.0, 90.0)]),
+        ];
+        let PageHtml { text: html, words, .. } = page_html(None, Some(previous), 1, lines.clone());
+        assert!(html.contains("<body><p style=\"text-indent: 0\"><a id=\"page-2\"></a>continues here.</p>\
+                               <p>Next</p></body>"));
+        assert_eq!(words.len(), 3);
+        assert!(html[words[0].0..].starts_with("continues"));
+        assert!(html[words[2].0..].starts_with("Next"));
+
+        let html = page_html(None, None, 1, lines).text;
+        assert!(html.contains("<body><p><a id=\"page-2\"></a>continues"));
+    }
+
+    #[test]
+    fn test_location_round_trip() {
+        // The words of the second column come first in the original page.
+        let mut lines = vec![
+            Line::new(vec![word("Left", 100.0, 100.0, 150.0, 110.0), word("column", 160.0, 100.0, 250.0, 110.0)]),
+            Line::new(vec![word("Right", 300.0, 100.0, 350.0, 110.0), word("column.", 360.0, 100.0, 450.0, 110.0)]),
+        ];
+        for (i, item) in lines.iter_mut().flat_map(|line| line.items.iter_mut()).enumerate() {
+            if let Content::Text(_, ref mut index) = item.content {
+                *index = (i + 2) % 4;
+            }
+        }
+        let PageHtml { text: html, words, .. } = page_html(None, None, 0, lines);
+        for word in 0..4 {
+            let offset = word_offset(&words, word);
+            assert!(offset > 0 && offset < html.len());
+            assert_eq!(offset_word(&words, offset), word);
+        }
+        // Later offsets within a word map to that word.
+        assert_eq!(offset_word(&words, word_offset(&words, 1) + 2), 1);
+        // The words past the last one map to it.
+        assert_eq!(word_offset(&words, 7), word_offset(&words, 3));
+    }
+
+    #[test]
+    fn test_ink_boxes() {
+        let mut pixmap = Pixmap::new(12, 8);
+        for (x, y) in [(2, 2), (3, 3), (3, 4), (8, 2), (9, 2), (8, 3), (9, 3), (5, 6), (11, 4)] {
+            pixmap.data[y * 12 + x] = 0;
+        }
+        let mut boxes = ink_boxes(&pixmap);
+        boxes.sort_by(|a, b| a.min.x.total_cmp(&b.min.x));
+        assert_eq!(boxes.len(), 2);
+        assert_eq!((boxes[0].min.x, boxes[0].min.y, boxes[0].max.x, boxes[0].max.y), (2.0, 2.0, 4.0, 5.0));
+        assert_eq!((boxes[1].min.x, boxes[1].min.y, boxes[1].max.x, boxes[1].max.y), (8.0, 2.0, 10.0, 4.0));
+
+        let line = Line::new(vec![
+            Item { rect: bndr!(0.0, 2.0, 4.0, 8.0), content: Content::Glyph },
+            Item { rect: bndr!(4.5, 0.0, 8.0, 8.0), content: Content::Glyph },
+            Item { rect: bndr!(12.0, 2.0, 16.0, 10.0), content: Content::Glyph },
+        ]);
+        let line = glyph_words(line);
+        assert_eq!(line.items.len(), 2);
+        assert_eq!((line.items[0].rect.min.y, line.items[0].rect.max.x), (0.0, 8.0));
+        assert_eq!(line.items[1].rect.max.y, 10.0);
+    }
+}
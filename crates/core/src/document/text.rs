@@ -0,0 +1,351 @@
+use std::fs;
+use std::path::Path;
+use anyhow::Error;
+use encoding_rs::Encoding;
+use chardetng::EncodingDetector;
+use pulldown_cmark::{Parser, Options, Event, Tag, TagEnd, CowStr, html};
+use super::{Document, Location, TocEntry, BoundedText, Footnote};
+use super::{chapter, chapter_relative, collect_headings, headings_toc, chapter_heading_level, HEADING_NAMES};
+use super::html::HtmlDocument;
+use super::html::dom::NodeRef;
+use super::html::engine::ResourceFetcher;
+use super::html::xml::XmlParser;
+use crate::helpers::encode_entities;
+use crate::framebuffer::Pixmap;
+use crate::metadata::TextAlign;
+use crate::geom::{Boundary, CycleDir};
+
+// Plain text and Markdown files, converted to HTML.
+pub struct TextDocument {
+    html: HtmlDocument,
+    toc: Vec<TocEntry>,
+}
+
+fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "md" | "markdown"))
+}
+
+// Text files are usually either UTF-8 or encoded with one of the legacy 8-bit encodings.
+fn decode(buf: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(buf) {
+        return encoding.decode_without_bom_handling(&buf[bom_length..]).0.into_owned();
+    }
+
+    if let Ok(text) = std::str::from_utf8(buf) {
+        return text.to_string();
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(buf, true);
+    let encoding = detector.guess(None, true);
+
+    encoding.decode_without_bom_handling(buf).0.into_owned()
+}
+
+fn wrap_body(title: Option<&str>, body: &str) -> String {
+    let mut text = String::with_capacity(body.len() + 128);
+    text.push_str("<html><head>");
+    if let Some(title) = title {
+        text.push_str("<title>");
+        text.push_str(&encode_entities(title));
+        text.push_str("</title>");
+    }
+    text.push_str("</head><body>");
+    text.push_str(body);
+    text.push_str("</body></html>");
+    text
+}
+
+// Hard wrapped texts separate their paragraphs with blank lines, the others
+// usually have one paragraph per line. Single line paragraphs that look like
+// chapter titles become headings.
+fn text_to_html(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let has_blank_lines = lines.iter().any(|line| line.trim().is_empty());
+    let long_lines_count = lines.iter().filter(|line| line.chars().count() > 100).count();
+    let is_wrapped = has_blank_lines && 20 * long_lines_count < lines.len();
+    let mut body = String::with_capacity(text.len() + text.len() / 8);
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut count = 0;
+
+    for line in lines.iter().map(|line| line.trim()).chain(Some("")) {
+        if !line.is_empty() {
+            paragraph.push(line);
+        }
+        if !paragraph.is_empty() && (line.is_empty() || !is_wrapped) {
+            let level = if paragraph.len() == 1 { chapter_heading_level(paragraph[0]) } else { None };
+            if let Some(level) = level {
+                count += 1;
+                body.push_str(&format!("<h{} id=\"chapter-{}\">", level, count));
+                body.push_str(&encode_entities(paragraph[0]));
+                body.push_str(&format!("</h{}>", level));
+            } else {
+                body.push_str("<p>");
+                body.push_str(&encode_entities(&paragraph.join(" ")));
+                body.push_str("</p>");
+            }
+            paragraph.clear();
+        }
+    }
+
+    wrap_body(None, &body)
+}
+
+// Headings without identifiers get one, so that the TOC entries can point to them.
+// The first heading doubles as the title.
+fn markdown_to_html(text: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES |
+                  Options::ENABLE_STRIKETHROUGH | Options::ENABLE_HEADING_ATTRIBUTES |
+                  Options::ENABLE_SMART_PUNCTUATION;
+    let mut count = 0;
+    let mut title: Option<String> = None;
+    let mut in_title = false;
+
+    let events: Vec<Event> = Parser::new_ext(text, options).map(|event| {
+        match event {
+            Event::Start(Tag::Heading { level, id, classes, attrs }) => {
+                count += 1;
+                in_title = count == 1;
+                let id = id.unwrap_or_else(|| CowStr::from(format!("heading-{}", count)));
+                Event::Start(Tag::Heading { level, id: Some(id), classes, attrs })
+            },
+            Event::End(TagEnd::Heading(..)) => {
+                in_title = false;
+                event
+            },
+            Event::Text(ref text) | Event::Code(ref text) if in_title => {
+                title.get_or_insert_with(String::new).push_str(text);
+                event
+            },
+            _ => event,
+        }
+    }).collect();
+
+    let mut body = String::with_capacity(text.len() + text.len() / 4);
+    html::push_html(&mut body, events.into_iter());
+
+    let title = title.filter(|title| !title.trim().is_empty());
+    wrap_body(title.as_deref(), &body)
+}
+
+fn build_toc(root: NodeRef) -> Vec<TocEntry> {
+    let mut nodes = Vec::new();
+    collect_headings(root, HEADING_NAMES.len(), &mut nodes);
+    let headings: Vec<(usize, String, Location)> = nodes.into_iter()
+        .filter(|(_, _, node)| node.id().is_some())
+        .map(|(level, title, node)| (level, title, Location::Exact(node.offset())))
+        .collect();
+    headings_toc(&headings)
+}
+
+impl TextDocument {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<TextDocument, Error> {
+        let path = path.as_ref();
+        let buf = fs::read(path)?;
+        let text = decode(&buf);
+        let text = if is_markdown(path) {
+            markdown_to_html(&text)
+        } else {
+            text_to_html(&text)
+        };
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(TextDocument::new_from_html(text, Box::new(parent.to_path_buf())))
+    }
+
+    fn new_from_html(text: String, fetcher: Box<dyn ResourceFetcher>) -> TextDocument {
+        let mut content = XmlParser::new(&text).parse();
+        content.wrap_lost_inlines();
+        let toc = build_toc(content.root());
+
+        TextDocument {
+            html: HtmlDocument::new_from_tree(text, content, fetcher),
+            toc,
+        }
+    }
+}
+
+impl Document for TextDocument {
+    fn dims(&self, index: usize) -> Option<(f32, f32)> {
+        self.html.dims(index)
+    }
+
+    fn pages_count(&self) -> usize {
+        self.html.pages_count()
+    }
+
+    fn toc(&mut self) -> Option<Vec<TocEntry>> {
+        if self.toc.is_empty() {
+            None
+        } else {
+            Some(self.toc.clone())
+        }
+    }
+
+    fn chapter<'a>(&mut self, offset: usize, toc: &'a [TocEntry]) -> Option<(&'a TocEntry, f32)> {
+        chapter(offset, self.pages_count(), toc)
+    }
+
+    fn chapter_relative<'a>(&mut self, offset: usize, dir: CycleDir, toc: &'a [TocEntry]) -> Option<&'a TocEntry> {
+        chapter_relative(offset, dir, toc)
+    }
+
+    fn resolve_location(&mut self, loc: Location) -> Option<usize> {
+        self.html.resolve_location(loc)
+    }
+
+    fn words(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.html.words(loc)
+    }
+
+    fn lines(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.html.lines(loc)
+    }
+
+    fn links(&mut self, loc: Location) -> Option<(Vec<BoundedText>, usize)> {
+        self.html.links(loc)
+    }
+
+    fn images(&mut self, loc: Location) -> Option<(Vec<Boundary>, usize)> {
+        self.html.images(loc)
+    }
+
+    fn pixmap(&mut self, loc: Location, scale: f32) -> Option<(Pixmap, usize)> {
+        self.html.pixmap(loc, scale)
+    }
+
+    fn layout(&mut self, width: u32, height: u32, font_size: f32, dpi: u16) {
+        self.html.layout(width, height, font_size, dpi);
+    }
+
+    fn set_font_family(&mut self, family_name: &str, search_path: &str) {
+        self.html.set_font_family(family_name, search_path);
+    }
+
+    fn set_margin_width(&mut self, width: i32) {
+        self.html.set_margin_width(width);
+    }
+
+    fn set_text_align(&mut self, text_align: TextAlign) {
+        self.html.set_text_align(text_align);
+    }
+
+    fn set_line_height(&mut self, line_height: f32) {
+        self.html.set_line_height(line_height);
+    }
+
+    fn set_hyphen_penalty(&mut self, hyphen_penalty: i32) {
+        self.html.set_hyphen_penalty(hyphen_penalty);
+    }
+
+    fn set_stretch_tolerance(&mut self, stretch_tolerance: f32) {
+        self.html.set_stretch_tolerance(stretch_tolerance);
+    }
+
+    fn set_ignore_document_css(&mut self, ignore: bool) {
+        self.html.set_ignore_document_css(ignore);
+    }
+
+    fn set_ignore_embedded_fonts(&mut self, ignore: bool) {
+        self.html.set_ignore_embedded_fonts(ignore);
+    }
+
+    fn set_hide_ruby(&mut self, hide: bool) {
+        self.html.set_hide_ruby(hide);
+    }
+
+    fn set_extra_css(&mut self, css: &str) {
+        self.html.set_extra_css(css);
+    }
+
+    fn title(&self) -> Option<String> {
+        self.html.title()
+    }
+
+    fn author(&self) -> Option<String> {
+        None
+    }
+
+    fn metadata(&self, key: &str) -> Option<String> {
+        self.html.metadata(key)
+    }
+
+    fn save(&self, path: &str) -> Result<(), Error> {
+        self.html.save(path)
+    }
+
+    fn footnote(&mut self, offset: usize, uri: &str) -> Option<Footnote> {
+        self.html.footnote(offset, uri)
+    }
+
+    fn is_reflowable(&self) -> bool {
+        true
+    }
+
+    fn has_synthetic_page_numbers(&self) -> bool {
+        true
+    }
+
+    fn is_rtl(&self) -> bool {
+        self.html.is_rtl()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("Привет".as_bytes()), "Привет");
+        assert_eq!(decode(b"\xef\xbb\xbfHello"), "Hello");
+        let buf = b"\xc2 \xe4\xe0\xeb\xfc\xed\xe5\xe9 \xe4\xe5\xf0\xe5\xe2\xed\xe5 \xe6\xe8\xeb \xf1\xf2\xe0\xf0\xfb\xe9 \xe4\xe5\xe4.";
+        assert_eq!(decode(buf), "В дальней деревне жил старый дед.");
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let html = text_to_html("First line\nof a paragraph.\n\nA <second> one.\n");
+        assert!(html.contains("<p>First line of a paragraph.</p><p>A &lt;second&gt; one.</p>"));
+        let html = text_to_html("One paragraph.\nAnother paragraph.\n");
+        assert!(html.contains("<p>One paragraph.</p><p>Another paragraph.</p>"));
+    }
+
+    #[test]
+    fn test_chapter_headings() {
+        let text = "Part One\n\nCHAPTER I.\n\nIt was a\ndark night.\n\nChapter 2: The Storm\n\nChapter two was\nlong.\n";
+        let html = text_to_html(text);
+        assert!(html.contains("<h1 id=\"chapter-1\">Part One</h1><h2 id=\"chapter-2\">CHAPTER I.</h2><p>It was a dark night.</p>"));
+        assert!(html.contains("<h2 id=\"chapter-3\">Chapter 2: The Storm</h2><p>Chapter two was long.</p>"));
+        let content = XmlParser::new(&html).parse();
+        let toc = build_toc(content.root());
+        assert_eq!(toc.len(), 1);
+        assert_eq!(toc[0].title, "Part One");
+        assert_eq!(toc[0].children.len(), 2);
+        assert_eq!(toc[0].children[1].title, "Chapter 2: The Storm");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let text = "# The *Title*\n\nSome **bold** text.\n\n## Section {#sec}\n\n![Map](images/map.png)\n\n### Sub\n\n# Appendix\n";
+        let html = markdown_to_html(text);
+        assert!(html.contains("<title>The Title</title>"));
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(html.contains("<img src=\"images/map.png\""));
+
+        let content = XmlParser::new(&html).parse();
+        let toc = build_toc(content.root());
+        let titles: Vec<&str> = toc.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["The Title", "Appendix"]);
+        assert_eq!(toc[0].children.len(), 1);
+        assert_eq!(toc[0].children[0].title, "Section");
+        assert_eq!(toc[0].children[0].children[0].index, 2);
+        assert_eq!(toc[1].index, 3);
+        if let Location::Exact(offset) = toc[0].children[0].location {
+            assert!(html[offset..].starts_with("<h2 id=\"sec\">"));
+        } else {
+            panic!("unexpected location");
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Error, format_err};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use crate::document::{Document, TocEntry, TextLocation, BYTES_PER_PAGE};
+use crate::helpers::{encode_entities, save_json, datetime_format};
+use crate::metadata::Info;
+use crate::settings::ExportFormat;
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notes {
+    pub title: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub author: String,
+    pub path: PathBuf,
+    pub annotations: Vec<Note>,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    pub text: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chapter: Option<String>,
+    pub page: String,
+    pub location: TextLocation,
+    #[serde(with = "datetime_format")]
+    pub modified: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bookmark {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chapter: Option<String>,
+    pub page: String,
+    pub location: usize,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+fn page_label(location: usize, synthetic: bool) -> String {
+    if synthetic {
+        format!("{:.1}", location as f64 / BYTES_PER_PAGE)
+    } else {
+        format!("{}", location + 1)
+    }
+}
+
+// The chapters are only named when a table of contents is given: the chapters of
+// reflowable documents can't be located before the document is laid out.
+pub fn collect_notes(info: &Info, doc: &mut dyn Document, toc: Option<&[TocEntry]>) -> Notes {
+    let synthetic = doc.has_synthetic_page_numbers();
+    let mut chapter = |location: usize| {
+        toc.and_then(|toc| doc.chapter(location, toc))
+           .map(|(chap, _)| chap.title.clone())
+    };
+    let (mut annotations, bookmarks) = info.reader.as_ref().map(|r| {
+        let annotations: Vec<Note> = r.annotations.iter().map(|annot| {
+            let location = annot.selection[0].location();
+            Note {
+                text: annot.text.clone(),
+                note: annot.note.clone(),
+                chapter: chapter(location),
+                page: page_label(location, synthetic),
+                location: annot.selection[0],
+                modified: annot.modified,
+            }
+        }).collect();
+        let bookmarks = r.bookmarks.iter().map(|&location| {
+            Bookmark {
+                chapter: chapter(location),
+                page: page_label(location, synthetic),
+                location,
+            }
+        }).collect();
+        (annotations, bookmarks)
+    }).unwrap_or_default();
+
+    annotations.sort_by_key(|note| note.location);
+
+    Notes {
+        title: info.title.clone(),
+        author: info.author.clone(),
+        path: info.file.path.clone(),
+        annotations,
+        bookmarks,
+    }
+}
+
+fn notes_title(notes: &Notes) -> String {
+    if notes.title.is_empty() {
+        notes.path.file_stem()
+             .map(|stem| stem.to_string_lossy().into_owned())
+             .unwrap_or_default()
+    } else {
+        notes.title.clone()
+    }
+}
+
+fn location_line(page: &str, modified: Option<&NaiveDateTime>) -> String {
+    match modified {
+        Some(modified) => format!("Page {} · {}", page, modified.format(DATE_FORMAT)),
+        None => format!("Page {}", page),
+    }
+}
+
+pub fn notes_as_markdown(notes: &Notes) -> String {
+    let mut buf = format!("# {}\n\n", notes_title(notes));
+
+    if !notes.author.is_empty() {
+        buf.push_str(&format!("*{}*\n\n", notes.author));
+    }
+
+    if !notes.annotations.is_empty() {
+        buf.push_str("## Annotations\n\n");
+        let mut chapter = None;
+        for note in &notes.annotations {
+            if note.chapter.is_some() && note.chapter != chapter {
+                chapter = note.chapter.clone();
+                buf.push_str(&format!("### {}\n\n", chapter.as_deref().unwrap_or_default()));
+            }
+            for line in note.text.lines() {
+                buf.push_str(&format!("> {}\n", line));
+            }
+            buf.push('\n');
+            if !note.note.is_empty() {
+                buf.push_str(&format!("{}\n\n", note.note));
+            }
+            buf.push_str(&format!("*{}*\n\n", location_line(&note.page, Some(&note.modified))));
+        }
+    }
+
+    if !notes.bookmarks.is_empty() {
+        buf.push_str("## Bookmarks\n\n");
+        for bkm in &notes.bookmarks {
+            match bkm.chapter {
+                Some(ref chapter) => buf.push_str(&format!("- {} — {}\n", location_line(&bkm.page, None), chapter)),
+                None => buf.push_str(&format!("- {}\n", location_line(&bkm.page, None))),
+            }
+        }
+        buf.push('\n');
+    }
+
+    buf
+}
+
+pub fn notes_as_html(notes: &Notes) -> String {
+    let title = encode_entities(&notes_title(notes));
+    let mut buf = format!("<!DOCTYPE html>\n<html>\n\t<head>\n\t\t<meta charset=\"utf-8\"/>\n\t\t\
+                           <title>{}</title>\n\t\t<style>\n\t\t\t\
+                           blockquote {{ margin: 1em 0; padding-left: 1em; border-left: 3px solid #999; }}\n\t\t\t\
+                           .location {{ color: #666; font-size: 0.9em; }}\n\t\t\
+                           </style>\n\t</head>\n\t<body>\n\t\t<h1>{}</h1>\n", title, title);
+
+    if !notes.author.is_empty() {
+        buf.push_str(&format!("\t\t<p><em>{}</em></p>\n", encode_entities(&notes.author)));
+    }
+
+    if !notes.annotations.is_empty() {
+        buf.push_str("\t\t<h2>Annotations</h2>\n");
+        let mut chapter = None;
+        for note in &notes.annotations {
+            if note.chapter.is_some() && note.chapter != chapter {
+                chapter = note.chapter.clone();
+                buf.push_str(&format!("\t\t<h3>{}</h3>\n", encode_entities(chapter.as_deref().unwrap_or_default())));
+            }
+            buf.push_str(&format!("\t\t<blockquote>{}</blockquote>\n", encode_entities(&note.text)));
+            if !note.note.is_empty() {
+                buf.push_str(&format!("\t\t<p>{}</p>\n", encode_entities(&note.note)));
+            }
+            buf.push_str(&format!("\t\t<p class=\"location\">{}</p>\n",
+                                  encode_entities(&location_line(&note.page, Some(&note.modified)))));
+        }
+    }
+
+    if !notes.bookmarks.is_empty() {
+        buf.push_str("\t\t<h2>Bookmarks</h2>\n\t\t<ul>\n");
+        for bkm in &notes.bookmarks {
+            let mut text = location_line(&bkm.page, None);
+            if let Some(ref chapter) = bkm.chapter {
+                text = format!("{} — {}", text, chapter);
+            }
+            buf.push_str(&format!("\t\t\t<li>{}</li>\n", encode_entities(&text)));
+        }
+        buf.push_str("\t\t</ul>\n");
+    }
+
+    buf.push_str("\t</body>\n</html>\n");
+    buf
+}
+
+// The notes of each document are saved at its relative path within the library, with
+// its extension kept, so that the files of different documents never collide.
+fn notes_path(directory: &Path, path: &Path, format: ExportFormat) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    Some(directory.join(path).with_file_name(format!("{}.{}", name, format.extension())))
+}
+
+// Writes the notes in each format, and returns the paths of the created files.
+pub fn export_notes<P: AsRef<Path>>(notes: &Notes, directory: P, formats: &[ExportFormat]) -> Result<Vec<PathBuf>, Error> {
+    if notes.annotations.is_empty() && notes.bookmarks.is_empty() {
+        return Err(format_err!("no annotations or bookmarks"));
+    }
+
+    let mut paths = Vec::new();
+
+    for format in formats {
+        let path = notes_path(directory.as_ref(), &notes.path, *format)
+                       .ok_or_else(|| format_err!("invalid document path"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match format {
+            ExportFormat::Markdown => fs::write(&path, notes_as_markdown(notes))?,
+            ExportFormat::Json => save_json(notes, &path)?,
+            ExportFormat::Html => fs::write(&path, notes_as_html(notes))?,
+        }
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_notes() -> Notes {
+        let modified = NaiveDateTime::parse_from_str("2024-03-01 09:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        Notes {
+            title: "Walden".to_string(),
+            author: "Henry David Thoreau".to_string(),
+            path: PathBuf::from("Thoreau/walden.epub"),
+            annotations: vec![
+                Note {
+                    text: "I went to the woods because I wished to live deliberately.".to_string(),
+                    note: String::new(),
+                    chapter: Some("Where I Lived".to_string()),
+                    page: "12.3".to_string(),
+                    location: TextLocation::Dynamic(25190),
+                    modified,
+                },
+                Note {
+                    text: "Simplify, simplify.".to_string(),
+                    note: "Cf. <Economy>.".to_string(),
+                    chapter: Some("Where I Lived".to_string()),
+                    page: "13.0".to_string(),
+                    location: TextLocation::Dynamic(26624),
+                    modified,
+                },
+            ],
+            bookmarks: vec![Bookmark { chapter: None, page: "2.0".to_string(), location: 4096 }],
+        }
+    }
+
+    #[test]
+    fn test_notes_as_markdown() {
+        let text = notes_as_markdown(&sample_notes());
+        assert!(text.starts_with("# Walden\n\n*Henry David Thoreau*\n\n## Annotations\n\n### Where I Lived\n\n> I went"));
+        assert_eq!(text.matches("### ").count(), 1);
+        assert!(text.contains("> Simplify, simplify.\n\nCf. <Economy>.\n\n*Page 13.0 · 2024-03-01 09:30*\n\n"));
+        assert!(text.ends_with("## Bookmarks\n\n- Page 2.0\n\n"));
+    }
+
+    #[test]
+    fn test_notes_path() {
+        let directory = Path::new("/mnt/onboard/.notes");
+        assert_eq!(notes_path(directory, Path::new("Thoreau/walden.epub"), ExportFormat::Markdown),
+                   Some(PathBuf::from("/mnt/onboard/.notes/Thoreau/walden.epub.md")));
+        assert_eq!(notes_path(directory, Path::new("Thoreau/walden.pdf"), ExportFormat::Markdown),
+                   Some(PathBuf::from("/mnt/onboard/.notes/Thoreau/walden.pdf.md")));
+        assert_eq!(notes_path(directory, Path::new("walden.epub"), ExportFormat::Json),
+                   Some(PathBuf::from("/mnt/onboard/.notes/walden.epub.json")));
+        assert_eq!(notes_path(directory, Path::new(""), ExportFormat::Html), None);
+    }
+
+    #[test]
+    fn test_notes_as_html() {
+        let text = notes_as_html(&sample_notes());
+        assert!(text.contains("<title>Walden</title>"));
+        assert!(text.contains("<h3>Where I Lived</h3>"));
+        assert!(text.contains("<p>Cf. &lt;Economy&gt;.</p>"));
+        assert!(text.contains("<li>Page 2.0</li>"));
+    }
+}
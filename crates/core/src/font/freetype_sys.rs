@@ -179,7 +179,7 @@ pub struct FtGlyphMetrics {
     pub height: FtPos,
 
     hori_bearing_x: FtPos,
-    hori_bearing_y: FtPos,
+    pub hori_bearing_y: FtPos,
     hori_advance: FtPos,
 
     vert_bearing_x: FtPos,
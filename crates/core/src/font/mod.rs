@@ -11,6 +11,7 @@ use std::ffi::{CString, CStr};
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use std::collections::BTreeSet;
+use std::cmp::Reverse;
 use std::rc::Rc;
 use fxhash::FxHashMap;
 use bitflags::bitflags;
@@ -1370,6 +1371,15 @@ impl Font {
     }
 
     #[inline]
+    // Checks whether the given OpenType feature changes the glyphs of `sample`.
+    pub fn has_feature(&mut self, feature: &str, sample: &str) -> bool {
+        let features = [feature.to_string()];
+        let plain = self.plan(sample, None, None);
+        let featured = self.plan(sample, None, Some(&features));
+        plain.glyphs.iter().map(|g| g.codepoint)
+             .ne(featured.glyphs.iter().map(|g| g.codepoint))
+    }
+
     pub fn crop_right(&self, render_plan: &mut RenderPlan, max_width: i32) {
         if render_plan.width <= max_width {
             return;
@@ -1553,6 +1563,62 @@ impl Font {
         }
     }
 
+    // Renders a plan along a vertical line starting at `origin`.
+    // The abscissa of `origin` is the center of the column for upright glyphs,
+    // and the baseline for sideways glyphs (rotated by a quarter turn clockwise).
+    pub fn render_vertical(&mut self, fb: &mut dyn Framebuffer, color: u8, render_plan: &RenderPlan, origin: Point, upright: bool) {
+        let ascender = self.ascender();
+        let descender = self.descender();
+
+        unsafe {
+            let mut pos = origin;
+            let mut fallback_faces = FxHashMap::default();
+
+            for (index, glyph) in render_plan.glyphs.iter().enumerate() {
+                let face = if let Some(script) = render_plan.scripts.get(&index) {
+                    *fallback_faces.entry(script).or_insert_with(|| {
+                        let font_data = font_data_from_script(*script);
+                        let mut face = ptr::null_mut();
+                        FT_New_Memory_Face((self.lib).0, font_data.as_ptr() as *const FtByte,
+                                           font_data.len() as libc::c_long, 0, &mut face);
+                        FT_Set_Pixel_Sizes(face, (*(*self.face).size).metrics.x_ppem as libc::c_uint, 0);
+                        face
+                    })
+                } else {
+                    self.face
+                };
+
+                FT_Load_Glyph(face, glyph.codepoint, FT_LOAD_RENDER | FT_LOAD_NO_HINTING);
+
+                let glyph_slot = (*face).glyph;
+                let bitmap = &(*glyph_slot).bitmap;
+                let advance = glyph.advance.x;
+                let left = glyph.offset.x + (*glyph_slot).bitmap_left;
+                let top = glyph.offset.y - (*glyph_slot).bitmap_top;
+
+                for y in 0..bitmap.rows {
+                    for x in 0..bitmap.width {
+                        let blackness = *bitmap.buffer.offset((bitmap.pitch * y + x) as isize);
+                        let alpha = blackness as f32 / 255.0;
+                        let pt = if upright {
+                            let baseline = pos.y + advance * ascender / (ascender - descender).max(1);
+                            pt!(pos.x - advance / 2 + left + x, baseline + top + y)
+                        } else {
+                            pt!(pos.x - top - y, pos.y + left + x)
+                        };
+                        fb.set_blended_pixel(pt.x as u32, pt.y as u32, color, alpha);
+                    }
+                }
+
+                pos.y += advance;
+            }
+
+            for (_, face) in fallback_faces {
+                FT_Done_Face(face);
+            }
+        }
+    }
+
     pub fn height(&self, c: char) -> u32 {
         unsafe {
             FT_Load_Char(self.face, c as libc::c_ulong, FT_LOAD_DEFAULT);
@@ -1561,6 +1627,16 @@ impl Font {
         }
     }
 
+    // Returns the distances from the baseline to the top and bottom of the glyph of `c`.
+    pub fn vertical_extents(&self, c: char) -> (i32, i32) {
+        unsafe {
+            FT_Load_Char(self.face, c as libc::c_ulong, FT_LOAD_DEFAULT);
+            let metrics = &((*(*self.face).glyph).metrics);
+            let top = (metrics.hori_bearing_y >> 6) as i32;
+            (top, (metrics.height >> 6) as i32 - top)
+        }
+    }
+
     pub fn em(&self) -> u16 {
         unsafe {
             (*(*self.face).size).metrics.x_ppem as u16
@@ -1643,6 +1719,19 @@ impl RenderPlan {
         }
     }
 
+    // Puts the glyphs of a text shaped left-to-right in right-to-left visual order,
+    // the glyphs belonging to the same cluster keep their relative order.
+    pub fn reverse(&mut self) {
+        let mut indices: Vec<usize> = (0..self.glyphs.len()).collect();
+        indices.sort_by_key(|&i| Reverse(self.glyphs[i].cluster));
+        let scripts = indices.iter().enumerate()
+                             .filter_map(|(j, i)| self.scripts.get(i).map(|s| (j, *s)))
+                             .collect();
+        let glyphs = indices.iter().map(|&i| self.glyphs[i]).collect();
+        self.scripts = scripts;
+        self.glyphs = glyphs;
+    }
+
     pub fn split_off(&mut self, index: usize, width: i32) -> RenderPlan {
         let mut next_scripts = FxHashMap::default();
         if !self.scripts.is_empty() {
@@ -1170,6 +1170,21 @@ impl Boundary {
     pub fn height(&self) -> f32 {
         self.max.y - self.min.y
     }
+
+    pub fn absorb(&mut self, rect: &Boundary) {
+        if self.min.x > rect.min.x {
+            self.min.x = rect.min.x;
+        }
+        if self.max.x < rect.max.x {
+            self.max.x = rect.max.x;
+        }
+        if self.min.y > rect.min.y {
+            self.min.y = rect.min.y;
+        }
+        if self.max.y < rect.max.y {
+            self.max.y = rect.max.y;
+        }
+    }
 }
 
 #[macro_export]
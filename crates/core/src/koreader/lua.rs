@@ -0,0 +1,375 @@
+use anyhow::{Error, format_err};
+
+// The subset of Lua values found in KOReader's settings files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Table(Vec<(LuaValue, LuaValue)>),
+}
+
+impl LuaValue {
+    pub fn get(&self, key: &str) -> Option<&LuaValue> {
+        self.entries().iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+            .filter(|v| !matches!(v, LuaValue::Nil))
+    }
+
+    pub fn entries(&self) -> &[(LuaValue, LuaValue)] {
+        match self {
+            LuaValue::Table(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    // Returns the values of the array part of a table, in order.
+    pub fn values(&self) -> Vec<&LuaValue> {
+        let mut values: Vec<(usize, &LuaValue)> = self.entries().iter()
+            .filter_map(|(k, v)| k.as_usize().map(|i| (i, v)))
+            .collect();
+        values.sort_by_key(|(i, _)| *i);
+        values.into_iter().map(|(_, v)| v).collect()
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            LuaValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            LuaValue::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64().filter(|n| *n >= 0.0 && n.fract() == 0.0).map(|n| n as usize)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            LuaValue::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<LuaValue, Error> {
+    let mut parser = LuaParser { input: text.as_bytes(), offset: 0 };
+    parser.skip_blanks();
+    if parser.starts_with_word("return") {
+        parser.offset += "return".len();
+    }
+    let value = parser.parse_value()?;
+    parser.skip_blanks();
+    if parser.offset < parser.input.len() {
+        return Err(format_err!("unexpected trailing characters at offset {}", parser.offset));
+    }
+    Ok(value)
+}
+
+struct LuaParser<'a> {
+    input: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> LuaParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.offset).cloned()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.offset..].starts_with(s.as_bytes())
+    }
+
+    fn starts_with_word(&self, word: &str) -> bool {
+        self.starts_with(word) &&
+        !self.input.get(self.offset + word.len())
+             .is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_')
+    }
+
+    fn skip_blanks(&mut self) {
+        loop {
+            while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                self.offset += 1;
+            }
+            if !self.starts_with("--") {
+                break;
+            }
+            self.offset += 2;
+            if self.long_bracket_level().is_some() {
+                self.parse_long_string().ok();
+            } else {
+                while self.peek().is_some_and(|c| c != b'\n') {
+                    self.offset += 1;
+                }
+            }
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        self.skip_blanks();
+        if self.peek() == Some(c) {
+            self.offset += 1;
+            Ok(())
+        } else {
+            Err(format_err!("expected '{}' at offset {}", c as char, self.offset))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<LuaValue, Error> {
+        self.skip_blanks();
+        match self.peek() {
+            Some(b'{') => self.parse_table(),
+            Some(b'"') | Some(b'\'') => self.parse_string().map(LuaValue::String),
+            Some(b'[') if self.long_bracket_level().is_some() => self.parse_long_string().map(LuaValue::String),
+            Some(c) if c == b'-' || c == b'.' || c.is_ascii_digit() => self.parse_number().map(LuaValue::Number),
+            _ if self.starts_with_word("true") => {
+                self.offset += 4;
+                Ok(LuaValue::Boolean(true))
+            },
+            _ if self.starts_with_word("false") => {
+                self.offset += 5;
+                Ok(LuaValue::Boolean(false))
+            },
+            _ if self.starts_with_word("nil") => {
+                self.offset += 3;
+                Ok(LuaValue::Nil)
+            },
+            _ => Err(format_err!("unexpected value at offset {}", self.offset)),
+        }
+    }
+
+    fn parse_table(&mut self) -> Result<LuaValue, Error> {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+        let mut index = 1;
+
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                Some(b'}') => {
+                    self.offset += 1;
+                    break;
+                },
+                Some(b'[') if self.long_bracket_level().is_none() => {
+                    self.offset += 1;
+                    let key = self.parse_value()?;
+                    self.expect(b']')?;
+                    self.expect(b'=')?;
+                    let value = self.parse_value()?;
+                    entries.push((key, value));
+                },
+                Some(c) if (c.is_ascii_alphabetic() || c == b'_') && self.is_named_field() => {
+                    let start = self.offset;
+                    while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_') {
+                        self.offset += 1;
+                    }
+                    let key = String::from_utf8_lossy(&self.input[start..self.offset]).into_owned();
+                    self.expect(b'=')?;
+                    let value = self.parse_value()?;
+                    entries.push((LuaValue::String(key), value));
+                },
+                Some(_) => {
+                    let value = self.parse_value()?;
+                    entries.push((LuaValue::Number(index as f64), value));
+                    index += 1;
+                },
+                None => return Err(format_err!("unterminated table")),
+            }
+
+            self.skip_blanks();
+            match self.peek() {
+                Some(b',') | Some(b';') => self.offset += 1,
+                Some(b'}') => (),
+                _ => return Err(format_err!("expected ',' or '}}' at offset {}", self.offset)),
+            }
+        }
+
+        Ok(LuaValue::Table(entries))
+    }
+
+    // Distinguishes `name = value` from the keywords `true`, `false` and `nil`.
+    fn is_named_field(&self) -> bool {
+        let mut offset = self.offset;
+        while self.input.get(offset).is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_') {
+            offset += 1;
+        }
+        while self.input.get(offset).is_some_and(|c| c.is_ascii_whitespace()) {
+            offset += 1;
+        }
+        self.input.get(offset) == Some(&b'=') && self.input.get(offset + 1) != Some(&b'=')
+    }
+
+    fn parse_number(&mut self) -> Result<f64, Error> {
+        let start = self.offset;
+        let negative = self.peek() == Some(b'-');
+        if negative {
+            self.offset += 1;
+        }
+        if self.starts_with("0x") || self.starts_with("0X") {
+            self.offset += 2;
+            let digits_start = self.offset;
+            while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                self.offset += 1;
+            }
+            let digits = std::str::from_utf8(&self.input[digits_start..self.offset])?;
+            let n = u64::from_str_radix(digits, 16)? as f64;
+            return Ok(if negative { -n } else { n });
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == b'.' ||
+                                          ((c == b'-' || c == b'+') &&
+                                           matches!(self.input[self.offset - 1], b'e' | b'E'))) {
+            self.offset += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.offset])?;
+        text.parse::<f64>().map_err(|_| format_err!("invalid number {:?} at offset {}", text, start))
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        let quote = self.input[self.offset];
+        self.offset += 1;
+        let mut buf = Vec::new();
+
+        loop {
+            let c = self.peek().ok_or_else(|| format_err!("unterminated string"))?;
+            self.offset += 1;
+            if c == quote {
+                break;
+            }
+            if c != b'\\' {
+                buf.push(c);
+                continue;
+            }
+            let e = self.peek().ok_or_else(|| format_err!("unterminated string"))?;
+            self.offset += 1;
+            match e {
+                b'n' | b'\n' => buf.push(b'\n'),
+                b't' => buf.push(b'\t'),
+                b'r' => buf.push(b'\r'),
+                b'a' => buf.push(0x07),
+                b'b' => buf.push(0x08),
+                b'f' => buf.push(0x0c),
+                b'v' => buf.push(0x0b),
+                b'x' => {
+                    let digits = self.input.get(self.offset..self.offset+2)
+                                     .ok_or_else(|| format_err!("invalid escape sequence"))?;
+                    buf.push(u8::from_str_radix(std::str::from_utf8(digits)?, 16)?);
+                    self.offset += 2;
+                },
+                b'z' => {
+                    while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+                        self.offset += 1;
+                    }
+                },
+                b'u' => {
+                    self.expect(b'{')?;
+                    let start = self.offset;
+                    while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                        self.offset += 1;
+                    }
+                    let code = u32::from_str_radix(std::str::from_utf8(&self.input[start..self.offset])?, 16)?;
+                    self.expect(b'}')?;
+                    let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                    buf.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                b'0'..=b'9' => {
+                    let start = self.offset - 1;
+                    while self.offset - start < 3 && self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        self.offset += 1;
+                    }
+                    let code = std::str::from_utf8(&self.input[start..self.offset])?.parse::<u8>()?;
+                    buf.push(code);
+                },
+                _ => buf.push(e),
+            }
+        }
+
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    // Returns the level of the long bracket opening at the current offset.
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek() != Some(b'[') {
+            return None;
+        }
+        let level = self.input[self.offset+1..].iter().take_while(|&&c| c == b'=').count();
+        if self.input.get(self.offset + level + 1) == Some(&b'[') {
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    fn parse_long_string(&mut self) -> Result<String, Error> {
+        let level = self.long_bracket_level().ok_or_else(|| format_err!("expected long bracket"))?;
+        self.offset += level + 2;
+        if self.peek() == Some(b'\n') {
+            self.offset += 1;
+        }
+        let closing = format!("]{}]", "=".repeat(level));
+        let start = self.offset;
+        while !self.starts_with(&closing) {
+            if self.offset >= self.input.len() {
+                return Err(format_err!("unterminated long string"));
+            }
+            self.offset += 1;
+        }
+        let text = String::from_utf8_lossy(&self.input[start..self.offset]).into_owned();
+        self.offset += closing.len();
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sidecar() {
+        let text = r#"-- we can read Lua syntax here!
+return {
+    ["annotations"] = {
+        [1] = {
+            ["datetime"] = "2024-03-01 09:30:00",
+            ["pos0"] = "/body/DocFragment[3]/body/p[2]/text().4",
+            ["text"] = "Simplify,\
+simplify.",
+        },
+        [2] = {
+            ["page"] = 12,
+            ["pos0"] = { ["page"] = 12, ["x"] = 10.5, ["y"] = -2e1 },
+        },
+    },
+    ["doc_pages"] = 0x10,
+    ["percent_finished"] = 0.35,
+    ["summary"] = {
+        status = "complete";
+        ["note"] = 'it\'s \"\x41\066\u{e9}\"',
+    },
+    ["readermenu_tab_index"] = nil,
+    ["stats"] = { true, false, [[long]] },
+}
+"#;
+        let value = parse(text).unwrap();
+        let annotations = value.get("annotations").unwrap().values();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].get("text").and_then(LuaValue::as_str), Some("Simplify,\nsimplify."));
+        assert_eq!(annotations[1].get("pos0").and_then(|p| p.get("y")).and_then(LuaValue::as_f64), Some(-20.0));
+        assert_eq!(value.get("doc_pages").and_then(LuaValue::as_usize), Some(16));
+        assert_eq!(value.get("percent_finished").and_then(LuaValue::as_f64), Some(0.35));
+        let summary = value.get("summary").unwrap();
+        assert_eq!(summary.get("status").and_then(LuaValue::as_str), Some("complete"));
+        assert_eq!(summary.get("note").and_then(LuaValue::as_str), Some("it's \"AB\u{e9}\""));
+        assert_eq!(value.get("readermenu_tab_index"), None);
+        let stats = value.get("stats").unwrap().values();
+        assert_eq!(stats, vec![&LuaValue::Boolean(true), &LuaValue::Boolean(false), &LuaValue::String("long".to_string())]);
+        assert!(parse("return { 1, ").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use anyhow::Error;
+
+const SHIFTS: [u32; 64] = [7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+                           5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+                           4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+                           6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21];
+
+const SAMPLE_SIZE: usize = 1024;
+const SAMPLES_COUNT: u32 = 11;
+
+pub struct Md5 {
+    state: [u32; 4],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Md5 {
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+}
+
+impl Md5 {
+    pub fn new() -> Md5 {
+        Md5::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        self.buffer.extend_from_slice(data);
+        let count = self.buffer.len() / 64;
+        for i in 0..count {
+            let mut block = [0u8; 64];
+            block.copy_from_slice(&self.buffer[i*64..(i+1)*64]);
+            self.compress(&block);
+        }
+        self.buffer.drain(..count * 64);
+    }
+
+    pub fn finalize(mut self) -> [u8; 16] {
+        let bits = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize((119 - self.buffer.len()) % 64 + 1, 0);
+        padding.extend_from_slice(&bits.to_le_bytes());
+        let length = self.length;
+        self.update(&padding);
+        self.length = length;
+        let mut digest = [0u8; 16];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[4*i..4*i+4].copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for (i, word) in m.iter_mut().enumerate() {
+            *word = u32::from_le_bytes([block[4*i], block[4*i+1], block[4*i+2], block[4*i+3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for (i, &shift) in SHIFTS.iter().enumerate() {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let k = ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32;
+            let f = f.wrapping_add(a).wrapping_add(k).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(shift));
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn md5_hex(data: &[u8]) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(data);
+    to_hex(&hasher.finalize())
+}
+
+// KOReader identifies documents by hashing samples taken at exponentially
+// growing offsets: 0, 1 KiB, 4 KiB, 16 KiB, etc.
+pub fn partial_md5<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let mut file = File::open(path.as_ref())?;
+    let mut hasher = Md5::new();
+    let mut buf = [0u8; SAMPLE_SIZE];
+
+    for i in 0..=SAMPLES_COUNT {
+        let offset = if i == 0 { 0 } else { (SAMPLE_SIZE as u64) << (2 * (i - 1)) };
+        file.seek(SeekFrom::Start(offset))?;
+        let mut count = 0;
+        while count < SAMPLE_SIZE {
+            let n = file.read(&mut buf[count..])?;
+            if n == 0 {
+                break;
+            }
+            count += n;
+        }
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buf[..count]);
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(md5_hex(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"),
+                   "57edf4a22be3c955ac49da2e2107b67a");
+    }
+}
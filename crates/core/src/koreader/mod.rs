@@ -0,0 +1,308 @@
+pub mod lua;
+pub mod md5;
+pub mod sync;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Error, format_err};
+use chrono::{Local, DateTime, NaiveDateTime};
+use crate::document::{Document, Location, TextLocation, open};
+use crate::document::epub::EpubDocument;
+use crate::metadata::{Info, ReaderInfo, Annotation};
+use self::lua::LuaValue;
+use self::md5::partial_md5;
+
+pub const SIDECAR_EXTENSION: &str = "sdr";
+pub const DOCSETTINGS_DIRNAME: &str = "docsettings";
+pub const HASHDOCSETTINGS_DIRNAME: &str = "hashdocsettings";
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// KOReader keeps the settings of a document either next to it, in a mirror
+// of the file system, or in a directory named after its partial MD5 hash.
+pub fn find_sidecar<P: AsRef<Path>, Q: AsRef<Path>>(path: P, directory: Q) -> Option<PathBuf> {
+    let path = path.as_ref();
+    let directory = directory.as_ref();
+    let extension = path.extension()?.to_str()?;
+    let names = [format!("metadata.{}.lua", extension),
+                 format!("metadata.{}.lua", extension.to_lowercase())];
+    let sidecar_dir = path.with_extension(SIDECAR_EXTENSION);
+    let mut dirs = vec![sidecar_dir.clone(),
+                        directory.join(DOCSETTINGS_DIRNAME)
+                                 .join(sidecar_dir.strip_prefix("/").unwrap_or(&sidecar_dir))];
+
+    if let Ok(digest) = partial_md5(path) {
+        dirs.push(directory.join(HASHDOCSETTINGS_DIRNAME)
+                           .join(&digest[..2])
+                           .join(format!("{}.{}", digest, SIDECAR_EXTENSION)));
+    }
+
+    dirs.iter()
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+enum Book {
+    Epub(Box<EpubDocument>),
+    Other(Box<dyn Document>),
+}
+
+impl Book {
+    fn doc(&mut self) -> &mut dyn Document {
+        match self {
+            Book::Epub(doc) => doc.as_mut(),
+            Book::Other(doc) => doc.as_mut(),
+        }
+    }
+
+    // Positions are XPointers in reflowable documents, and page
+    // coordinates, `{ page, x, y }`, in the others.
+    fn locate(&mut self, pos: &LuaValue) -> Option<TextLocation> {
+        match pos {
+            LuaValue::String(xpointer) => {
+                if let Book::Epub(doc) = self {
+                    doc.xpointer_location(xpointer)
+                } else {
+                    None
+                }
+            },
+            LuaValue::Table(..) => {
+                let doc = self.doc();
+                if doc.is_reflowable() {
+                    return None;
+                }
+                let index = pos.get("page").and_then(LuaValue::as_usize)?.checked_sub(1)?;
+                let x = pos.get("x").and_then(LuaValue::as_f64)? as f32;
+                let y = pos.get("y").and_then(LuaValue::as_f64)? as f32;
+                let (words, _) = doc.words(Location::Exact(index))?;
+                words.iter().min_by(|a, b| {
+                    let da = rect_distance(a.rect.min.x, a.rect.min.y, a.rect.max.x, a.rect.max.y, x, y);
+                    let db = rect_distance(b.rect.min.x, b.rect.min.y, b.rect.max.x, b.rect.max.y, x, y);
+                    da.total_cmp(&db)
+                }).map(|word| word.location)
+            },
+            _ => None,
+        }
+    }
+
+    // Bookmarks are designated by a page number or an XPointer.
+    fn locate_page(&mut self, page: &LuaValue) -> Option<usize> {
+        match page {
+            LuaValue::String(..) => self.locate(page).map(TextLocation::location),
+            LuaValue::Number(..) if !self.doc().is_reflowable() => {
+                page.as_usize()?.checked_sub(1)
+                    .filter(|&index| index < self.doc().pages_count())
+            },
+            _ => None,
+        }
+    }
+}
+
+fn rect_distance(min_x: f32, min_y: f32, max_x: f32, max_y: f32, x: f32, y: f32) -> f32 {
+    let dx = (min_x - x).max(x - max_x).max(0.0);
+    let dy = (min_y - y).max(y - max_y).max(0.0);
+    dx * dx + dy * dy
+}
+
+fn parse_datetime(value: Option<&LuaValue>) -> Option<NaiveDateTime> {
+    value.and_then(LuaValue::as_str)
+         .and_then(|text| NaiveDateTime::parse_from_str(text, DATETIME_FORMAT).ok())
+}
+
+#[derive(Debug)]
+struct Highlight {
+    pos0: LuaValue,
+    pos1: LuaValue,
+    text: String,
+    note: String,
+    modified: Option<NaiveDateTime>,
+}
+
+// Recent versions of KOReader store highlights and bookmarks in a single
+// list, older versions use two separate tables.
+fn collect_marks(settings: &LuaValue) -> (Vec<Highlight>, Vec<LuaValue>) {
+    let mut highlights = Vec::new();
+    let mut bookmarks = Vec::new();
+
+    if let Some(annotations) = settings.get("annotations") {
+        for annot in annotations.values() {
+            match (annot.get("pos0"), annot.get("pos1")) {
+                (Some(pos0), Some(pos1)) => highlights.push(Highlight {
+                    pos0: pos0.clone(),
+                    pos1: pos1.clone(),
+                    text: annot.get("text").and_then(LuaValue::as_str).unwrap_or_default().to_string(),
+                    note: annot.get("note").and_then(LuaValue::as_str).unwrap_or_default().to_string(),
+                    modified: parse_datetime(annot.get("datetime_updated"))
+                                  .or_else(|| parse_datetime(annot.get("datetime"))),
+                }),
+                _ => bookmarks.extend(annot.get("page").cloned()),
+            }
+        }
+        return (highlights, bookmarks);
+    }
+
+    let old_bookmarks = settings.get("bookmarks").map(LuaValue::values).unwrap_or_default();
+
+    if let Some(highlight) = settings.get("highlight") {
+        for (_, items) in highlight.entries() {
+            for item in items.values() {
+                let (pos0, pos1) = match (item.get("pos0"), item.get("pos1")) {
+                    (Some(pos0), Some(pos1)) => (pos0, pos1),
+                    _ => continue,
+                };
+                // The text of the bookmark associated with a highlight is its note,
+                // unless the user never edited it.
+                let note = old_bookmarks.iter()
+                                        .find(|bkm| bkm.get("highlighted").and_then(LuaValue::as_bool) == Some(true) &&
+                                                    bkm.get("pos0") == Some(pos0))
+                                        .and_then(|bkm| {
+                                            let text = bkm.get("text").and_then(LuaValue::as_str)?;
+                                            let notes = bkm.get("notes").and_then(LuaValue::as_str);
+                                            Some(text).filter(|t| Some(*t) != notes && !t.starts_with("Page "))
+                                        })
+                                        .unwrap_or_default().to_string();
+                highlights.push(Highlight {
+                    pos0: pos0.clone(),
+                    pos1: pos1.clone(),
+                    text: item.get("text").and_then(LuaValue::as_str).unwrap_or_default().to_string(),
+                    note,
+                    modified: parse_datetime(item.get("datetime")),
+                });
+            }
+        }
+    }
+
+    bookmarks.extend(old_bookmarks.iter()
+                                  .filter(|bkm| bkm.get("highlighted").and_then(LuaValue::as_bool) != Some(true))
+                                  .filter_map(|bkm| bkm.get("page").cloned()));
+
+    (highlights, bookmarks)
+}
+
+// Merges the reading state stored by KOReader into the given document information.
+// The position is only taken when KOReader saved it after the last time Plato opened the document.
+// Returns whether the reading state was changed.
+pub fn import_reading_state<P: AsRef<Path>, Q: AsRef<Path>>(home: P, info: &mut Info, directory: Q) -> Result<bool, Error> {
+    let path = home.as_ref().join(&info.file.path);
+    let sidecar = match find_sidecar(&path, directory) {
+        Some(sidecar) => sidecar,
+        None => return Ok(false),
+    };
+    let settings = lua::parse(&fs::read_to_string(&sidecar)?)?;
+    let saved = DateTime::<Local>::from(fs::metadata(&sidecar)?.modified()?).naive_local();
+
+    let mut book = if info.file.kind == "epub" {
+        Book::Epub(Box::new(EpubDocument::new(&path)?))
+    } else {
+        Book::Other(open(&path).ok_or_else(|| format_err!("can't open document"))?)
+    };
+
+    if book.doc().is_protected() {
+        let password = info.reader.as_ref().and_then(|r| r.password.as_deref()).unwrap_or_default();
+        if !book.doc().authenticate(password) {
+            return Err(format_err!("invalid password"));
+        }
+    }
+
+    let mut changed = info.reader.is_none();
+    let pages_count = book.doc().pages_count();
+    let reader = info.reader.get_or_insert_with(|| ReaderInfo { opened: saved, .. Default::default() });
+
+    if changed || saved > reader.opened {
+        let current_page = settings.get("last_xpointer")
+                                   .and_then(|xpointer| book.locate(xpointer))
+                                   .map(TextLocation::location)
+                                   .or_else(|| settings.get("last_page")
+                                                       .filter(|_| !book.doc().is_reflowable())
+                                                       .and_then(LuaValue::as_usize)
+                                                       .and_then(|page| page.checked_sub(1)))
+                                   .or_else(|| settings.get("percent_finished")
+                                                       .and_then(LuaValue::as_f64)
+                                                       .map(|percent| (percent * pages_count as f64) as usize));
+        if let Some(current_page) = current_page {
+            reader.current_page = current_page.min(pages_count.saturating_sub(1));
+            reader.pages_count = pages_count;
+            changed = true;
+        }
+        if let Some(status) = settings.get("summary").and_then(|s| s.get("status")).and_then(LuaValue::as_str) {
+            reader.finished = status == "complete";
+            changed = true;
+        }
+        reader.opened = reader.opened.max(saved);
+    }
+
+    let (highlights, bookmarks) = collect_marks(&settings);
+
+    for hl in highlights {
+        let (start, end) = match (book.locate(&hl.pos0), book.locate(&hl.pos1)) {
+            (Some(start), Some(end)) => start.min_max(end),
+            _ => continue,
+        };
+        if reader.annotations.iter().any(|annot| annot.selection == [start, end]) {
+            continue;
+        }
+        reader.annotations.push(Annotation {
+            note: hl.note,
+            text: hl.text,
+            selection: [start, end],
+            modified: hl.modified.unwrap_or(saved),
+        });
+        changed = true;
+    }
+
+    for page in bookmarks {
+        if let Some(location) = book.locate_page(&page) {
+            changed |= reader.bookmarks.insert(location);
+        }
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_marks() {
+        let recent = lua::parse(r#"return {
+            ["annotations"] = {
+                [1] = { ["page"] = 3, ["pos0"] = { ["page"] = 3, ["x"] = 1, ["y"] = 2 },
+                        ["pos1"] = { ["page"] = 3, ["x"] = 9, ["y"] = 2 }, ["text"] = "Foo",
+                        ["note"] = "Bar", ["datetime"] = "2024-03-01 09:30:00" },
+                [2] = { ["page"] = 7, ["datetime"] = "2024-03-02 10:00:00" },
+            },
+        }"#).unwrap();
+        let (highlights, bookmarks) = collect_marks(&recent);
+        assert_eq!(highlights.len(), 1);
+        assert_eq!((highlights[0].text.as_str(), highlights[0].note.as_str()), ("Foo", "Bar"));
+        assert!(highlights[0].modified.is_some());
+        assert_eq!(bookmarks, vec![LuaValue::Number(7.0)]);
+
+        let older = lua::parse(r#"return {
+            ["highlight"] = {
+                [3] = {
+                    [1] = { ["pos0"] = "/body/DocFragment[2]/body/p/text().0",
+                            ["pos1"] = "/body/DocFragment[2]/body/p/text().3", ["text"] = "Foo" },
+                    [2] = { ["pos0"] = "/body/DocFragment[2]/body/p/text().5",
+                            ["pos1"] = "/body/DocFragment[2]/body/p/text().8", ["text"] = "Baz" },
+                },
+            },
+            ["bookmarks"] = {
+                [1] = { ["highlighted"] = true, ["notes"] = "Foo", ["text"] = "Bar",
+                        ["pos0"] = "/body/DocFragment[2]/body/p/text().0" },
+                [2] = { ["highlighted"] = true, ["notes"] = "Baz", ["text"] = "Page 3 @ 10:00",
+                        ["pos0"] = "/body/DocFragment[2]/body/p/text().5" },
+                [3] = { ["page"] = "/body/DocFragment[4]/body/p/text().0" },
+            },
+        }"#).unwrap();
+        let (highlights, bookmarks) = collect_marks(&older);
+        assert_eq!(highlights.iter().map(|hl| hl.note.as_str()).collect::<Vec<_>>(), vec!["Bar", ""]);
+        assert_eq!(bookmarks.len(), 1);
+    }
+
+    #[test]
+    fn test_rect_distance() {
+        assert_eq!(rect_distance(0.0, 0.0, 10.0, 5.0, 4.0, 3.0), 0.0);
+        assert_eq!(rect_distance(0.0, 0.0, 10.0, 5.0, 13.0, 9.0), 25.0);
+    }
+}
@@ -0,0 +1,279 @@
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{Error, format_err};
+use reqwest::{Method, StatusCode};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value as JsonValue};
+use rand_core::RngCore;
+use crate::document::epub::EpubDocument;
+use crate::context::Context;
+use crate::helpers::Fingerprint;
+use crate::library::Library;
+use crate::settings::{SyncSettings, DocumentKey};
+use super::md5::{md5_hex, partial_md5};
+
+const ACCEPT: &str = "application/vnd.koreader.v1+json";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// The reading position of a document, as stored by the server.
+// The position is an XPointer in ePUBs and a page number otherwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f64,
+    pub device: String,
+    pub device_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+}
+
+pub struct SyncClient {
+    client: Client,
+    server: String,
+    username: String,
+    key: String,
+    device: String,
+    device_id: String,
+}
+
+// Returns the sync settings, after giving this device a persistent identifier.
+pub fn sync_settings(context: &mut Context) -> SyncSettings {
+    if context.settings.koreader.sync.device_id.is_empty() {
+        context.settings.koreader.sync.device_id = format!("{:016X}{:016X}", context.rng.next_u64(),
+                                                           context.rng.next_u64());
+    }
+    context.settings.koreader.sync.clone()
+}
+
+pub fn document_key(library: &Library, path: &Path, key: DocumentKey) -> Result<String, Error> {
+    let full_path = library.home.join(path);
+    match key {
+        DocumentKey::PartialMd5 => partial_md5(&full_path),
+        DocumentKey::Fingerprint => {
+            let fp = library.paths.get(path).cloned().or_else(|| {
+                full_path.metadata().ok()
+                         .and_then(|md| md.fingerprint(library.fat32_epoch).ok())
+            }).ok_or_else(|| format_err!("can't get fingerprint of {}", path.display()))?;
+            Ok(fp.to_string())
+        },
+    }
+}
+
+// Converts a location into a position understood by KOReader.
+pub fn position(path: &Path, kind: &str, location: usize) -> Option<String> {
+    if kind == "epub" {
+        EpubDocument::new(path).ok()?.location_xpointer(location)
+    } else {
+        Some((location + 1).to_string())
+    }
+}
+
+// Converts a position saved by KOReader into a location. The pages of reflowed documents
+// are found in their page list. The percentage is used when the position can't be resolved,
+// e.g. in reflowable documents other than ePUBs.
+pub fn locate(path: &Path, kind: &str, reflowable: bool, page_list: &[usize], progress: &Progress, pages_count: usize) -> Option<usize> {
+    let location = if kind == "epub" {
+        EpubDocument::new(path).ok()
+                               .and_then(|mut doc| doc.xpointer_location(&progress.progress))
+                               .map(|loc| loc.location())
+    } else {
+        let index = progress.progress.parse::<usize>().ok()
+                            .and_then(|page| page.checked_sub(1));
+        if reflowable {
+            index.and_then(|index| page_list.get(index).cloned())
+        } else {
+            index
+        }
+    };
+    location.or_else(|| Some((progress.percentage.clamp(0.0, 1.0) * pages_count as f64) as usize))
+            .map(|location| location.min(pages_count.saturating_sub(1)))
+}
+
+impl SyncClient {
+    pub fn new(settings: &SyncSettings) -> Result<SyncClient, Error> {
+        if settings.username.is_empty() {
+            return Err(format_err!("no username"));
+        }
+        let client = Client::builder().timeout(TIMEOUT).build()?;
+        Ok(SyncClient {
+            client,
+            server: settings.server.trim_end_matches('/').to_string(),
+            username: settings.username.clone(),
+            key: md5_hex(settings.password.as_bytes()),
+            device: settings.device_name.clone(),
+            device_id: settings.device_id.clone(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client.request(method, format!("{}{}", self.server, path))
+                   .header("Accept", ACCEPT)
+                   .header("x-auth-user", &self.username)
+                   .header("x-auth-key", &self.key)
+    }
+
+    pub fn register(&self) -> Result<(), Error> {
+        let response = self.client.post(format!("{}/users/create", self.server))
+                                  .header("Accept", ACCEPT)
+                                  .json(&json!({"username": &self.username, "password": &self.key}))
+                                  .send()?;
+        check_status(response).map(|_| ())
+    }
+
+    // Returns whether the credentials were accepted.
+    pub fn authorize(&self) -> Result<bool, Error> {
+        let response = self.request(Method::GET, "/users/auth").send()?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Ok(false);
+        }
+        check_status(response).map(|_| true)
+    }
+
+    pub fn update_progress(&self, document: &str, progress: &str, percentage: f64) -> Result<(), Error> {
+        let progress = Progress {
+            document: document.to_string(),
+            progress: progress.to_string(),
+            percentage,
+            device: self.device.clone(),
+            device_id: self.device_id.clone(),
+            timestamp: None,
+        };
+        let response = self.request(Method::PUT, "/syncs/progress")
+                           .json(&progress)
+                           .send()?;
+        check_status(response).map(|_| ())
+    }
+
+    // Returns the last position pushed by another device.
+    pub fn progress(&self, document: &str) -> Result<Option<Progress>, Error> {
+        let response = self.request(Method::GET, &format!("/syncs/progress/{}", document)).send()?;
+        let value = check_status(response)?;
+        // The server answers with an empty object for unknown documents.
+        if value.get("document").is_none() {
+            return Ok(None);
+        }
+        let progress: Progress = serde_json::from_value(value)?;
+        Ok(Some(progress).filter(|p| p.device_id != self.device_id))
+    }
+}
+
+fn check_status(response: Response) -> Result<JsonValue, Error> {
+    let status = response.status();
+    let value = response.json::<JsonValue>().unwrap_or(JsonValue::Null);
+    if status.is_success() {
+        return Ok(value);
+    }
+    let message = value.get("message").and_then(JsonValue::as_str)
+                       .map(|msg| msg.trim_end_matches('.').to_lowercase())
+                       .unwrap_or_else(|| status.to_string());
+    Err(format_err!("{}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // A minimal stand-in for the progress sync server.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut users: HashMap<String, String> = HashMap::new();
+            let mut documents: HashMap<String, JsonValue> = HashMap::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                    }
+                }
+                let length = headers.get("content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let body: JsonValue = serde_json::from_slice(&body).unwrap_or(JsonValue::Null);
+                let mut parts = request_line.split_whitespace();
+                let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+                let authorized = headers.get("x-auth-user")
+                                        .and_then(|user| users.get(user))
+                                        .is_some_and(|key| Some(key) == headers.get("x-auth-key"));
+                let (status, reply) = match (method, path) {
+                    ("POST", "/users/create") => {
+                        let username = body["username"].as_str().unwrap().to_string();
+                        if users.contains_key(&username) {
+                            (402, json!({"code": 2002, "message": "Username is already registered."}))
+                        } else {
+                            users.insert(username.clone(), body["password"].as_str().unwrap().to_string());
+                            (201, json!({"username": username}))
+                        }
+                    },
+                    _ if !authorized => (401, json!({"code": 2001, "message": "Unauthorized"})),
+                    ("GET", "/users/auth") => (200, json!({"authorized": "OK"})),
+                    ("PUT", "/syncs/progress") => {
+                        let mut progress = body.clone();
+                        progress["timestamp"] = json!(1_700_000_000);
+                        documents.insert(body["document"].as_str().unwrap().to_string(), progress);
+                        (200, json!({"document": body["document"], "timestamp": 1_700_000_000}))
+                    },
+                    ("GET", _) if path.starts_with("/syncs/progress/") => {
+                        let document = &path["/syncs/progress/".len()..];
+                        (200, documents.get(document).cloned().unwrap_or_else(|| json!({})))
+                    },
+                    _ => (404, json!({})),
+                };
+                let reply = reply.to_string();
+                write!(stream, "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\n\
+                                Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       status, reply.len(), reply).unwrap();
+            }
+        });
+        format!("http://{}/", address)
+    }
+
+    fn settings(server: &str, password: &str, device_id: &str) -> SyncSettings {
+        SyncSettings {
+            server: server.to_string(),
+            username: "reader".to_string(),
+            password: password.to_string(),
+            device_id: device_id.to_string(),
+            .. Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sync_client() {
+        let server = serve();
+        let kobo = SyncClient::new(&settings(&server, "secret", "kobo")).unwrap();
+        let phone = SyncClient::new(&settings(&server, "secret", "phone")).unwrap();
+        let intruder = SyncClient::new(&settings(&server, "guess", "laptop")).unwrap();
+
+        assert!(!kobo.authorize().unwrap());
+        kobo.register().unwrap();
+        assert!(phone.register().is_err());
+        assert!(kobo.authorize().unwrap());
+        assert!(!intruder.authorize().unwrap());
+
+        assert_eq!(kobo.progress("3a5f").unwrap(), None);
+        phone.update_progress("3a5f", "/body/DocFragment[3]/body/p[2]/text().4", 0.25).unwrap();
+        let progress = kobo.progress("3a5f").unwrap().unwrap();
+        assert_eq!((progress.device.as_str(), progress.device_id.as_str()), ("Plato", "phone"));
+        assert_eq!(progress.percentage, 0.25);
+        assert_eq!(progress.timestamp, Some(1_700_000_000));
+        assert_eq!(phone.progress("3a5f").unwrap(), None);
+        assert!(intruder.progress("3a5f").is_err());
+    }
+}
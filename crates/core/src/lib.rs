@@ -14,6 +14,9 @@ pub mod document;
 pub mod library;
 pub mod view;
 pub mod metadata;
+pub mod export;
+pub mod koreader;
+pub mod statistics;
 mod symbolic_path;
 pub mod rtc;
 pub mod settings;
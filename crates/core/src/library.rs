@@ -13,6 +13,7 @@ use crate::metadata::{Info, ReaderInfo, FileInfo, BookQuery, SimpleStatus, SortM
 use crate::metadata::{sort, sorter, extract_metadata_from_document};
 use crate::settings::{LibraryMode, ImportSettings};
 use crate::document::file_kind;
+use crate::document::comic::is_image_folder;
 use crate::helpers::{Fingerprint, Fp, save_json, load_json, IsHidden};
 
 pub const METADATA_FILENAME: &str = ".metadata.json";
@@ -162,22 +163,30 @@ impl Library {
                     1
                 };
 
-                for entry in WalkDir::new(prefix.as_ref())
-                                     .min_depth(1)
-                                     .max_depth(max_depth)
-                                     .into_iter()
-                                     .filter_entry(|e| self.show_hidden || !e.is_hidden()) {
+                let mut entries = WalkDir::new(prefix.as_ref())
+                                          .min_depth(1)
+                                          .max_depth(max_depth)
+                                          .into_iter()
+                                          .filter_entry(|e| self.show_hidden || !e.is_hidden());
+
+                while let Some(entry) = entries.next() {
                     if entry.is_err() {
                         continue;
                     }
                     let entry = entry.unwrap();
                     let path = entry.path();
+                    let is_dir = path.is_dir();
 
-                    if path.is_dir() {
+                    if is_dir && !is_image_folder(path) {
                         if entry.depth() == 1 {
                             dirs.insert(path.to_path_buf());
                         }
                     } else {
+                        // The images of an image folder are its pages.
+                        if is_dir {
+                            entries.skip_current_dir();
+                        }
+
                         let relat = path.strip_prefix(&self.home)
                                         .unwrap_or(path);
                         if skip_files || query.map_or(false, |q| {
@@ -188,7 +197,7 @@ impl Library {
 
                         let kind = file_kind(&path).unwrap_or_default();
                         let md = entry.metadata().unwrap();
-                        let size = md.len();
+                        let size = file_size(path, &md);
                         let fp = md.fingerprint(self.fat32_epoch).unwrap();
                         let file = FileInfo {
                             path: relat.to_path_buf(),
@@ -221,15 +230,21 @@ impl Library {
             return;
         }
 
-        for entry in WalkDir::new(&self.home).min_depth(1).into_iter()
-                             .filter_entry(|e| !e.is_hidden()) {
+        let mut entries = WalkDir::new(&self.home).min_depth(1).into_iter()
+                                  .filter_entry(|e| !e.is_hidden());
+
+        while let Some(entry) = entries.next() {
             if entry.is_err() {
                 continue;
             }
 
             let entry = entry.unwrap();
             if entry.file_type().is_dir() {
-                continue;
+                if is_image_folder(entry.path()) {
+                    entries.skip_current_dir();
+                } else {
+                    continue;
+                }
             }
 
             let path = entry.path();
@@ -256,7 +271,7 @@ impl Library {
                     extract_metadata_from_document(&self.home, &mut info);
                 }
                 self.db.insert(fp, info);
-                self.db[&fp].file.size = md.len();
+                self.db[&fp].file.size = file_size(path, &md);
                 self.paths.insert(relat.to_path_buf(), fp);
                 let rp1 = self.reading_state_path(fp2);
                 let rp2 = self.reading_state_path(fp);
@@ -308,7 +323,7 @@ impl Library {
                         continue;
                     }
                     println!("Add new entry: {}, {}.", fp, relat.display());
-                    let size = md.len();
+                    let size = file_size(path, &md);
                     let file = FileInfo {
                         path: relat.to_path_buf(),
                         kind,
@@ -419,7 +434,9 @@ impl Library {
                     .and_then(|md| md.fingerprint(self.fat32_epoch).ok())
         }).ok_or_else(|| format_err!("can't get fingerprint of {}", path.as_ref().display()))?;
 
-        if full_path.exists() {
+        if full_path.is_dir() {
+            fs::remove_dir_all(&full_path)?;
+        } else if full_path.exists() {
             fs::remove_file(&full_path)?;
         }
 
@@ -478,7 +495,15 @@ impl Library {
             dest.set_file_name(name);
         }
 
-        fs::copy(&src, &dest)?;
+        if src.is_dir() {
+            fs::create_dir(&dest)?;
+            for entry in fs::read_dir(&src)? {
+                let entry = entry?;
+                fs::copy(entry.path(), dest.join(entry.file_name()))?;
+            }
+        } else {
+            fs::copy(&src, &dest)?;
+        }
         {
             let fdest = File::open(&dest)?;
             fdest.set_modified(md.modified()?)?;
@@ -685,6 +710,24 @@ impl Library {
         self.has_db_changed = true;
     }
 
+    // Unlike `apply`, persists the reading states of the documents for which `f` returns true.
+    pub fn update_reading_states<F>(&mut self, mut f: F) -> usize where F: FnMut(&Path, &mut Info) -> bool {
+        if self.mode == LibraryMode::Filesystem {
+            return 0;
+        }
+
+        let mut count = 0;
+
+        for (fp, info) in &mut self.db {
+            if f(&self.home, info) {
+                self.modified_reading_states.insert(*fp);
+                count += 1;
+            }
+        }
+
+        count
+    }
+
     pub fn sync_reader_info<P: AsRef<Path>>(&mut self, path: P, reader: &ReaderInfo) {
         let fp = self.paths.get(path.as_ref()).cloned().unwrap_or_else(|| {
             self.home.join(path.as_ref())
@@ -847,3 +890,17 @@ impl Library {
             .join(format!("{}.png", fp))
     }
 }
+
+// The size of a folder of images is the sum of the sizes of its files.
+fn file_size(path: &Path, md: &fs::Metadata) -> u64 {
+    if md.is_dir() {
+        fs::read_dir(path).map(|entries| {
+            entries.flatten()
+                   .filter_map(|entry| entry.metadata().ok())
+                   .map(|md| md.len())
+                   .sum()
+        }).unwrap_or_default()
+    } else {
+        md.len()
+    }
+}
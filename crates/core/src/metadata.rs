@@ -17,6 +17,9 @@ use crate::document::epub::EpubDocument;
 use crate::document::html::HtmlDocument;
 use crate::document::pdf::PdfOpener;
 use crate::document::djvu::DjvuOpener;
+use crate::document::comic::ComicDocument;
+use crate::document::fb2::Fb2Document;
+use crate::document::text::TextDocument;
 use crate::helpers::datetime_format;
 
 pub const DEFAULT_CONTRAST_EXPONENT: f32 = 1.0;
@@ -92,6 +95,33 @@ pub struct Annotation {
     pub modified: NaiveDateTime,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadingSession {
+    #[serde(with = "datetime_format")]
+    pub start: NaiveDateTime,
+    #[serde(with = "datetime_format")]
+    pub end: NaiveDateTime,
+    pub pages_turned: usize,
+    // The distance covered by forward page turns, in locations.
+    pub progress: usize,
+    // The active reading time, in seconds.
+    pub duration: u64,
+}
+
+impl Default for ReadingSession {
+    fn default() -> Self {
+        let now = Local::now().naive_local();
+        ReadingSession {
+            start: now,
+            end: now,
+            pages_turned: 0,
+            progress: 0,
+            duration: 0,
+        }
+    }
+}
+
 impl Default for Annotation {
     fn default() -> Self {
         Annotation {
@@ -183,6 +213,14 @@ impl TextAlign {
             TextAlign::Center => "align-center",
         }
     }
+
+    pub fn mirror(self) -> TextAlign {
+        match self {
+            TextAlign::Left => TextAlign::Right,
+            TextAlign::Right => TextAlign::Left,
+            _ => self,
+        }
+    }
 }
 
 impl fmt::Display for TextAlign {
@@ -200,6 +238,16 @@ pub struct ReaderInfo {
     pub pages_count: usize,
     pub finished: bool,
     pub dithered: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub reflow: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spreads: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lone_cover: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right_to_left: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub zoom_mode: Option<ZoomMode>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -222,6 +270,8 @@ pub struct ReaderInfo {
     pub text_align: Option<TextAlign>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub line_height: Option<f32>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub stylesheets: BTreeSet<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub contrast_exponent: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -232,6 +282,8 @@ pub struct ReaderInfo {
     pub bookmarks: BTreeSet<usize>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub annotations: Vec<Annotation>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sessions: Vec<ReadingSession>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
@@ -274,6 +326,11 @@ impl Default for ReaderInfo {
             pages_count: 1,
             finished: false,
             dithered: false,
+            reflow: false,
+            spreads: None,
+            lone_cover: None,
+            right_to_left: None,
+            password: None,
             zoom_mode: None,
             scroll_mode: None,
             page_offset: None,
@@ -285,11 +342,13 @@ impl Default for ReaderInfo {
             font_size: None,
             text_align: None,
             line_height: None,
+            stylesheets: BTreeSet::new(),
             contrast_exponent: None,
             contrast_gray: None,
             page_names: BTreeMap::new(),
             bookmarks: BTreeSet::new(),
             annotations: Vec::new(),
+            sessions: Vec::new(),
         }
     }
 }
@@ -783,6 +842,29 @@ pub fn extract_metadata_from_document(prefix: &Path, info: &mut Info) {
                 Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
             }
         },
+        "fb2" => {
+            match Fb2Document::new(&path) {
+                Ok(doc) => {
+                    info.title = doc.title().unwrap_or_default();
+                    info.author = doc.author().unwrap_or_default();
+                    info.year = doc.year().unwrap_or_default();
+                    info.publisher = doc.publisher().unwrap_or_default();
+                    info.series = doc.series().unwrap_or_default();
+                    info.number = doc.number().unwrap_or_default();
+                    info.language = doc.language().unwrap_or_default();
+                    info.categories.append(&mut doc.categories());
+                },
+                Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
+            }
+        },
+        "md" | "markdown" => {
+            match TextDocument::new(&path) {
+                Ok(doc) => {
+                    info.title = doc.title().unwrap_or_default();
+                },
+                Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
+            }
+        },
         "html" | "htm" => {
             match HtmlDocument::new(&path) {
                 Ok(doc) => {
@@ -814,6 +896,22 @@ pub fn extract_metadata_from_document(prefix: &Path, info: &mut Info) {
                 None => eprintln!("Can't open {}.", info.file.path.display()),
             }
         },
+        "cbz" | "cbr" | "cb7" | "cbt" | "dir" => {
+            match ComicDocument::new(&path) {
+                Ok(doc) => {
+                    info.title = doc.title().unwrap_or_default();
+                    info.author = doc.author().unwrap_or_default();
+                    info.year = doc.year().unwrap_or_default();
+                    info.publisher = doc.publisher().unwrap_or_default();
+                    info.series = doc.series().unwrap_or_default();
+                    info.number = doc.number().unwrap_or_default();
+                    info.volume = doc.volume().unwrap_or_default();
+                    info.language = doc.language().unwrap_or_default();
+                    info.categories.append(&mut doc.categories());
+                },
+                Err(e) => eprintln!("Can't open {}: {:#}.", info.file.path.display(), e),
+            }
+        },
         _ => {
                 eprintln!("Don't know how to extract metadata from {}.", &info.file.kind);
         },
@@ -122,6 +122,8 @@ pub struct Settings {
     pub import: ImportSettings,
     pub dictionary: DictionarySettings,
     pub sketch: SketchSettings,
+    pub export: ExportSettings,
+    pub koreader: KoreaderSettings,
     pub calculator: CalculatorSettings,
     pub battery: BatterySettings,
     pub frontlight_levels: LightLevels,
@@ -201,6 +203,47 @@ pub struct SketchSettings {
     pub pen: Pen,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ExportSettings {
+    pub directory: PathBuf,
+    pub formats: Vec<ExportFormat>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct KoreaderSettings {
+    pub directory: PathBuf,
+    pub sync: SyncSettings,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DocumentKey {
+    PartialMd5,
+    Fingerprint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SyncSettings {
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub device_name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub device_id: String,
+    pub document_key: DocumentKey,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct CalculatorSettings {
@@ -243,6 +286,38 @@ impl Default for SketchSettings {
     }
 }
 
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            // The hidden directories aren't part of the library.
+            directory: PathBuf::from(".notes"),
+            formats: vec![ExportFormat::Markdown, ExportFormat::Json, ExportFormat::Html],
+        }
+    }
+}
+
+impl Default for KoreaderSettings {
+    fn default() -> Self {
+        KoreaderSettings {
+            directory: PathBuf::from("/mnt/onboard/.adds/koreader"),
+            sync: SyncSettings::default(),
+        }
+    }
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        SyncSettings {
+            server: "https://sync.koreader.rocks".to_string(),
+            username: String::new(),
+            password: String::new(),
+            device_name: "Plato".to_string(),
+            device_id: String::new(),
+            document_key: DocumentKey::PartialMd5,
+        }
+    }
+}
+
 impl Default for CalculatorSettings {
     fn default() -> Self {
         CalculatorSettings {
@@ -345,9 +420,20 @@ pub struct ReaderSettings {
     pub line_height: f32,
     pub continuous_fit_to_width: bool,
     pub ignore_document_css: bool,
+    pub ignore_embedded_fonts: bool,
+    pub hide_ruby: bool,
+    pub remember_passwords: bool,
     pub dithered_kinds: FxHashSet<String>,
     pub paragraph_breaker: ParagraphBreakerSettings,
     pub refresh_rate: RefreshRateSettings,
+    pub spreads: SpreadSettings,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SpreadSettings {
+    pub enabled: bool,
+    pub lone_cover: bool,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -460,9 +546,23 @@ impl Default for ReaderSettings {
             line_height: DEFAULT_LINE_HEIGHT,
             continuous_fit_to_width: true,
             ignore_document_css: false,
-            dithered_kinds: ["cbz", "png", "jpg", "jpeg"].iter().map(|k| k.to_string()).collect(),
+            ignore_embedded_fonts: false,
+            hide_ruby: false,
+            remember_passwords: false,
+            dithered_kinds: ["cbz", "cbr", "cb7", "cbt", "dir",
+                                 "png", "jpg", "jpeg"].iter().map(|k| k.to_string()).collect(),
             paragraph_breaker: ParagraphBreakerSettings::default(),
             refresh_rate: RefreshRateSettings::default(),
+            spreads: SpreadSettings::default(),
+        }
+    }
+}
+
+impl Default for SpreadSettings {
+    fn default() -> Self {
+        SpreadSettings {
+            enabled: true,
+            lone_cover: true,
         }
     }
 }
@@ -473,9 +573,10 @@ impl Default for ImportSettings {
             unshare_trigger: true,
             startup_trigger: true,
             sync_metadata: true,
-            metadata_kinds: ["epub", "pdf", "djvu"].iter().map(|k| k.to_string()).collect(),
-            allowed_kinds: ["pdf", "djvu", "epub", "fb2", "txt",
-                            "xps", "oxps", "mobi", "cbz"].iter().map(|k| k.to_string()).collect(),
+            metadata_kinds: ["epub", "pdf", "djvu", "fb2", "md", "cbz",
+                             "cbr", "cb7", "cbt", "dir"].iter().map(|k| k.to_string()).collect(),
+            allowed_kinds: ["pdf", "djvu", "epub", "fb2", "txt", "md", "xps", "oxps",
+                            "mobi", "cbz", "cbr", "cb7", "cbt", "dir"].iter().map(|k| k.to_string()).collect(),
         }
     }
 }
@@ -547,6 +648,8 @@ impl Default for Settings {
             import: ImportSettings::default(),
             dictionary: DictionarySettings::default(),
             sketch: SketchSettings::default(),
+            export: ExportSettings::default(),
+            koreader: KoreaderSettings::default(),
             calculator: CalculatorSettings::default(),
             battery: BatterySettings::default(),
             frontlight_levels: LightLevels::default(),
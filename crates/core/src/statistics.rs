@@ -0,0 +1,249 @@
+use chrono::{NaiveDate, NaiveDateTime, Datelike, Duration};
+use crate::helpers::encode_entities;
+use crate::library::Library;
+use crate::metadata::{Info, ReaderInfo, ReadingSession};
+use crate::settings::LibraryMode;
+
+// A page displayed for longer than this is assumed to have been left unattended.
+pub const MAX_PAGE_DURATION: u64 = 300;
+// The reading speed isn't estimated from shorter reading times.
+const MIN_SPEED_DURATION: u64 = 60;
+const DAYS_COUNT: usize = 7;
+const WEEKS_COUNT: usize = 8;
+const MONTHS_COUNT: usize = 12;
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TIME_FORMAT: &str = "%H:%M";
+
+// Returns the reading time per location, in seconds.
+pub fn reading_speed<'a, I>(sessions: I) -> Option<f64> where I: IntoIterator<Item=&'a ReadingSession> {
+    let (duration, progress) = sessions.into_iter()
+                                       .fold((0, 0), |(d, p), s| (d + s.duration, p + s.progress));
+    if duration < MIN_SPEED_DURATION || progress == 0 {
+        return None;
+    }
+    Some(duration as f64 / progress as f64)
+}
+
+pub fn format_duration(secs: u64) -> String {
+    let minutes = (secs + 30) / 60;
+    if minutes >= 60 {
+        format!("{} h {:02} min", minutes / 60, minutes % 60)
+    } else if minutes > 0 {
+        format!("{} min", minutes)
+    } else {
+        "< 1 min".to_string()
+    }
+}
+
+// The reading time of each of the last `count` days, the most recent last.
+pub fn daily_durations<'a, I>(sessions: I, today: NaiveDate, count: usize) -> Vec<(NaiveDate, u64)>
+                              where I: IntoIterator<Item=&'a ReadingSession> {
+    let mut durations: Vec<(NaiveDate, u64)> = (0..count).rev()
+                                                         .map(|n| (today - Duration::days(n as i64), 0))
+                                                         .collect();
+    for session in sessions {
+        let date = session.start.date();
+        if let Some(entry) = durations.iter_mut().find(|(d, _)| *d == date) {
+            entry.1 += session.duration;
+        }
+    }
+    durations
+}
+
+// The reading time of each of the last `count` weeks, identified by their first day.
+pub fn weekly_durations<'a, I>(sessions: I, today: NaiveDate, count: usize) -> Vec<(NaiveDate, u64)>
+                               where I: IntoIterator<Item=&'a ReadingSession> {
+    let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let mut durations: Vec<(NaiveDate, u64)> = (0..count).rev()
+                                                         .map(|n| (monday - Duration::weeks(n as i64), 0))
+                                                         .collect();
+    for session in sessions {
+        let date = session.start.date();
+        if let Some(entry) = durations.iter_mut().rev().find(|(d, _)| *d <= date && date < *d + Duration::weeks(1)) {
+            entry.1 += session.duration;
+        }
+    }
+    durations
+}
+
+// The date at which a finished book was last read.
+fn finished_date(reader: &ReaderInfo) -> Option<NaiveDate> {
+    if !reader.finished {
+        return None;
+    }
+    Some(reader.sessions.last().map_or(reader.opened, |s| s.end).date())
+}
+
+// The number of books finished in each of the last `count` months, as (year, month, count).
+pub fn finished_per_month<'a, I>(readers: I, today: NaiveDate, count: usize) -> Vec<(i32, u32, usize)>
+                                 where I: IntoIterator<Item=&'a ReaderInfo> {
+    let mut months: Vec<(i32, u32, usize)> = (0..count as i32).rev().map(|n| {
+        let index = today.year() * 12 + today.month0() as i32 - n;
+        (index.div_euclid(12), index.rem_euclid(12) as u32 + 1, 0)
+    }).collect();
+    for date in readers.into_iter().filter_map(finished_date) {
+        if let Some(entry) = months.iter_mut().find(|(y, m, _)| *y == date.year() && *m == date.month()) {
+            entry.2 += 1;
+        }
+    }
+    months
+}
+
+fn html_head(title: &str) -> String {
+    format!("<html>\n\t<head>\n\t\t<title>{}</title>\n\t\t\
+             <link rel=\"stylesheet\" type=\"text/css\" href=\"css/statistics.css\"/>\n\t\
+             </head>\n\t<body>\n", encode_entities(title))
+}
+
+fn push_row(buf: &mut String, cells: &[String]) {
+    buf.push_str("\t\t\t<tr>\n");
+    for (index, cell) in cells.iter().enumerate() {
+        let class = if index == 0 { "key" } else { "value" };
+        buf.push_str(&format!("\t\t\t\t<td class=\"{}\">{}</td>\n", class, cell));
+    }
+    buf.push_str("\t\t\t</tr>\n");
+}
+
+fn push_table(buf: &mut String, title: &str, rows: &[Vec<String>]) {
+    buf.push_str(&format!("\t\t<h2>{}</h2>\n\t\t<table>\n", title));
+    for row in rows {
+        push_row(buf, row);
+    }
+    buf.push_str("\t\t</table>\n");
+}
+
+pub fn statistics_as_html(library: &Library, now: NaiveDateTime) -> String {
+    let books: Vec<(String, &ReaderInfo)> = if library.mode == LibraryMode::Database {
+        library.db.values()
+               .filter_map(|info| info.reader.as_ref().map(|r| (info.label(), r)))
+               .collect()
+    } else {
+        library.reading_states.values()
+               .map(|r| (String::new(), r))
+               .collect()
+    };
+    let sessions = books.iter().flat_map(|(_, r)| r.sessions.iter());
+    let today = now.date();
+    let mut buf = html_head("Reading Statistics");
+
+    let total = sessions.clone().map(|s| s.duration).sum::<u64>();
+    let finished = books.iter().filter(|(_, r)| r.finished).count();
+    push_table(&mut buf, "Summary", &[
+        vec!["Reading time".to_string(), format_duration(total)],
+        vec!["Sessions".to_string(), sessions.clone().count().to_string()],
+        vec!["Books finished".to_string(), finished.to_string()],
+    ]);
+
+    let days = daily_durations(sessions.clone(), today, DAYS_COUNT).into_iter().rev()
+                   .map(|(date, secs)| vec![date.format("%A").to_string(), format_duration(secs)])
+                   .collect::<Vec<Vec<String>>>();
+    push_table(&mut buf, "Daily Reading Time", &days);
+
+    let weeks = weekly_durations(sessions.clone(), today, WEEKS_COUNT).into_iter().rev()
+                    .map(|(date, secs)| vec![date.format(DATE_FORMAT).to_string(), format_duration(secs)])
+                    .collect::<Vec<Vec<String>>>();
+    push_table(&mut buf, "Weekly Reading Time", &weeks);
+
+    let months = finished_per_month(books.iter().map(|(_, r)| *r), today, MONTHS_COUNT).into_iter().rev()
+                     .map(|(year, month, count)| vec![format!("{}-{:02}", year, month), count.to_string()])
+                     .collect::<Vec<Vec<String>>>();
+    push_table(&mut buf, "Books Finished", &months);
+
+    let mut recent: Vec<&(String, &ReaderInfo)> = books.iter()
+                                                       .filter(|(label, r)| !label.is_empty() && !r.sessions.is_empty())
+                                                       .collect();
+    recent.sort_by_key(|(_, r)| std::cmp::Reverse(r.sessions.last().map(|s| s.end)));
+    if !recent.is_empty() {
+        let rows = recent.iter().map(|(label, r)| {
+            vec![encode_entities(label), format_duration(r.sessions.iter().map(|s| s.duration).sum())]
+        }).collect::<Vec<Vec<String>>>();
+        push_table(&mut buf, "Books", &rows);
+    }
+
+    buf.push_str("\t</body>\n</html>");
+    buf
+}
+
+// The sessions of a book, most recent first.
+pub fn book_statistics_as_html(info: &Info) -> String {
+    let mut buf = html_head(&info.title());
+    buf.push_str(&format!("\t\t<h1>{}</h1>\n", encode_entities(&info.label())));
+
+    let sessions = info.reader.as_ref().map(|r| r.sessions.as_slice()).unwrap_or_default();
+    let total = sessions.iter().map(|s| s.duration).sum::<u64>();
+    let pages_turned = sessions.iter().map(|s| s.pages_turned).sum::<usize>();
+    let mut summary = vec![vec!["Reading time".to_string(), format_duration(total)],
+                           vec!["Sessions".to_string(), sessions.len().to_string()],
+                           vec!["Pages turned".to_string(), pages_turned.to_string()]];
+
+    if let Some(r) = info.reader.as_ref() {
+        if let Some(speed) = reading_speed(sessions).filter(|_| !r.finished) {
+            let left = r.pages_count.saturating_sub(r.current_page) as f64 * speed;
+            summary.push(vec!["Time left".to_string(), format_duration(left as u64)]);
+        }
+    }
+
+    push_table(&mut buf, "Summary", &summary);
+
+    if !sessions.is_empty() {
+        let rows = sessions.iter().rev().map(|s| {
+            vec![s.start.format(DATE_FORMAT).to_string(),
+                 format!("{}–{}", s.start.format(TIME_FORMAT), s.end.format(TIME_FORMAT)),
+                 format_duration(s.duration),
+                 format!("{} p.", s.pages_turned)]
+        }).collect::<Vec<Vec<String>>>();
+        push_table(&mut buf, "Timeline", &rows);
+    }
+
+    buf.push_str("\t</body>\n</html>");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(start: &str, duration: u64, progress: usize) -> ReadingSession {
+        let start = NaiveDateTime::parse_from_str(start, "%Y-%m-%d %H:%M").unwrap();
+        ReadingSession {
+            start,
+            end: start + Duration::seconds(duration as i64),
+            pages_turned: progress,
+            progress,
+            duration,
+        }
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(10), "< 1 min");
+        assert_eq!(format_duration(620), "10 min");
+        assert_eq!(format_duration(3 * 3600 + 5 * 60), "3 h 05 min");
+    }
+
+    #[test]
+    fn test_durations() {
+        let sessions = [session("2024-03-04 21:00", 1200, 12),
+                        session("2024-03-06 08:00", 600, 4),
+                        session("2024-03-06 22:30", 300, 2),
+                        session("2024-02-20 22:30", 900, 9)];
+        let today = NaiveDate::from_ymd_opt(2024, 3, 6).unwrap();
+        let days = daily_durations(&sessions, today, 3);
+        assert_eq!(days.iter().map(|(_, d)| *d).collect::<Vec<u64>>(), vec![1200, 0, 900]);
+        let weeks = weekly_durations(&sessions, today, 3);
+        assert_eq!(weeks[2], (NaiveDate::from_ymd_opt(2024, 3, 4).unwrap(), 2100));
+        assert_eq!(weeks.iter().map(|(_, d)| *d).collect::<Vec<u64>>(), vec![900, 0, 2100]);
+        assert_eq!(reading_speed(&sessions[..1]), Some(100.0));
+        assert_eq!(reading_speed(&[session("2024-03-04 21:00", 30, 2)]), None);
+    }
+
+    #[test]
+    fn test_finished_per_month() {
+        let mut finished = ReaderInfo { finished: true, .. Default::default() };
+        finished.sessions.push(session("2023-12-31 23:00", 600, 3));
+        let reading = ReaderInfo { sessions: vec![session("2024-01-02 10:00", 600, 3)], .. Default::default() };
+        let today = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+        let months = finished_per_month([&finished, &reading], today, 3);
+        assert_eq!(months, vec![(2023, 12, 1), (2024, 1, 0), (2024, 2, 0)]);
+    }
+}
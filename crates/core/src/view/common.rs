@@ -94,6 +94,8 @@ pub fn toggle_main_menu(view: &mut dyn View, rect: Rectangle, enable: Option<boo
                                                   EntryId::About),
                                EntryKind::Command("System Info".to_string(),
                                                   EntryId::SystemInfo),
+                               EntryKind::Command("Reading Statistics".to_string(),
+                                                  EntryId::ReadingStatistics),
                                EntryKind::Separator,
                                EntryKind::CheckBox("Invert Colors".to_string(),
                                                    EntryId::ToggleInverted,
@@ -19,6 +19,11 @@ use rand_core::RngCore;
 use serde_json::{json, Value as JsonValue};
 use anyhow::{Error, format_err};
 use crate::library::Library;
+use crate::document::open;
+use crate::export::{collect_notes, export_notes};
+use crate::koreader::import_reading_state;
+use crate::koreader::sync::{SyncClient, sync_settings};
+use crate::statistics::book_statistics_as_html;
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use crate::metadata::{Info, Metadata, SortMethod, BookQuery, SimpleStatus, sort};
 use crate::view::{View, Event, Hub, Bus, RenderQueue, RenderData};
@@ -947,6 +952,16 @@ impl Home {
                 entries.push(EntryKind::SubMenu("Move To".to_string(), move_to));
             }
 
+            if info.reader.as_ref().is_some_and(|r| !r.annotations.is_empty() || !r.bookmarks.is_empty()) {
+                entries.push(EntryKind::Command("Export Notes".to_string(),
+                                                EntryId::ExportNotes(path.clone())));
+            }
+
+            if info.reader.as_ref().is_some_and(|r| !r.sessions.is_empty()) {
+                entries.push(EntryKind::Command("Statistics".to_string(),
+                                                EntryId::BookStatistics(path.clone())));
+            }
+
             entries.push(EntryKind::Command("Rename".to_string(),
                                             EntryId::Rename(path.clone())));
             entries.push(EntryKind::Command("Remove".to_string(),
@@ -981,6 +996,7 @@ impl Home {
 
             let database = if library_settings.mode == LibraryMode::Database {
                 vec![EntryKind::Command("Import".to_string(), EntryId::Import),
+                     EntryKind::Command("Import KOReader Data".to_string(), EntryId::ImportKoreader),
                      EntryKind::Command("Flush".to_string(), EntryId::Flush)]
             } else {
                 Vec::new()
@@ -1014,6 +1030,11 @@ impl Home {
                 entries.push(EntryKind::SubMenu("Toggle Select".to_string(), hooks));
             }
 
+            if !context.settings.koreader.sync.username.is_empty() {
+                entries.push(EntryKind::Command("Sign In to Sync Server".to_string(),
+                                                EntryId::SignInSyncServer));
+            }
+
             entries.push(EntryKind::Separator);
 
             let first_column = library_settings.first_column;
@@ -1128,6 +1149,27 @@ impl Home {
         Ok(())
     }
 
+    // Reflowable documents are laid out with the default settings, so that their chapters can be located.
+    fn export_notes(&mut self, path: &Path, context: &mut Context) -> Result<(), Error> {
+        let info = self.visible_books.iter().find(|info| info.file.path == path)
+                       .ok_or_else(|| format_err!("unknown document"))?;
+        let mut doc = open(context.library.home.join(path))
+                          .ok_or_else(|| format_err!("can't open document"))?;
+        if doc.is_protected() {
+            let password = info.reader.as_ref().and_then(|r| r.password.as_deref()).unwrap_or_default();
+            doc.authenticate(password);
+        }
+        if doc.is_reflowable() {
+            let (width, height) = context.display.dims;
+            doc.layout(width, height, context.settings.reader.font_size, CURRENT_DEVICE.dpi);
+        }
+        let toc = doc.full_toc();
+        let notes = collect_notes(info, doc.as_mut(), toc.as_deref());
+        let directory = context.library.home.join(&context.settings.export.directory);
+        export_notes(&notes, directory, &context.settings.export.formats)?;
+        Ok(())
+    }
+
     fn copy_to(&mut self, path: &Path, index: usize, context: &mut Context) -> Result<(), Error> {
         let library_settings = &context.settings.libraries[index];
         let mut library = Library::new(&library_settings.path, library_settings.mode)?;
@@ -1238,6 +1280,40 @@ impl Home {
         self.refresh_visibles(true, false, hub, rq, context);
     }
 
+    fn import_koreader(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        let directory = context.settings.koreader.directory.clone();
+        let count = context.library.update_reading_states(|home, info| {
+            import_reading_state(home, info, &directory)
+                .map_err(|e| eprintln!("Can't import KOReader data for {}: {:#}.", info.file.path.display(), e))
+                .unwrap_or(false)
+        });
+        context.library.sort(self.sort_method, self.reverse_order);
+        self.refresh_visibles(true, false, hub, rq, context);
+        let msg = format!("Imported {} reading state{} from KOReader.", count, if count == 1 { "" } else { "s" });
+        let notif = Notification::new(msg, hub, rq, context);
+        self.children.push(Box::new(notif) as Box<dyn View>);
+    }
+
+    // Registers the user when the server doesn't know them yet.
+    fn sign_in_sync_server(&mut self, hub: &Hub, context: &mut Context) {
+        let settings = sync_settings(context);
+        let hub2 = hub.clone();
+        thread::spawn(move || {
+            let result = SyncClient::new(&settings).and_then(|client| {
+                if client.authorize()? {
+                    Ok("Signed in to the sync server.")
+                } else {
+                    client.register().map(|_| "Registered on the sync server.")
+                }
+            });
+            let msg = match result {
+                Ok(msg) => msg.to_string(),
+                Err(e) => format!("Can't sign in to the sync server: {:#}.", e),
+            };
+            hub2.send(Event::Notify(msg)).ok();
+        });
+    }
+
     fn clean_up(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
         context.library.clean_up();
         self.refresh_visibles(true, false, hub, rq, context);
@@ -1539,6 +1615,14 @@ impl View for Home {
                 self.import(hub, rq, context);
                 true
             },
+            Event::Select(EntryId::ImportKoreader) => {
+                self.import_koreader(hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::SignInSyncServer) => {
+                self.sign_in_sync_server(hub, context);
+                true
+            },
             Event::Select(EntryId::CleanUp) => {
                 self.clean_up(hub, rq, context);
                 true
@@ -1639,6 +1723,21 @@ impl View for Home {
                     .ok();
                 true
             },
+            Event::Select(EntryId::ExportNotes(ref path)) => {
+                let msg = match self.export_notes(path, context) {
+                    Err(e) => format!("Can't export notes: {}.", e),
+                    Ok(_) => format!("Saved notes in {}.", context.settings.export.directory.display()),
+                };
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
+            Event::Select(EntryId::BookStatistics(ref path)) => {
+                if let Some(info) = self.visible_books.iter().find(|info| info.file.path == *path) {
+                    hub.send(Event::OpenHtml(book_statistics_as_html(info), None)).ok();
+                }
+                true
+            },
             Event::Select(EntryId::CopyTo(ref path, index)) => {
                 self.copy_to(path, index, context)
                     .map_err(|e| eprintln!("Can't copy document: {:#}.", e))
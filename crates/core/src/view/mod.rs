@@ -48,14 +48,14 @@ use std::time::{Instant, Duration};
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::{self, Debug};
 use fxhash::FxHashMap;
 use downcast_rs::{Downcast, impl_downcast};
 use crate::font::Fonts;
 use crate::document::{Location, TextLocation};
 use crate::settings::{ButtonScheme, FirstColumn, SecondColumn, RotationLock};
-use crate::metadata::{Info, ZoomMode, ScrollMode, SortMethod, TextAlign, SimpleStatus, PageScheme, Margin};
+use crate::metadata::{Info, Annotation, ZoomMode, ScrollMode, SortMethod, TextAlign, SimpleStatus, PageScheme, Margin};
 use crate::geom::{LinearDir, CycleDir, Rectangle, Boundary};
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use crate::input::{DeviceEvent, FingerStatus};
@@ -305,6 +305,9 @@ pub enum Event {
     ResultsPage(CycleDir),
     GoTo(usize),
     GoToLocation(Location),
+    RemoteProgress(PathBuf, usize, String),
+    EmbeddedAnnotations(PathBuf, Vec<Annotation>),
+    PageList(PathBuf, BTreeMap<usize, String>),
     ResultsGoTo(usize),
     CropMargins(Box<Margin>),
     Chapter(CycleDir),
@@ -420,6 +423,8 @@ pub enum ViewId {
     EditNoteInput,
     EditLanguages,
     EditLanguagesInput,
+    EnterPassword,
+    EnterPasswordInput,
     HomeSearchInput,
     ReaderSearchInput,
     DictionarySearchInput,
@@ -430,9 +435,11 @@ pub enum ViewId {
     Keyboard,
     AboutDialog,
     ShareDialog,
+    SyncDialog,
     MarginCropper,
     TopBottomBars,
     TableOfContents,
+    FootnotePopup,
     MessageNotif(Id),
     SubMenu(u8),
 }
@@ -507,11 +514,14 @@ pub enum EntryKind {
 pub enum EntryId {
     About,
     SystemInfo,
+    ReadingStatistics,
     LoadLibrary(usize),
     Load(PathBuf),
     Flush,
     Save,
     Import,
+    ImportKoreader,
+    SignInSyncServer,
     CleanUp,
     Sort(SortMethod),
     ReverseOrder,
@@ -541,6 +551,9 @@ pub enum EntryId {
     SearchForSelection,
     AdjustSelection,
     Annotations,
+    ExportAnnotations,
+    ExportNotes(PathBuf),
+    BookStatistics(PathBuf),
     Bookmarks,
     RemoveAnnotation([TextLocation; 2]),
     EditAnnotationNote([TextLocation; 2]),
@@ -564,6 +577,11 @@ pub enum EntryId {
     ToggleFuzzy,
     ToggleInverted,
     ToggleDithered,
+    ToggleReflow,
+    ToggleStylesheet(String),
+    ToggleSpreads,
+    ToggleLoneCover,
+    ToggleRightToLeft,
     ToggleWifi,
     Rotate(i8),
     Launch(AppCmd),
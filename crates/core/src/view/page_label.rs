@@ -6,6 +6,7 @@ use crate::geom::{Rectangle};
 use crate::document::BYTES_PER_PAGE;
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use super::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, ViewId};
+use crate::statistics::format_duration;
 use crate::context::Context;
 
 pub struct PageLabel {
@@ -15,6 +16,10 @@ pub struct PageLabel {
     current_page: usize,
     pages_count: usize,
     synthetic: bool,
+    // The print page names of the current and last pages.
+    page_name: Option<(String, String)>,
+    // The estimated reading time left, in seconds.
+    time_left: Option<u64>,
 }
 
 impl PageLabel {
@@ -26,6 +31,8 @@ impl PageLabel {
             current_page,
             pages_count,
             synthetic,
+            page_name: None,
+            time_left: None,
         }
     }
 
@@ -44,10 +51,43 @@ impl PageLabel {
         }
     }
 
+    pub fn update_page_name(&mut self, page_name: Option<(String, String)>, rq: &mut RenderQueue) {
+        if self.page_name != page_name {
+            self.page_name = page_name;
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+        }
+    }
+
+    pub fn update_time_left(&mut self, time_left: Option<u64>, rq: &mut RenderQueue) {
+        if self.time_left != time_left {
+            self.time_left = time_left;
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+        }
+    }
+
     pub fn text(&self, size: u8) -> String {
+        let text = self.page_text(size);
+        match (self.time_left, size) {
+            (Some(secs), 0) => format!("{} · {} left", text, format_duration(secs)),
+            (Some(secs), 1..=2) => format!("{} · {}", text, format_duration(secs)),
+            _ => text,
+        }
+    }
+
+    fn page_text(&self, size: u8) -> String {
         if self.pages_count == 0 {
             return "No pages".to_string();
         }
+        if let Some((current_name, last_name)) = self.page_name.as_ref() {
+            let percent = 100.0 * self.current_page as f32 / self.pages_count as f32;
+            return match size {
+                0 => format!("Page {} of {} ({:.1}%)", current_name, last_name, percent),
+                1 => format!("P. {} of {} ({:.1}%)", current_name, last_name, percent),
+                2 => format!("{}/{} ({:.1}%)", current_name, last_name, percent),
+                3 => format!("{} ({:.1}%)", current_name, percent),
+                _ => format!("{:.1}%", percent),
+            };
+        }
         let (current_page, pages_count, precision) = if self.synthetic {
             (self.current_page as f64 / BYTES_PER_PAGE,
              self.pages_count as f64 / BYTES_PER_PAGE, 1)
@@ -97,6 +97,18 @@ impl BottomBar {
         page_label.update(current_page, pages_count, rq);
     }
 
+    pub fn update_page_name(&mut self, page_name: Option<(String, String)>, rq: &mut RenderQueue) {
+        let page_label = self.child_mut(2).downcast_mut::<PageLabel>().unwrap();
+        page_label.update_page_name(page_name, rq);
+    }
+
+    pub fn update_time_left(&mut self, chapter: Option<u64>, book: Option<u64>, rq: &mut RenderQueue) {
+        let chapter_label = self.child_mut(1).downcast_mut::<ChapterLabel>().unwrap();
+        chapter_label.update_time_left(chapter, rq);
+        let page_label = self.child_mut(2).downcast_mut::<PageLabel>().unwrap();
+        page_label.update_time_left(book, rq);
+    }
+
     pub fn update_icons(&mut self, neighbors: &Neighbors, rq: &mut RenderQueue) {
         let is_prev_disabled = neighbors.previous_page.is_none();
 
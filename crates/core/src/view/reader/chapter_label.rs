@@ -5,6 +5,7 @@ use crate::gesture::GestureEvent;
 use crate::geom::{Rectangle};
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use super::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, RenderData, ViewId};
+use crate::statistics::format_duration;
 use crate::context::Context;
 
 pub struct ChapterLabel {
@@ -13,6 +14,7 @@ pub struct ChapterLabel {
     children: Vec<Box<dyn View>>,
     title: String,
     progress: f32,
+    time_left: Option<u64>,
 }
 
 impl ChapterLabel {
@@ -23,6 +25,7 @@ impl ChapterLabel {
             children: Vec::new(),
             title,
             progress,
+            time_left: None,
         }
     }
 
@@ -40,6 +43,13 @@ impl ChapterLabel {
             rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
         }
     }
+
+    pub fn update_time_left(&mut self, time_left: Option<u64>, rq: &mut RenderQueue) {
+        if self.time_left != time_left {
+            self.time_left = time_left;
+            rq.add(RenderData::new(self.id, self.rect, UpdateMode::Gui));
+        }
+    }
 }
 
 
@@ -62,7 +72,11 @@ impl View for ChapterLabel {
             let padding = font.em() as i32 / 2;
             let max_width = self.rect.width().saturating_sub(2 * padding as u32) as i32;
             let max_progress_width = max_width - font.ellipsis.width;
-            let progress_plan = font.plan(&format!(" ({:.1}%)", 100.0 * self.progress),
+            let progress_text = match self.time_left {
+                Some(secs) => format!(" ({:.1}% · {})", 100.0 * self.progress, format_duration(secs)),
+                None => format!(" ({:.1}%)", 100.0 * self.progress),
+            };
+            let progress_plan = font.plan(&progress_text,
                                           Some(max_progress_width),
                                           None);
             let max_title_width = max_width - progress_plan.width;
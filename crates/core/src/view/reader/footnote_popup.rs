@@ -0,0 +1,182 @@
+use crate::device::CURRENT_DEVICE;
+use crate::document::{Document, Location, Footnote};
+use crate::document::html::HtmlDocument;
+use crate::framebuffer::{Framebuffer, Pixmap};
+use crate::geom::{Rectangle, CornerSpec, BorderSpec, CycleDir, Dir};
+use crate::font::{Fonts, font_from_style, NORMAL_STYLE};
+use crate::view::{View, Event, Hub, Bus, Id, ID_FEEDER, RenderQueue, ViewId};
+use crate::view::{THICKNESS_LARGE, BORDER_RADIUS_MEDIUM};
+use crate::view::button::Button;
+use crate::view::image::Image;
+use crate::settings::DEFAULT_FONT_FAMILY;
+use crate::gesture::GestureEvent;
+use crate::color::{BLACK, WHITE};
+use crate::unit::scale_by_dpi;
+use crate::context::Context;
+
+const LABEL_GO_TO_NOTE: &str = "Go to Note";
+const MARGIN_WIDTH: i32 = 4;
+
+// Shows a note over the page that refers to it.
+pub struct FootnotePopup {
+    id: Id,
+    rect: Rectangle,
+    children: Vec<Box<dyn View>>,
+    doc: HtmlDocument,
+    location: usize,
+    target: usize,
+}
+
+impl FootnotePopup {
+    pub fn new(link_rect: Rectangle, footnote: Footnote, font_family: &str, font_size: f32,
+               line_height: f32, context: &mut Context) -> FootnotePopup {
+        let id = ID_FEEDER.next();
+        let mut children = Vec::new();
+        let dpi = CURRENT_DEVICE.dpi;
+        let (width, height) = context.display.dims;
+
+        let font = font_from_style(&mut context.fonts, &NORMAL_STYLE, dpi);
+        let x_height = font.x_heights.0 as i32;
+        let padding = font.em() as i32;
+        let button_height = 4 * x_height;
+        let max_button_width = width as i32 / 2;
+        let plan = font.plan(LABEL_GO_TO_NOTE, Some(max_button_width), None);
+        let button_width = plan.width + padding;
+
+        let border_radius = scale_by_dpi(BORDER_RADIUS_MEDIUM, dpi) as i32;
+        let border_thickness = scale_by_dpi(THICKNESS_LARGE, dpi) as i32;
+
+        // Stay clear of the link.
+        let popup_height = 2 * height as i32 / 5;
+        let rect = if link_rect.center().y < height as i32 / 2 {
+            rect![padding, height as i32 - padding - popup_height,
+                  width as i32 - padding, height as i32 - padding]
+        } else {
+            rect![padding, padding,
+                  width as i32 - padding, padding + popup_height]
+        };
+
+        let image_rect = rect![rect.min.x + border_thickness,
+                               rect.min.y + border_radius,
+                               rect.max.x - border_thickness,
+                               rect.max.y - button_height - 2 * padding];
+
+        let mut doc = HtmlDocument::new_from_memory(&footnote.text);
+        doc.layout(image_rect.width(), image_rect.height(), font_size, dpi);
+        doc.set_margin_width(MARGIN_WIDTH);
+        doc.set_line_height(line_height);
+
+        if font_family != DEFAULT_FONT_FAMILY {
+            doc.set_font_family(font_family, &context.settings.reader.font_path);
+        }
+
+        let (pixmap, location) = doc.pixmap(Location::Exact(0), 1.0)
+                                    .unwrap_or_else(|| (Pixmap::new(1, 1), 0));
+        let image = Image::new(image_rect, pixmap);
+        children.push(Box::new(image) as Box<dyn View>);
+
+        let button_rect = rect![rect.max.x - button_width - 2 * padding,
+                                rect.max.y - button_height - padding,
+                                rect.max.x - padding,
+                                rect.max.y - padding];
+        let button = Button::new(button_rect, Event::Validate, LABEL_GO_TO_NOTE.to_string());
+        children.push(Box::new(button) as Box<dyn View>);
+
+        FootnotePopup {
+            id,
+            rect,
+            children,
+            doc,
+            location,
+            target: footnote.location,
+        }
+    }
+
+    fn go_to_neighbor(&mut self, dir: CycleDir, rq: &mut RenderQueue) {
+        let location = match dir {
+            CycleDir::Previous => Location::Previous(self.location),
+            CycleDir::Next => Location::Next(self.location),
+        };
+        if let Some((pixmap, location)) = self.doc.pixmap(location, 1.0) {
+            if let Some(image) = self.children[0].downcast_mut::<Image>() {
+                image.update(pixmap, rq);
+                self.location = location;
+            }
+        }
+    }
+}
+
+impl View for FootnotePopup {
+    fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, _context: &mut Context) -> bool {
+        match *evt {
+            Event::Validate => {
+                hub.send(Event::Close(ViewId::FootnotePopup)).ok();
+                hub.send(Event::GoTo(self.target)).ok();
+                true
+            },
+            Event::Gesture(GestureEvent::Tap(center)) if !self.rect.includes(center) => {
+                hub.send(Event::Close(ViewId::FootnotePopup)).ok();
+                true
+            },
+            Event::Gesture(GestureEvent::Tap(center)) if self.children[0].rect().includes(center) => {
+                if center.x < self.children[0].rect().center().x {
+                    self.go_to_neighbor(CycleDir::Previous, rq);
+                } else {
+                    self.go_to_neighbor(CycleDir::Next, rq);
+                }
+                true
+            },
+            Event::Gesture(GestureEvent::Swipe { dir, start, .. }) if self.rect.includes(start) => {
+                match dir {
+                    Dir::North => self.go_to_neighbor(CycleDir::Next, rq),
+                    Dir::South => self.go_to_neighbor(CycleDir::Previous, rq),
+                    _ => (),
+                }
+                true
+            },
+            Event::Gesture(..) => true,
+            _ => false,
+        }
+    }
+
+    fn render(&self, fb: &mut dyn Framebuffer, _rect: Rectangle, _fonts: &mut Fonts) {
+        let dpi = CURRENT_DEVICE.dpi;
+
+        let border_radius = scale_by_dpi(BORDER_RADIUS_MEDIUM, dpi) as i32;
+        let border_thickness = scale_by_dpi(THICKNESS_LARGE, dpi) as u16;
+
+        fb.draw_rounded_rectangle_with_border(&self.rect,
+                                              &CornerSpec::Uniform(border_radius),
+                                              &BorderSpec { thickness: border_thickness,
+                                                            color: BLACK },
+                                              &WHITE);
+    }
+
+    fn is_background(&self) -> bool {
+        true
+    }
+
+    fn rect(&self) -> &Rectangle {
+        &self.rect
+    }
+
+    fn rect_mut(&mut self) -> &mut Rectangle {
+        &mut self.rect
+    }
+
+    fn children(&self) -> &Vec<Box<dyn View>> {
+        &self.children
+    }
+
+    fn children_mut(&mut self) -> &mut Vec<Box<dyn View>> {
+        &mut self.children
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn view_id(&self) -> Option<ViewId> {
+        Some(ViewId::FootnotePopup)
+    }
+}
@@ -4,15 +4,19 @@ mod results_bar;
 mod margin_cropper;
 mod chapter_label;
 mod results_label;
+mod footnote_popup;
 
 use std::thread;
+use std::iter;
+use std::mem;
+use std::time::Instant;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering as AtomicOrdering;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
-use std::fs::OpenOptions;
-use std::collections::{VecDeque, BTreeMap};
+use std::fs::{self, OpenOptions};
+use std::collections::{VecDeque, BTreeMap, BTreeSet};
 use fxhash::{FxHashMap, FxHashSet};
 use chrono::Local;
 use regex::Regex;
@@ -34,6 +38,7 @@ use super::top_bar::TopBar;
 use self::tool_bar::ToolBar;
 use self::bottom_bar::BottomBar;
 use self::results_bar::ResultsBar;
+use self::footnote_popup::FootnotePopup;
 use crate::view::common::{locate, rlocate, locate_by_id};
 use crate::view::common::{toggle_main_menu, toggle_battery_menu, toggle_clock_menu};
 use crate::view::filler::Filler;
@@ -43,15 +48,23 @@ use crate::view::keyboard::Keyboard;
 use crate::view::menu::{Menu, MenuKind};
 use crate::view::menu_entry::MenuEntry;
 use crate::view::notification::Notification;
+use crate::view::dialog::Dialog;
 use crate::settings::{guess_frontlight, FinishedAction, SouthEastCornerAction, BottomRightGestureAction, SouthStripAction, WestStripAction, EastStripAction};
 use crate::settings::{DEFAULT_FONT_FAMILY, DEFAULT_TEXT_ALIGN, DEFAULT_LINE_HEIGHT, DEFAULT_MARGIN_WIDTH};
 use crate::settings::{HYPHEN_PENALTY, STRETCH_TOLERANCE};
 use crate::frontlight::LightLevels;
 use crate::gesture::GestureEvent;
-use crate::document::{Document, open, Location, TextLocation, BoundedText, Neighbors, BYTES_PER_PAGE};
+use crate::document::{Document, open, Location, TextLocation, BoundedText, Footnote, Neighbors, BYTES_PER_PAGE};
+use crate::document::spread_pages;
 use crate::document::{TocEntry, SimpleTocEntry, TocLocation, toc_as_html, annotations_as_html, bookmarks_as_html};
 use crate::document::html::HtmlDocument;
-use crate::metadata::{Info, FileInfo, ReaderInfo, Annotation, TextAlign, ZoomMode, ScrollMode, PageScheme};
+use crate::document::epub::EpubDocument;
+use crate::document::reflow::ReflowDocument;
+use crate::document::pdf::{PdfOpener, export_annotations, annotated_path};
+use crate::export::{collect_notes, export_notes};
+use crate::statistics::{reading_speed, book_statistics_as_html, MAX_PAGE_DURATION};
+use crate::koreader::sync::{SyncClient, sync_settings, document_key, position, locate as locate_progress};
+use crate::metadata::{Info, FileInfo, ReaderInfo, ReadingSession, Annotation, TextAlign, ZoomMode, ScrollMode, PageScheme};
 use crate::metadata::{Margin, CroppingMargins, make_query};
 use crate::metadata::{DEFAULT_CONTRAST_EXPONENT, DEFAULT_CONTRAST_GRAY};
 use crate::geom::{Point, Vec2, Rectangle, Boundary, CornerSpec, BorderSpec};
@@ -64,6 +77,7 @@ const RECT_DIST_JITTER: f32 = 24.0;
 const ANNOTATION_DRIFT: u8 =  0x44;
 const HIGHLIGHT_DRIFT: u8 =  0x22;
 const MEM_SCHEME: &str = "mem:";
+const STYLESHEETS_DIRECTORY: &str = "css/snippets";
 
 pub struct Reader {
     id: Id,
@@ -86,13 +100,21 @@ pub struct Reader {
     info: Info,
     current_page: usize,
     pages_count: usize,
+    page_list: BTreeMap<usize, String>,              // Print page names.
     view_port: ViewPort,
     contrast: Contrast,
     synthetic: bool,
     page_turns: usize,
+    session: ReadingSession,
+    last_activity: Instant,
     reflowable: bool,
+    rtl: bool,
+    spreads: bool,
+    lone_cover: bool,
     ephemeral: bool,
     finished: bool,
+    password: Option<String>,
+    locked: bool,                                    // Waiting for the document's password.
 }
 
 #[derive(Debug)]
@@ -238,160 +260,283 @@ fn word_separator(lang: &str) -> &'static str {
     }
 }
 
-impl Reader {
-    pub fn new(rect: Rectangle, mut info: Info, hub: &Hub, context: &mut Context) -> Option<Reader> {
-        let id = ID_FEEDER.next();
-        let settings = &context.settings;
-        let path = context.library.home.join(&info.file.path);
+fn open_document(path: &Path, reflow: bool, password: Option<&str>) -> Option<Box<dyn Document>> {
+    if reflow {
+        ReflowDocument::new(path, password)
+                       .map_err(|e| eprintln!("Can't reflow {}: {:#}.", path.display(), e))
+                       .ok()
+                       .map(|doc| Box::new(doc) as Box<dyn Document>)
+    } else {
+        open(path).map(|mut doc| {
+            if let Some(password) = password.filter(|_| doc.is_protected()) {
+                doc.authenticate(password);
+            }
+            doc
+        })
+    }
+}
 
-        open(&path).and_then(|mut doc| {
-            let (width, height) = context.display.dims;
-            let font_size = info.reader.as_ref().and_then(|r| r.font_size)
-                                .unwrap_or(settings.reader.font_size);
+// The names of the style snippets that can be applied to a document.
+fn stylesheet_names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(STYLESHEETS_DIRECTORY).map(|entries| {
+        entries.filter_map(|entry| entry.ok().map(|e| e.path()))
+               .filter(|path| path.extension().is_some_and(|ext| ext == "css"))
+               .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(String::from))
+               .collect()
+    }).unwrap_or_default();
+    names.sort();
+    names
+}
 
-            doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
+fn stylesheets_css(names: &BTreeSet<String>) -> String {
+    names.iter().filter_map(|name| {
+        let path = Path::new(STYLESHEETS_DIRECTORY).join(format!("{}.css", name));
+        fs::read_to_string(&path)
+           .map_err(|e| eprintln!("Can't read {}: {:#}.", path.display(), e))
+           .ok()
+    }).collect::<Vec<String>>().join("\n")
+}
 
-            let margin_width = info.reader.as_ref().and_then(|r| r.margin_width)
-                                   .unwrap_or(settings.reader.margin_width);
+fn setup_document(doc: &mut dyn Document, reader_info: Option<&ReaderInfo>, context: &Context) {
+    let settings = &context.settings;
+    let (width, height) = context.display.dims;
+    let font_size = reader_info.and_then(|r| r.font_size)
+                               .unwrap_or(settings.reader.font_size);
 
-            if margin_width != DEFAULT_MARGIN_WIDTH {
-                doc.set_margin_width(margin_width);
-            }
+    doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
 
-            let font_family = info.reader.as_ref().and_then(|r| r.font_family.as_ref())
-                                  .unwrap_or(&settings.reader.font_family);
+    let margin_width = reader_info.and_then(|r| r.margin_width)
+                                  .unwrap_or(settings.reader.margin_width);
 
-            if font_family != DEFAULT_FONT_FAMILY {
-                doc.set_font_family(font_family, &settings.reader.font_path);
-            }
+    if margin_width != DEFAULT_MARGIN_WIDTH {
+        doc.set_margin_width(margin_width);
+    }
 
-            let line_height = info.reader.as_ref().and_then(|r| r.line_height)
-                                  .unwrap_or(settings.reader.line_height);
+    let font_family = reader_info.and_then(|r| r.font_family.as_ref())
+                                 .unwrap_or(&settings.reader.font_family);
 
-            if (line_height - DEFAULT_LINE_HEIGHT).abs() > f32::EPSILON {
-                doc.set_line_height(line_height);
-            }
+    if font_family != DEFAULT_FONT_FAMILY {
+        doc.set_font_family(font_family, &settings.reader.font_path);
+    }
 
-            let text_align = info.reader.as_ref().and_then(|r| r.text_align)
-                                 .unwrap_or(settings.reader.text_align);
+    let line_height = reader_info.and_then(|r| r.line_height)
+                                 .unwrap_or(settings.reader.line_height);
 
-            if text_align != DEFAULT_TEXT_ALIGN {
-                doc.set_text_align(text_align);
-            }
+    if (line_height - DEFAULT_LINE_HEIGHT).abs() > f32::EPSILON {
+        doc.set_line_height(line_height);
+    }
 
-            let hyphen_penalty = settings.reader.paragraph_breaker.hyphen_penalty;
+    let text_align = reader_info.and_then(|r| r.text_align)
+                                .unwrap_or(settings.reader.text_align);
 
-            if hyphen_penalty != HYPHEN_PENALTY {
-                doc.set_hyphen_penalty(hyphen_penalty);
-            }
+    if text_align != DEFAULT_TEXT_ALIGN {
+        doc.set_text_align(text_align);
+    }
 
-            let stretch_tolerance = settings.reader.paragraph_breaker.stretch_tolerance;
+    let hyphen_penalty = settings.reader.paragraph_breaker.hyphen_penalty;
 
-            if stretch_tolerance != STRETCH_TOLERANCE {
-                doc.set_stretch_tolerance(stretch_tolerance);
-            }
+    if hyphen_penalty != HYPHEN_PENALTY {
+        doc.set_hyphen_penalty(hyphen_penalty);
+    }
+
+    let stretch_tolerance = settings.reader.paragraph_breaker.stretch_tolerance;
+
+    if stretch_tolerance != STRETCH_TOLERANCE {
+        doc.set_stretch_tolerance(stretch_tolerance);
+    }
+
+    if settings.reader.ignore_document_css {
+        doc.set_ignore_document_css(true);
+    }
+    if settings.reader.ignore_embedded_fonts {
+        doc.set_ignore_embedded_fonts(true);
+    }
+    if settings.reader.hide_ruby {
+        doc.set_hide_ruby(true);
+    }
+
+    if let Some(r) = reader_info.filter(|r| !r.stylesheets.is_empty()) {
+        doc.set_extra_css(&stylesheets_css(&r.stylesheets));
+    }
+}
+
+fn restore_view_port(r: &ReaderInfo, context: &Context) -> ViewPort {
+    let mut view_port = ViewPort::default();
+
+    if let Some(zoom_mode) = r.zoom_mode {
+        view_port.zoom_mode = zoom_mode;
+    }
+
+    if let Some(scroll_mode) = r.scroll_mode {
+        view_port.scroll_mode = scroll_mode;
+    } else {
+        view_port.scroll_mode = if context.settings.reader.continuous_fit_to_width {
+            ScrollMode::Screen
+        } else {
+            ScrollMode::Page
+        };
+    }
+
+    if let Some(page_offset) = r.page_offset {
+        view_port.page_offset = page_offset;
+    }
+
+    view_port.margin_width = mm_to_px(r.screen_margin_width.unwrap_or(0) as f32,
+                                      CURRENT_DEVICE.dpi) as i32;
+    view_port
+}
+
+fn store_view_port(view_port: &ViewPort, r: &mut ReaderInfo) {
+    if view_port.zoom_mode == ZoomMode::FitToPage {
+        r.zoom_mode = None;
+        r.page_offset = None;
+    } else {
+        r.zoom_mode = Some(view_port.zoom_mode);
+        r.page_offset = Some(view_port.page_offset);
+    }
+
+    if view_port.zoom_mode == ZoomMode::FitToWidth {
+        r.scroll_mode = Some(view_port.scroll_mode);
+    } else {
+        r.scroll_mode = None;
+    }
+}
+
+impl Reader {
+    pub fn new(rect: Rectangle, info: Info, hub: &Hub, context: &mut Context) -> Option<Reader> {
+        let id = ID_FEEDER.next();
+        let path = context.library.home.join(&info.file.path);
+        let reflow = info.reader.as_ref().is_some_and(|r| r.reflow) && info.file.kind == "pdf";
+        let password = info.reader.as_ref().and_then(|r| r.password.clone());
+
+        let mut doc = open(&path)?;
+        let locked = doc.is_protected() &&
+                     !password.as_deref().is_some_and(|password| doc.authenticate(password));
+
+        if reflow && !locked {
+            doc = open_document(&path, true, password.as_deref())?;
+        }
 
-            if settings.reader.ignore_document_css {
-                doc.set_ignore_document_css(true);
+        let mut reader = Reader {
+            id,
+            rect,
+            children: Vec::new(),
+            doc: Arc::new(Mutex::new(doc)),
+            cache: BTreeMap::new(),
+            chunks: Vec::new(),
+            text: FxHashMap::default(),
+            annotations: FxHashMap::default(),
+            noninverted_regions: FxHashMap::default(),
+            focus: None,
+            search: None,
+            search_direction: LinearDir::Forward,
+            held_buttons: FxHashSet::default(),
+            selection: None,
+            target_annotation: None,
+            history: VecDeque::new(),
+            state: State::Idle,
+            info,
+            current_page: 0,
+            pages_count: 0,
+            page_list: BTreeMap::new(),
+            view_port: ViewPort::default(),
+            synthetic: false,
+            page_turns: 0,
+            session: ReadingSession::default(),
+            last_activity: Instant::now(),
+            contrast: Contrast::default(),
+            ephemeral: false,
+            reflowable: false,
+            rtl: false,
+            spreads: false,
+            lone_cover: false,
+            finished: false,
+            password,
+            locked,
+        };
+
+        let fresh = reader.info.reader.is_none();
+
+        if locked {
+            hub.send(Event::Show(ViewId::EnterPassword)).ok();
+        } else if !reader.load(context) {
+            return None;
+        } else {
+            reader.pull_progress(hub, context);
+            reader.load_page_list(hub, context);
+            if fresh {
+                reader.import_embedded_annotations(hub, context);
             }
+        }
 
-            let first_location = doc.resolve_location(Location::Exact(0))?;
+        println!("{}", reader.info.file.path.display());
 
-            let mut view_port = ViewPort::default();
-            let mut contrast = Contrast::default();
-            let pages_count = doc.pages_count();
-            let current_page;
+        hub.send(Event::Update(UpdateMode::Partial)).ok();
 
-            // TODO: use get_or_insert_with?
-            if let Some(ref mut r) = info.reader {
-                r.opened = Local::now().naive_local();
+        Some(reader)
+    }
 
-                if r.finished {
-                    r.finished = false;
-                    r.current_page = first_location;
-                    r.page_offset = None;
-                }
+    // Initializes the reading state from the freshly opened document.
+    fn load(&mut self, context: &Context) -> bool {
+        let mut doc = self.doc.lock().unwrap();
+        setup_document(doc.as_mut(), self.info.reader.as_ref(), context);
 
-                current_page = doc.resolve_location(Location::Exact(r.current_page))
-                                  .unwrap_or(first_location);
+        let first_location = if let Some(location) = doc.resolve_location(Location::Exact(0)) {
+            location
+        } else {
+            return false;
+        };
 
-                if let Some(zoom_mode) = r.zoom_mode {
-                    view_port.zoom_mode = zoom_mode;
-                }
+        self.pages_count = doc.pages_count();
 
-                if let Some(scroll_mode) = r.scroll_mode {
-                    view_port.scroll_mode = scroll_mode;
-                } else {
-                    view_port.scroll_mode = if settings.reader.continuous_fit_to_width {
-                        ScrollMode::Screen
-                    } else {
-                        ScrollMode::Page
-                    };
-                }
+        // TODO: use get_or_insert_with?
+        if let Some(ref mut r) = self.info.reader {
+            r.opened = Local::now().naive_local();
 
-                if let Some(page_offset) = r.page_offset {
-                    view_port.page_offset = page_offset;
-                }
+            if r.finished {
+                r.finished = false;
+                r.current_page = first_location;
+                r.page_offset = None;
+            }
 
-                if !doc.is_reflowable() {
-                    view_port.margin_width = mm_to_px(r.screen_margin_width.unwrap_or(0) as f32,
-                                                      CURRENT_DEVICE.dpi) as i32;
-                }
+            self.current_page = doc.resolve_location(Location::Exact(r.current_page))
+                                   .unwrap_or(first_location);
 
-                if let Some(exponent) = r.contrast_exponent {
-                    contrast.exponent = exponent;
-                }
+            if !doc.is_reflowable() {
+                self.view_port = restore_view_port(r, context);
+            }
 
-                if let Some(gray) = r.contrast_gray {
-                    contrast.gray = gray;
-                }
-            } else {
-                current_page = first_location;
+            if let Some(exponent) = r.contrast_exponent {
+                self.contrast.exponent = exponent;
+            }
 
-                info.reader = Some(ReaderInfo {
-                    current_page,
-                    pages_count,
-                    .. Default::default()
-                });
+            if let Some(gray) = r.contrast_gray {
+                self.contrast.gray = gray;
             }
+        } else {
+            self.current_page = first_location;
 
-            let synthetic = doc.has_synthetic_page_numbers();
-            let reflowable = doc.is_reflowable();
-
-            println!("{}", info.file.path.display());
-
-            hub.send(Event::Update(UpdateMode::Partial)).ok();
-
-            Some(Reader {
-                id,
-                rect,
-                children: Vec::new(),
-                doc: Arc::new(Mutex::new(doc)),
-                cache: BTreeMap::new(),
-                chunks: Vec::new(),
-                text: FxHashMap::default(),
-                annotations: FxHashMap::default(),
-                noninverted_regions: FxHashMap::default(),
-                focus: None,
-                search: None,
-                search_direction: LinearDir::Forward,
-                held_buttons: FxHashSet::default(),
-                selection: None,
-                target_annotation: None,
-                history: VecDeque::new(),
-                state: State::Idle,
-                info,
-                current_page,
-                pages_count,
-                view_port,
-                synthetic,
-                page_turns: 0,
-                contrast,
-                ephemeral: false,
-                reflowable,
-                finished: false,
-            })
-        })
+            self.info.reader = Some(ReaderInfo {
+                current_page: first_location,
+                pages_count: self.pages_count,
+                .. Default::default()
+            });
+        }
+
+        self.synthetic = doc.has_synthetic_page_numbers();
+        self.reflowable = doc.is_reflowable();
+        self.rtl = self.info.reader.as_ref().and_then(|r| r.right_to_left)
+                       .unwrap_or_else(|| doc.is_rtl());
+        self.spreads = self.info.reader.as_ref().and_then(|r| r.spreads)
+                           .unwrap_or(context.settings.reader.spreads.enabled);
+        self.lone_cover = self.info.reader.as_ref().and_then(|r| r.lone_cover)
+                              .unwrap_or(context.settings.reader.spreads.lone_cover);
+        // The page list of an ePUB might require parsing every chapter.
+        if self.info.file.kind != "epub" {
+            self.page_list = doc.page_list().unwrap_or_default();
+        }
+
+        true
     }
 
     pub fn from_html(rect: Rectangle, html: &str, link_uri: Option<&str>, hub: &Hub, context: &mut Context) -> Reader {
@@ -411,6 +556,7 @@ impl Reader {
         let font_size = context.settings.reader.font_size;
         doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
         let pages_count = doc.pages_count();
+        let rtl = doc.is_rtl();
         info.title = doc.title().unwrap_or_default();
 
         let mut current_page = 0;
@@ -448,13 +594,55 @@ impl Reader {
             info,
             current_page,
             pages_count,
+            page_list: BTreeMap::new(),
             view_port: ViewPort::default(),
             synthetic: true,
             page_turns: 0,
+            session: ReadingSession::default(),
+            last_activity: Instant::now(),
             contrast: Contrast::default(),
             ephemeral: true,
             reflowable: true,
+            rtl,
+            spreads: false,
+            lone_cover: false,
             finished: false,
+            password: None,
+            locked: false,
+        }
+    }
+
+    // Horizontal page turns are mirrored when the document is read from right to left.
+    fn reading_dir(&self, dir: CycleDir) -> CycleDir {
+        if !self.rtl {
+            return dir;
+        }
+        match dir {
+            CycleDir::Next => CycleDir::Previous,
+            CycleDir::Previous => CycleDir::Next,
+        }
+    }
+
+    // Fixed-layout documents that fit the page are shown two pages at a time in landscape.
+    fn is_spread(&self) -> bool {
+        self.spreads && !self.reflowable && self.view_port.zoom_mode == ZoomMode::FitToPage &&
+        self.rect.width() > self.rect.height()
+    }
+
+    // Returns the pages, in reading order, of the spread that includes the given location.
+    fn spread(&self, location: usize) -> (usize, Option<usize>) {
+        let doc = self.doc.lock().unwrap();
+        spread_pages(location, self.pages_count, self.lone_cover, self.rtl, |index| doc.page_spread(index))
+    }
+
+    // The rectangle in which a page has to fit. The margins of the two halves of
+    // a spread overlap so that the pages meet in the middle of the screen.
+    fn page_rect(&self) -> Rectangle {
+        if self.is_spread() {
+            let width = self.rect.width() as i32 / 2 + self.view_port.margin_width;
+            rect![self.rect.min, pt!(self.rect.min.x + width, self.rect.max.y)]
+        } else {
+            self.rect
         }
     }
 
@@ -463,6 +651,8 @@ impl Reader {
             return;
         }
 
+        let page_rect = self.page_rect();
+
         let mut doc = self.doc.lock().unwrap();
         let cropping_margin = self.info.reader.as_ref()
                                   .and_then(|r| r.cropping_margins.as_ref()
@@ -470,7 +660,7 @@ impl Reader {
                                   .cloned().unwrap_or_default();
         let dims = doc.dims(location).unwrap_or((3.0, 4.0));
         let screen_margin_width = self.view_port.margin_width;
-        let scale = scaling_factor(&self.rect, &cropping_margin, screen_margin_width, dims, self.view_port.zoom_mode);
+        let scale = scaling_factor(&page_rect, &cropping_margin, screen_margin_width, dims, self.view_port.zoom_mode);
         if let Some((pixmap, _)) = doc.pixmap(Location::Exact(location), scale) {
             let frame = rect![(cropping_margin.left * pixmap.width as f32).ceil() as i32,
                               (cropping_margin.top * pixmap.height as f32).ceil() as i32,
@@ -535,7 +725,7 @@ impl Reader {
         let loc = {
             let mut doc = self.doc.lock().unwrap();
             if let Some(toc) = self.toc()
-                                   .or_else(|| doc.toc()) {
+                                   .or_else(|| doc.full_toc()) {
                 let chap_offset = if dir == CycleDir::Previous {
                    doc.chapter(current_page, &toc)
                       .and_then(|(chap, _)| doc.resolve_location(chap.location.clone()))
@@ -738,7 +928,7 @@ impl Reader {
             let neighloc = match dir { 
                 CycleDir::Previous => {
                     match self.view_port.zoom_mode {
-                        ZoomMode::FitToPage => Location::Previous(current_page),
+                        ZoomMode::FitToPage => Location::Previous(self.chunks[0].location),
                         ZoomMode::FitToWidth => match self.view_port.scroll_mode {
                             ScrollMode::Screen => {
                                 let first_chunk = self.chunks.first().cloned().unwrap();
@@ -806,7 +996,7 @@ impl Reader {
                 },
                 CycleDir::Next => {
                     match self.view_port.zoom_mode {
-                        ZoomMode::FitToPage => Location::Next(current_page),
+                        ZoomMode::FitToPage => Location::Next(self.chunks[self.chunks.len() - 1].location),
                         ZoomMode::FitToWidth => match self.view_port.scroll_mode {
                             ScrollMode::Screen => {
                                 let &RenderChunk { location, frame, .. } = self.chunks.last().unwrap();
@@ -851,6 +1041,7 @@ impl Reader {
                     s.current_page = s.highlights.range(..=location).count().saturating_sub(1);
                 }
 
+                self.record_page_turn(current_page, location);
                 self.current_page = location;
                 self.selection = None;
                 self.state = State::Idle;
@@ -892,6 +1083,160 @@ impl Reader {
         }
     }
 
+    // The time spent on a page is capped, and the suspended time isn't counted
+    // since the session is closed on suspend and the clock restarted on wake up.
+    fn record_page_turn(&mut self, previous_location: usize, location: usize) {
+        let elapsed = self.last_activity.elapsed().as_secs().min(MAX_PAGE_DURATION);
+        let now = Local::now().naive_local();
+        if self.session.pages_turned == 0 {
+            self.session.start = now - chrono::Duration::seconds(elapsed as i64);
+        }
+        self.session.end = now;
+        self.session.duration += elapsed;
+        self.session.pages_turned += 1;
+        self.session.progress += location.saturating_sub(previous_location);
+        self.last_activity = Instant::now();
+    }
+
+    // Estimates the reading time left in the current chapter and in the book.
+    fn time_left(&self, doc: &mut dyn Document, toc: Option<&[TocEntry]>) -> (Option<u64>, Option<u64>) {
+        let sessions = self.info.reader.iter()
+                           .flat_map(|r| r.sessions.iter())
+                           .chain(iter::once(&self.session));
+        let speed = match reading_speed(sessions) {
+            Some(speed) if !self.ephemeral => speed,
+            _ => return (None, None),
+        };
+        let book = (self.pages_count.saturating_sub(self.current_page) as f64 * speed) as u64;
+        let chapter = toc.filter(|toc| doc.chapter(self.current_page, toc).is_some()).map(|toc| {
+            let end = doc.chapter_relative(self.current_page, CycleDir::Next, toc)
+                         .and_then(|chap| doc.resolve_location(chap.location.clone()))
+                         .filter(|&end| end > self.current_page)
+                         .unwrap_or(self.pages_count);
+            (end.saturating_sub(self.current_page) as f64 * speed) as u64
+        });
+        (chapter, Some(book))
+    }
+
+    // Fetches the position reached on the other devices from the progress sync server.
+    fn pull_progress(&self, hub: &Hub, context: &mut Context) {
+        if self.ephemeral || !context.online || context.settings.koreader.sync.username.is_empty() {
+            return;
+        }
+
+        let settings = sync_settings(context);
+        let key = match document_key(&context.library, &self.info.file.path, settings.document_key) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("Can't get the document key of {}: {:#}.", self.info.file.path.display(), e);
+                return;
+            },
+        };
+        let path = context.library.home.join(&self.info.file.path);
+        let file_path = self.info.file.path.clone();
+        let kind = self.info.file.kind.clone();
+        let reflowable = self.reflowable;
+        let page_list: Vec<usize> = self.page_list.keys().cloned().collect();
+        let pages_count = self.pages_count;
+        let hub2 = hub.clone();
+
+        thread::spawn(move || {
+            match SyncClient::new(&settings).and_then(|client| client.progress(&key)) {
+                Ok(Some(progress)) => {
+                    if let Some(location) = locate_progress(&path, &kind, reflowable, &page_list, &progress, pages_count) {
+                        hub2.send(Event::RemoteProgress(file_path, location, progress.device)).ok();
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => eprintln!("Can't pull the reading progress of {}: {:#}.", file_path.display(), e),
+            }
+        });
+    }
+
+    fn load_page_list(&self, hub: &Hub, context: &Context) {
+        if self.info.file.kind != "epub" {
+            return;
+        }
+
+        let path = context.library.home.join(&self.info.file.path);
+        let file_path = self.info.file.path.clone();
+        let hub2 = hub.clone();
+
+        thread::spawn(move || {
+            if let Some(page_list) = EpubDocument::new(&path).ok().and_then(|mut doc| doc.page_list()) {
+                hub2.send(Event::PageList(file_path, page_list)).ok();
+            }
+        });
+    }
+
+    // Walking through every page can take a while: the document is opened anew in the background.
+    fn import_embedded_annotations(&self, hub: &Hub, context: &Context) {
+        if self.info.file.kind != "pdf" {
+            return;
+        }
+
+        let path = context.library.home.join(&self.info.file.path);
+        let file_path = self.info.file.path.clone();
+        let password = self.password.clone();
+        let hub2 = hub.clone();
+
+        thread::spawn(move || {
+            let annotations = PdfOpener::new().and_then(|opener| opener.open(&path))
+                                        .map(|mut doc| {
+                                            if let Some(password) = password.as_deref() {
+                                                doc.authenticate(password);
+                                            }
+                                            doc.embedded_annotations()
+                                        }).unwrap_or_default();
+            if !annotations.is_empty() {
+                hub2.send(Event::EmbeddedAnnotations(file_path, annotations)).ok();
+            }
+        });
+    }
+
+    fn push_progress(&self, context: &mut Context) {
+        if !context.online || context.settings.koreader.sync.username.is_empty() {
+            return;
+        }
+
+        // The locations of the other reflowable documents can't be expressed as KOReader positions.
+        if self.reflowable && self.info.file.kind != "epub" && self.page_list.is_empty() {
+            return;
+        }
+
+        let settings = sync_settings(context);
+        let key = match document_key(&context.library, &self.info.file.path, settings.document_key) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("Can't get the document key of {}: {:#}.", self.info.file.path.display(), e);
+                return;
+            },
+        };
+        let path = context.library.home.join(&self.info.file.path);
+        let file_path = self.info.file.path.clone();
+        let kind = self.info.file.kind.clone();
+        // The pages of reflowed documents are sent as their original page numbers.
+        let location = if self.reflowable && kind != "epub" {
+            self.page_list.range(..=self.current_page).count().saturating_sub(1)
+        } else {
+            self.current_page
+        };
+        let percentage = if self.finished {
+            1.0
+        } else {
+            self.current_page as f64 / self.pages_count.max(1) as f64
+        };
+
+        thread::spawn(move || {
+            if let Some(progress) = position(&path, &kind, location) {
+                SyncClient::new(&settings)
+                           .and_then(|client| client.update_progress(&key, &progress, percentage))
+                           .map_err(|e| eprintln!("Can't push the reading progress of {}: {:#}.", file_path.display(), e))
+                           .ok();
+            }
+        });
+    }
+
     fn go_to_results_page(&mut self, index: usize, hub: &Hub, rq: &mut RenderQueue, context: &Context) {
         let mut loc = None;
         if let Some(ref mut s) = self.search {
@@ -935,6 +1280,7 @@ impl Reader {
     fn update_bottom_bar(&mut self, rq: &mut RenderQueue) {
         if let Some(index) = locate::<BottomBar>(self) {
             let current_page = self.current_page;
+            let page_name = self.page_name();
             let mut doc = self.doc.lock().unwrap();
             let rtoc = self.toc().or_else(|| doc.toc());
             let chapter = rtoc.as_ref().and_then(|toc| doc.chapter(current_page, toc));
@@ -942,13 +1288,16 @@ impl Reader {
                                .unwrap_or_default();
             let progress = chapter.map(|(_, p)| p)
                                   .unwrap_or_default();
+            let (chapter_left, book_left) = self.time_left(doc.as_mut(), rtoc.as_deref());
             let bottom_bar = self.children[index].as_mut().downcast_mut::<BottomBar>().unwrap();
             let neighbors = Neighbors {
                 previous_page: doc.resolve_location(Location::Previous(current_page)),
                 next_page: doc.resolve_location(Location::Next(current_page)),
             };
             bottom_bar.update_chapter_label(title, progress, rq);
+            bottom_bar.update_time_left(chapter_left, book_left, rq);
             bottom_bar.update_page_label(self.current_page, self.pages_count, rq);
+            bottom_bar.update_page_name(page_name, rq);
             bottom_bar.update_icons(&neighbors, rq);
         }
     }
@@ -1051,10 +1400,45 @@ impl Reader {
         });
 
         self.chunks.clear();
+
+        if self.locked {
+            rq.add(RenderData::new(self.id, self.rect, update_mode));
+            return;
+        }
+
         let mut location = self.current_page;
         let smw = self.view_port.margin_width;
 
         match self.view_port.zoom_mode {
+            ZoomMode::FitToPage if self.is_spread() => {
+                let (first, second) = self.spread(location);
+                let mut locations = vec![first];
+                locations.extend(second);
+                for &location in &locations {
+                    self.load_pixmap(location);
+                    self.load_text(location);
+                }
+                // The chunks are kept in reading order, only their positions are mirrored.
+                let width: u32 = locations.iter().map(|loc| self.cache[loc].frame.width()).sum();
+                let mut dx = smw + ((self.rect.width() - width) as i32 - 2 * smw) / 2;
+                let visual_order: Vec<usize> = if self.rtl {
+                    locations.iter().rev().cloned().collect()
+                } else {
+                    locations.clone()
+                };
+                let mut positions = Vec::with_capacity(locations.len());
+                for location in visual_order {
+                    let frame = self.cache[&location].frame;
+                    let dy = smw + ((self.rect.height() - frame.height()) as i32 - 2 * smw) / 2;
+                    positions.push((location, pt!(dx, dy)));
+                    dx += frame.width() as i32;
+                }
+                for location in locations {
+                    let Resource { frame, scale, .. } = self.cache[&location];
+                    let position = positions.iter().find(|(loc, _)| *loc == location).unwrap().1;
+                    self.chunks.push(RenderChunk { frame, location, position, scale });
+                }
+            },
             ZoomMode::FitToPage => {
                 self.load_pixmap(location);
                 self.load_text(location);
@@ -1133,7 +1517,9 @@ impl Reader {
         let first_location = self.chunks.first().map(|c| c.location).unwrap();
         let last_location = self.chunks.last().map(|c| c.location).unwrap();
 
-        while self.cache.len() > 3 {
+        let (cache_size, preload_count) = if self.is_spread() { (6, 2) } else { (3, 1) };
+
+        while self.cache.len() > cache_size {
             let left_count = self.cache.range(..first_location).count();
             let right_count = self.cache.range(last_location+1..).count();
             let extremum = if left_count >= right_count {
@@ -1153,16 +1539,28 @@ impl Reader {
             let hub2 = hub.clone();
             thread::spawn(move || {
                 let mut doc = doc2.lock().unwrap();
-                if let Some(next_location) = doc.resolve_location(Location::Next(last_location)) {
-                    hub2.send(Event::LoadPixmap(next_location)).ok();
+                let mut location = last_location;
+                for _ in 0..preload_count {
+                    if let Some(next_location) = doc.resolve_location(Location::Next(location)) {
+                        hub2.send(Event::LoadPixmap(next_location)).ok();
+                        location = next_location;
+                    } else {
+                        break;
+                    }
                 }
             });
             let doc3 = self.doc.clone();
             let hub3 = hub.clone();
             thread::spawn(move || {
                 let mut doc = doc3.lock().unwrap();
-                if let Some(previous_location) = doc.resolve_location(Location::Previous(first_location)) {
-                    hub3.send(Event::LoadPixmap(previous_location)).ok();
+                let mut location = first_location;
+                for _ in 0..preload_count {
+                    if let Some(previous_location) = doc.resolve_location(Location::Previous(location)) {
+                        hub3.send(Event::LoadPixmap(previous_location)).ok();
+                        location = previous_location;
+                    } else {
+                        break;
+                    }
                 }
             });
         }
@@ -1596,7 +1994,7 @@ impl Reader {
                 next_page: doc.resolve_location(Location::Next(self.current_page)),
             };
 
-            let bottom_bar = BottomBar::new(rect![self.rect.min.x,
+            let mut bottom_bar = BottomBar::new(rect![self.rect.min.x,
                                                   self.rect.max.y - small_height + big_thickness,
                                                   self.rect.max.x,
                                                   self.rect.max.y],
@@ -1606,6 +2004,10 @@ impl Reader {
                                             self.pages_count,
                                             &neighbors,
                                             self.synthetic);
+            bottom_bar.update_page_name(self.page_name(), &mut RenderQueue::new());
+            let toc = self.toc().or_else(|| doc.toc());
+            let (chapter_left, book_left) = self.time_left(doc.as_mut(), toc.as_deref());
+            bottom_bar.update_time_left(chapter_left, book_left, &mut RenderQueue::new());
             self.children.insert(index, Box::new(bottom_bar) as Box<dyn View>);
 
             for i in 0..=index {
@@ -1648,6 +2050,30 @@ impl Reader {
         }
     }
 
+    fn footnote(&mut self, link: &BoundedText) -> Option<Footnote> {
+        if let TextLocation::Dynamic(offset) = link.location {
+            self.doc.lock().ok()
+                .and_then(|mut doc| doc.footnote(offset, &link.text))
+        } else {
+            None
+        }
+    }
+
+    fn show_footnote(&mut self, link_rect: Rectangle, footnote: Footnote, rq: &mut RenderQueue, context: &mut Context) {
+        let font_family = self.info.reader.as_ref()
+                              .and_then(|r| r.font_family.clone())
+                              .unwrap_or_else(|| context.settings.reader.font_family.clone());
+        let font_size = self.info.reader.as_ref()
+                            .and_then(|r| r.font_size)
+                            .unwrap_or(context.settings.reader.font_size);
+        let line_height = self.info.reader.as_ref()
+                              .and_then(|r| r.line_height)
+                              .unwrap_or(context.settings.reader.line_height);
+        let popup = FootnotePopup::new(link_rect, footnote, &font_family, font_size, line_height, context);
+        rq.add(RenderData::new(popup.id(), *popup.rect(), UpdateMode::Gui));
+        self.children.push(Box::new(popup) as Box<dyn View>);
+    }
+
     fn toggle_edit_note(&mut self, text: Option<String>, enable: Option<bool>, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
         if let Some(index) = locate_by_id(self, ViewId::EditNote) {
             if let Some(true) = enable {
@@ -1702,6 +2128,74 @@ impl Reader {
         }
     }
 
+    fn toggle_enter_password(&mut self, enable: Option<bool>, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        if let Some(index) = locate_by_id(self, ViewId::EnterPassword) {
+            if let Some(true) = enable {
+                return;
+            }
+
+            rq.add(RenderData::expose(*self.child(index).rect(), UpdateMode::Gui));
+            self.children.remove(index);
+
+            if self.focus.map(|focus_id| focus_id == ViewId::EnterPasswordInput).unwrap_or(false) {
+                self.toggle_keyboard(false, None, hub, rq, context);
+            }
+        } else {
+            if let Some(false) = enable {
+                return;
+            }
+
+            let enter_password = NamedInput::new("Password".to_string(), ViewId::EnterPassword, ViewId::EnterPasswordInput, 16, context);
+            rq.add(RenderData::new(enter_password.id(), *enter_password.rect(), UpdateMode::Gui));
+            hub.send(Event::Focus(Some(ViewId::EnterPasswordInput))).ok();
+
+            self.children.push(Box::new(enter_password) as Box<dyn View>);
+        }
+    }
+
+    fn unlock(&mut self, password: &str, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        if !self.doc.lock().unwrap().authenticate(password) {
+            let notif = Notification::new("Wrong password.".to_string(), hub, rq, context);
+            self.children.push(Box::new(notif) as Box<dyn View>);
+            hub.send(Event::Show(ViewId::EnterPassword)).ok();
+            return;
+        }
+
+        if self.info.reader.as_ref().is_some_and(|r| r.reflow) {
+            let path = context.library.home.join(&self.info.file.path);
+            if let Some(doc) = open_document(&path, true, Some(password)) {
+                self.doc = Arc::new(Mutex::new(doc));
+            } else {
+                hub.send(Event::Back).ok();
+                return;
+            }
+        }
+
+        let fresh = self.info.reader.is_none();
+
+        if !self.load(context) {
+            hub.send(Event::Back).ok();
+            return;
+        }
+
+        self.locked = false;
+        self.password = Some(password.to_string());
+        self.pull_progress(hub, context);
+        self.load_page_list(hub, context);
+
+        if fresh {
+            self.import_embedded_annotations(hub, context);
+        }
+
+        if context.settings.reader.remember_passwords {
+            if let Some(ref mut r) = self.info.reader {
+                r.password = self.password.clone();
+            }
+        }
+
+        self.update(Some(UpdateMode::Full), hub, rq, context);
+    }
+
     fn toggle_go_to_page(&mut self, enable: Option<bool>, id: ViewId, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
         let (text, input_id) = if id == ViewId::GoToPage {
             ("Go to page", ViewId::GoToPageInput)
@@ -1787,7 +2281,7 @@ impl Reader {
             entries.push(EntryKind::Command("Define".to_string(), EntryId::DefineSelection));
             entries.push(EntryKind::Command("Search".to_string(), EntryId::SearchForSelection));
 
-            if self.info.reader.as_ref().map_or(false, |r| !r.page_names.is_empty()) {
+            if !self.page_list.is_empty() || self.info.reader.as_ref().map_or(false, |r| !r.page_names.is_empty()) {
                 entries.push(EntryKind::Command("Go To".to_string(), EntryId::GoToSelectedPageName));
             }
 
@@ -1846,22 +2340,68 @@ impl Reader {
                                         EntryId::SetScrollMode(ScrollMode::Page),
                                         scroll_mode == ScrollMode::Page)]));
 
+            if !self.reflowable {
+                entries.push(EntryKind::SubMenu("Spreads".to_string(), vec![
+                     EntryKind::CheckBox("Two Pages in Landscape".to_string(),
+                                         EntryId::ToggleSpreads, self.spreads),
+                     EntryKind::CheckBox("Lone Cover".to_string(),
+                                         EntryId::ToggleLoneCover, self.lone_cover),
+                     EntryKind::CheckBox("Right to Left".to_string(),
+                                         EntryId::ToggleRightToLeft, self.rtl)]));
+            }
+
             if self.ephemeral {
                 entries.push(EntryKind::Command("Save".to_string(), EntryId::Save));
             }
 
             if self.info.reader.as_ref().map_or(false, |r| !r.annotations.is_empty()) {
                 entries.push(EntryKind::Command("Annotations".to_string(), EntryId::Annotations));
+                if self.info.file.kind == "pdf" {
+                    entries.push(EntryKind::Command("Export Annotations".to_string(), EntryId::ExportAnnotations));
+                }
             }
 
             if self.info.reader.as_ref().map_or(false, |r| !r.bookmarks.is_empty()) {
                 entries.push(EntryKind::Command("Bookmarks".to_string(), EntryId::Bookmarks));
             }
 
+            if self.info.reader.as_ref().is_some_and(|r| !r.annotations.is_empty() || !r.bookmarks.is_empty()) {
+                entries.push(EntryKind::Command("Export Notes".to_string(),
+                                                EntryId::ExportNotes(self.info.file.path.clone())));
+            }
+
+            if !self.ephemeral {
+                entries.push(EntryKind::Command("Statistics".to_string(),
+                                                EntryId::BookStatistics(self.info.file.path.clone())));
+            }
+
             if !entries.is_empty() {
                 entries.push(EntryKind::Separator);
             }
 
+            if self.info.file.kind == "pdf" {
+                entries.push(EntryKind::CheckBox("Reflow".to_string(),
+                                                 EntryId::ToggleReflow,
+                                                 self.reflowable));
+            }
+
+            if self.reflowable {
+                let names = stylesheet_names();
+                if !names.is_empty() {
+                    let stylesheets = self.info.reader.as_ref().map(|r| r.stylesheets.clone())
+                                          .unwrap_or_default();
+                    let stylesheet_entries = names.into_iter().map(|name| {
+                        let mut label = name.replace(['-', '_'], " ");
+                        if let Some(first) = label.get(..1).map(str::to_uppercase) {
+                            label.replace_range(..1, &first);
+                        }
+                        let checked = stylesheets.contains(&name);
+                        EntryKind::CheckBox(label, EntryId::ToggleStylesheet(name), checked)
+                    }).collect();
+                    entries.push(EntryKind::SubMenu("Stylesheets".to_string(), stylesheet_entries));
+                }
+            }
+
             entries.push(EntryKind::CheckBox("Apply Dithering".to_string(),
                                              EntryId::ToggleDithered,
                                              context.fb.dithered()));
@@ -2207,6 +2747,91 @@ impl Reader {
         self.update_bottom_bar(rq);
     }
 
+    // The locations of the reflowed document and the pages of the PDF are related
+    // through the page list of the former.
+    fn toggle_reflow(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        let reflow = !self.reflowable;
+        let path = context.library.home.join(&self.info.file.path);
+        let mut doc = if let Some(doc) = open_document(&path, reflow, self.password.as_deref()) {
+            doc
+        } else {
+            return;
+        };
+
+        setup_document(doc.as_mut(), self.info.reader.as_ref(), context);
+
+        let page_list = doc.page_list().unwrap_or_default();
+        let mut convert = |location: usize| -> Option<usize> {
+            let location = if reflow {
+                page_list.keys().nth(location).cloned()
+            } else {
+                self.page_list.range(..=location).count().checked_sub(1)
+            };
+            location.and_then(|location| doc.resolve_location(Location::Exact(location)))
+        };
+
+        let current_page = convert(self.current_page);
+
+        if let Some(ref mut r) = self.info.reader {
+            r.reflow = reflow;
+            // The items that can't be converted are kept as they are.
+            r.bookmarks = r.bookmarks.iter().map(|&location| convert(location).unwrap_or(location)).collect();
+            // The annotations refer to words: their locations are mapped through the reflowed pages.
+            if !r.annotations.is_empty() {
+                if let Ok(mut converter) = ReflowDocument::new(&path, self.password.as_deref()) {
+                    for annot in &mut r.annotations {
+                        let [start, end] = annot.selection;
+                        let selection = if reflow {
+                            converter.reflowed_location(start).zip(converter.reflowed_location(end))
+                        } else {
+                            converter.original_location(start).zip(converter.original_location(end))
+                        };
+                        if let Some((start, end)) = selection {
+                            annot.selection = [start, end];
+                        }
+                    }
+                }
+            }
+            if reflow {
+                store_view_port(&self.view_port, r);
+            }
+        }
+
+        self.current_page = current_page.or_else(|| doc.resolve_location(Location::Exact(0)))
+                                        .unwrap_or(0);
+        self.pages_count = doc.pages_count();
+        self.synthetic = doc.has_synthetic_page_numbers();
+        self.reflowable = doc.is_reflowable();
+        self.rtl = self.info.reader.as_ref().and_then(|r| r.right_to_left)
+                       .unwrap_or_else(|| doc.is_rtl());
+        self.page_list = page_list;
+        self.view_port = match self.info.reader.as_ref() {
+            Some(r) if !reflow => restore_view_port(r, context),
+            _ => ViewPort::default(),
+        };
+        self.doc = Arc::new(Mutex::new(doc));
+
+        if let Some(ref mut s) = self.search {
+            s.running.store(false, AtomicOrdering::Relaxed);
+        }
+
+        self.search = None;
+        self.selection = None;
+        self.target_annotation = None;
+        self.history.clear();
+        self.cache.clear();
+        self.text.clear();
+        self.annotations.clear();
+        self.noninverted_regions.clear();
+
+        self.toggle_bars(Some(false), hub, rq, context);
+        self.update(Some(UpdateMode::Full), hub, rq, context);
+    }
+
     fn set_text_align(&mut self, text_align: TextAlign, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
         if Arc::strong_count(&self.doc) > 1 {
             return;
@@ -2275,6 +2900,42 @@ impl Reader {
         self.update_bottom_bar(rq);
     }
 
+    fn toggle_stylesheet(&mut self, name: &str, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        let css = if let Some(ref mut r) = self.info.reader {
+            if !r.stylesheets.remove(name) {
+                r.stylesheets.insert(name.to_string());
+            }
+            stylesheets_css(&r.stylesheets)
+        } else {
+            return;
+        };
+
+        {
+            let mut doc = self.doc.lock().unwrap();
+            doc.set_extra_css(&css);
+
+            if self.synthetic {
+                let current_page = self.current_page.min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_page)) {
+                    self.current_page = location;
+                }
+            } else {
+                self.pages_count = doc.pages_count();
+                self.current_page = self.current_page.min(self.pages_count - 1);
+            }
+        }
+
+        self.cache.clear();
+        self.text.clear();
+        self.update(None, hub, rq, context);
+        self.update_tool_bar(rq, context);
+        self.update_bottom_bar(rq);
+    }
+
     fn set_line_height(&mut self, line_height: f32, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
         if Arc::strong_count(&self.doc) > 1 {
             return;
@@ -2419,6 +3080,36 @@ impl Reader {
         self.update(None, hub, rq, context);
     }
 
+    fn toggle_spreads(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &Context) {
+        self.spreads = !self.spreads;
+        if let Some(ref mut r) = self.info.reader {
+            r.spreads = Some(self.spreads);
+        }
+        self.cache.clear();
+        self.update(None, hub, rq, context);
+    }
+
+    fn toggle_lone_cover(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &Context) {
+        self.lone_cover = !self.lone_cover;
+        if let Some(ref mut r) = self.info.reader {
+            r.lone_cover = Some(self.lone_cover);
+        }
+        if self.is_spread() {
+            self.update(None, hub, rq, context);
+        }
+    }
+
+    // Swaps the pages of spreads and the direction of swipes.
+    fn toggle_right_to_left(&mut self, hub: &Hub, rq: &mut RenderQueue, context: &Context) {
+        self.rtl = !self.rtl;
+        if let Some(ref mut r) = self.info.reader {
+            r.right_to_left = Some(self.rtl);
+        }
+        if self.is_spread() {
+            self.update(None, hub, rq, context);
+        }
+    }
+
     fn crop_margins(&mut self, index: usize, margin: &Margin, hub: &Hub, rq: &mut RenderQueue, context: &Context) {
         if self.view_port.zoom_mode != ZoomMode::FitToPage {
             let Resource { pixmap, frame, .. } = self.cache.get(&index).unwrap();
@@ -2492,7 +3183,20 @@ impl Reader {
         toc
     }
 
+    // Returns the print page names of the current and last pages.
+    fn page_name(&self) -> Option<(String, String)> {
+        let current = self.page_list.range(..=self.current_page).next_back()?;
+        let last = self.page_list.values().next_back()?;
+        Some((current.1.clone(), last.clone()))
+    }
+
     fn find_page_by_name(&self, name: &str) -> Option<usize> {
+        if let Some(location) = self.page_list.iter()
+                                    .find(|(_, s)| s.eq_ignore_ascii_case(name))
+                                    .map(|(i, _)| *i) {
+            return Some(location);
+        }
+
         self.info.reader.as_ref().and_then(|r| {
             if let Ok(a) = name.parse::<u32>() {
                 r.page_names
@@ -2623,7 +3327,7 @@ impl Reader {
             s.running.store(false, AtomicOrdering::Relaxed);
         }
 
-        if self.ephemeral {
+        if self.ephemeral || self.locked {
             return;
         }
 
@@ -2633,22 +3337,17 @@ impl Reader {
             r.finished = self.finished;
             r.dithered = context.fb.dithered();
 
-            if self.view_port.zoom_mode == ZoomMode::FitToPage {
-                r.zoom_mode = None;
-                r.page_offset = None;
-            } else {
-                r.zoom_mode = Some(self.view_port.zoom_mode);
-                r.page_offset = Some(self.view_port.page_offset);
-            }
-
-            if self.view_port.zoom_mode == ZoomMode::FitToWidth {
-                r.scroll_mode = Some(self.view_port.scroll_mode);
-            } else {
-                r.scroll_mode = None;
+            if !self.reflowable {
+                store_view_port(&self.view_port, r);
             }
 
             r.rotation = Some(CURRENT_DEVICE.to_canonical(context.display.rotation));
 
+            if self.session.pages_turned > 0 {
+                r.sessions.push(mem::take(&mut self.session));
+            }
+            self.last_activity = Instant::now();
+
             if (self.contrast.exponent - DEFAULT_CONTRAST_EXPONENT).abs() > f32::EPSILON {
                 r.contrast_exponent = Some(self.contrast.exponent);
                 if (self.contrast.gray - DEFAULT_CONTRAST_GRAY).abs() > f32::EPSILON {
@@ -2663,6 +3362,8 @@ impl Reader {
 
             context.library.sync_reader_info(&self.info.file.path, r);
         }
+
+        self.push_progress(context);
     }
 
     fn scale_page(&mut self, center: Point, factor: f32, hub: &Hub, rq: &mut RenderQueue, context: &mut Context) {
@@ -2694,6 +3395,13 @@ impl Reader {
 
 impl View for Reader {
     fn handle_event(&mut self, evt: &Event, hub: &Hub, _bus: &mut Bus, rq: &mut RenderQueue, context: &mut Context) -> bool {
+        if self.locked && !matches!(*evt, Event::Show(ViewId::EnterPassword) |
+                                          Event::Close(ViewId::EnterPassword) |
+                                          Event::Submit(ViewId::EnterPasswordInput, _) |
+                                          Event::Focus(..) | Event::Back | Event::Suspend) {
+            return false;
+        }
+
         match *evt {
             Event::Gesture(GestureEvent::Rotate { quarter_turns, .. }) if quarter_turns != 0 => {
                 let (_, dir) = CURRENT_DEVICE.mirroring_scheme();
@@ -2705,8 +3413,8 @@ impl View for Reader {
                 match self.view_port.zoom_mode {
                     ZoomMode::FitToPage | ZoomMode::FitToWidth => {
                         match dir {
-                            Dir::West => self.go_to_neighbor(CycleDir::Next, hub, rq, context),
-                            Dir::East => self.go_to_neighbor(CycleDir::Previous, hub, rq, context),
+                            Dir::West => self.go_to_neighbor(self.reading_dir(CycleDir::Next), hub, rq, context),
+                            Dir::East => self.go_to_neighbor(self.reading_dir(CycleDir::Previous), hub, rq, context),
                             Dir::South | Dir::North => self.vertical_scroll(start.y - end.y, hub, rq, context),
                         };
                     },
@@ -3092,12 +3800,12 @@ impl View for Reader {
                         let d = center.rdist2(&rect);
                         if d < dmax && d < dmin {
                             dmin = d;
-                            nearest_link = Some(link.clone());
+                            nearest_link = Some((link.clone(), rect));
                         }
                     }
                 }
 
-                if let Some(link) = nearest_link.take() {
+                if let Some((link, rect)) = nearest_link.take() {
                     let pdf_page = Regex::new(r"^#page=(\d+).*$").unwrap();
                     let djvu_page = Regex::new(r"^#([+-])?(\d+)$").unwrap();
                     let toc_page = Regex::new(r"^@(.+)$").unwrap();
@@ -3128,6 +3836,8 @@ impl View for Reader {
                             }
                             self.go_to_page(index, true, hub, rq, context);
                         }
+                    } else if let Some(footnote) = self.footnote(&link) {
+                        self.show_footnote(rect, footnote, rq, context);
                     } else {
                         let mut doc = self.doc.lock().unwrap();
                         let loc = Location::LocalUri(self.current_page, link.text.clone());
@@ -3198,11 +3908,11 @@ impl View for Reader {
                                             hub.send(Event::Toggle(ViewId::GoToPage)).ok();
                                         },
                                         SouthEastCornerAction::NextPage => {
-                                            self.go_to_neighbor(CycleDir::Next, hub, rq, context);
+                                            self.go_to_neighbor(self.reading_dir(CycleDir::Next), hub, rq, context);
                                         },
                                     }
                                 } else {
-                                    self.go_to_neighbor(CycleDir::Next, hub, rq, context);
+                                    self.go_to_neighbor(self.reading_dir(CycleDir::Next), hub, rq, context);
                                 }
                             },
                             DiagDir::SouthWest => {
@@ -3214,7 +3924,7 @@ impl View for Reader {
                                         hub.send(Event::Show(ViewId::TableOfContents)).ok();
                                     }
                                 } else {
-                                    self.go_to_neighbor(CycleDir::Previous, hub, rq, context);
+                                    self.go_to_neighbor(self.reading_dir(CycleDir::Previous), hub, rq, context);
                                 }
                             },
                         }
@@ -3225,10 +3935,10 @@ impl View for Reader {
                                 if self.search.is_none() {
                                     match context.settings.reader.west_strip {
                                         WestStripAction::PreviousPage => {
-                                            self.go_to_neighbor(CycleDir::Previous, hub, rq, context);
+                                            self.go_to_neighbor(self.reading_dir(CycleDir::Previous), hub, rq, context);
                                         }
                                         WestStripAction::NextPage => {
-                                            self.go_to_neighbor(CycleDir::Next, hub, rq, context);
+                                            self.go_to_neighbor(self.reading_dir(CycleDir::Next), hub, rq, context);
                                         }
                                         WestStripAction::None => (),
                                     }
@@ -3240,10 +3950,10 @@ impl View for Reader {
                                 if self.search.is_none() {
                                     match context.settings.reader.east_strip {
                                         EastStripAction::PreviousPage => {
-                                            self.go_to_neighbor(CycleDir::Previous, hub, rq, context);
+                                            self.go_to_neighbor(self.reading_dir(CycleDir::Previous), hub, rq, context);
                                         }
                                         EastStripAction::NextPage => {
-                                            self.go_to_neighbor(CycleDir::Next, hub, rq, context);
+                                            self.go_to_neighbor(self.reading_dir(CycleDir::Next), hub, rq, context);
                                         }
                                         EastStripAction::None => (),
                                     }
@@ -3297,6 +4007,13 @@ impl View for Reader {
                     }
                 }
 
+                if found.is_none() && self.chunks.iter().all(|chunk| self.text[&chunk.location].is_empty()) &&
+                   !self.doc.lock().unwrap().has_text() {
+                    let notif = Notification::new("The document has no text layer.".to_string(),
+                                                  hub, rq, context);
+                    self.children.push(Box::new(notif) as Box<dyn View>);
+                }
+
                 if let Some((nearest_word, rect)) = found {
                     let anchor = nearest_word.location;
                     if let Some(annot) = self.annotations.values().flatten()
@@ -3342,6 +4059,10 @@ impl View for Reader {
                         if let Some(location) = self.find_page_by_name(&caps[2]) {
                             self.go_to_page(location, true, hub, rq, context);
                         }
+                    } else if let Some(location) = self.page_list.iter()
+                                                       .find(|(_, s)| prefix.is_none() && s.eq_ignore_ascii_case(text))
+                                                       .map(|(i, _)| *i) {
+                        self.go_to_page(location, true, hub, rq, context);
                     } else {
                         if text == "_" {
                             let location = (context.rng.next_u64() % self.pages_count as u64) as usize;
@@ -3378,6 +4099,13 @@ impl View for Reader {
                 }
                 true
             },
+            Event::Submit(ViewId::EnterPasswordInput, ref text) => {
+                // The dialog is removed beforehand so that the ensuing close event
+                // doesn't leave the reader.
+                self.toggle_enter_password(Some(false), hub, rq, context);
+                self.unlock(text, hub, rq, context);
+                true
+            },
             Event::Submit(ViewId::NamePageInput, ref text) => {
                 if !text.is_empty() {
                     if let Some(ref mut r) = self.info.reader {
@@ -3438,6 +4166,29 @@ impl View for Reader {
                 self.go_to_neighbor(dir, hub, rq, context);
                 true
             },
+            Event::RemoteProgress(ref path, location, ref device) if *path == self.info.file.path => {
+                if location > self.current_page && locate_by_id(self, ViewId::SyncDialog).is_none() {
+                    let percent = 100.0 * location as f32 / self.pages_count.max(1) as f32;
+                    let msg = format!("Go to {:.0}%, reached on {}?", percent, device);
+                    let dialog = Dialog::new(ViewId::SyncDialog, Some(Event::GoTo(location)), msg, context);
+                    rq.add(RenderData::new(dialog.id(), *dialog.rect(), UpdateMode::Gui));
+                    self.children.push(Box::new(dialog) as Box<dyn View>);
+                }
+                true
+            },
+            Event::PageList(ref path, ref page_list) if *path == self.info.file.path => {
+                self.page_list = page_list.clone();
+                self.update_bottom_bar(rq);
+                true
+            },
+            Event::EmbeddedAnnotations(ref path, ref annotations) if *path == self.info.file.path => {
+                if let Some(ref mut r) = self.info.reader {
+                    r.annotations.extend(annotations.iter().cloned());
+                }
+                self.update_annotations();
+                self.update(None, hub, rq, context);
+                true
+            },
             Event::GoTo(location) | Event::Select(EntryId::GoTo(location)) => {
                 self.go_to_page(location, true, hub, rq, context);
                 true
@@ -3585,6 +4336,19 @@ impl View for Reader {
                 self.target_annotation = None;
                 false
             },
+            Event::Show(ViewId::EnterPassword) => {
+                self.toggle_enter_password(Some(true), hub, rq, context);
+                true
+            },
+            Event::Close(ViewId::EnterPassword) => {
+                if locate_by_id(self, ViewId::EnterPassword).is_some() {
+                    self.toggle_enter_password(Some(false), hub, rq, context);
+                    if self.locked {
+                        hub.send(Event::Back).ok();
+                    }
+                }
+                true
+            },
             Event::Close(ViewId::NamePage) => {
                 self.toggle_keyboard(false, None, hub, rq, context);
                 false
@@ -3595,7 +4359,7 @@ impl View for Reader {
                 }
                 let mut doc = self.doc.lock().unwrap();
                 if let Some(toc) = self.toc()
-                                       .or_else(|| doc.toc())
+                                       .or_else(|| doc.full_toc())
                                        .filter(|toc| !toc.is_empty()) {
                     let chap = doc.chapter(self.current_page, &toc)
                                   .map(|(c, _)| c);
@@ -3684,8 +4448,13 @@ impl View for Reader {
                 let results_count = self.search.as_ref().map(|s| s.results_count)
                                         .unwrap_or(usize::MAX);
                 if results_count == 0 {
-                    let notif = Notification::new("No search results.".to_string(),
-                                                  hub, rq, context);
+                    let has_text = self.doc.lock().unwrap().has_text();
+                    let msg = if has_text {
+                        "No search results."
+                    } else {
+                        "The document has no text layer."
+                    };
+                    let notif = Notification::new(msg.to_string(), hub, rq, context);
                     self.children.push(Box::new(notif) as Box<dyn View>);
                     self.toggle_search_bar(true, hub, rq, context);
                     hub.send(Event::Focus(Some(ViewId::ReaderSearchInput))).ok();
@@ -3799,6 +4568,26 @@ impl View for Reader {
                 self.set_scroll_mode(scroll_mode, hub, rq, context);
                 true
             },
+            Event::Select(EntryId::ToggleReflow) => {
+                self.toggle_reflow(hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::ToggleStylesheet(ref name)) => {
+                self.toggle_stylesheet(name, hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::ToggleSpreads) => {
+                self.toggle_spreads(hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::ToggleLoneCover) => {
+                self.toggle_lone_cover(hub, rq, context);
+                true
+            },
+            Event::Select(EntryId::ToggleRightToLeft) => {
+                self.toggle_right_to_left(hub, rq, context);
+                true
+            },
             Event::Select(EntryId::Save) => {
                 let name = format!("{}-{}.{}", self.info.title.to_lowercase().replace(' ', "_"),
                                    Local::now().format("%Y%m%d_%H%M%S"),
@@ -3812,6 +4601,50 @@ impl View for Reader {
                 self.children.push(Box::new(notif) as Box<dyn View>);
                 true
             },
+            Event::Select(EntryId::ExportAnnotations) => {
+                let path = context.library.home.join(&self.info.file.path);
+                let directory = context.library.home.join(&context.settings.export.directory);
+                let target = annotated_path(&directory, &self.info.file.path);
+                let annotations = self.info.reader.as_ref()
+                                      .map(|r| r.annotations.as_slice())
+                                      .unwrap_or_default();
+                let msg = match export_annotations(&path, &target, annotations, self.password.as_deref()) {
+                    Err(e) => format!("Can't export annotations: {}.", e),
+                    Ok(_) => format!("Saved {} in {}.", target.file_name().unwrap_or_default().to_string_lossy(),
+                                     context.settings.export.directory.display()),
+                };
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
+            Event::Select(EntryId::ExportNotes(..)) => {
+                let directory = context.library.home.join(&context.settings.export.directory);
+                let toc = self.toc().or_else(|| self.doc.lock().unwrap().full_toc());
+                let notes = {
+                    let mut doc = self.doc.lock().unwrap();
+                    collect_notes(&self.info, doc.as_mut(), toc.as_deref())
+                };
+                let msg = match export_notes(&notes, &directory, &context.settings.export.formats) {
+                    Err(e) => format!("Can't export notes: {}.", e),
+                    Ok(_) => format!("Saved notes in {}.", context.settings.export.directory.display()),
+                };
+                let notif = Notification::new(msg, hub, rq, context);
+                self.children.push(Box::new(notif) as Box<dyn View>);
+                true
+            },
+            Event::Select(EntryId::BookStatistics(..)) => {
+                self.toggle_bars(Some(false), hub, rq, context);
+                let mut info = self.info.clone();
+                if let Some(r) = info.reader.as_mut() {
+                    r.current_page = self.current_page;
+                    r.pages_count = self.pages_count;
+                    if self.session.pages_turned > 0 {
+                        r.sessions.push(self.session.clone());
+                    }
+                }
+                hub.send(Event::OpenHtml(book_statistics_as_html(&info), None)).ok();
+                true
+            },
             Event::Select(EntryId::ApplyCroppings(index, scheme)) => {
                 self.info.reader.as_mut().map(|r| {
                     if r.cropping_margins.is_none() {
@@ -3905,6 +4738,10 @@ impl View for Reader {
                 self.quit(context);
                 false
             },
+            Event::WakeUp => {
+                self.last_activity = Instant::now();
+                false
+            },
             Event::Focus(v) => {
                 if self.focus != v {
                     if let Some(ViewId::ReaderSearchInput) = v {
@@ -4065,7 +4902,7 @@ impl View for Reader {
                                               scale_by_dpi(BIG_BAR_HEIGHT, dpi) as i32);
             let mut floating_layer_start = 0;
 
-            self.children.retain(|child| !child.is::<Menu>());
+            self.children.retain(|child| !child.is::<Menu>() && !child.is::<FootnotePopup>());
 
             if self.children[0].is::<TopBar>() {
                 let top_bar_rect = rect![rect.min.x, rect.min.y,
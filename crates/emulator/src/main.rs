@@ -17,6 +17,7 @@ use sdl2::rect::Rect as SdlRect;
 use plato_core::framebuffer::{Framebuffer, UpdateMode};
 use plato_core::input::{DeviceEvent, FingerStatus, ButtonCode, ButtonStatus};
 use plato_core::document::sys_info_as_html;
+use plato_core::statistics::statistics_as_html;
 use plato_core::view::{View, Event, ViewId, EntryId, AppCmd, EntryKind};
 use plato_core::view::{process_render_queue, wait_for_all, handle_event, RenderQueue, RenderData};
 use plato_core::view::home::Home;
@@ -357,6 +358,7 @@ fn main() -> Result<(), Error> {
                                         let rect = *view.child(index).rect();
                                         view.children_mut().remove(index);
                                         rq.add(RenderData::expose(rect, UpdateMode::Full));
+                                        tx.send(Event::WakeUp).ok();
                                     } else {
                                         view.handle_event(&Event::Suspend, &tx, &mut VecDeque::new(), &mut RenderQueue::new(), &mut context);
                                         let kind = match scancode {
@@ -513,6 +515,10 @@ fn main() -> Result<(), Error> {
                     rq.add(RenderData::new(dialog.id(), *dialog.rect(), UpdateMode::Gui));
                     view.children_mut().push(Box::new(dialog) as Box<dyn View>);
                 },
+                Event::Select(EntryId::ReadingStatistics) => {
+                    let html = statistics_as_html(&context.library, Local::now().naive_local());
+                    tx.send(Event::OpenHtml(html, None)).ok();
+                },
                 Event::Select(EntryId::SystemInfo) => {
                     view.children_mut().retain(|child| !child.is::<Menu>());
                     let html = sys_info_as_html();
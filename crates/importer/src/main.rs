@@ -3,11 +3,28 @@ use std::path::Path;
 use getopts::Options;
 use plato_core::chrono::NaiveDateTime;
 use plato_core::anyhow::{Error, Context, format_err};
-use plato_core::helpers::datetime_format;
+use plato_core::helpers::{datetime_format, load_toml};
 use plato_core::library::Library;
-use plato_core::settings::{LibraryMode, ImportSettings};
+use plato_core::settings::{Settings, LibraryMode, ImportSettings, ExportFormat, SETTINGS_PATH};
+use plato_core::metadata::{Info, BookQuery};
 use plato_core::metadata::{extract_metadata_from_document, extract_metadata_from_filename};
 use plato_core::metadata::{consolidate, rename_from_info};
+use plato_core::document::open;
+use plato_core::document::pdf::{export_annotations, annotated_path};
+use plato_core::export::{collect_notes, export_notes};
+use plato_core::koreader::import_reading_state;
+
+// The chapters of reflowable documents are left out: laying them out requires Plato's fonts.
+fn export_book_notes(path: &Path, info: &Info, directory: &str, formats: &[ExportFormat]) -> Result<(), Error> {
+    let mut doc = open(path).ok_or_else(|| format_err!("can't open document"))?;
+    if doc.is_protected() {
+        doc.authenticate(info.reader.as_ref().and_then(|r| r.password.as_deref()).unwrap_or_default());
+    }
+    let toc = if doc.is_reflowable() { None } else { doc.full_toc() };
+    let notes = collect_notes(info, doc.as_mut(), toc.as_deref());
+    export_notes(&notes, directory, formats)?;
+    Ok(())
+}
 
 fn main() -> Result<(), Error> {
     let args: Vec<String> = env::args().skip(1).collect();
@@ -21,6 +38,9 @@ fn main() -> Result<(), Error> {
     opts.optflag("F", "extract-metadata-filename", "Extract metadata from filenames.");
     opts.optflag("S", "consolidate", "Autocorrect simple typographic mistakes.");
     opts.optflag("N", "rename-from-info", "Rename files based on their information.");
+    opts.optflag("A", "export-annotations", "Write the annotations into copies of the PDF files.");
+    opts.optopt("x", "export-notes", "Write the annotations and bookmarks in the formats given by the settings to the given directory.", "EXPORT_DIRECTORY");
+    opts.optopt("K", "import-koreader", "Import the reading states saved by the KOReader installed in the given directory.", "KOREADER_DIRECTORY");
     opts.optopt("k", "allowed-kinds", "Comma separated list of allowed kinds.", "ALLOWED_KINDS");
     opts.optopt("e", "metadata-kinds", "Comma separated list of metadata kinds.", "METADATA_KINDS");
     opts.optopt("a", "added-after", "Only process entries added after the given date-time.", "ADDED_DATETIME");
@@ -29,7 +49,7 @@ fn main() -> Result<(), Error> {
     let matches = opts.parse(&args).context("failed to parse the command line arguments")?;
 
     if matches.opt_present("h") {
-        println!("{}", opts.usage("Usage: plato-import -h|-I|-C|-EFSN [-k ALLOWED_KINDS] [-e METADATA_KINDS] [-a ADDED_DATETIME] [-m LIBRARY_MODE] LIBRARY_PATH"));
+        println!("{}", opts.usage("Usage: plato-import -h|-I|-C|-EFSNA [-x EXPORT_DIRECTORY] [-K KOREADER_DIRECTORY] [-k ALLOWED_KINDS] [-e METADATA_KINDS] [-a ADDED_DATETIME] [-m LIBRARY_MODE] LIBRARY_PATH"));
         return Ok(());
     }
 
@@ -75,6 +95,10 @@ fn main() -> Result<(), Error> {
         let opt_extract_metadata_filename = matches.opt_present("F");
         let opt_consolidate = matches.opt_present("S");
         let opt_rename_from_info = matches.opt_present("N");
+        let opt_export_annotations = matches.opt_present("A");
+        let export_settings = load_toml::<Settings, _>(SETTINGS_PATH)
+                                       .map(|settings| settings.export)
+                                       .unwrap_or_default();
 
         library.apply(|path, info| {
             if added_after.map_or(true, |added| info.added >= added) {
@@ -94,8 +118,42 @@ fn main() -> Result<(), Error> {
                 if opt_rename_from_info {
                     rename_from_info(path, info);
                 }
+
+                if opt_export_annotations && info.file.kind == "pdf" {
+                    if let Some(r) = info.reader.as_ref().filter(|r| !r.annotations.is_empty()) {
+                        let target = annotated_path(&path.join(&export_settings.directory), &info.file.path);
+                        let path = path.join(&info.file.path);
+                        if let Err(e) = export_annotations(&path, &target, &r.annotations, r.password.as_deref()) {
+                            eprintln!("Can't export annotations from {}: {:#}.", path.display(), e);
+                        }
+                    }
+                }
             }
         });
+
+        if let Some(directory) = matches.opt_str("x") {
+            let formats = &export_settings.formats;
+            // Unlike `apply`, this also works in filesystem mode.
+            let (infos, _) = library.list(&library.home, Some(&BookQuery::default()), false);
+            for info in infos {
+                if added_after.is_none_or(|added| info.added >= added) &&
+                   info.reader.as_ref().is_some_and(|r| !r.annotations.is_empty() || !r.bookmarks.is_empty()) {
+                    let path = library.home.join(&info.file.path);
+                    if let Err(e) = export_book_notes(&path, &info, &directory, formats) {
+                        eprintln!("Can't export notes from {}: {:#}.", path.display(), e);
+                    }
+                }
+            }
+        }
+
+        if let Some(directory) = matches.opt_str("K") {
+            library.update_reading_states(|path, info| {
+                added_after.is_none_or(|added| info.added >= added) &&
+                import_reading_state(path, info, &directory)
+                    .map_err(|e| eprintln!("Can't import KOReader data for {}: {:#}.", info.file.path.display(), e))
+                    .unwrap_or(false)
+            });
+        }
     }
 
     library.flush();
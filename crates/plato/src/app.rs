@@ -21,6 +21,7 @@ use plato_core::view::sketch::Sketch;
 use plato_core::view::touch_events::TouchEvents;
 use plato_core::view::rotation_values::RotationValues;
 use plato_core::document::sys_info_as_html;
+use plato_core::statistics::statistics_as_html;
 use plato_core::input::{DeviceEvent, PowerSource, ButtonCode, ButtonStatus, VAL_RELEASE, VAL_PRESS};
 use plato_core::input::{raw_events, device_events, usb_events, display_rotate_event, button_scheme_event};
 use plato_core::gesture::{GestureEvent, gesture_events};
@@ -166,6 +167,7 @@ fn resume(id: TaskId, tasks: &mut Vec<Task>, view: &mut dyn View, hub: &Sender<E
         }
         hub.send(Event::ClockTick).ok();
         hub.send(Event::BatteryTick).ok();
+        hub.send(Event::WakeUp).ok();
     }
 }
 
@@ -763,6 +765,10 @@ pub fn run() -> Result<(), Error> {
                 rq.add(RenderData::new(dialog.id(), *dialog.rect(), UpdateMode::Gui));
                 view.children_mut().push(Box::new(dialog) as Box<dyn View>);
             },
+            Event::Select(EntryId::ReadingStatistics) => {
+                let html = statistics_as_html(&context.library, Local::now().naive_local());
+                tx.send(Event::OpenHtml(html, None)).ok();
+            },
             Event::Select(EntryId::SystemInfo) => {
                 view.children_mut().retain(|child| !child.is::<Menu>());
                 let html = sys_info_as_html();
@@ -6,6 +6,7 @@
 [ -d resources ] || ./download.sh 'resources/*'
 [ -d hyphenation-patterns ] || ./download.sh 'hyphenation-patterns/*'
 [ -e target/arm-unknown-linux-gnueabihf/release/plato ] || ./build.sh
+[ -e thirdparty/unrar/unrar ] || (cd thirdparty && ./download.sh unrar && ./build.sh unrar)
 
 mkdir -p dist/libs
 mkdir dist/dictionaries
@@ -28,6 +29,7 @@ cp libs/libmupdf.so dist/libs
 cp -R hyphenation-patterns dist
 cp -R keyboard-layouts dist
 cp -R bin dist
+cp thirdparty/unrar/unrar dist/bin
 cp -R scripts dist
 cp -R icons dist
 cp -R resources dist
@@ -1,4 +1,5 @@
 #include <mupdf/fitz.h>
+#include <mupdf/pdf.h>
 
 #define WRAP(name, ret_type, failure_val, call, ...) \
     ret_type mp_##name(fz_context *ctx, ##__VA_ARGS__) { \
@@ -8,6 +9,16 @@
         return ret; \
     }
 
+#define WRAP_VOID(name, call, ...) \
+    int mp_##name(fz_context *ctx, ##__VA_ARGS__) { \
+        int ret = 0; \
+        fz_try (ctx) { call; } \
+        fz_catch (ctx) { ret = -1; } \
+        return ret; \
+    }
+
+static const fz_quad empty_quad = { { 0, 0 }, { 0, 0 }, { 0, 0 }, { 0, 0 } };
+
 WRAP(open_document, fz_document*, NULL, fz_open_document(ctx, path), char *path)
 WRAP(open_document_with_stream, fz_document*, NULL, fz_open_document_with_stream(ctx, kind, stream), const char *kind, fz_stream *stream)
 WRAP(load_page, fz_page*, NULL, fz_load_page(ctx, doc, pageno), fz_document *doc, int pageno)
@@ -17,3 +28,16 @@ WRAP(count_pages, int, -1, fz_count_pages(ctx, doc), fz_document *doc)
 WRAP(page_number_from_location, int, -1, fz_page_number_from_location(ctx, doc, loc), fz_document *doc, fz_location loc)
 WRAP(new_pixmap_from_page, fz_pixmap*, NULL, fz_new_pixmap_from_page(ctx, page, mat, cs, alpha), fz_page *page, fz_matrix mat, fz_colorspace *cs, int alpha)
 WRAP(new_stext_page_from_page, fz_stext_page*, NULL, fz_new_stext_page_from_page(ctx, page, options), fz_page *page, fz_stext_options *options)
+
+WRAP(create_annot, pdf_annot*, NULL, pdf_create_annot(ctx, pdf_page_from_fz_page(ctx, page), type), fz_page *page, int type)
+WRAP(update_annot, int, -1, pdf_update_annot(ctx, annot), pdf_annot *annot)
+WRAP(annot_type, int, -1, pdf_annot_type(ctx, annot), pdf_annot *annot)
+WRAP(bound_annot, fz_rect, fz_empty_rect, pdf_bound_annot(ctx, annot), pdf_annot *annot)
+WRAP(annot_contents, const char*, NULL, pdf_annot_contents(ctx, annot), pdf_annot *annot)
+WRAP(annot_modification_date, int64_t, 0, pdf_annot_modification_date(ctx, annot), pdf_annot *annot)
+WRAP(annot_quad_point_count, int, 0, pdf_annot_quad_point_count(ctx, annot), pdf_annot *annot)
+WRAP(annot_quad_point, fz_quad, empty_quad, pdf_annot_quad_point(ctx, annot, i), pdf_annot *annot, int i)
+WRAP_VOID(set_annot_quad_points, pdf_set_annot_quad_points(ctx, annot, n, qv), pdf_annot *annot, int n, const fz_quad *qv)
+WRAP_VOID(set_annot_contents, pdf_set_annot_contents(ctx, annot, text), pdf_annot *annot, const char *text)
+WRAP_VOID(set_annot_modification_date, pdf_set_annot_modification_date(ctx, annot, time), pdf_annot *annot, int64_t time)
+WRAP_VOID(save_document, pdf_save_document(ctx, pdf_specifics(ctx, doc), path, NULL), fz_document *doc, const char *path)
@@ -2,7 +2,7 @@
 
 set -e
 
-declare -a packages=(zlib bzip2 libpng libjpeg openjpeg jbig2dec freetype2 harfbuzz gumbo djvulibre mupdf)
+declare -a packages=(zlib bzip2 libpng libjpeg openjpeg jbig2dec freetype2 harfbuzz gumbo djvulibre mupdf unrar)
 
 for name in "${@:-${packages[@]}}" ; do
 	cd "$name"
@@ -16,6 +16,8 @@ declare -A urls=(
 	["gumbo"]="https://github.com/google/gumbo-parser/archive/v0.10.1.tar.gz"
 	["djvulibre"]="http://downloads.sourceforge.net/djvu/djvulibre-3.5.28.tar.gz"
 	["mupdf"]="https://mupdf.com/downloads/archive/mupdf-1.23.11-source.tar.gz"
+	# Archives
+	["unrar"]="https://www.rarlab.com/rar/unrarsrc-7.0.9.tar.gz"
 )
 
 for name in "${@:-${!urls[@]}}" ; do
@@ -0,0 +1,6 @@
+#! /bin/sh
+
+TRIPLE=arm-linux-gnueabihf
+export CXXFLAGS="-O2 -mcpu=cortex-a9 -mfpu=neon"
+
+make -f makefile CXX=${TRIPLE}-g++ STRIP=${TRIPLE}-strip CXXFLAGS="$CXXFLAGS" LDFLAGS="-static -pthread" unrar